@@ -0,0 +1,155 @@
+use std::f64::consts::PI;
+
+/// The number of output samples across which each step is spread
+const KERNEL_WIDTH: usize = 16;
+
+/// The number of positions between two output samples for which the kernel is calculated
+const PHASES: usize = 64;
+
+/// The cutoff frequency of the kernel, as a fraction of the Nyquist frequency of the output
+const CUTOFF: f64 = 0.9;
+
+/// Converts a signal made up of steps at clock cycle timestamps into samples
+///
+/// Rather than point-sampling the signal, which aliases badly when it contains frequencies
+/// above the Nyquist frequency, each change in amplitude is added as a band-limited step. The
+/// steps are windowed sinc functions, so the output lags the input by half the kernel width.
+///
+/// Time is divided into frames. Deltas are added at clock cycles relative to the start of the
+/// current frame, and ending the frame makes the samples before its end available to read.
+pub struct BlipBuffer {
+    /// The number of output samples per clock cycle
+    factor: f64,
+
+    /// The position of the start of the current frame, in samples from the start of `buffer`
+    offset: f64,
+
+    buffer: Vec<f32>,
+    integrator: f32,
+    kernel: Vec<[f32; KERNEL_WIDTH]>
+}
+
+impl BlipBuffer {
+    /// Creates a buffer which converts a signal at `clock_rate` to samples at `sample_rate`
+    pub fn new(clock_rate: f64, sample_rate: u32) -> BlipBuffer {
+        BlipBuffer {
+            factor: sample_rate as f64 / clock_rate,
+            offset: 0.0,
+            buffer: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+            kernel: (0..PHASES).map(kernel).collect()
+        }
+    }
+
+    /// Adds a change in amplitude of `delta` at `time` clock cycles into the current frame
+    pub fn add_delta(&mut self, time: u64, delta: f32) {
+        let pos = self.offset + time as f64 * self.factor;
+        let mut index = pos.floor() as usize;
+        let mut phase = ((pos - pos.floor()) * PHASES as f64).round() as usize;
+        if phase == PHASES {
+            index += 1;
+            phase = 0;
+        }
+
+        if self.buffer.len() < index + KERNEL_WIDTH {
+            self.buffer.resize(index + KERNEL_WIDTH, 0.0);
+        }
+        for (i, k) in self.kernel[phase].iter().enumerate() {
+            self.buffer[index + i] += delta * k;
+        }
+    }
+
+    /// Ends the current frame after `time` clock cycles, and starts the next one
+    pub fn end_frame(&mut self, time: u64) {
+        self.offset += time as f64 * self.factor;
+        let len = self.offset.floor() as usize + KERNEL_WIDTH;
+        if self.buffer.len() < len {
+            self.buffer.resize(len, 0.0);
+        }
+    }
+
+    /// Gets the number of samples available to read
+    pub fn samples_avail(&self) -> usize {
+        self.offset.floor() as usize
+    }
+
+    /// Reads up to `count` available samples into `out`, returning the number read
+    pub fn read_samples(&mut self, out: &mut Vec<f32>, count: usize) -> usize {
+        let count = ::std::cmp::min(count, self.samples_avail());
+        for delta in self.buffer.drain(..count) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.offset -= count as f64;
+        count
+    }
+}
+
+/// Calculates the kernel for steps which occur `phase / PHASES` of the way between two samples
+///
+/// Each value is the difference between two adjacent samples of the step, so the kernel sums to
+/// one and the step is recovered by integrating the output.
+fn kernel(phase: usize) -> [f32; KERNEL_WIDTH] {
+    let center = (KERNEL_WIDTH / 2) as f64 + phase as f64 / PHASES as f64;
+    let mut values = [0.0f64; KERNEL_WIDTH];
+    for (i, value) in values.iter_mut().enumerate() {
+        let x = i as f64 - center;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+        let w = 2.0 * PI * (x / KERNEL_WIDTH as f64 + 0.5);
+        let blackman = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+        *value = CUTOFF * sinc * blackman.max(0.0);
+    }
+
+    let sum: f64 = values.iter().sum();
+    let mut kernel = [0.0f32; KERNEL_WIDTH];
+    for (k, value) in kernel.iter_mut().zip(values.iter()) {
+        *k = (value / sum) as f32;
+    }
+    kernel
+}
+
+#[cfg(test)]
+mod test {
+    use audio::BlipBuffer;
+
+    #[test]
+    pub fn frame_makes_samples_available() {
+        let mut blip = BlipBuffer::new(1000.0, 100);
+        blip.end_frame(105);
+        assert_eq!(10, blip.samples_avail());
+
+        // The remaining half sample carries into the next frame
+        blip.end_frame(5);
+        assert_eq!(11, blip.samples_avail());
+    }
+
+    #[test]
+    pub fn step_settles_at_delta() {
+        let mut blip = BlipBuffer::new(1000.0, 100);
+        blip.add_delta(15, 1.0);
+        blip.end_frame(1000);
+
+        let mut out = Vec::new();
+        assert_eq!(100, blip.read_samples(&mut out, 1000));
+        assert!(out[0].abs() < 0.01);
+        assert!((out[99] - 1.0).abs() < 0.001);
+        assert_eq!(0, blip.samples_avail());
+    }
+
+    #[test]
+    pub fn high_frequencies_are_attenuated() {
+        // A square wave at the input rate's Nyquist frequency averages out to half its
+        // amplitude, rather than aliasing
+        let mut blip = BlipBuffer::new(1000.0, 100);
+        for time in 0..1000 {
+            blip.add_delta(time, if time % 2 == 0 { 1.0 } else { -1.0 });
+        }
+        blip.end_frame(1000);
+
+        let mut out = Vec::new();
+        blip.read_samples(&mut out, 100);
+        for sample in &out[20..] {
+            assert!((sample - 0.5).abs() < 0.05, "sample {} is not near 0.5", sample);
+        }
+    }
+}
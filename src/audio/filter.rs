@@ -0,0 +1,85 @@
+use std::f32::consts::PI;
+
+/// Represents a filter which processes a stream of samples one at a time
+pub trait Filter {
+    /// Filters the next sample in the stream
+    fn process(&mut self, input: f32) -> f32;
+}
+
+/// A first-order high-pass filter, which removes frequencies below its cutoff
+pub struct HighPass {
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32
+}
+
+impl HighPass {
+    /// Creates a filter with a cutoff of `cutoff` Hz for samples at `sample_rate`
+    pub fn new(cutoff: f32, sample_rate: u32) -> HighPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        HighPass {
+            alpha: rc / (rc + dt),
+            prev_input: 0.0,
+            prev_output: 0.0
+        }
+    }
+}
+
+impl Filter for HighPass {
+    fn process(&mut self, input: f32) -> f32 {
+        self.prev_output = self.alpha * (self.prev_output + input - self.prev_input);
+        self.prev_input = input;
+        self.prev_output
+    }
+}
+
+/// A first-order low-pass filter, which removes frequencies above its cutoff
+pub struct LowPass {
+    alpha: f32,
+    prev_output: f32
+}
+
+impl LowPass {
+    /// Creates a filter with a cutoff of `cutoff` Hz for samples at `sample_rate`
+    pub fn new(cutoff: f32, sample_rate: u32) -> LowPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        LowPass {
+            alpha: dt / (rc + dt),
+            prev_output: 0.0
+        }
+    }
+}
+
+impl Filter for LowPass {
+    fn process(&mut self, input: f32) -> f32 {
+        self.prev_output += self.alpha * (input - self.prev_output);
+        self.prev_output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::{Filter,HighPass,LowPass};
+
+    #[test]
+    pub fn high_pass_removes_dc_offset() {
+        let mut filter = HighPass::new(90.0, 44100);
+        let mut output = 1.0;
+        for _ in 0..44100 {
+            output = filter.process(1.0);
+        }
+        assert!(output.abs() < 0.001);
+    }
+
+    #[test]
+    pub fn low_pass_passes_dc_offset() {
+        let mut filter = LowPass::new(14000.0, 44100);
+        let mut output = 0.0;
+        for _ in 0..100 {
+            output = filter.process(1.0);
+        }
+        assert!((output - 1.0).abs() < 0.001);
+    }
+}
@@ -0,0 +1,17 @@
+pub use audio::blip::BlipBuffer;
+pub use audio::filter::{Filter,HighPass,LowPass};
+pub use audio::wav::WavWriter;
+pub use audio::vgm::VgmLog;
+
+/// Provides a buffer which synthesizes band-limited steps, to resample a signal from a
+/// (much higher) clock rate without aliasing
+pub mod blip;
+
+/// Provides simple first-order filters
+pub mod filter;
+
+/// Provides a writer for WAV files
+pub mod wav;
+
+/// Provides a log of sound chip register writes in the VGM format
+pub mod vgm;
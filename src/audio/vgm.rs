@@ -0,0 +1,162 @@
+use std::io;
+use std::collections::HashMap;
+
+use byteorder::{LittleEndian,WriteBytesExt};
+
+/// The version of the format written, 1.71
+const VERSION: u32 = 0x0171;
+
+/// The size of the 1.71 header, which is where the command data begins
+const HEADER_SIZE: usize = 0x100;
+
+/// The rate at which VGM files count time
+pub const SAMPLE_RATE: u64 = 44100;
+
+const CMD_NES_APU_WRITE: u8 = 0xB4;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_DATA_BLOCK: u8 = 0x67;
+const CMD_END: u8 = 0x66;
+
+/// The data block type holding data to be written to the NES APU's address space, from where
+/// the DMC reads its samples
+const BLOCK_NES_APU_RAM: u8 = 0xC2;
+
+/// Records writes to the NES APU in the VGM format, which can be played by standard players
+///
+/// Commands are kept in memory (they rarely amount to more than a few kilobytes a minute) and
+/// the complete file is written by `finish`, once the total length is known.
+pub struct VgmLog {
+    clock_rate: f64,
+    apu_clock: u32,
+    start_cycle: u64,
+    samples: u64,
+    data: Vec<u8>,
+    dpcm_blocks: HashMap<u16, Vec<u8>>
+}
+
+impl VgmLog {
+    /// Starts a log at `cycle` of a CPU running at `clock_rate`, whose APU is clocked at
+    /// `apu_clock` Hz
+    pub fn new(clock_rate: f64, apu_clock: u32, cycle: u64) -> VgmLog {
+        VgmLog {
+            clock_rate: clock_rate,
+            apu_clock: apu_clock,
+            start_cycle: cycle,
+            samples: 0,
+            data: Vec::new(),
+            dpcm_blocks: HashMap::new()
+        }
+    }
+
+    /// Records a write of `val` to APU register `reg` (an offset from $4000) at `cycle`
+    pub fn write_apu(&mut self, cycle: u64, reg: u8, val: u8) {
+        self.wait_until(cycle);
+        self.data.extend_from_slice(&[CMD_NES_APU_WRITE, reg, val]);
+    }
+
+    /// Records the DMC sample data at `addr`, unless the same data was already recorded there
+    pub fn write_dpcm(&mut self, addr: u16, data: &[u8]) {
+        if self.dpcm_blocks.get(&addr).map_or(false, |d| &d[..] == data) {
+            return;
+        }
+
+        self.data.extend_from_slice(&[CMD_DATA_BLOCK, 0x66, BLOCK_NES_APU_RAM]);
+        self.data.write_u32::<LittleEndian>(data.len() as u32 + 2).unwrap();
+        self.data.write_u16::<LittleEndian>(addr).unwrap();
+        self.data.extend_from_slice(data);
+        self.dpcm_blocks.insert(addr, data.to_vec());
+    }
+
+    /// Ends the log at `cycle`, and writes the complete file to `out`
+    pub fn finish<W>(mut self, cycle: u64, out: &mut W) -> io::Result<()> where W: io::Write {
+        self.wait_until(cycle);
+        self.data.push(CMD_END);
+
+        let mut header = vec![0u8; HEADER_SIZE];
+        {
+            let mut set = |offset: usize, val: u32| {
+                (&mut header[offset..offset + 4]).write_u32::<LittleEndian>(val).unwrap();
+            };
+            set(0x00, 0x206D6756); // "Vgm "
+            set(0x04, (HEADER_SIZE + self.data.len() - 4) as u32);
+            set(0x08, VERSION);
+            set(0x18, self.samples as u32);
+            set(0x34, (HEADER_SIZE - 0x34) as u32);
+            set(0x84, self.apu_clock);
+        }
+
+        try!(out.write_all(&header));
+        out.write_all(&self.data)
+    }
+
+    /// Adds wait commands to bring the log up to `cycle`
+    fn wait_until(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.start_cycle) as f64 / self.clock_rate;
+        let target = (elapsed * SAMPLE_RATE as f64) as u64;
+        while self.samples < target {
+            let wait = ::std::cmp::min(target - self.samples, 0xFFFF);
+            if wait <= 16 {
+                self.data.push(CMD_WAIT_SHORT + (wait - 1) as u8);
+            } else {
+                self.data.push(CMD_WAIT);
+                self.data.write_u16::<LittleEndian>(wait as u16).unwrap();
+            }
+            self.samples += wait;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use audio::VgmLog;
+
+    fn finish(log: VgmLog, cycle: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        log.finish(cycle, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    pub fn header_describes_nes_apu() {
+        let out = finish(VgmLog::new(44100.0, 1789772, 0), 0);
+
+        assert_eq!(b"Vgm ", &out[0..4]);
+        assert_eq!(&[0x71, 0x01, 0x00, 0x00], &out[0x08..0x0C]);
+        assert_eq!(&[0xCC, 0x00, 0x00, 0x00], &out[0x34..0x38]);
+        assert_eq!(&[0x4C, 0x4F, 0x1B, 0x00], &out[0x84..0x88]);
+        assert_eq!(&[0x66], &out[0x100..]);
+        assert_eq!(out.len() as u32 - 4, out[4] as u32);
+    }
+
+    #[test]
+    pub fn writes_are_separated_by_waits() {
+        // Using the sample rate as the clock rate makes each cycle one sample
+        let mut log = VgmLog::new(44100.0, 1789772, 100);
+        log.write_apu(100, 0x15, 0x0F);
+        log.write_apu(104, 0x00, 0xBF);
+        log.write_apu(1104, 0x01, 0x08);
+        let out = finish(log, 1104);
+
+        assert_eq!(&[
+            0xB4, 0x15, 0x0F,
+            0x73, 0xB4, 0x00, 0xBF,
+            0x61, 0xE8, 0x03, 0xB4, 0x01, 0x08,
+            0x66], &out[0x100..]);
+        assert_eq!(&[0xEC, 0x03, 0x00, 0x00], &out[0x18..0x1C]);
+    }
+
+    #[test]
+    pub fn identical_dpcm_data_is_only_written_once() {
+        let mut log = VgmLog::new(44100.0, 1789772, 0);
+        log.write_dpcm(0xC000, &[1, 2, 3]);
+        log.write_dpcm(0xC000, &[1, 2, 3]);
+        log.write_dpcm(0xC000, &[4]);
+        let out = finish(log, 0);
+
+        assert_eq!(&[
+            0x67, 0x66, 0xC2, 0x05, 0x00, 0x00, 0x00, 0x00, 0xC0, 1, 2, 3,
+            0x67, 0x66, 0xC2, 0x03, 0x00, 0x00, 0x00, 0x00, 0xC0, 4,
+            0x66], &out[0x100..]);
+    }
+}
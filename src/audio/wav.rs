@@ -0,0 +1,105 @@
+use std::io::{self,SeekFrom};
+
+use byteorder::{LittleEndian,WriteBytesExt};
+
+/// The size of the RIFF and format chunks which precede the sample data
+const HEADER_SIZE: u32 = 44;
+
+/// Format tag for uncompressed PCM samples
+const FORMAT_PCM: u16 = 1;
+
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes 16-bit mono samples to a WAV file as they are produced
+///
+/// The sizes in the header are filled in by `finish`, so the writer must be seekable. A file
+/// which is not finished (for example, if the emulator panics) still contains all of the
+/// samples written to it, but most programs will report its length as zero.
+pub struct WavWriter<W> where W: io::Write + io::Seek {
+    out: W,
+    samples: u32
+}
+
+impl<W> WavWriter<W> where W: io::Write + io::Seek {
+    /// Creates a writer for samples at `sample_rate`, writing the header to `out`
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let block_align = BITS_PER_SAMPLE / 8;
+
+        try!(out.write_all(b"RIFF"));
+        try!(out.write_u32::<LittleEndian>(HEADER_SIZE - 8));
+        try!(out.write_all(b"WAVE"));
+
+        try!(out.write_all(b"fmt "));
+        try!(out.write_u32::<LittleEndian>(16));
+        try!(out.write_u16::<LittleEndian>(FORMAT_PCM));
+        try!(out.write_u16::<LittleEndian>(1));
+        try!(out.write_u32::<LittleEndian>(sample_rate));
+        try!(out.write_u32::<LittleEndian>(sample_rate * block_align as u32));
+        try!(out.write_u16::<LittleEndian>(block_align));
+        try!(out.write_u16::<LittleEndian>(BITS_PER_SAMPLE));
+
+        try!(out.write_all(b"data"));
+        try!(out.write_u32::<LittleEndian>(0));
+
+        Ok(WavWriter {
+            out: out,
+            samples: 0
+        })
+    }
+
+    /// Appends `samples` to the file
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for &sample in samples {
+            try!(self.out.write_i16::<LittleEndian>(sample));
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Gets the number of samples written so far
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Fills in the sizes in the header, and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * (BITS_PER_SAMPLE / 8) as u32;
+        try!(self.out.seek(SeekFrom::Start(4)));
+        try!(self.out.write_u32::<LittleEndian>(HEADER_SIZE - 8 + data_size));
+        try!(self.out.seek(SeekFrom::Start(40)));
+        try!(self.out.write_u32::<LittleEndian>(data_size));
+        try!(self.out.seek(SeekFrom::End(0)));
+        try!(self.out.flush());
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use audio::WavWriter;
+
+    #[test]
+    pub fn header_describes_mono_16_bit_pcm() {
+        let wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(44, data.len());
+        assert_eq!(b"RIFF", &data[0..4]);
+        assert_eq!(b"WAVEfmt ", &data[8..16]);
+        assert_eq!(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 0x01, 0, 2, 0, 16, 0], &data[20..36]);
+    }
+
+    #[test]
+    pub fn finish_fills_in_sizes() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write_samples(&[1, -1, 0x1234]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&[42, 0, 0, 0], &data[4..8]);
+        assert_eq!(b"data", &data[36..40]);
+        assert_eq!(&[6, 0, 0, 0], &data[40..44]);
+        assert_eq!(&[1, 0, 0xFF, 0xFF, 0x34, 0x12], &data[44..]);
+    }
+}
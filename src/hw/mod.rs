@@ -11,3 +11,10 @@ pub mod mos6502;
 /// This processor is found in the [Nintendo Entertainment System](http://en.wikipedia.org/wiki/Nintendo_Entertainment_System)
 #[allow(non_snake_case)]
 pub mod rp2C02;
+
+/// Provides emulation for the Audio Processing Unit of the [Ricoh RP2A03](http://wiki.nesdev.com/w/index.php/2A03) as well
+/// as the RP2A07 (for PAL television systems)
+///
+/// This processor is found in the [Nintendo Entertainment System](http://en.wikipedia.org/wiki/Nintendo_Entertainment_System)
+#[allow(non_snake_case)]
+pub mod rp2A03;
@@ -115,6 +115,30 @@ impl fmt::Display for RegisterName {
     }
 }
 
+/// Denotes an interrupt that can be serviced by the processor
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Interrupt {
+    /// Denotes a non-maskable interrupt, which jumps to the vector at $FFFA
+    Nmi,
+
+    /// Denotes a maskable interrupt request, which jumps to the vector at $FFFE
+    Irq
+}
+
+impl Interrupt {
+    /// Gets the address of the vector containing the interrupt handler
+    pub fn vector(self) -> u64 {
+        match self {
+            Interrupt::Nmi => 0xFFFA,
+            Interrupt::Irq => 0xFFFE
+        }
+    }
+}
+
+serialize_via_debug!(Interrupt);
+
+impl ::slog::ser::SyncSerialize for Interrupt {}
+
 /// Represents a MOS 6502 Central Processing Unit
 ///
 /// Includes support for Binary Coded Decimal arithmetic, does
@@ -130,6 +154,10 @@ pub struct Mos6502 {
     pub bcd_enabled: bool,
     /// Tracks CPU cycles spent during execution
     pub clock: clock::Clock,
+    /// The cycle at which an edge was detected on the NMI line, if it has not been serviced
+    nmi: Option<u64>,
+    /// Indicates if the IRQ line is asserted
+    irq: bool
 }
 
 impl Mos6502 {
@@ -143,7 +171,9 @@ impl Mos6502 {
             flags: Flags::RESERVED(),
             pc: pc::ProgramCounter::new(),
             bcd_enabled: true,
-            clock: clock::Clock::new()
+            clock: clock::Clock::new(),
+            nmi: None,
+            irq: false
         }
     }
 
@@ -157,7 +187,48 @@ impl Mos6502 {
             flags: Flags::RESERVED(),
             pc: pc::ProgramCounter::new(),
             bcd_enabled: false,
-            clock: clock::Clock::new()
+            clock: clock::Clock::new(),
+            nmi: None,
+            irq: false
+        }
+    }
+
+    /// Signals an edge on the NMI line, detected during `cycle`
+    ///
+    /// The NMI input is edge sensitive, so the interrupt remains pending until it is serviced
+    /// even if the line is released in the meantime.
+    pub fn nmi(&mut self, cycle: u64) {
+        if self.nmi.is_none() {
+            self.nmi = Some(cycle);
+        }
+    }
+
+    /// Asserts or releases the IRQ line
+    ///
+    /// The IRQ input is level sensitive, so the interrupt is only serviced while the line is
+    /// asserted and the `INTERRUPT` flag is clear.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    /// Gets the interrupt that the processor would service if it polled its inputs at the end
+    /// of `cycle`, if any
+    ///
+    /// The 6502 polls its interrupt inputs before the final cycle of each instruction, so an
+    /// NMI detected during the final cycle is not serviced until the next instruction has
+    /// completed.
+    pub fn poll_interrupt(&self, cycle: u64) -> Option<Interrupt> {
+        match self.nmi {
+            Some(detected) if detected < cycle => Some(Interrupt::Nmi),
+            _ if self.irq && !self.flags.intersects(Flags::INTERRUPT()) => Some(Interrupt::Irq),
+            _ => None
+        }
+    }
+
+    /// Acknowledges an interrupt that is being serviced
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        if interrupt == Interrupt::Nmi {
+            self.nmi = None;
         }
     }
 
@@ -14,7 +14,7 @@ pub fn exec<M>(cpu: &mut Mos6502, mem: &mut M, op: Operand, log: &slog::Logger)
         "op" => op;
         "evaluated b << 1 = r");
 
-    try_log!(op.set_u8(cpu, mem, r), log);
+    try_log!(op.set_modified_u8(cpu, mem, b, r), log);
 
     if cpu.flags.set_if(Flags::CARRY(), b & 0x80 != 0) {
         trace!(log, "cpu" => cpu; "setting CARRY");
@@ -33,7 +33,7 @@ pub fn mem<M>(cpu: &mut Mos6502, mem: &mut M, op: Operand, log: &slog::Logger) -
         "evaluated mem-- = r");
 
     cpu.flags.set_sign_and_zero(new_val); 
-    try_log!(op.set_u8(cpu, mem, new_val), log);
+    try_log!(op.set_modified_u8(cpu, mem, old_val, new_val), log);
     trace!(log, "cpu" => cpu, "addr" => op.get_addr(cpu, mem).ok(); "stored result");
 
     Ok(())
@@ -32,7 +32,7 @@ pub fn mem<M>(cpu: &mut Mos6502, mem: &mut M, op: Operand, log: &slog::Logger) -
         "evaluated mem++ = r");
 
     cpu.flags.set_sign_and_zero(new_val);
-    try_log!(op.set_u8(cpu, mem, new_val), log);
+    try_log!(op.set_modified_u8(cpu, mem, old_val, new_val), log);
     trace!(log, "cpu" => cpu, "addr" => op.get_addr(cpu, mem).ok(); "stored result"); 
 
     Ok(())
@@ -49,7 +49,7 @@ mod test {
     fn inc_sets_sign_flag_if_new_value_is_negative() {
         let (mut cpu, mut mem) = init_cpu();
         mem.set_u8(0, 127u8).unwrap();
-        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0)).unwrap();
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0), &log()).unwrap();
         assert!(cpu.flags.intersects(Flags::SIGN()));
     }
 
@@ -58,7 +58,7 @@ mod test {
         let (mut cpu, mut mem) = init_cpu();
         cpu.flags.set(Flags::SIGN());
         mem.set_u8(0, -1i8 as u8).unwrap();
-        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0)).unwrap();
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0), &log()).unwrap();
         assert!(!cpu.flags.intersects(Flags::SIGN()));
     }
 
@@ -66,7 +66,7 @@ mod test {
     fn inc_sets_zero_flag_if_new_value_is_zero() {
         let (mut cpu, mut mem) = init_cpu();
         mem.set_u8(0, -1i8 as u8).unwrap();
-        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0)).unwrap();
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0), &log()).unwrap();
         assert!(cpu.flags.intersects(Flags::ZERO()));
     }
 
@@ -75,7 +75,7 @@ mod test {
         let (mut cpu, mut mem) = init_cpu();
         cpu.flags.set(Flags::ZERO());
         mem.set_u8(0, 0).unwrap();
-        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0)).unwrap();
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0), &log()).unwrap();
         assert!(!cpu.flags.intersects(Flags::ZERO()));
     }
 
@@ -83,10 +83,36 @@ mod test {
     fn inc_sets_operand_to_original_value_plus_one() {
         let (mut cpu, mut mem) = init_cpu();
         mem.set_u8(0, 42).unwrap();
-        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0)).unwrap();
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0), &log()).unwrap();
         assert_eq!(Ok(43), mem.get_u8(0));
     }
 
+    #[test]
+    fn inc_writes_original_value_before_new_value() {
+        let mut cpu = Mos6502::new();
+        let mut mem = WriteLog { writes: Vec::new() };
+        inc::mem(&mut cpu, &mut mem, Operand::Absolute(0x8000), &log()).unwrap();
+        assert_eq!(vec![(0x8000, 42), (0x8000, 43)], mem.writes);
+    }
+
+    /// A memory which always reads 42 and records the writes made to it
+    struct WriteLog {
+        writes: Vec<(u64, u8)>
+    }
+
+    impl Memory for WriteLog {
+        fn len(&self) -> u64 { 0x10000 }
+        fn get_u8(&self, _addr: u64) -> mem::Result<u8> { Ok(42) }
+        fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+            self.writes.push((addr, val));
+            Ok(())
+        }
+    }
+
+    fn log() -> ::slog::Logger {
+        unwrap_logger!(None)
+    }
+
     fn init_cpu() -> (Mos6502,mem::Virtual<'static>) {
         let base_memory = mem::Fixed::new(10);
         let mut vm = mem::Virtual::new();
@@ -0,0 +1,132 @@
+use slog;
+use byteorder::LittleEndian;
+
+use mem::{Memory,MemoryExt};
+use hw::mos6502::exec;
+use hw::mos6502::{Mos6502,Flags,Interrupt};
+
+pub fn exec<M>(cpu: &mut Mos6502, mem: &mut M, interrupt: Interrupt, log: &slog::Logger) -> Result<(), exec::Error> where M: Memory {
+    let pc = cpu.pc.get();
+    try_log!(cpu.push(mem, ((pc & 0xFF00) >> 8) as u8), log);
+    try_log!(cpu.push(mem, (pc & 0x00FF) as u8), log);
+    trace!(log, "cpu" => cpu, "return_pc" => pc; "pushed return PC value on stack");
+
+    // Unlike BRK, hardware interrupts push the flags with the break flag clear
+    let pushed_flags = cpu.flags & !Flags::BREAK();
+    try_log!(cpu.push(mem, pushed_flags.bits), log);
+    trace!(log, "cpu" => cpu, "pushed_flags" => pushed_flags; "pushed flags on stack");
+
+    cpu.flags.set(Flags::INTERRUPT());
+    cpu.acknowledge(interrupt);
+
+    let vector = interrupt.vector();
+    trace!(log, "cpu" => cpu, "vector" => format!("${:04X}", vector); "jumping to interrupt handler");
+    cpu.pc.set(try_log!(mem.get_u16::<LittleEndian>(vector), log) as u64);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use byteorder::LittleEndian;
+
+    use mem::{self,Memory,MemoryExt};
+    use hw::mos6502::exec::interrupt;
+    use hw::mos6502::{Mos6502,Flags,Interrupt};
+    use hw::mos6502::STACK_START;
+
+    #[test]
+    pub fn interrupt_pushes_pc_on_to_stack() {
+        let (mut cpu, mut mem) = init_cpu();
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Nmi, &log()).unwrap();
+
+        assert_eq!(Ok(0xAB), mem.get_u8(STACK_START + 16));
+        assert_eq!(Ok(0xCD), mem.get_u8(STACK_START + 15));
+    }
+
+    #[test]
+    pub fn interrupt_pushes_flags_without_break_flag() {
+        let (mut cpu, mut mem) = init_cpu();
+        let flags = Flags::SIGN() | Flags::BREAK() | Flags::RESERVED();
+        cpu.flags.set(flags);
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Irq, &log()).unwrap();
+
+        assert_eq!(Ok((Flags::SIGN() | Flags::RESERVED()).bits), mem.get_u8(STACK_START + 14));
+    }
+
+    #[test]
+    pub fn interrupt_sets_interrupt_flag() {
+        let (mut cpu, mut mem) = init_cpu();
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Nmi, &log()).unwrap();
+
+        assert!(cpu.flags.intersects(Flags::INTERRUPT()));
+    }
+
+    #[test]
+    pub fn nmi_sets_pc_to_address_at_nmi_vector() {
+        let (mut cpu, mut mem) = init_cpu();
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Nmi, &log()).unwrap();
+
+        assert_eq!(0xCAFE, cpu.pc.get());
+    }
+
+    #[test]
+    pub fn irq_sets_pc_to_address_at_irq_vector() {
+        let (mut cpu, mut mem) = init_cpu();
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Irq, &log()).unwrap();
+
+        assert_eq!(0xBEEF, cpu.pc.get());
+    }
+
+    #[test]
+    pub fn nmi_is_acknowledged() {
+        let (mut cpu, mut mem) = init_cpu();
+        cpu.nmi(10);
+        assert_eq!(Some(Interrupt::Nmi), cpu.poll_interrupt(11));
+        interrupt::exec(&mut cpu, &mut mem, Interrupt::Nmi, &log()).unwrap();
+
+        assert_eq!(None, cpu.poll_interrupt(20));
+    }
+
+    #[test]
+    pub fn nmi_detected_on_polling_cycle_is_delayed() {
+        let (mut cpu, _) = init_cpu();
+        cpu.nmi(10);
+
+        assert_eq!(None, cpu.poll_interrupt(10));
+        assert_eq!(Some(Interrupt::Nmi), cpu.poll_interrupt(11));
+    }
+
+    #[test]
+    pub fn irq_is_masked_by_interrupt_flag() {
+        let (mut cpu, _) = init_cpu();
+        cpu.set_irq(true);
+        assert_eq!(Some(Interrupt::Irq), cpu.poll_interrupt(0));
+
+        cpu.flags.set(Flags::INTERRUPT());
+        assert_eq!(None, cpu.poll_interrupt(0));
+    }
+
+    fn log() -> ::slog::Logger {
+        unwrap_logger!(None)
+    }
+
+    fn init_cpu() -> (Mos6502, mem::Virtual<'static>) {
+        let base_memory = mem::Fixed::new(32);
+        let stack_memory = mem::Fixed::new(32);
+        let vector_memory = mem::Fixed::new(6);
+        let mut vm = mem::Virtual::new();
+
+        vm.attach(0, Box::new(base_memory)).unwrap();
+        vm.attach(STACK_START, Box::new(stack_memory)).unwrap();
+        vm.attach(0xFFFA, Box::new(vector_memory)).unwrap();
+
+        let mut cpu = Mos6502::new();
+
+        cpu.registers.sp = 16;
+        cpu.pc.set(0xABCD);
+        vm.set_u16::<LittleEndian>(0xFFFA, 0xCAFE).unwrap();
+        vm.set_u16::<LittleEndian>(0xFFFE, 0xBEEF).unwrap();
+
+        (cpu, vm)
+    }
+}
@@ -29,7 +29,7 @@ pub fn exec<M>(cpu: &mut Mos6502, mem: &mut M, op: Operand, log: &slog::Logger)
         trace!(log, "cpu" => cpu; "clearing ZERO");
     }
 
-    try!(op.set_u8(cpu, mem, m));
+    try!(op.set_modified_u8(cpu, mem, n, m));
     trace!(log, "cpu" => cpu, "addr" => op.get_addr(cpu, mem).ok(); "storing result");
 
     Ok(())
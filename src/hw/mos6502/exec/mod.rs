@@ -3,7 +3,7 @@ use std::{error,fmt};
 
 use mem;
 
-use hw::mos6502::{cpu,operand,Mos6502,Flags,Instruction};
+use hw::mos6502::{cpu,operand,Mos6502,Flags,Instruction,Interrupt};
 
 mod adc;
 mod and;
@@ -17,6 +17,7 @@ mod compare;
 mod dec;
 mod eor;
 mod inc;
+mod interrupt;
 mod jmp;
 mod jsr;
 mod load;
@@ -212,3 +213,28 @@ pub fn dispatch<M>(inst: Instruction, cpu: &mut Mos6502, mem: &mut M, logger: Op
 
     result
 }
+
+/// Services an interrupt on the provided CPU
+///
+/// The return address and flags are pushed on to the stack, further IRQs are disabled and
+/// execution continues at the address in the interrupt's vector. Servicing an interrupt takes
+/// 7 cycles.
+///
+/// # Arguments
+///
+/// * `interrupt` - The interrupt to service
+/// * `cpu` - The processor on which to service the interrupt
+pub fn interrupt<M>(interrupt: Interrupt, cpu: &mut Mos6502, mem: &mut M, logger: Option<slog::Logger>) -> Result where M: mem::Memory {
+    let log = unwrap_logger!(logger).new(o!(
+        "interrupt" => interrupt
+    ));
+
+    trace!(log, "servicing"; "cpu" => cpu);
+
+    cpu.clock.tick(7);
+    let result = interrupt::exec(cpu, mem, interrupt, &log);
+
+    debug!(log, "serviced");
+
+    result
+}
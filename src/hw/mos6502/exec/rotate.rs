@@ -39,7 +39,7 @@ fn exec<M>(cpu: &mut Mos6502, mem: &mut M, op: Operand, left: bool, log: &slog::
         "carry_in" => carry_byte;
         "rotated mem {}", if left { "left" } else { "right" });
 
-    try_log!(op.set_u8(cpu, mem, b), log);
+    try_log!(op.set_modified_u8(cpu, mem, n, b), log);
     trace!(log, "cpu" => cpu, "addr" => addr_str!(op.get_addr(cpu, mem)); "stored result");
 
     // Set the flags
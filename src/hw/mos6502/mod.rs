@@ -1,7 +1,7 @@
 pub use hw::mos6502::operand::Operand;
 pub use hw::mos6502::instr::Instruction;
-pub use hw::mos6502::cpu::{Mos6502,Flags,RegisterName};
-pub use hw::mos6502::exec::dispatch;
+pub use hw::mos6502::cpu::{Mos6502,Flags,Interrupt,RegisterName};
+pub use hw::mos6502::exec::{dispatch,interrupt};
 
 /// Defines the instructions that can be executed on the processor
 pub mod instr;
@@ -151,6 +151,24 @@ impl Operand {
         }
     }
 
+    /// Writes the result of a read-modify-write instruction to the operand
+    ///
+    /// The 6502 writes the unmodified value back to memory on the cycle before it writes the
+    /// result, and hardware watching the bus (such as the serial port of the MMC1) sees both
+    /// writes, so both are performed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu` - The cpu on which to set the operand value
+    /// * `old` - The value read from the operand
+    /// * `new` - The result to set the operand to
+    pub fn set_modified_u8<M>(&self, cpu: &mut Mos6502, mem: &mut M, old: u8, new: u8) -> Result<()> where M: mem::Memory {
+        if self.has_addr() {
+            try!(self.set_u8(cpu, mem, old));
+        }
+        self.set_u8(cpu, mem, new)
+    }
+
     /// Retrieves the address of the operand on the specified cpu
     ///
     /// # Arguments
@@ -343,6 +361,38 @@ mod test {
             assert_eq!(cpu.registers.a, 42);
         }
 
+        /// A memory which records the writes made to it
+        struct WriteLog {
+            writes: Vec<(u64, u8)>
+        }
+
+        impl Memory for WriteLog {
+            fn len(&self) -> u64 { 0x10000 }
+            fn get_u8(&self, _addr: u64) -> mem::Result<u8> { Ok(0) }
+            fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+                self.writes.push((addr, val));
+                Ok(())
+            }
+        }
+
+        #[test]
+        pub fn set_modified_writes_old_value_then_new_value_to_memory() {
+            let mut mem = WriteLog { writes: Vec::new() };
+            let mut cpu = Mos6502::new();
+            Operand::Absolute(0x8000).set_modified_u8(&mut cpu, &mut mem, 24, 42).unwrap();
+            assert_eq!(vec![(0x8000, 24), (0x8000, 42)], mem.writes);
+        }
+
+        #[test]
+        pub fn set_modified_accumulator_only_sets_new_value() {
+            let mut mem = WriteLog { writes: Vec::new() };
+            let mut cpu = Mos6502::new();
+            cpu.registers.a = 24;
+            Operand::Accumulator.set_modified_u8(&mut cpu, &mut mem, 24, 42).unwrap();
+            assert_eq!(42, cpu.registers.a);
+            assert!(mem.writes.is_empty());
+        }
+
         #[test]
         pub fn get_accumulator_returns_value_from_accumulator() {
             let mut cpu = Mos6502::new();
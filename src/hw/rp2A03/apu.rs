@@ -0,0 +1,551 @@
+use std::{error,fmt};
+
+use slog;
+
+use mem;
+use clock;
+use audio::{BlipBuffer,Filter,HighPass,LowPass};
+use hw::rp2A03::Timing;
+use hw::rp2A03::pulse::Pulse;
+use hw::rp2A03::triangle::Triangle;
+use hw::rp2A03::noise::Noise;
+use hw::rp2A03::dmc::Dmc;
+use hw::rp2A03::frame_counter::FrameCounter;
+use hw::rp2A03::mixer::{self,Mixer,Channel};
+
+/// The sample rate used until one is configured
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// The CPU clock rate of an NTSC console, used until one is configured
+const DEFAULT_CLOCK_RATE: f64 = 1789772.7272727;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Represents an error that occurs while the APU is running
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum Error {
+    /// Indicates that an error occurred while the DMC was reading a sample
+    ErrorAccessingMemory(mem::Error)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::ErrorAccessingMemory(_) => "error reading DMC sample"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::ErrorAccessingMemory(ref err) => Some(err)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::ErrorAccessingMemory(ref err) => write!(fmt, "error reading DMC sample: {}", err)
+        }
+    }
+}
+
+impl From<mem::Error> for Error {
+    fn from(err: mem::Error) -> Error {
+        Error::ErrorAccessingMemory(err)
+    }
+}
+
+serialize_via_debug!(Error);
+
+/// Represents the parts of the system reachable by the APU
+///
+/// The DMC reads its samples through the bus, and the bus provides the output of any expansion
+/// audio hardware so that it can be mixed with the APU's output.
+pub trait Bus: mem::Memory {
+    /// Clocks any expansion audio hardware for one CPU cycle, and gets its output level
+    ///
+    /// The level is relative to the APU's mixed output, where 1.0 is as loud as the APU can be.
+    fn expansion_audio(&mut self) -> f32 {
+        0.0
+    }
+}
+
+/// Represents the Audio Processing Unit of the RP2A03
+///
+/// The APU runs on the CPU clock. Each cycle the output of the channels is mixed, and changes
+/// in the mixed level are synthesized into band-limited samples at the configured sample rate.
+///
+/// The DMC reads its samples from the bus provided to `step`. The CPU is halted while each
+/// byte is read, so the cycles on which reads occurred are recorded and can be retrieved with
+/// `take_dma_reads` in order to stall the CPU.
+///
+/// Along with the mixed output, samples can be produced for individual channels before they
+/// are mixed, which are unaffected by the mixer's volume, mute and solo settings.
+pub struct Apu {
+    clock: clock::Clock,
+    timing: Timing,
+
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    mixer: Mixer,
+
+    dma_reads: Vec<u64>,
+    clock_rate: f64,
+    output: Output,
+    channel_outputs: Vec<Option<Output>>,
+    log: slog::Logger
+}
+
+impl Apu {
+    pub fn new(logger: Option<slog::Logger>) -> Apu {
+        let timing = Timing::ntsc();
+        Apu {
+            clock: clock::Clock::new(),
+            timing: timing,
+            pulse1: Pulse::first(),
+            pulse2: Pulse::second(),
+            triangle: Triangle::new(),
+            noise: Noise::new(timing.noise_periods),
+            dmc: Dmc::new(timing.dmc_rates),
+            frame_counter: FrameCounter::new(&timing),
+            mixer: Mixer::new(),
+            dma_reads: Vec::new(),
+            clock_rate: DEFAULT_CLOCK_RATE,
+            output: Output::new(DEFAULT_CLOCK_RATE, DEFAULT_SAMPLE_RATE, 0),
+            channel_outputs: (0..mixer::CHANNELS).map(|_| None).collect(),
+            log: unwrap_logger!(logger).new(o!("device" => "apu"))
+        }
+    }
+
+    /// Gets the number of CPU cycles the APU has run for
+    pub fn cycles(&self) -> u64 {
+        self.clock.get()
+    }
+
+    /// Gets the timing the APU is following
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Switches the APU to the timing of a different variant
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.noise.set_periods(timing.noise_periods);
+        self.dmc.set_rates(timing.dmc_rates);
+        self.frame_counter.set_timing(&timing);
+    }
+
+    /// Sets the rate at which samples are produced, given the rate of the CPU clock driving
+    /// the APU
+    ///
+    /// Any samples that have not yet been taken are discarded.
+    pub fn set_sample_rate(&mut self, sample_rate: u32, clock_rate: f64) {
+        debug!(self.log, "sample_rate" => sample_rate, "clock_rate" => clock_rate; "setting sample rate");
+        self.clock_rate = clock_rate;
+        self.output = Output::new(clock_rate, sample_rate, self.clock.get());
+        for channel in Channel::all().iter() {
+            if self.channel_outputs[channel.index()].is_some() {
+                self.set_channel_output(*channel, false);
+                self.set_channel_output(*channel, true);
+            }
+        }
+    }
+
+    /// Gets the rate at which samples are produced
+    pub fn sample_rate(&self) -> u32 {
+        self.output.sample_rate
+    }
+
+    /// Takes the samples produced since the last call
+    ///
+    /// The number of samples depends only on the number of cycles the APU has run for, so
+    /// taking the samples once per video frame produces a stable number of samples per frame.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.output.take(self.clock.get())
+    }
+
+    /// Gets the DMC
+    pub fn dmc(&self) -> &Dmc {
+        &self.dmc
+    }
+
+    /// Gets the mixer, which controls the volume of each channel in the mixed output
+    pub fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+
+    /// Gets a mutable reference to the mixer, to adjust the volume of each channel
+    pub fn mixer_mut(&mut self) -> &mut Mixer {
+        &mut self.mixer
+    }
+
+    /// Enables or disables producing samples for `channel`, in addition to the mixed output
+    ///
+    /// The channel is passed through the mixer and filters on its own, as if the other
+    /// channels were silent.
+    pub fn set_channel_output(&mut self, channel: Channel, enabled: bool) {
+        let output = &mut self.channel_outputs[channel.index()];
+        if !enabled {
+            *output = None;
+        } else if output.is_none() {
+            *output = Some(Output::new(self.clock_rate, self.output.sample_rate, self.clock.get()));
+        }
+    }
+
+    /// Takes the samples produced for `channel` since the last call, which is empty unless
+    /// output for the channel is enabled
+    pub fn take_channel_samples(&mut self, channel: Channel) -> Vec<i16> {
+        let cycle = self.clock.get();
+        match self.channel_outputs[channel.index()] {
+            Some(ref mut output) => output.take(cycle),
+            None => Vec::new()
+        }
+    }
+
+    /// Returns a value indicating if the APU is asserting the CPU's IRQ line
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    /// Takes the CPU cycles on which the DMC has read sample bytes since the last call
+    pub fn take_dma_reads(&mut self) -> Vec<u64> {
+        ::std::mem::replace(&mut self.dma_reads, Vec::new())
+    }
+
+    /// Runs the APU until it has run for `target_cycle` CPU cycles
+    pub fn step<B>(&mut self, target_cycle: u64, bus: &mut B) -> Result<()> where B: Bus {
+        while self.clock.get() < target_cycle {
+            try!(self.tick(bus));
+        }
+        Ok(())
+    }
+
+    /// Reads the register at `reg` (an offset from $4000)
+    ///
+    /// Only the status register ($4015) can be read, the other registers are write-only and
+    /// read as open bus. Reading the status register acknowledges the frame interrupt.
+    pub fn read_register(&mut self, reg: u64) -> Option<u8> {
+        match reg {
+            0x15 => {
+                let mut val = 0;
+                if !self.pulse1.length.silenced() { val |= 0x01; }
+                if !self.pulse2.length.silenced() { val |= 0x02; }
+                if !self.triangle.length.silenced() { val |= 0x04; }
+                if !self.noise.length.silenced() { val |= 0x08; }
+                if self.dmc.active() { val |= 0x10; }
+                if self.frame_counter.irq() { val |= 0x40; }
+                if self.dmc.irq() { val |= 0x80; }
+                self.frame_counter.acknowledge();
+                Some(val)
+            },
+            _ => None
+        }
+    }
+
+    /// Writes `val` to the register at `reg` (an offset from $4000)
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        trace!(self.log,
+            "reg" => format!("$40{:02X}", reg),
+            "val" => format!("${:02X}", val),
+            "cycle" => self.clock.get();
+            "register write");
+        if reg < 0x04 {
+            self.pulse1.write_register(reg, val);
+        } else if reg < 0x08 {
+            self.pulse2.write_register(reg, val);
+        } else if reg < 0x0C {
+            self.triangle.write_register(reg, val);
+        } else if reg < 0x10 {
+            self.noise.write_register(reg, val);
+        } else if reg < 0x14 {
+            self.dmc.write_register(reg, val);
+        } else if reg == 0x15 {
+            self.pulse1.length.set_enabled(val & 0x01 != 0);
+            self.pulse2.length.set_enabled(val & 0x02 != 0);
+            self.triangle.length.set_enabled(val & 0x04 != 0);
+            self.noise.length.set_enabled(val & 0x08 != 0);
+            self.dmc.set_enabled(val & 0x10 != 0);
+        } else if reg == 0x17 {
+            let odd_cycle = self.clock.get() & 1 == 1;
+            self.frame_counter.write(val, odd_cycle);
+        }
+    }
+
+    /// Resets the APU, as happens when the console's reset button is pressed
+    ///
+    /// All channels are silenced as if $4015 was cleared, and the frame interrupt is
+    /// acknowledged. The other registers keep their values.
+    pub fn reset(&mut self) {
+        debug!(self.log, "cycle" => self.clock.get(); "reset");
+        self.write_register(0x15, 0x00);
+        self.frame_counter.acknowledge();
+    }
+
+    fn tick<B>(&mut self, bus: &mut B) -> Result<()> where B: Bus {
+        let frame = self.frame_counter.clock();
+        if frame.quarter() {
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if frame.half() {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+
+        // The pulse timers are clocked on every APU cycle, which is every other CPU cycle
+        if self.clock.get() & 1 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if let Some(addr) = self.dmc.dma_address() {
+            let val = try!(bus.get_u8(addr as u64));
+            trace!(self.log,
+                "addr" => format!("${:04X}", addr),
+                "val" => format!("${:02X}", val),
+                "cycle" => self.clock.get();
+                "DMC DMA read");
+            self.dmc.fill(val);
+            self.dma_reads.push(self.clock.get());
+        }
+
+        let levels = self.levels(bus.expansion_audio());
+        let cycle = self.clock.get();
+        self.output.update(cycle, self.mixer.mix(&levels));
+        for (channel, output) in Channel::all().iter().zip(self.channel_outputs.iter_mut()) {
+            if let Some(ref mut output) = *output {
+                output.update(cycle, self.mixer.mix_channel(*channel, levels[channel.index()]));
+            }
+        }
+        self.clock.tick(1);
+        Ok(())
+    }
+
+    /// Gets the output level of each channel, in the order of `Channel::all()`
+    fn levels(&self, expansion: f32) -> [f32; mixer::CHANNELS] {
+        [
+            self.pulse1.output() as f32,
+            self.pulse2.output() as f32,
+            self.triangle.output() as f32,
+            self.noise.output() as f32,
+            self.dmc.output() as f32,
+            expansion
+        ]
+    }
+}
+
+/// Converts the mixed level on each cycle into filtered samples
+///
+/// The filters are those found between the APU and the NES's audio output: two high-pass
+/// filters at 90Hz and 440Hz, and a low-pass filter at 14kHz.
+struct Output {
+    sample_rate: u32,
+    blip: BlipBuffer,
+    frame_start: u64,
+    level: f32,
+    high_pass_90: HighPass,
+    high_pass_440: HighPass,
+    low_pass_14k: LowPass
+}
+
+impl Output {
+    fn new(clock_rate: f64, sample_rate: u32, cycle: u64) -> Output {
+        Output {
+            sample_rate: sample_rate,
+            blip: BlipBuffer::new(clock_rate, sample_rate),
+            frame_start: cycle,
+            level: 0.0,
+            high_pass_90: HighPass::new(90.0, sample_rate),
+            high_pass_440: HighPass::new(440.0, sample_rate),
+            low_pass_14k: LowPass::new(14000.0, sample_rate)
+        }
+    }
+
+    fn update(&mut self, cycle: u64, level: f32) {
+        if level != self.level {
+            self.blip.add_delta(cycle - self.frame_start, level - self.level);
+            self.level = level;
+        }
+    }
+
+    fn take(&mut self, cycle: u64) -> Vec<i16> {
+        self.blip.end_frame(cycle - self.frame_start);
+        self.frame_start = cycle;
+
+        let mut levels = Vec::with_capacity(self.blip.samples_avail());
+        let count = self.blip.samples_avail();
+        self.blip.read_samples(&mut levels, count);
+
+        levels.into_iter().map(|level| {
+            let level = self.high_pass_90.process(level);
+            let level = self.high_pass_440.process(level);
+            let level = self.low_pass_14k.process(level);
+            (level * 32767.0).max(-32768.0).min(32767.0) as i16
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem;
+    use hw::rp2A03::apu::{Apu,Bus};
+    use hw::rp2A03::mixer::Channel;
+
+    impl Bus for mem::Fixed {}
+
+    fn samples() -> mem::Fixed {
+        mem::Fixed::new(0x10000)
+    }
+
+    /// Provides expansion audio at a constant level
+    struct ExpansionBus(mem::Fixed, f32);
+
+    impl mem::Memory for ExpansionBus {
+        fn len(&self) -> u64 { self.0.len() }
+        fn get_u8(&self, addr: u64) -> mem::Result<u8> { self.0.get_u8(addr) }
+        fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> { self.0.set_u8(addr, val) }
+    }
+
+    impl Bus for ExpansionBus {
+        fn expansion_audio(&mut self) -> f32 {
+            self.1
+        }
+    }
+
+    #[test]
+    pub fn status_reports_active_length_counters() {
+        let mut apu = Apu::new(None);
+        apu.write_register(0x15, 0x0F);
+        apu.write_register(0x03, 0x08);
+        apu.write_register(0x0F, 0x08);
+
+        assert_eq!(Some(0x09), apu.read_register(0x15));
+
+        apu.write_register(0x15, 0x08);
+        assert_eq!(Some(0x08), apu.read_register(0x15));
+    }
+
+    #[test]
+    pub fn write_only_registers_are_not_readable() {
+        let mut apu = Apu::new(None);
+        assert_eq!(None, apu.read_register(0x00));
+    }
+
+    #[test]
+    pub fn samples_are_produced_at_sample_rate() {
+        let mut apu = Apu::new(None);
+        apu.set_sample_rate(48000, 1_000_000.0);
+        apu.step(1_000_000, &mut samples()).unwrap();
+
+        let samples = apu.take_samples().len();
+        assert!(samples >= 47999 && samples <= 48000);
+        assert_eq!(0, apu.take_samples().len());
+    }
+
+    #[test]
+    pub fn pulse_produces_audible_samples() {
+        let mut apu = Apu::new(None);
+        apu.write_register(0x15, 0x01);
+        apu.write_register(0x00, 0xBF);
+        apu.write_register(0x02, 0xFD);
+        apu.write_register(0x03, 0x00);
+        apu.step(20000, &mut samples()).unwrap();
+
+        let samples = apu.take_samples();
+        let max = *samples.iter().max().unwrap();
+        let min = *samples.iter().min().unwrap();
+        assert!(max > min);
+    }
+
+    #[test]
+    pub fn channel_samples_are_only_produced_when_enabled() {
+        let mut apu = Apu::new(None);
+        apu.write_register(0x15, 0x01);
+        apu.write_register(0x00, 0xBF);
+        apu.write_register(0x02, 0xFD);
+        apu.write_register(0x03, 0x00);
+        apu.step(1000, &mut samples()).unwrap();
+        assert_eq!(0, apu.take_channel_samples(Channel::Pulse1).len());
+
+        apu.set_channel_output(Channel::Pulse1, true);
+        apu.set_channel_output(Channel::Noise, true);
+        apu.step(20000, &mut samples()).unwrap();
+        let pulse = apu.take_channel_samples(Channel::Pulse1);
+        let noise = apu.take_channel_samples(Channel::Noise);
+        assert!(pulse.len() > 400);
+        assert!(pulse.iter().any(|&s| s != 0));
+        assert_eq!(pulse.len(), noise.len());
+        assert!(noise.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    pub fn frame_irq_is_acknowledged_by_status_read() {
+        let mut apu = Apu::new(None);
+        apu.step(29831, &mut samples()).unwrap();
+        assert!(apu.irq());
+
+        assert_eq!(Some(0x40), apu.read_register(0x15));
+        assert!(!apu.irq());
+        assert_eq!(Some(0x00), apu.read_register(0x15));
+    }
+
+    #[test]
+    pub fn dmc_reads_are_recorded() {
+        let mut apu = Apu::new(None);
+        apu.write_register(0x12, 0x00);
+        apu.write_register(0x13, 0x01);
+        apu.write_register(0x15, 0x10);
+        apu.step(10, &mut samples()).unwrap();
+
+        assert_eq!(vec![0], apu.take_dma_reads());
+        assert_eq!(Some(0x10), apu.read_register(0x15));
+    }
+
+    #[test]
+    pub fn dmc_irq_is_acknowledged_by_status_write() {
+        let mut apu = Apu::new(None);
+        apu.write_register(0x10, 0x80);
+        apu.write_register(0x15, 0x10);
+        apu.step(1, &mut samples()).unwrap();
+        assert!(apu.irq());
+        assert_eq!(Some(0x80), apu.read_register(0x15));
+
+        apu.write_register(0x15, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    pub fn expansion_audio_is_mixed() {
+        let mut apu = Apu::new(None);
+        apu.set_channel_output(Channel::Expansion, true);
+        apu.step(20000, &mut ExpansionBus(samples(), 0.5)).unwrap();
+
+        assert!(apu.take_samples().iter().any(|&s| s > 1000));
+        assert!(apu.take_channel_samples(Channel::Expansion).iter().any(|&s| s > 1000));
+    }
+
+    #[test]
+    pub fn muted_channel_is_silent_in_mix_but_not_in_its_own_output() {
+        let mut apu = Apu::new(None);
+        // The triangle channel outputs its first step even when silenced
+        apu.mixer_mut().set_muted(Channel::Triangle, true);
+        apu.mixer_mut().set_muted(Channel::Expansion, true);
+        apu.set_channel_output(Channel::Expansion, true);
+        apu.step(20000, &mut ExpansionBus(samples(), 0.5)).unwrap();
+
+        assert!(apu.take_samples().iter().all(|&s| s == 0));
+        assert!(apu.take_channel_samples(Channel::Expansion).iter().any(|&s| s > 1000));
+    }
+}
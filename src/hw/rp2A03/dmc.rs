@@ -0,0 +1,255 @@
+/// The address at which samples start when the sample address register is zero
+const SAMPLE_BASE: u16 = 0xC000;
+
+/// Represents the delta modulation channel
+///
+/// The DMC plays 1-bit delta encoded samples which are read from $8000-$FFFF using DMA. Each
+/// bit of the sample moves the 7-bit output level up or down by two.
+pub struct Dmc {
+    rates: [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+
+    level: u8,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    irq: bool
+}
+
+impl Dmc {
+    /// Creates the DMC, with the timer periods (in CPU cycles) used by the APU
+    pub fn new(rates: [u16; 16]) -> Dmc {
+        Dmc {
+            rates: rates,
+            irq_enabled: false,
+            looping: false,
+            period: rates[0],
+            timer: 0,
+            level: 0,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            sample_address: SAMPLE_BASE,
+            sample_length: 1,
+            address: SAMPLE_BASE,
+            bytes_remaining: 0,
+            buffer: None,
+            irq: false
+        }
+    }
+
+    /// Replaces the timer periods, which differ between NTSC and PAL
+    pub fn set_rates(&mut self, rates: [u16; 16]) {
+        self.rates = rates;
+    }
+
+    /// Writes `val` to one of the channel's registers ($4010-$4013)
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        match reg & 0x03 {
+            0 => {
+                self.irq_enabled = val & 0x80 != 0;
+                self.looping = val & 0x40 != 0;
+                self.period = self.rates[(val & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            },
+            1 => self.level = val & 0x7F,
+            2 => self.sample_address = SAMPLE_BASE + ((val as u16) << 6),
+            _ => self.sample_length = ((val as u16) << 4) + 1
+        }
+    }
+
+    /// Enables or disables the channel, as controlled by $4015
+    ///
+    /// Enabling the channel restarts the sample if it has finished playing, disabling it stops
+    /// the sample once the byte in the buffer has been played. Either clears the DMC interrupt.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Gets the address of the first byte of the sample, as set by $4012
+    pub fn sample_address(&self) -> u16 {
+        self.sample_address
+    }
+
+    /// Gets the length of the sample in bytes, as set by $4013
+    pub fn sample_length(&self) -> u16 {
+        self.sample_length
+    }
+
+    /// Returns a value indicating if there are bytes of the sample left to read
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    /// Returns a value indicating if the DMC is asserting its interrupt
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Gets the address the DMC needs to read, if the sample buffer is empty and there are
+    /// bytes of the sample left to read
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.address)
+        } else {
+            None
+        }
+    }
+
+    /// Fills the sample buffer with the byte read by DMA from `dma_address`
+    pub fn fill(&mut self, val: u8) {
+        self.buffer = Some(val);
+        self.address = if self.address == 0xFFFF { 0x8000 } else { self.address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocks the channel's timer, which happens on every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(val) => {
+                    self.silence = false;
+                    self.shift = val;
+                },
+                None => self.silence = true
+            }
+        }
+    }
+
+    /// Gets the channel's current output level (0-127)
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::Timing;
+    use hw::rp2A03::dmc::Dmc;
+
+    fn dmc() -> Dmc {
+        Dmc::new(Timing::ntsc().dmc_rates)
+    }
+
+    #[test]
+    pub fn sample_is_read_from_sample_address() {
+        let mut dmc = dmc();
+        dmc.write_register(2, 0x01);
+        dmc.write_register(3, 0x01);
+        dmc.set_enabled(true);
+
+        assert_eq!(Some(0xC040), dmc.dma_address());
+        dmc.fill(0);
+        assert_eq!(None, dmc.dma_address());
+        assert!(dmc.active());
+    }
+
+    #[test]
+    pub fn address_wraps_to_8000() {
+        let mut dmc = dmc();
+        dmc.write_register(2, 0xFF);
+        dmc.write_register(3, 0xFF);
+        dmc.set_enabled(true);
+        for _ in 0..0x40 {
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+
+        assert_eq!(Some(0x8000), dmc.dma_address());
+    }
+
+    #[test]
+    pub fn completed_sample_raises_irq() {
+        let mut dmc = dmc();
+        dmc.write_register(0, 0x80);
+        dmc.set_enabled(true);
+        dmc.fill(0);
+
+        assert!(!dmc.active());
+        assert!(dmc.irq());
+
+        dmc.write_register(0, 0x00);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    pub fn looping_sample_restarts() {
+        let mut dmc = dmc();
+        dmc.write_register(0, 0xC0);
+        dmc.set_enabled(true);
+        dmc.fill(0);
+
+        assert!(dmc.active());
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    pub fn sample_bits_move_output_level() {
+        let mut dmc = dmc();
+        dmc.write_register(0, 0x0F);
+        dmc.write_register(1, 0x40);
+        dmc.set_enabled(true);
+        dmc.fill(0xFF);
+
+        // The output unit finishes its current (silent) byte before playing the buffered one
+        let period = Timing::ntsc().dmc_rates[15] as usize;
+        for _ in 0..(period * 8) {
+            dmc.clock_timer();
+        }
+        assert_eq!(0x40, dmc.output());
+
+        for _ in 0..(period * 8) {
+            dmc.clock_timer();
+        }
+        assert_eq!(0x40 + 16, dmc.output());
+    }
+}
@@ -0,0 +1,110 @@
+/// Represents the envelope generator shared by the pulse and noise channels
+///
+/// The envelope either outputs a constant volume, or a decaying volume which starts at 15 and
+/// decreases at a rate set by the divider period.
+pub struct Envelope {
+    /// Indicates if the constant volume is output instead of the decaying volume
+    pub constant_volume: bool,
+
+    /// Indicates if the decaying volume restarts at 15 after reaching zero
+    pub looping: bool,
+
+    /// The constant volume, which is also the period of the divider
+    pub volume: u8,
+
+    start: bool,
+    divider: u8,
+    decay: u8
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            constant_volume: false,
+            looping: false,
+            volume: 0,
+            start: false,
+            divider: 0,
+            decay: 0
+        }
+    }
+
+    /// Updates the envelope from a write to the channel's first register
+    pub fn write_control(&mut self, val: u8) {
+        self.looping = val & 0x20 != 0;
+        self.constant_volume = val & 0x10 != 0;
+        self.volume = val & 0x0F;
+    }
+
+    /// Restarts the envelope on the next quarter frame, as happens when the channel's length
+    /// counter is loaded
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocks the envelope, which happens on every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    /// Gets the current volume of the envelope
+    pub fn output(&self) -> u8 {
+        if self.constant_volume { self.volume } else { self.decay }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::envelope::Envelope;
+
+    #[test]
+    pub fn constant_volume_is_output_directly() {
+        let mut env = Envelope::new();
+        env.write_control(0x1A);
+        env.restart();
+        env.clock();
+
+        assert_eq!(0x0A, env.output());
+    }
+
+    #[test]
+    pub fn decaying_volume_decreases_every_period() {
+        let mut env = Envelope::new();
+        env.write_control(0x01);
+        env.restart();
+        env.clock();
+        assert_eq!(15, env.output());
+
+        env.clock();
+        assert_eq!(15, env.output());
+        env.clock();
+        assert_eq!(14, env.output());
+    }
+
+    #[test]
+    pub fn decaying_volume_loops_if_requested() {
+        let mut env = Envelope::new();
+        env.write_control(0x20);
+        env.restart();
+        for _ in 0..16 {
+            env.clock();
+        }
+        assert_eq!(0, env.output());
+
+        env.clock();
+        assert_eq!(15, env.output());
+    }
+}
@@ -0,0 +1,203 @@
+use hw::rp2A03::Timing;
+
+/// Describes the units clocked by the frame counter on a given cycle
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum FrameClock {
+    /// Nothing is clocked
+    None,
+
+    /// The envelopes and the triangle's linear counter are clocked
+    Quarter,
+
+    /// The units clocked on a quarter frame are clocked, as are the length counters and sweeps
+    Half
+}
+
+impl FrameClock {
+    /// Returns a value indicating if quarter frame units are clocked
+    pub fn quarter(&self) -> bool {
+        *self != FrameClock::None
+    }
+
+    /// Returns a value indicating if half frame units are clocked
+    pub fn half(&self) -> bool {
+        *self == FrameClock::Half
+    }
+}
+
+/// Represents the frame counter, which divides the CPU clock to clock the envelopes, sweeps,
+/// length counters and linear counter at (roughly) 240Hz
+///
+/// In the 4-step mode the counter clocks a half frame on every second step, and raises the
+/// frame interrupt (unless inhibited) around the final step. The 5-step mode has an extra,
+/// silent, step and never raises the interrupt.
+pub struct FrameCounter {
+    steps: [u32; 4],
+    step_5: u32,
+    cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+
+    /// The mode written to $4017, and the number of cycles until the sequence is reset with it
+    pending: Option<(bool, u8)>
+}
+
+impl FrameCounter {
+    pub fn new(timing: &Timing) -> FrameCounter {
+        FrameCounter {
+            steps: timing.frame_steps,
+            step_5: timing.frame_step_5,
+            cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            pending: None
+        }
+    }
+
+    /// Switches to the step timing of a different APU variant
+    pub fn set_timing(&mut self, timing: &Timing) {
+        self.steps = timing.frame_steps;
+        self.step_5 = timing.frame_step_5;
+    }
+
+    /// Handles a write to $4017
+    ///
+    /// Setting the inhibit flag clears the interrupt immediately, but the sequence is only
+    /// reset 3 cycles later if the write occurred on an odd cycle (during an APU cycle), or 4
+    /// cycles later if it occurred on an even cycle.
+    pub fn write(&mut self, val: u8, odd_cycle: bool) {
+        self.irq_inhibit = val & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.pending = Some((val & 0x80 != 0, if odd_cycle { 3 } else { 4 }));
+    }
+
+    /// Returns a value indicating if the frame interrupt is asserted
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Clears the frame interrupt, as happens when $4015 is read
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+    }
+
+    /// Clocks the frame counter, which happens on every CPU cycle
+    pub fn clock(&mut self) -> FrameClock {
+        if let Some((five_step, delay)) = self.pending {
+            if delay == 0 {
+                // Resetting into the 5-step mode immediately clocks a half frame
+                self.pending = None;
+                self.five_step = five_step;
+                self.cycle = 0;
+                return if five_step { FrameClock::Half } else { FrameClock::None };
+            }
+            self.pending = Some((five_step, delay - 1));
+        }
+
+        self.cycle += 1;
+        let last = if self.five_step { self.step_5 } else { self.steps[3] };
+
+        // The interrupt flag is set on the three cycles around the final step
+        if !self.five_step && !self.irq_inhibit && self.cycle + 1 >= last {
+            self.irq = true;
+        }
+
+        if self.cycle == self.steps[0] || self.cycle == self.steps[2] {
+            FrameClock::Quarter
+        } else if self.cycle == self.steps[1] || self.cycle == last {
+            FrameClock::Half
+        } else {
+            if self.cycle > last {
+                self.cycle = 0;
+            }
+            FrameClock::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::Timing;
+    use hw::rp2A03::frame_counter::{FrameCounter,FrameClock};
+
+    fn run(counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, FrameClock)> {
+        let mut clocks = Vec::new();
+        for cycle in 1..(cycles + 1) {
+            match counter.clock() {
+                FrameClock::None => {},
+                c => clocks.push((cycle, c))
+            }
+        }
+        clocks
+    }
+
+    #[test]
+    pub fn four_step_sequence_clocks_two_half_frames() {
+        let timing = Timing::ntsc();
+        let mut counter = FrameCounter::new(&timing);
+
+        assert_eq!(vec![
+            (7457, FrameClock::Quarter),
+            (14913, FrameClock::Half),
+            (22371, FrameClock::Quarter),
+            (29829, FrameClock::Half),
+            (29830 + 7457, FrameClock::Quarter)], run(&mut counter, 29830 + 7457));
+    }
+
+    #[test]
+    pub fn five_step_sequence_clocks_half_frame_on_reset() {
+        let timing = Timing::ntsc();
+        let mut counter = FrameCounter::new(&timing);
+        counter.write(0x80, true);
+
+        assert_eq!(vec![
+            (4, FrameClock::Half),
+            (4 + 7457, FrameClock::Quarter),
+            (4 + 14913, FrameClock::Half),
+            (4 + 22371, FrameClock::Quarter),
+            (4 + 37281, FrameClock::Half)], run(&mut counter, 4 + 37282));
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    pub fn write_on_even_cycle_is_delayed_by_extra_cycle() {
+        let timing = Timing::ntsc();
+        let mut counter = FrameCounter::new(&timing);
+        counter.write(0x80, false);
+
+        assert_eq!(vec![(5, FrameClock::Half)], run(&mut counter, 5));
+    }
+
+    #[test]
+    pub fn four_step_sequence_raises_irq() {
+        let timing = Timing::ntsc();
+        let mut counter = FrameCounter::new(&timing);
+        run(&mut counter, 29827);
+        assert!(!counter.irq());
+
+        run(&mut counter, 1);
+        assert!(counter.irq());
+
+        // The flag is set again on the following two cycles, even if acknowledged
+        counter.acknowledge();
+        run(&mut counter, 1);
+        assert!(counter.irq());
+    }
+
+    #[test]
+    pub fn inhibit_flag_clears_and_prevents_irq() {
+        let timing = Timing::ntsc();
+        let mut counter = FrameCounter::new(&timing);
+        run(&mut counter, 29830);
+        assert!(counter.irq());
+
+        counter.write(0x40, true);
+        assert!(!counter.irq());
+        run(&mut counter, 29834);
+        assert!(!counter.irq());
+    }
+}
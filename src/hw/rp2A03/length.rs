@@ -0,0 +1,99 @@
+/// The lengths loaded into the length counter, indexed by the upper 5 bits of the channel's
+/// last register
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+/// Represents the length counter which silences a channel after a number of half frames
+pub struct LengthCounter {
+    /// Indicates if the counter is halted (and so holds its current value)
+    pub halted: bool,
+
+    enabled: bool,
+    counter: u8
+}
+
+impl LengthCounter {
+    pub fn new() -> LengthCounter {
+        LengthCounter {
+            halted: false,
+            enabled: false,
+            counter: 0
+        }
+    }
+
+    /// Enables or disables the counter, as controlled by $4015
+    ///
+    /// Disabling the counter immediately silences the channel.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Loads the counter from a write to the channel's last register, if the channel is enabled
+    pub fn load(&mut self, val: u8) {
+        if self.enabled {
+            self.counter = LENGTHS[(val >> 3) as usize];
+        }
+    }
+
+    /// Clocks the counter, which happens on every half frame
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    /// Gets the current value of the counter
+    pub fn value(&self) -> u8 {
+        self.counter
+    }
+
+    /// Returns a value indicating if the counter has silenced the channel
+    pub fn silenced(&self) -> bool {
+        self.counter == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::length::LengthCounter;
+
+    #[test]
+    pub fn counter_is_only_loaded_while_enabled() {
+        let mut len = LengthCounter::new();
+        len.load(0x08);
+        assert_eq!(0, len.value());
+
+        len.set_enabled(true);
+        len.load(0x08);
+        assert_eq!(254, len.value());
+    }
+
+    #[test]
+    pub fn disabling_counter_clears_it() {
+        let mut len = LengthCounter::new();
+        len.set_enabled(true);
+        len.load(0x00);
+        len.set_enabled(false);
+
+        assert!(len.silenced());
+    }
+
+    #[test]
+    pub fn halted_counter_holds_its_value() {
+        let mut len = LengthCounter::new();
+        len.set_enabled(true);
+        len.load(0x00);
+        len.halted = true;
+        len.clock();
+        assert_eq!(10, len.value());
+
+        len.halted = false;
+        len.clock();
+        assert_eq!(9, len.value());
+    }
+}
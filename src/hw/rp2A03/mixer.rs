@@ -0,0 +1,214 @@
+/// The number of channels mixed by the APU, including expansion audio
+pub const CHANNELS: usize = 6;
+
+/// Identifies one of the channels mixed by the APU
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+
+    /// The sound hardware on the cartridge, if any, which is mixed with the APU's output
+    Expansion
+}
+
+impl Channel {
+    /// Gets every channel, in the order of their registers, followed by the expansion audio
+    pub fn all() -> [Channel; CHANNELS] {
+        [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc, Channel::Expansion]
+    }
+
+    /// Gets a short lowercase name for the channel, which is suitable for use in file names
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Channel::Pulse1    => "pulse1",
+            &Channel::Pulse2    => "pulse2",
+            &Channel::Triangle  => "triangle",
+            &Channel::Noise     => "noise",
+            &Channel::Dmc       => "dmc",
+            &Channel::Expansion => "expansion"
+        }
+    }
+
+    /// Gets the position of the channel in `Channel::all()`
+    pub fn index(&self) -> usize {
+        match self {
+            &Channel::Pulse1    => 0,
+            &Channel::Pulse2    => 1,
+            &Channel::Triangle  => 2,
+            &Channel::Noise     => 3,
+            &Channel::Dmc       => 4,
+            &Channel::Expansion => 5
+        }
+    }
+}
+
+/// Represents the APU's mixer, which combines the output levels of the channels
+///
+/// The mixer is nonlinear: each group of channels is mixed through a resistor network, so the
+/// louder the other channels in the group are, the less effect a channel has. The formulas
+/// are the approximations from the NesDev wiki. Expansion audio is already a mixed level, so
+/// it is simply added to the output.
+///
+/// Each channel can be muted, soloed, or have its volume adjusted before it is mixed. If any
+/// channel is soloed, only the soloed channels are heard.
+pub struct Mixer {
+    volume: [f32; CHANNELS],
+    muted: [bool; CHANNELS],
+    soloed: [bool; CHANNELS]
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        Mixer {
+            volume: [1.0; CHANNELS],
+            muted: [false; CHANNELS],
+            soloed: [false; CHANNELS]
+        }
+    }
+
+    /// Gets the volume of `channel`, where 1.0 is its normal volume
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volume[channel.index()]
+    }
+
+    /// Sets the volume of `channel`, where 1.0 is its normal volume
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volume[channel.index()] = volume.max(0.0);
+    }
+
+    /// Returns a value indicating if `channel` is muted
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel.index()]
+    }
+
+    /// Mutes or unmutes `channel`
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    /// Returns a value indicating if `channel` is soloed
+    pub fn soloed(&self, channel: Channel) -> bool {
+        self.soloed[channel.index()]
+    }
+
+    /// Solos or unsolos `channel`
+    pub fn set_soloed(&mut self, channel: Channel, soloed: bool) {
+        self.soloed[channel.index()] = soloed;
+    }
+
+    /// Returns a value indicating if `channel` is heard in the mixed output, given the channels
+    /// which are muted and soloed
+    pub fn audible(&self, channel: Channel) -> bool {
+        let any_soloed = self.soloed.iter().any(|&s| s);
+        !self.muted(channel) && (!any_soloed || self.soloed(channel))
+    }
+
+    /// Mixes the output levels of the channels (in the order of `Channel::all()`), applying
+    /// the volume, mute and solo settings, to produce a level which is between 0.0 and 1.0
+    /// without expansion audio
+    pub fn mix(&self, levels: &[f32; CHANNELS]) -> f32 {
+        let mut adjusted = [0.0; CHANNELS];
+        for (i, channel) in Channel::all().iter().enumerate() {
+            if self.audible(*channel) {
+                adjusted[i] = levels[i] * self.volume[i];
+            }
+        }
+        mix(&adjusted)
+    }
+
+    /// Mixes the output level of a single channel as if the other channels were silent,
+    /// ignoring the volume, mute and solo settings
+    pub fn mix_channel(&self, channel: Channel, level: f32) -> f32 {
+        let mut levels = [0.0; CHANNELS];
+        levels[channel.index()] = level;
+        mix(&levels)
+    }
+}
+
+fn mix(levels: &[f32; CHANNELS]) -> f32 {
+    let pulse = levels[0] + levels[1];
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = levels[2] / 8227.0 + levels[3] / 12241.0 + levels[4] / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+
+    pulse_out + tnd_out + levels[5]
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::mixer::{Mixer,Channel};
+
+    #[test]
+    pub fn silence_mixes_to_zero() {
+        assert_eq!(0.0, Mixer::new().mix(&[0.0; 6]));
+    }
+
+    #[test]
+    pub fn full_volume_mixes_to_nearly_one() {
+        let level = Mixer::new().mix(&[15.0, 15.0, 15.0, 15.0, 127.0, 0.0]);
+        assert!(level > 0.99 && level < 1.01);
+    }
+
+    #[test]
+    pub fn pulse_channels_mix_nonlinearly() {
+        let mixer = Mixer::new();
+        let one = mixer.mix_channel(Channel::Pulse1, 15.0);
+        let both = mixer.mix(&[15.0, 15.0, 0.0, 0.0, 0.0, 0.0]);
+        assert!(both < one * 2.0);
+    }
+
+    #[test]
+    pub fn channels_are_indexed_in_register_order() {
+        for (i, channel) in Channel::all().iter().enumerate() {
+            assert_eq!(i, channel.index());
+        }
+    }
+
+    #[test]
+    pub fn muted_channel_is_not_mixed() {
+        let mut mixer = Mixer::new();
+        mixer.set_muted(Channel::Triangle, true);
+
+        assert_eq!(0.0, mixer.mix(&[0.0, 0.0, 15.0, 0.0, 0.0, 0.0]));
+        assert!(mixer.mix(&[0.0, 0.0, 15.0, 15.0, 0.0, 0.0]) > 0.0);
+    }
+
+    #[test]
+    pub fn soloed_channels_are_the_only_ones_mixed() {
+        let mut mixer = Mixer::new();
+        mixer.set_soloed(Channel::Noise, true);
+        mixer.set_soloed(Channel::Expansion, true);
+
+        assert!(!mixer.audible(Channel::Pulse1));
+        assert!(mixer.audible(Channel::Noise));
+        assert_eq!(0.25, mixer.mix(&[15.0, 15.0, 15.0, 0.0, 127.0, 0.25]));
+    }
+
+    #[test]
+    pub fn volume_scales_channel_level() {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(Channel::Pulse2, 0.5);
+
+        assert_eq!(mixer.mix_channel(Channel::Pulse2, 5.0), mixer.mix(&[0.0, 10.0, 0.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    pub fn channel_mix_ignores_mixer_settings() {
+        let mut mixer = Mixer::new();
+        mixer.set_muted(Channel::Dmc, true);
+
+        assert!(mixer.mix_channel(Channel::Dmc, 64.0) > 0.0);
+    }
+}
@@ -0,0 +1,33 @@
+pub use self::apu::{Apu,Bus,Result,Error};
+pub use self::mixer::{Mixer,Channel};
+pub use self::timing::Timing;
+
+/// Contains the APU itself, which mixes the output of its channels into samples
+pub mod apu;
+
+/// Contains the timing tables that differ between variants of the APU
+pub mod timing;
+
+/// Contains the mixer, which combines the output of the channels
+pub mod mixer;
+
+/// Contains the frame counter, which clocks the envelopes, sweeps and length counters
+pub mod frame_counter;
+
+/// Contains the envelope generator used by the pulse and noise channels
+pub mod envelope;
+
+/// Contains the length counter used by every channel except the DMC
+pub mod length;
+
+/// Contains the two pulse channels
+pub mod pulse;
+
+/// Contains the triangle channel
+pub mod triangle;
+
+/// Contains the noise channel
+pub mod noise;
+
+/// Contains the delta modulation channel, which plays samples read using DMA
+pub mod dmc;
@@ -0,0 +1,120 @@
+use hw::rp2A03::envelope::Envelope;
+use hw::rp2A03::length::LengthCounter;
+
+/// Represents the noise channel
+///
+/// The noise is produced by a 15-bit linear feedback shift register, which either has a long
+/// (32767 step) period, or in "short mode" a period of 93 steps that sounds metallic.
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+
+    periods: [u16; 16],
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16
+}
+
+impl Noise {
+    /// Creates the noise channel, with the timer periods (in CPU cycles) used by the APU
+    pub fn new(periods: [u16; 16]) -> Noise {
+        Noise {
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            periods: periods,
+            short_mode: false,
+            period: periods[0],
+            timer: 0,
+            shift: 1
+        }
+    }
+
+    /// Replaces the timer periods, which differ between NTSC and PAL
+    pub fn set_periods(&mut self, periods: [u16; 16]) {
+        self.periods = periods;
+    }
+
+    /// Writes `val` to one of the channel's registers ($400C-$400F)
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        match reg & 0x03 {
+            0 => {
+                self.length.halted = val & 0x20 != 0;
+                self.envelope.write_control(val);
+            },
+            1 => {},
+            2 => {
+                self.short_mode = val & 0x80 != 0;
+                self.period = self.periods[(val & 0x0F) as usize];
+            },
+            _ => {
+                self.length.load(val);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocks the channel's timer, which happens on every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the envelope, which happens on every quarter frame
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the length counter, which happens on every half frame
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Gets the channel's current output level (0-15)
+    pub fn output(&self) -> u8 {
+        if self.length.silenced() || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::Timing;
+    use hw::rp2A03::noise::Noise;
+
+    fn sequence_period(short_mode: bool) -> usize {
+        let mut noise = Noise::new(Timing::ntsc().noise_periods);
+        noise.write_register(2, if short_mode { 0x80 } else { 0x00 });
+        let start = noise.shift;
+        let mut steps = 0;
+        loop {
+            // The shortest period is 4 CPU cycles
+            for _ in 0..4 {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    pub fn long_mode_repeats_every_32767_steps() {
+        assert_eq!(32767, sequence_period(false));
+    }
+
+    #[test]
+    pub fn short_mode_repeats_every_93_steps() {
+        assert_eq!(93, sequence_period(true));
+    }
+}
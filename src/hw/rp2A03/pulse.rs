@@ -0,0 +1,230 @@
+use hw::rp2A03::envelope::Envelope;
+use hw::rp2A03::length::LengthCounter;
+
+/// The waveforms selected by the duty bits of the first register
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+/// Represents one of the two pulse (square wave) channels
+pub struct Pulse {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+
+    /// The first pulse channel's sweep unit subtracts using ones' complement, so it subtracts
+    /// one more than the second channel's does
+    ones_complement: bool,
+
+    /// Indicates if the channel has a sweep unit, which the MMC5's pulse channels lack
+    has_sweep: bool,
+
+    duty: u8,
+    sequence: u8,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8
+}
+
+impl Pulse {
+    /// Creates the first pulse channel ($4000-$4003)
+    pub fn first() -> Pulse {
+        Pulse::new(true, true)
+    }
+
+    /// Creates the second pulse channel ($4004-$4007)
+    pub fn second() -> Pulse {
+        Pulse::new(false, true)
+    }
+
+    /// Creates a pulse channel without a sweep unit, as found in the MMC5
+    ///
+    /// The second register of the channel is unused, and low periods do not mute the channel.
+    pub fn without_sweep() -> Pulse {
+        Pulse::new(false, false)
+    }
+
+    fn new(ones_complement: bool, has_sweep: bool) -> Pulse {
+        Pulse {
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            ones_complement: ones_complement,
+            has_sweep: has_sweep,
+            duty: 0,
+            sequence: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0
+        }
+    }
+
+    /// Writes `val` to one of the channel's four registers
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        match reg & 0x03 {
+            0 => {
+                self.duty = val >> 6;
+                self.length.halted = val & 0x20 != 0;
+                self.envelope.write_control(val);
+            },
+            1 if !self.has_sweep => {},
+            1 => {
+                self.sweep_enabled = val & 0x80 != 0;
+                self.sweep_period = (val >> 4) & 0x07;
+                self.sweep_negate = val & 0x08 != 0;
+                self.sweep_shift = val & 0x07;
+                self.sweep_reload = true;
+            },
+            2 => self.period = (self.period & 0x0700) | val as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((val & 0x07) as u16) << 8);
+                self.length.load(val);
+                self.sequence = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocks the channel's timer, which happens on every APU cycle (every other CPU cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequence = (self.sequence + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the units driven by quarter frames
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the units driven by half frames
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Gets the channel's current output level (0-15)
+    pub fn output(&self) -> u8 {
+        if self.length.silenced() || self.sweep_muted() || DUTY_SEQUENCES[self.duty as usize][self.sequence as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    /// The sweep unit mutes the channel if the period is too small, or would overflow, even if
+    /// the sweep is disabled
+    fn sweep_muted(&self) -> bool {
+        self.has_sweep && (self.period < 8 || self.sweep_target() > 0x07FF)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::pulse::Pulse;
+
+    fn playing(pulse: &mut Pulse, period: u16) {
+        pulse.length.set_enabled(true);
+        pulse.write_register(0, 0xBF);
+        pulse.write_register(2, (period & 0xFF) as u8);
+        pulse.write_register(3, (period >> 8) as u8);
+    }
+
+    #[test]
+    pub fn pulse_follows_duty_sequence() {
+        let mut pulse = Pulse::first();
+        playing(&mut pulse, 8);
+
+        let mut high = 0;
+        for _ in 0..(9 * 8) {
+            pulse.clock_timer();
+            if pulse.output() != 0 {
+                high += 1;
+            }
+        }
+        // 50% duty
+        assert_eq!(9 * 4, high);
+    }
+
+    #[test]
+    pub fn small_periods_are_muted() {
+        let mut pulse = Pulse::first();
+        playing(&mut pulse, 7);
+        for _ in 0..8 {
+            pulse.clock_timer();
+            assert_eq!(0, pulse.output());
+        }
+    }
+
+    #[test]
+    pub fn small_periods_are_heard_without_sweep_unit() {
+        let mut pulse = Pulse::without_sweep();
+        playing(&mut pulse, 7);
+        let high = (0..64).filter(|_| {
+            pulse.clock_timer();
+            pulse.output() != 0
+        }).count();
+        assert_eq!(32, high);
+    }
+
+    #[test]
+    pub fn first_channel_negates_with_ones_complement() {
+        let mut first = Pulse::first();
+        let mut second = Pulse::second();
+        playing(&mut first, 0x100);
+        playing(&mut second, 0x100);
+        first.write_register(1, 0x89);
+        second.write_register(1, 0x89);
+
+        first.clock_half_frame();
+        second.clock_half_frame();
+
+        assert_eq!(0x100 - 0x80 - 1, first.period);
+        assert_eq!(0x100 - 0x80, second.period);
+    }
+
+    #[test]
+    pub fn sweep_overflow_mutes_channel_even_when_disabled() {
+        let mut pulse = Pulse::second();
+        playing(&mut pulse, 0x7F0);
+        pulse.write_register(1, 0x01);
+        for _ in 0..16 {
+            pulse.clock_timer();
+            assert_eq!(0, pulse.output());
+        }
+    }
+}
@@ -0,0 +1,39 @@
+/// Describes the timing of an APU variant, in CPU cycles
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Timing {
+    /// The CPU cycles, relative to the start of the sequence, on which the frame counter clocks
+    /// the first three quarter frames, and the final step of the 4-step sequence
+    pub frame_steps: [u32; 4],
+
+    /// The CPU cycle, relative to the start of the sequence, on which the frame counter clocks
+    /// the final step of the 5-step sequence
+    pub frame_step_5: u32,
+
+    /// The timer periods selected by the noise channel's period register
+    pub noise_periods: [u16; 16],
+
+    /// The timer periods selected by the DMC's rate register
+    pub dmc_rates: [u16; 16]
+}
+
+impl Timing {
+    /// Gets the timing of the RP2A03, used in NTSC (and Dendy) consoles
+    pub fn ntsc() -> Timing {
+        Timing {
+            frame_steps: [7457, 14913, 22371, 29829],
+            frame_step_5: 37281,
+            noise_periods: [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068],
+            dmc_rates: [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54]
+        }
+    }
+
+    /// Gets the timing of the RP2A07, used in PAL consoles
+    pub fn pal() -> Timing {
+        Timing {
+            frame_steps: [8313, 16627, 24939, 33253],
+            frame_step_5: 41565,
+            noise_periods: [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778],
+            dmc_rates: [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50]
+        }
+    }
+}
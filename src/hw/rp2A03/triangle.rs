@@ -0,0 +1,128 @@
+use hw::rp2A03::length::LengthCounter;
+
+/// The 32-step waveform output by the triangle channel
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+/// Represents the triangle channel
+pub struct Triangle {
+    pub length: LengthCounter,
+
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+
+    sequence: u8,
+    period: u16,
+    timer: u16
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            length: LengthCounter::new(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            sequence: 0,
+            period: 0,
+            timer: 0
+        }
+    }
+
+    /// Writes `val` to one of the channel's registers ($4008-$400B)
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        match reg & 0x03 {
+            0 => {
+                self.control = val & 0x80 != 0;
+                self.length.halted = self.control;
+                self.linear_reload_value = val & 0x7F;
+            },
+            1 => {},
+            2 => self.period = (self.period & 0x0700) | val as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (((val & 0x07) as u16) << 8);
+                self.length.load(val);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocks the channel's timer, which happens on every CPU cycle
+    ///
+    /// The sequencer only advances while both the linear counter and length counter are
+    /// non-zero. When silenced the channel holds its current output level.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.linear_counter > 0 && !self.length.silenced() {
+                self.sequence = (self.sequence + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocks the linear counter, which happens on every quarter frame
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Clocks the length counter, which happens on every half frame
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Gets the channel's current output level (0-15)
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2A03::triangle::Triangle;
+
+    #[test]
+    pub fn triangle_steps_through_sequence() {
+        let mut tri = Triangle::new();
+        tri.length.set_enabled(true);
+        tri.write_register(0, 0x7F);
+        tri.write_register(2, 0x00);
+        tri.write_register(3, 0x00);
+        tri.clock_quarter_frame();
+
+        assert_eq!(15, tri.output());
+        tri.clock_timer();
+        assert_eq!(14, tri.output());
+    }
+
+    #[test]
+    pub fn triangle_holds_output_when_linear_counter_expires() {
+        let mut tri = Triangle::new();
+        tri.length.set_enabled(true);
+        tri.write_register(0, 0x01);
+        tri.write_register(3, 0x00);
+        tri.clock_quarter_frame();
+        tri.clock_timer();
+        tri.clock_quarter_frame();
+
+        let level = tri.output();
+        for _ in 0..4 {
+            tri.clock_timer();
+        }
+        assert_eq!(level, tri.output());
+    }
+}
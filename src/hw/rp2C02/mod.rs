@@ -1,4 +1,8 @@
-//pub use self::ppu::Rp2C02;
+pub use self::ppu::{Rp2C02,Bus,Timing,Result,Error};
+pub use self::palette::{Palette,Pixel};
 
-//// Contains code to emulate the PPU
-//pub mod ppu;
+/// Contains code to emulate the PPU
+pub mod ppu;
+
+/// Contains code to convert the PPU's output to RGB colors
+pub mod palette;
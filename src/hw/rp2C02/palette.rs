@@ -0,0 +1,319 @@
+use std::{error,fmt,io};
+use std::f64::consts::PI;
+
+/// The number of colors the PPU can select from palette RAM
+pub const COLORS: usize = 64;
+
+/// The number of combinations of the three color emphasis bits
+pub const EMPHASIS_COMBINATIONS: usize = 8;
+
+/// The number of entries in a palette, one for each color under each emphasis combination
+pub const ENTRIES: usize = COLORS * EMPHASIS_COMBINATIONS;
+
+/// The size of a `.pal` file containing only the 64 base colors
+pub const BASE_FILE_SIZE: usize = COLORS * 3;
+
+/// The size of a `.pal` file containing all 512 emphasized colors
+pub const FULL_FILE_SIZE: usize = ENTRIES * 3;
+
+/// The factor each non-emphasized color component is multiplied by when generating emphasis
+/// for a palette which only contains the base colors
+const EMPHASIS_ATTENUATION: f64 = 0.816;
+
+// Composite signal levels, in volts, for each of the four luma levels (from the NesDev wiki)
+const SIGNAL_LOW: [f64; 4] = [0.228, 0.312, 0.552, 0.880];
+const SIGNAL_HIGH: [f64; 4] = [0.616, 0.840, 1.100, 1.100];
+const SIGNAL_BLACK: f64 = 0.312;
+const SIGNAL_WHITE: f64 = 1.100;
+const SIGNAL_ATTENUATION: f64 = 0.746;
+
+// Unapologetically yanked from https://github.com/pcwalton/sprocketnes/blob/master/ppu.rs
+const DEFAULT_COLORS: [u8; BASE_FILE_SIZE] = [
+    124,124,124,    0,0,252,        0,0,188,        68,40,188,
+    148,0,132,      168,0,32,       168,16,0,       136,20,0,
+    80,48,0,        0,120,0,        0,104,0,        0,88,0,
+    0,64,88,        0,0,0,          0,0,0,          0,0,0,
+    188,188,188,    0,120,248,      0,88,248,       104,68,252,
+    216,0,204,      228,0,88,       248,56,0,       228,92,16,
+    172,124,0,      0,184,0,        0,168,0,        0,168,68,
+    0,136,136,      0,0,0,          0,0,0,          0,0,0,
+    248,248,248,    60,188,252,     104,136,252,    152,120,248,
+    248,120,248,    248,88,152,     248,120,88,     252,160,68,
+    248,184,0,      184,248,24,     88,216,84,      88,248,152,
+    0,232,216,      120,120,120,    0,0,0,          0,0,0,
+    252,252,252,    164,228,252,    184,184,248,    216,184,248,
+    248,184,248,    248,164,192,    240,208,176,    252,224,168,
+    248,216,120,    216,248,120,    184,248,184,    184,248,216,
+    0,252,252,      248,216,248,    0,0,0,          0,0,0
+];
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Represents an error that occurs while loading a palette
+#[derive(Debug)]
+pub enum Error {
+    /// Indicates that the palette data is neither 192 nor 1536 bytes long
+    InvalidSize(usize),
+
+    /// Indicates that an I/O error occurred while reading the palette
+    IoError(io::Error)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::InvalidSize(_) => "palette must contain either 64 or 512 colors",
+            &Error::IoError(_)     => "i/o error"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::IoError(ref err) => Some(err),
+            _                        => None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::InvalidSize(size) => write!(fmt, "palette is {} bytes, expected {} or {}", size, BASE_FILE_SIZE, FULL_FILE_SIZE),
+            &Error::IoError(ref err)  => write!(fmt, "i/o error: {}", err)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+/// Represents a single RGB pixel
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Pixel {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8
+}
+
+/// Controls the palette produced by `Palette::generate`
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct Settings {
+    /// Rotates the hue of every color, in degrees
+    pub hue: f64,
+
+    /// Scales the saturation of every color (1.0 leaves it unchanged)
+    pub saturation: f64,
+
+    /// Scales the luma of every color (1.0 leaves it unchanged)
+    pub contrast: f64,
+
+    /// Offsets the luma of every color (0.0 leaves it unchanged)
+    pub brightness: f64,
+
+    /// The gamma of the display the palette is intended for (2.2 applies no correction)
+    pub gamma: f64
+}
+
+impl Settings {
+    /// Creates settings which decode the signal without any adjustment
+    pub fn new() -> Settings {
+        Settings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2
+        }
+    }
+}
+
+/// Maps the 9-bit pixel values output by the PPU to RGB colors
+///
+/// Pixel values contain the color selected from palette RAM in bits 0-5, and the red, green
+/// and blue emphasis bits in bits 6, 7 and 8 respectively.
+#[derive(Clone)]
+pub struct Palette {
+    entries: Vec<Pixel>
+}
+
+impl Palette {
+    /// Creates the default palette
+    pub fn new() -> Palette {
+        Palette::from_bytes(&DEFAULT_COLORS).unwrap()
+    }
+
+    /// Creates a palette from the contents of a `.pal` file
+    ///
+    /// Files containing 64 colors (192 bytes) have the emphasized colors derived from the base
+    /// colors, files containing 512 colors (1536 bytes) are used as-is.
+    pub fn from_bytes(data: &[u8]) -> Result<Palette> {
+        let base = |i: usize| Pixel { red: data[i * 3], green: data[i * 3 + 1], blue: data[i * 3 + 2] };
+        let entries = match data.len() {
+            BASE_FILE_SIZE => (0..ENTRIES).map(|i| emphasize(base(i % COLORS), i / COLORS)).collect(),
+            FULL_FILE_SIZE => (0..ENTRIES).map(base).collect(),
+            size => return Err(Error::InvalidSize(size))
+        };
+        Ok(Palette { entries: entries })
+    }
+
+    /// Reads a palette in the `.pal` format from the provided reader
+    pub fn load<R>(input: &mut R) -> Result<Palette> where R: io::Read {
+        let mut data = Vec::with_capacity(FULL_FILE_SIZE);
+        try!(input.read_to_end(&mut data));
+        Palette::from_bytes(&data)
+    }
+
+    /// Generates a palette by decoding the composite video signal produced by the PPU
+    pub fn generate(settings: &Settings) -> Palette {
+        Palette {
+            entries: (0..ENTRIES).map(|i| decode_signal(i, settings)).collect()
+        }
+    }
+
+    /// Gets the RGB color of a 9-bit pixel value
+    pub fn pixel(&self, value: u16) -> Pixel {
+        self.entries[value as usize % ENTRIES]
+    }
+
+    /// Encodes the palette in the 512-color `.pal` format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(FULL_FILE_SIZE);
+        for pixel in self.entries.iter() {
+            data.push(pixel.red);
+            data.push(pixel.green);
+            data.push(pixel.blue);
+        }
+        data
+    }
+}
+
+impl fmt::Debug for Palette {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Palette")
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+/// Approximates the effect of the emphasis bits by darkening the non-emphasized components
+fn emphasize(pixel: Pixel, emphasis: usize) -> Pixel {
+    if emphasis == 0 {
+        return pixel;
+    }
+
+    let attenuate = |component: u8, bit: usize| {
+        if emphasis & bit != 0 {
+            component
+        } else {
+            (component as f64 * EMPHASIS_ATTENUATION) as u8
+        }
+    };
+    Pixel {
+        red: attenuate(pixel.red, 0x01),
+        green: attenuate(pixel.green, 0x02),
+        blue: attenuate(pixel.blue, 0x04)
+    }
+}
+
+/// Produces the color of a pixel value by sampling the signal the PPU would generate for it
+/// over one color subcarrier cycle, and decoding it as an NTSC television would
+fn decode_signal(value: usize, settings: &Settings) -> Pixel {
+    let color = value & 0x0F;
+    let emphasis = value >> 6;
+    let level = if color > 0x0D { 1 } else { (value >> 4) & 0x03 };
+
+    let low = if color == 0 { SIGNAL_HIGH[level] } else { SIGNAL_LOW[level] };
+    let high = if color > 0x0C { low } else { SIGNAL_HIGH[level] };
+
+    let in_color_phase = |hue: usize, phase: usize| (hue + phase) % 12 < 6;
+
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let mut signal = if in_color_phase(color, phase) { high } else { low };
+        if (emphasis & 0x01 != 0 && in_color_phase(0x00, phase)) ||
+            (emphasis & 0x02 != 0 && in_color_phase(0x04, phase)) ||
+            (emphasis & 0x04 != 0 && in_color_phase(0x08, phase)) {
+            signal *= SIGNAL_ATTENUATION;
+        }
+
+        let signal = (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK) / 12.0;
+        let angle = PI * phase as f64 / 6.0 + settings.hue.to_radians();
+        y += signal;
+        i += signal * angle.cos();
+        q += signal * angle.sin();
+    }
+
+    let y = y * settings.contrast + settings.brightness;
+    let i = i * settings.saturation;
+    let q = q * settings.saturation;
+
+    let gamma_correct = |component: f64| {
+        let corrected = if component <= 0.0 { 0.0 } else { component.powf(2.2 / settings.gamma) };
+        (corrected * 255.0).round().max(0.0).min(255.0) as u8
+    };
+    Pixel {
+        red: gamma_correct(y + 0.946882 * i + 0.623557 * q),
+        green: gamma_correct(y - 0.274788 * i - 0.635691 * q),
+        blue: gamma_correct(y - 1.108545 * i + 1.709007 * q)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::palette::{self,Palette,Pixel,Settings,Error};
+
+    #[test]
+    pub fn base_palette_generates_emphasis() {
+        let data = [0xFFu8; palette::BASE_FILE_SIZE];
+        let palette = Palette::from_bytes(&data).unwrap();
+
+        assert_eq!(Pixel { red: 0xFF, green: 0xFF, blue: 0xFF }, palette.pixel(0x3F));
+        let emphasized = palette.pixel(0x40 | 0x3F);
+        assert_eq!(0xFF, emphasized.red);
+        assert!(emphasized.green < 0xFF);
+        assert!(emphasized.blue < 0xFF);
+    }
+
+    #[test]
+    pub fn full_palette_is_used_as_is() {
+        let mut data = vec![0u8; palette::FULL_FILE_SIZE];
+        data[0x1FF * 3] = 0x12;
+        data[0x1FF * 3 + 1] = 0x34;
+        data[0x1FF * 3 + 2] = 0x56;
+        let palette = Palette::from_bytes(&data).unwrap();
+
+        assert_eq!(Pixel { red: 0x12, green: 0x34, blue: 0x56 }, palette.pixel(0x1FF));
+        assert_eq!(data, palette.to_bytes());
+    }
+
+    #[test]
+    pub fn palette_of_wrong_size_is_rejected() {
+        match Palette::from_bytes(&[0u8; 100]) {
+            Err(Error::InvalidSize(100)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    pub fn generated_palette_has_black_and_white() {
+        let palette = Palette::generate(&Settings::new());
+
+        assert_eq!(Pixel { red: 0, green: 0, blue: 0 }, palette.pixel(0x0F));
+        let white = palette.pixel(0x30);
+        assert!(white.red > 0xF0 && white.green > 0xF0 && white.blue > 0xF0);
+    }
+
+    #[test]
+    pub fn generated_palette_responds_to_saturation() {
+        let mut settings = Settings::new();
+        settings.saturation = 0.0;
+        let palette = Palette::generate(&settings);
+
+        let pixel = palette.pixel(0x16);
+        assert_eq!(pixel.red, pixel.green);
+        assert_eq!(pixel.green, pixel.blue);
+    }
+}
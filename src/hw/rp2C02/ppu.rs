@@ -1,13 +1,25 @@
-use mem::{self,Memory};
+use std::{error,fmt};
+
+use slog;
+
+use mem;
 use clock;
+use hw::rp2C02::palette::Palette;
 
 pub const NAMETABLE_SIZE: usize = 0x0400;
 pub const NAMETABLE_BASE: usize = 0x2000;
 pub const NAMETABLE_END: usize = 0x2C00;
+pub const ATTRIBUTE_TABLE_OFFSET: usize = 0x03C0;
 
+pub const PALETTE_BASE: usize = 0x3F00;
+pub const PALETTE_SIZE: usize = 0x20;
 pub const BACKDROP_COLOR_ADDR: usize = 0x3F00;
 pub const BG_PALETTE_BASE: usize = 0x3F01;
 
+pub const OAM_SIZE: usize = 0x100;
+pub const SECONDARY_OAM_SIZE: usize = 0x20;
+pub const SPRITES_PER_SCANLINE: usize = 8;
+
 pub const PIXELS_PER_SCANLINE: usize = 256;
 pub const PIXELS_PER_TILE: usize = 8;
 pub const PIXELS_PER_SCREEN: usize = PIXELS_PER_SCANLINE * SCANLINES_PER_FRAME;
@@ -15,52 +27,170 @@ pub const BYTES_PER_PIXEL: usize = 3;
 pub const BYTES_PER_SCREEN: usize = BYTES_PER_PIXEL * PIXELS_PER_SCREEN;
 pub const TILES_PER_SCANLINE: usize = PIXELS_PER_SCANLINE / PIXELS_PER_TILE;
 pub const SCANLINES_PER_FRAME: usize = 240;
-pub const CYCLES_PER_SCANLINE: usize = 114;
+pub const DOTS_PER_SCANLINE: usize = 341;
+
+// Frame timing of the NTSC RP2C02, see `Timing` for the other variants
+pub const TOTAL_SCANLINES: usize = 262;
 pub const VBLANK_SCANLINE: usize = 241;
 pub const END_SCANLINE: usize = 261;
 
-// Unapologetically yanked from https://github.com/pcwalton/sprocketnes/blob/master/ppu.rs
-const PALETTE: [u8; 192] = [
-    124,124,124,    0,0,252,        0,0,188,        68,40,188,
-    148,0,132,      168,0,32,       168,16,0,       136,20,0,
-    80,48,0,        0,120,0,        0,104,0,        0,88,0,
-    0,64,88,        0,0,0,          0,0,0,          0,0,0,
-    188,188,188,    0,120,248,      0,88,248,       104,68,252,
-    216,0,204,      228,0,88,       248,56,0,       228,92,16,
-    172,124,0,      0,184,0,        0,168,0,        0,168,68,
-    0,136,136,      0,0,0,          0,0,0,          0,0,0,
-    248,248,248,    60,188,252,     104,136,252,    152,120,248,
-    248,120,248,    248,88,152,     248,120,88,     252,160,68,
-    248,184,0,      184,248,24,     88,216,84,      88,248,152,
-    0,232,216,      120,120,120,    0,0,0,          0,0,0,
-    252,252,252,    164,228,252,    184,184,248,    216,184,248,
-    248,184,248,    248,164,192,    240,208,176,    252,224,168,
-    248,216,120,    216,248,120,    184,248,184,    184,248,216,
-    0,252,252,      248,216,248,    0,0,0,          0,0,0
-];
-
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// Represents an error that occurs while the PPU is running
+#[derive(Clone,Debug,Eq,PartialEq)]
 pub enum Error {
+    /// Indicates that an error occurred reading or writing the PPU address bus
+    ErrorAccessingMemory(mem::Error)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::ErrorAccessingMemory(_) => "error accessing PPU memory"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::ErrorAccessingMemory(ref err) => Some(err)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::ErrorAccessingMemory(ref err) => write!(fmt, "error accessing PPU memory: {}", err)
+        }
+    }
+}
+
+impl From<mem::Error> for Error {
+    fn from(err: mem::Error) -> Error {
+        Error::ErrorAccessingMemory(err)
+    }
+}
+
+serialize_via_debug!(Error);
+
+/// Represents the address bus used by the PPU to reach pattern tables and nametables
+///
+/// Palette RAM ($3F00-$3FFF) is internal to the PPU and is never requested from the bus.
+pub trait Bus: mem::Memory {
+    /// Notifies the bus that PPU address line A12 went from low to high
+    ///
+    /// Scanline-counting mappers (such as the MMC3) clock their IRQ counters from this signal.
+    /// `low_cycles` is the number of PPU cycles A12 was held low before rising, which allows
+    /// the short pulses caused by nametable fetches to be filtered out.
+    fn a12_rising(&mut self, _low_cycles: u64) {}
+
+    /// Notifies the bus that the PPU is about to read `addr` while rendering, on PPU cycle
+    /// `cycle`
+    ///
+    /// Mappers which follow the rendering fetches (such as the MMC5) use this to find where the
+    /// PPU is in the frame. CPU accesses through PPUDATA are not reported.
+    fn rendering_fetch(&mut self, _addr: u16, _cycle: u64) {}
+}
+
+/// Describes the frame timing of a PPU variant
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Timing {
+    /// The number of scanlines in a frame, including the pre-render line
+    pub scanlines: usize,
+
+    /// The scanline on which the vertical blank flag is set
+    pub vblank_scanline: usize,
+
+    /// Indicates if the last dot of the pre-render line is skipped on odd frames
+    pub skip_odd_dot: bool,
+
+    /// Indicates if the red and green emphasis bits of PPUMASK are swapped
+    pub swap_emphasis: bool
+}
+
+impl Timing {
+    /// Gets the timing of the RP2C02, used in NTSC consoles
+    pub fn ntsc() -> Timing {
+        Timing {
+            scanlines: TOTAL_SCANLINES,
+            vblank_scanline: VBLANK_SCANLINE,
+            skip_odd_dot: true,
+            swap_emphasis: false
+        }
+    }
+
+    /// Gets the timing of the RP2C07, used in PAL consoles
+    ///
+    /// The extra 50 scanlines are all part of vertical blank.
+    pub fn pal() -> Timing {
+        Timing {
+            scanlines: 312,
+            vblank_scanline: 241,
+            skip_odd_dot: false,
+            swap_emphasis: true
+        }
+    }
+
+    /// Gets the timing of the UA6538, used in Dendy consoles
+    ///
+    /// The extra 50 scanlines are added to the post-render period, so vertical blank starts
+    /// late and lasts as long as it does on NTSC.
+    pub fn dendy() -> Timing {
+        Timing {
+            scanlines: 312,
+            vblank_scanline: 291,
+            skip_odd_dot: false,
+            swap_emphasis: true
+        }
+    }
+
+    /// Gets the pre-render scanline, which is the last scanline of the frame
+    pub fn pre_render_scanline(&self) -> usize {
+        self.scanlines - 1
+    }
 }
 
+/// Represents a Ricoh RP2C02 Picture Processing Unit
+///
+/// The PPU is emulated one dot (PPU cycle) at a time. Each frame is made up of
+/// `Timing::scanlines` scanlines of `DOTS_PER_SCANLINE` dots, the first
+/// `SCANLINES_PER_FRAME` of which produce visible pixels.
 pub struct Rp2C02 {
     clock: clock::Clock,
+    timing: Timing,
     registers: Registers,
-    current_scanline: usize
-}
+    scroll: ScrollRegisters,
+    scanline: usize,
+    dot: usize,
+    frame: u64,
+    odd_frame: bool,
 
-pub struct Pixel {
-    red: u8,
-    green: u8,
-    blue: u8
+    oam: [u8; OAM_SIZE],
+    oam_addr: u8,
+    secondary_oam: [u8; SECONDARY_OAM_SIZE],
+    palette: [u8; PALETTE_SIZE],
+    read_buffer: u8,
+    io_latch: u8,
+
+    background: BackgroundPipeline,
+    sprites: SpritePipeline,
+
+    a12: bool,
+    a12_low_since: u64,
+
+    nmi: Option<u64>,
+    suppress_vblank: bool,
+
+    screen: Vec<u16>,
+    output_palette: Palette,
+    log: slog::Logger
 }
 
+/// Represents the memory-mapped registers exposed to the CPU at $2000-$2007
 pub struct Registers {
-    ppuctrl: PpuCtrl,
-    ppumask: PpuMask,
-    ppustatus: PpuStatus,
-    ppuscroll: PpuScroll
+    pub ppuctrl: PpuCtrl,
+    pub ppumask: PpuMask,
+    pub ppustatus: PpuStatus
 }
 
 impl Registers {
@@ -68,89 +198,198 @@ impl Registers {
         Registers {
             ppuctrl: PpuCtrl::new(),
             ppumask: PpuMask::new(),
-            ppustatus: PpuStatus::new(),
-            ppuscroll: PpuScroll::new()
+            ppustatus: PpuStatus::new()
         }
     }
 }
 
-pub enum NextScrollField { X, Y }
+/// Represents the internal scroll registers of the PPU (commonly called the "loopy" registers)
+///
+/// `v` and `t` are laid out as `yyy NN YYYYY XXXXX` (fine Y, nametable, coarse Y, coarse X).
+/// See the [NesDev Wiki](http://wiki.nesdev.com/w/index.php/PPU_scrolling) for details.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct ScrollRegisters {
+    /// The current VRAM address
+    pub v: u16,
+
+    /// The temporary VRAM address (the address of the top-left onscreen tile)
+    pub t: u16,
 
-pub struct PpuScroll {
-    x: u8,
-    y: u8,
-    next: NextScrollField
+    /// The fine X scroll
+    pub x: u8,
+
+    /// The write toggle shared by $2005 and $2006
+    pub w: bool
 }
 
-impl PpuScroll {
-    pub fn new() {
-        PpuScroll {
-            x: 0,
-            y: 0,
-            next: NextScrollField::X
+impl ScrollRegisters {
+    pub fn new() -> ScrollRegisters {
+        ScrollRegisters { v: 0, t: 0, x: 0, w: false }
+    }
+
+    /// Applies a write to PPUCTRL ($2000), which selects the base nametable
+    pub fn write_ctrl(&mut self, val: u8) {
+        self.t = (self.t & 0xF3FF) | (((val & 0x03) as u16) << 10);
+    }
+
+    /// Applies a write to PPUSCROLL ($2005)
+    pub fn write_scroll(&mut self, val: u8) {
+        if !self.w {
+            self.t = (self.t & 0xFFE0) | ((val >> 3) as u16);
+            self.x = val & 0x07;
+        } else {
+            self.t = (self.t & 0x8C1F) | (((val & 0x07) as u16) << 12) | (((val & 0xF8) as u16) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    /// Applies a write to PPUADDR ($2006)
+    pub fn write_addr(&mut self, val: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00FF) | (((val & 0x3F) as u16) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | (val as u16);
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// Increments coarse X, switching horizontal nametables when it wraps
+    pub fn increment_x(&mut self) {
+        if self.v & 0x001F == 0x001F {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Increments fine Y, carrying into coarse Y and switching vertical nametables at row 29
+    pub fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+        } else {
+            self.v &= !0x7000;
+            let mut y = (self.v & 0x03E0) >> 5;
+            if y == 29 {
+                y = 0;
+                self.v ^= 0x0800;
+            } else if y == 31 {
+                y = 0;
+            } else {
+                y += 1;
+            }
+            self.v = (self.v & !0x03E0) | (y << 5);
         }
     }
+
+    /// Copies the horizontal position (coarse X and horizontal nametable) from `t` to `v`
+    pub fn copy_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    /// Copies the vertical position (fine Y, coarse Y and vertical nametable) from `t` to `v`
+    pub fn copy_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    /// Gets the fine Y scroll stored in `v`
+    pub fn fine_y(&self) -> u16 {
+        (self.v >> 12) & 0x07
+    }
 }
 
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
 pub enum VramDirection {
     GoingAcross,
     GoingDown
 }
 
+/// Represents the PPUCTRL ($2000) register
 pub struct PpuCtrl {
-    nametable_base: u16,
-    vram_direction: VramDirection,
-    sprite_pattern_table: u8,
-    bg_pattern_table: u8,
-    large_sprites: bool,
-    secondary: bool,
-    generate_nmi: bool
+    pub nametable_base: u16,
+    pub vram_direction: VramDirection,
+    pub sprite_pattern_table: u16,
+    pub bg_pattern_table: u16,
+    pub large_sprites: bool,
+    pub secondary: bool,
+    pub generate_nmi: bool
 }
 
 impl PpuCtrl {
     pub fn new() -> PpuCtrl {
+        PpuCtrl::from_bits(0)
+    }
+
+    /// Decodes the value written to PPUCTRL
+    pub fn from_bits(val: u8) -> PpuCtrl {
         PpuCtrl {
-            nametable: 0,
-            vram_direction: VramDirection::GoingAcross,
-            sprite_pattern_table: 0,
-            bg_pattern_table: 0,
-            large_sprites: false,
-            secondary: false,
-            generate_nmi: false
+            nametable_base: 0x2000 | (((val & 0x03) as u16) << 10),
+            vram_direction: if val & 0x04 == 0 { VramDirection::GoingAcross } else { VramDirection::GoingDown },
+            sprite_pattern_table: if val & 0x08 == 0 { 0x0000 } else { 0x1000 },
+            bg_pattern_table: if val & 0x10 == 0 { 0x0000 } else { 0x1000 },
+            large_sprites: val & 0x20 != 0,
+            secondary: val & 0x40 != 0,
+            generate_nmi: val & 0x80 != 0
         }
     }
+
+    /// Gets the amount to increment the VRAM address by after each access to PPUDATA
+    pub fn vram_increment(&self) -> u16 {
+        match self.vram_direction {
+            VramDirection::GoingAcross => 1,
+            VramDirection::GoingDown => 32
+        }
+    }
+
+    /// Gets the height, in pixels, of each sprite
+    pub fn sprite_height(&self) -> usize {
+        if self.large_sprites { 16 } else { 8 }
+    }
 }
 
+/// Represents the PPUMASK ($2001) register
 pub struct PpuMask {
-    greyscale: bool,
-    leftmost_background: bool,
-    leftmost_sprites: bool,
-    background: bool,
-    sprites: bool,
-    emphasize_red: bool,
-    emphasize_green: bool,
-    emphasize_blue: bool
+    pub greyscale: bool,
+    pub leftmost_background: bool,
+    pub leftmost_sprites: bool,
+    pub background: bool,
+    pub sprites: bool,
+    pub emphasize_red: bool,
+    pub emphasize_green: bool,
+    pub emphasize_blue: bool
 }
 
 impl PpuMask {
     pub fn new() -> PpuMask {
+        PpuMask::from_bits(0)
+    }
+
+    /// Decodes the value written to PPUMASK
+    pub fn from_bits(val: u8) -> PpuMask {
         PpuMask {
-            greyscale: false,
-            leftmost_background: false,
-            leftmost_sprites: false,
-            background: false,
-            sprites: false,
-            emphasize_red: false,
-            emphasize_green: false,
-            emphasize_blue: false
+            greyscale: val & 0x01 != 0,
+            leftmost_background: val & 0x02 != 0,
+            leftmost_sprites: val & 0x04 != 0,
+            background: val & 0x08 != 0,
+            sprites: val & 0x10 != 0,
+            emphasize_red: val & 0x20 != 0,
+            emphasize_green: val & 0x40 != 0,
+            emphasize_blue: val & 0x80 != 0
         }
     }
+
+    /// Returns a value indicating if either the background or sprites are being rendered
+    pub fn rendering(&self) -> bool {
+        self.background || self.sprites
+    }
 }
 
+/// Represents the PPUSTATUS ($2002) register
 pub struct PpuStatus {
-    sprite_overflow: bool,
-    sprite_0_hit: bool,
-    vertical_blank: bool
+    pub sprite_overflow: bool,
+    pub sprite_0_hit: bool,
+    pub vertical_blank: bool
 }
 
 impl PpuStatus {
@@ -161,139 +400,967 @@ impl PpuStatus {
             vertical_blank: false
         }
     }
+
+    /// Encodes the status flags into the upper three bits of the register
+    pub fn bits(&self) -> u8 {
+        (if self.sprite_overflow { 0x20 } else { 0 }) |
+            (if self.sprite_0_hit { 0x40 } else { 0 }) |
+            (if self.vertical_blank { 0x80 } else { 0 })
+    }
+}
+
+/// Holds the latches and shift registers used to render the background
+struct BackgroundPipeline {
+    nametable: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    shift_pattern_lo: u16,
+    shift_pattern_hi: u16,
+    shift_attribute_lo: u16,
+    shift_attribute_hi: u16
+}
+
+impl BackgroundPipeline {
+    fn new() -> BackgroundPipeline {
+        BackgroundPipeline {
+            nametable: 0,
+            attribute: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+            shift_pattern_lo: 0,
+            shift_pattern_hi: 0,
+            shift_attribute_lo: 0,
+            shift_attribute_hi: 0
+        }
+    }
+
+    fn shift(&mut self) {
+        self.shift_pattern_lo <<= 1;
+        self.shift_pattern_hi <<= 1;
+        self.shift_attribute_lo <<= 1;
+        self.shift_attribute_hi <<= 1;
+    }
+
+    fn reload(&mut self) {
+        self.shift_pattern_lo = (self.shift_pattern_lo & 0xFF00) | (self.pattern_lo as u16);
+        self.shift_pattern_hi = (self.shift_pattern_hi & 0xFF00) | (self.pattern_hi as u16);
+        self.shift_attribute_lo = (self.shift_attribute_lo & 0xFF00) | (if self.attribute & 0x01 != 0 { 0xFF } else { 0x00 });
+        self.shift_attribute_hi = (self.shift_attribute_hi & 0xFF00) | (if self.attribute & 0x02 != 0 { 0xFF } else { 0x00 });
+    }
+
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 15 - fine_x as u16;
+        let pattern = (((self.shift_pattern_hi >> bit) & 1) << 1) | ((self.shift_pattern_lo >> bit) & 1);
+        let palette = (((self.shift_attribute_hi >> bit) & 1) << 1) | ((self.shift_attribute_lo >> bit) & 1);
+        (pattern as u8, palette as u8)
+    }
+}
+
+/// Holds the sprites fetched for the scanline being rendered
+struct SpritePipeline {
+    count: usize,
+    zero_in_line: bool,
+    pattern_lo: [u8; SPRITES_PER_SCANLINE],
+    pattern_hi: [u8; SPRITES_PER_SCANLINE],
+    attribute: [u8; SPRITES_PER_SCANLINE],
+    x: [u8; SPRITES_PER_SCANLINE],
+
+    next_count: usize,
+    next_zero_in_line: bool
+}
+
+impl SpritePipeline {
+    fn new() -> SpritePipeline {
+        SpritePipeline {
+            count: 0,
+            zero_in_line: false,
+            pattern_lo: [0; SPRITES_PER_SCANLINE],
+            pattern_hi: [0; SPRITES_PER_SCANLINE],
+            attribute: [0; SPRITES_PER_SCANLINE],
+            x: [0; SPRITES_PER_SCANLINE],
+            next_count: 0,
+            next_zero_in_line: false
+        }
+    }
+
+    /// Finds the first opaque sprite pixel at `x`, returning the pattern value, attribute
+    /// byte and whether it belongs to sprite 0
+    fn pixel(&self, x: usize) -> Option<(u8, u8, bool)> {
+        for i in 0..self.count {
+            let offset = x as isize - self.x[i] as isize;
+            if offset >= 0 && offset < 8 {
+                let bit = 7 - offset as u8;
+                let pattern = (((self.pattern_hi[i] >> bit) & 1) << 1) | ((self.pattern_lo[i] >> bit) & 1);
+                if pattern != 0 {
+                    return Some((pattern, self.attribute[i], i == 0 && self.zero_in_line));
+                }
+            }
+        }
+        None
+    }
 }
 
 impl Rp2C02 {
-    pub fn new() -> Rp2C02 {
+    pub fn new(logger: Option<slog::Logger>) -> Rp2C02 {
         Rp2C02 {
             clock: clock::Clock::new(),
+            timing: Timing::ntsc(),
             registers: Registers::new(),
-            current_scanline: 0
+            scroll: ScrollRegisters::new(),
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd_frame: false,
+            oam: [0; OAM_SIZE],
+            oam_addr: 0,
+            secondary_oam: [0xFF; SECONDARY_OAM_SIZE],
+            palette: [0; PALETTE_SIZE],
+            read_buffer: 0,
+            io_latch: 0,
+            background: BackgroundPipeline::new(),
+            sprites: SpritePipeline::new(),
+            a12: false,
+            a12_low_since: 0,
+            nmi: None,
+            suppress_vblank: false,
+            screen: vec![0; PIXELS_PER_SCREEN],
+            output_palette: Palette::new(),
+            log: unwrap_logger!(logger).new(o!("device" => "ppu"))
+        }
+    }
+
+    /// Gets the number of PPU cycles (dots) executed since power-on
+    pub fn cycles(&self) -> u64 {
+        self.clock.get()
+    }
+
+    /// Gets the frame timing used by the PPU
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Sets the frame timing used by the PPU, emulating a different variant of the chip
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        if self.scanline >= timing.scanlines {
+            self.scanline = timing.pre_render_scanline();
+        }
+    }
+
+    /// Resets the PPU, as happens when the console's reset button is pressed
+    ///
+    /// PPUCTRL and PPUMASK are cleared, along with the write toggle, the read buffer and the
+    /// odd frame flag. The scanline counter, VRAM, OAM and palette RAM are unaffected.
+    pub fn reset(&mut self) {
+        debug!(self.log, "cycle" => self.clock.get(); "reset");
+        self.registers.ppuctrl = PpuCtrl::new();
+        self.registers.ppumask = PpuMask::new();
+        self.scroll.w = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    /// Gets the scanline currently being processed (0-239 are visible, the last scanline of
+    /// the frame is the pre-render line)
+    pub fn scanline(&self) -> usize {
+        self.scanline
+    }
+
+    /// Gets the dot within the current scanline (0-340)
+    pub fn dot(&self) -> usize {
+        self.dot
+    }
+
+    /// Gets the number of frames completed since power-on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Gets the internal scroll registers
+    pub fn scroll(&self) -> &ScrollRegisters {
+        &self.scroll
+    }
+
+    /// Gets the memory-mapped registers
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Gets the current state of the NMI output, which is asserted while the vertical blank
+    /// flag is set and NMIs are enabled in PPUCTRL
+    pub fn nmi_output(&self) -> bool {
+        self.registers.ppustatus.vertical_blank && self.registers.ppuctrl.generate_nmi
+    }
+
+    /// Gets the PPU cycle at which the NMI output was last asserted, if it has been asserted
+    /// since the last call
+    pub fn take_nmi(&mut self) -> Option<u64> {
+        self.nmi.take()
+    }
+
+    /// Gets the most recently rendered pixels
+    ///
+    /// Each pixel holds the color selected from palette RAM (after greyscale is applied) in
+    /// bits 0-5, and the red, green and blue emphasis bits in bits 6-8.
+    pub fn screen(&self) -> &[u16] {
+        &self.screen
+    }
+
+    /// Gets the palette used to convert the screen to RGB
+    pub fn output_palette(&self) -> &Palette {
+        &self.output_palette
+    }
+
+    /// Sets the palette used to convert the screen to RGB
+    pub fn set_output_palette(&mut self, palette: Palette) {
+        self.output_palette = palette;
+    }
+
+    /// Converts the current screen to 24-bit RGB, writing `BYTES_PER_SCREEN` bytes to `out`
+    pub fn render(&self, out: &mut [u8]) {
+        assert!(out.len() >= BYTES_PER_SCREEN);
+        for (i, &value) in self.screen.iter().enumerate() {
+            let pixel = self.output_palette.pixel(value);
+            out[i * 3] = pixel.red;
+            out[i * 3 + 1] = pixel.green;
+            out[i * 3 + 2] = pixel.blue;
         }
     }
 
     /// Emulates the execution of PPU cycles until `target_cycle` is reached
     ///
-    /// The PPU emulation only runs entire scanlines at once. So, this method
-    /// only executes a scanline if the current clock, plus the number of cycles
-    /// required to render a scanline (`CYCLES_PER_SCANLINE`) is less than
-    /// the desired `target_cycle`. If it is, a single scan line is rendered,
-    /// the PPU clock is updated by `CYCLES_PER_SCANLINE` and the process is
-    /// repeated. If it is not, this method returns to allow the CPU to continue
-    /// processing.
-    pub fn step(&mut self, target_cycle: usize, mem: &mut mem::Memory, screen: &mut [u8; BYTES_PER_SCREEN]) -> Result<()> {
-        loop {
-            // Check when the next scan line is
-            let next_scan_line = self.clock.get() + CYCLES_PER_SCANLINE;
-            if next_scan_line > target_cycle {
-                // Next scan line is beyond our target, we've done enough.
-                break;
-            }
+    /// The PPU is emulated one dot at a time, so the caller can interleave register accesses
+    /// at any point and observe mid-scanline effects. Each visible dot produces one pixel in
+    /// the screen buffer.
+    pub fn step<B>(&mut self, target_cycle: u64, bus: &mut B) -> Result<()> where B: Bus {
+        while self.clock.get() < target_cycle {
+            try!(self.tick(bus));
+        }
+        Ok(())
+    }
 
-            if self.current_scanline < SCANLINES_PER_FRAME {
-                // Render the scanline
-                debug!("rendering scanline {}", self.current_scanline);
+    /// Reads the register at `reg` ($2000-$2007, mirrored every 8 bytes)
+    pub fn read_register<B>(&mut self, reg: u64, bus: &mut B) -> Result<u8> where B: Bus {
+        let val = match reg & 0x07 {
+            2 => {
+                if self.scanline == self.timing.vblank_scanline {
+                    match self.dot {
+                        // Reading the flag on the dot before it is set reads it as clear, and
+                        // prevents it (and the NMI) from being set this frame
+                        1 => self.suppress_vblank = true,
+                        // Reading the flag on the dot it is set, or the one after, clears it
+                        // before the CPU sees the NMI
+                        2 | 3 => self.nmi = None,
+                        _ => {}
+                    }
+                }
+                let val = self.registers.ppustatus.bits() | (self.io_latch & 0x1F);
+                self.registers.ppustatus.vertical_blank = false;
+                self.scroll.w = false;
+                val
+            },
+            4 => {
+                let val = self.oam[self.oam_addr as usize];
+                if self.oam_addr & 0x03 == 0x02 {
+                    // The unimplemented attribute bits always read back as zero
+                    val & 0xE3
+                } else {
+                    val
+                }
+            },
+            7 => {
+                let addr = self.scroll.v & 0x3FFF;
+                let val = if addr as usize >= PALETTE_BASE {
+                    // Palette reads are not buffered, but the buffer is still filled with the
+                    // nametable data "underneath" the palette
+                    self.read_buffer = try!(bus.get_u8((addr - 0x1000) as u64));
+                    (self.palette[palette_index(addr)] & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    let val = self.read_buffer;
+                    self.read_buffer = try!(bus.get_u8(addr as u64));
+                    val
+                };
+                self.increment_vram_addr(bus);
+                val
+            },
+            _ => self.io_latch
+        };
 
-                let start = PIXELS_PER_SCANLINE * self.current_scanline;
-                let end = start + PIXELS_PER_SCANLINE;
-                assert!(start < end && start < BYTES_PER_SCREEN && end < BYTES_PER_SCREEN);
+        trace!(self.log,
+            "read";
+            "register" => format!("$200{:X}", reg & 0x07),
+            "value" => format!("${:02X}", val),
+            "scanline" => self.scanline,
+            "dot" => self.dot);
+        self.io_latch = val;
+        Ok(val)
+    }
 
-                let scanline_screen = screen[start .. end];
+    /// Writes `val` to the register at `reg` ($2000-$2007, mirrored every 8 bytes)
+    pub fn write_register<B>(&mut self, reg: u64, val: u8, bus: &mut B) -> Result<()> where B: Bus {
+        trace!(self.log,
+            "write";
+            "register" => format!("$200{:X}", reg & 0x07),
+            "value" => format!("${:02X}", val),
+            "scanline" => self.scanline,
+            "dot" => self.dot);
+        self.io_latch = val;
 
-                try!(self.render_scanline(mem, scanline_screen));
-            } else if self.current_scanline == VBLANK_SCANLINE {
-                debug!("vblank starting");
-                self.start_vblank();
-            } else if self.current_scanline == END_SCANLINE {
-                debug!("frame completed");
-                self.end_frame();
-            }
+        match reg & 0x07 {
+            0 => {
+                let nmi_output = self.nmi_output();
+                self.registers.ppuctrl = PpuCtrl::from_bits(val);
+                self.scroll.write_ctrl(val);
+
+                // Enabling NMIs during vertical blank immediately asserts the NMI output, while
+                // disabling them on the dot the flag is set prevents the CPU from seeing it
+                if !nmi_output && self.nmi_output() {
+                    self.nmi = Some(self.clock.get());
+                } else if !self.registers.ppuctrl.generate_nmi && self.scanline == self.timing.vblank_scanline && self.dot == 2 {
+                    self.nmi = None;
+                }
+            },
+            1 => {
+                let mut mask = PpuMask::from_bits(val);
+                if self.timing.swap_emphasis {
+                    ::std::mem::swap(&mut mask.emphasize_red, &mut mask.emphasize_green);
+                }
+                self.registers.ppumask = mask;
+            },
+            3 => self.oam_addr = val,
+            4 => {
+                self.oam[self.oam_addr as usize] = val;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            5 => self.scroll.write_scroll(val),
+            6 => {
+                self.scroll.write_addr(val);
+                if !self.scroll.w {
+                    let addr = self.scroll.v & 0x3FFF;
+                    self.set_address(bus, addr);
+                }
+            },
+            7 => {
+                let addr = self.scroll.v & 0x3FFF;
+                if addr as usize >= PALETTE_BASE {
+                    self.palette[palette_index(addr)] = val & 0x3F;
+                } else {
+                    try!(bus.set_u8(addr as u64, val));
+                }
+                self.increment_vram_addr(bus);
+            },
+            _ => {}
+        }
+        Ok(())
+    }
 
-            // Advance to the next scanline
-            self.current_scanline += 1;
+    /// Applies the greyscale and emphasis bits of PPUMASK to a color from palette RAM
+    fn output_color(&self, color: u8) -> u16 {
+        let mask = &self.registers.ppumask;
+        let color = if mask.greyscale { color & 0x30 } else { color & 0x3F };
+        let emphasis = (if mask.emphasize_red { 0x01 } else { 0 }) |
+            (if mask.emphasize_green { 0x02 } else { 0 }) |
+            (if mask.emphasize_blue { 0x04 } else { 0 });
+        (color as u16) | (emphasis << 6)
+    }
 
-            self.clock.tick(CYCLES_PER_SCANLINE);
+    fn rendering_line(&self) -> bool {
+        self.scanline < SCANLINES_PER_FRAME || self.scanline == self.timing.pre_render_scanline()
+    }
+
+    fn increment_vram_addr<B>(&mut self, bus: &mut B) where B: Bus {
+        if self.registers.ppumask.rendering() && self.rendering_line() {
+            // Accessing PPUDATA while rendering triggers both of the rendering increments
+            self.scroll.increment_x();
+            self.scroll.increment_y();
+        } else {
+            self.scroll.v = self.scroll.v.wrapping_add(self.registers.ppuctrl.vram_increment()) & 0x7FFF;
+            let addr = self.scroll.v & 0x3FFF;
+            self.set_address(bus, addr);
         }
     }
 
-    fn get_pixel(&self, index: usize) -> Pixel {
-        assert!(index * 3 + 2 < PALETTE.len());
-        Pixel {
-            red: PALETTE[index * 3 + 2],
-            green: PALETTE[index * 3 + 1],
-            blue: PALETTE[index * 3]
+    /// Places `addr` on the address bus, tracking the state of the A12 line
+    fn set_address<B>(&mut self, bus: &mut B, addr: u16) where B: Bus {
+        let a12 = addr & 0x1000 != 0;
+        let now = self.clock.get();
+        if a12 && !self.a12 {
+            bus.a12_rising(now - self.a12_low_since);
+        } else if !a12 && self.a12 {
+            self.a12_low_since = now;
+        }
+        self.a12 = a12;
+    }
+
+    fn fetch<B>(&mut self, bus: &mut B, addr: u16) -> Result<u8> where B: Bus {
+        self.set_address(bus, addr);
+        bus.rendering_fetch(addr, self.clock.get());
+        Ok(try!(bus.get_u8(addr as u64)))
+    }
+
+    fn tick<B>(&mut self, bus: &mut B) -> Result<()> where B: Bus {
+        if self.rendering_line() {
+            if self.registers.ppumask.rendering() {
+                try!(self.render_dot(bus));
+            } else if self.scanline < SCANLINES_PER_FRAME && self.dot >= 1 && self.dot <= PIXELS_PER_SCANLINE {
+                // With rendering disabled the backdrop is drawn, unless the VRAM address points
+                // into the palette in which case that colour is shown instead
+                let addr = self.scroll.v & 0x3FFF;
+                let color = if addr as usize >= PALETTE_BASE {
+                    self.palette[palette_index(addr)]
+                } else {
+                    self.palette[0]
+                };
+                self.screen[self.scanline * PIXELS_PER_SCANLINE + self.dot - 1] = self.output_color(color);
+            }
+        }
+
+        if self.scanline == self.timing.vblank_scanline && self.dot == 1 {
+            self.start_vblank();
+        } else if self.scanline == self.timing.pre_render_scanline() && self.dot == 1 {
+            self.end_frame();
         }
+
+        self.advance();
+        self.clock.tick(1);
+        Ok(())
     }
 
-    fn get_background(&mut self, mem: &mut mem::Memory, x: usize, y: usize) -> Option<Pixel> {
-        // Determine active nametable
-        let nametable = self.registers.ppuctrl.nametable;
-        assert!(nametable == 0x2000 || nametable == 0x2400 || nametable == 0x2800 || nametable == 0x2C00);
+    fn render_dot<B>(&mut self, bus: &mut B) -> Result<()> where B: Bus {
+        let dot = self.dot;
+        let fetching = (dot >= 1 && dot <= 256) || (dot >= 321 && dot <= 336);
+
+        if (dot >= 2 && dot <= 257) || (dot >= 322 && dot <= 337) {
+            self.background.shift();
+            if (dot - 1) % 8 == 0 {
+                self.background.reload();
+            }
+        }
+
+        if self.scanline < SCANLINES_PER_FRAME && dot >= 1 && dot <= PIXELS_PER_SCANLINE {
+            self.output_pixel();
+        }
+
+        if fetching {
+            match (dot - 1) % 8 {
+                0 => {
+                    let addr = 0x2000 | (self.scroll.v & 0x0FFF);
+                    self.background.nametable = try!(self.fetch(bus, addr));
+                },
+                2 => {
+                    let v = self.scroll.v;
+                    let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let attribute = try!(self.fetch(bus, addr));
+                    let shift = ((v >> 4) & 0x04) | (v & 0x02);
+                    self.background.attribute = (attribute >> shift) & 0x03;
+                },
+                4 => {
+                    let addr = self.background_pattern_addr();
+                    self.background.pattern_lo = try!(self.fetch(bus, addr));
+                },
+                6 => {
+                    let addr = self.background_pattern_addr() + 8;
+                    self.background.pattern_hi = try!(self.fetch(bus, addr));
+                },
+                7 => self.scroll.increment_x(),
+                _ => {}
+            }
+        }
 
-        // Calculate nametable cell and tile offset
-        let (col, tile_x) = (x / 8, x % 8);
-        let (row, tile_y) = (y / 8, y % 8);
+        if dot == 256 {
+            self.scroll.increment_y();
+        } else if dot == 257 {
+            self.scroll.copy_x();
+            if self.scanline < SCANLINES_PER_FRAME {
+                self.evaluate_sprites();
+            } else {
+                // Sprites are never evaluated on the pre-render line, so nothing is drawn on
+                // the first visible line
+                self.sprites.next_count = 0;
+                self.sprites.next_zero_in_line = false;
+            }
+        } else if dot == 337 || dot == 339 {
+            // Unused nametable fetches at the end of each line
+            let addr = 0x2000 | (self.scroll.v & 0x0FFF);
+            try!(self.fetch(bus, addr));
+        }
 
-        // Load tile from nametable
-        let tile_index = try!(mem.get_u8(nametable + (row * TILES_PER_SCANLINE) + col));
+        if self.scanline == self.timing.pre_render_scanline() && dot >= 280 && dot <= 304 {
+            self.scroll.copy_y();
+        }
 
-        // Load palette index from the pattern table
-        let pattern_table = self.registers.ppuctrl.bg_pattern_table;
-        let pattern_color = self.get_pattern_value(pattern_table, tile_index, tile_x, tile_y);
-        if pattern_color == 0 {
-            // Pattern has no value here, background is transparent.
-            return None;
+        if dot >= 257 && dot <= 320 {
+            self.oam_addr = 0;
+            try!(self.fetch_sprite(bus));
         }
 
-        // Load attribute table
-        let attribute_group = row / 4 * 8 + col / 4;
-        let attribute = try!(mem.get_u8(nametable + 0x3C0 + attribute_group));
-        let palette_index = match (col % 4 < 2, row % 4 < 2) {
-            (true, true) => attribute & 0x0003,
-            (false, true) => (attribute >> 2) & 0x0003,
-            (true, false) => (attribute >> 4) & 0x0003,
-            (false, false) => (attribute >> 6) & 0x0003
+        Ok(())
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        self.registers.ppuctrl.bg_pattern_table |
+            ((self.background.nametable as u16) << 4) |
+            self.scroll.fine_y()
+    }
+
+    fn output_pixel(&mut self) {
+        let x = self.dot - 1;
+        let mask = &self.registers.ppumask;
+
+        let (bg_pattern, bg_palette) = if mask.background && (x >= 8 || mask.leftmost_background) {
+            self.background.pixel(self.scroll.x)
+        } else {
+            (0, 0)
+        };
+
+        let sprite = if mask.sprites && (x >= 8 || mask.leftmost_sprites) {
+            self.sprites.pixel(x)
+        } else {
+            None
         };
 
-        // Load the colour out of the palette
-        let color_offset = (palette_index << 2) | pattern_color;
-        let color = try!(mem.get_u8(BG_PALETTE_BASE + color_offset));
-        Some(self.get_color(color))
+        let addr = match sprite {
+            Some((sp_pattern, sp_attr, sprite_zero)) => {
+                if sprite_zero && bg_pattern != 0 && x != 255 {
+                    self.registers.ppustatus.sprite_0_hit = true;
+                }
+
+                if bg_pattern != 0 && sp_attr & 0x20 != 0 {
+                    (bg_palette << 2) | bg_pattern
+                } else {
+                    0x10 | ((sp_attr & 0x03) << 2) | sp_pattern
+                }
+            },
+            None if bg_pattern != 0 => (bg_palette << 2) | bg_pattern,
+            None => 0
+        };
+
+        let color = self.palette[palette_index(PALETTE_BASE as u16 | addr as u16)];
+        self.screen[self.scanline * PIXELS_PER_SCANLINE + x] = self.output_color(color);
     }
 
-    fn render_scanline(&mut self, mem: &mut mem::Memory, screen: &mut [u8]) -> Result<()> {
-        let mut cursor = 0;
+    fn evaluate_sprites(&mut self) {
+        let height = self.registers.ppuctrl.sprite_height();
+        let mut count = 0;
 
-        let backdrop_index = try!(mem.get_u8(BACKDROP_COLOR_ADDR));
-        let backdrop = self.get_pixel(backdrop_index);
+        self.secondary_oam = [0xFF; SECONDARY_OAM_SIZE];
+        self.sprites.next_zero_in_line = false;
 
-        for x in 0 .. PIXELS_PER_SCANLINE {
-            let background = if self.ppumask.background {
-                self.get_background(mem, x, self.current_scanline)
-            } else {
-                None
-            };
+        for i in 0..(OAM_SIZE / 4) {
+            let y = self.oam[i * 4] as usize;
+            if self.scanline >= y && self.scanline - y < height {
+                if count == SPRITES_PER_SCANLINE {
+                    self.registers.ppustatus.sprite_overflow = true;
+                    break;
+                }
 
-            // TODO: Sprites
+                for b in 0..4 {
+                    self.secondary_oam[count * 4 + b] = self.oam[i * 4 + b];
+                }
+                if i == 0 {
+                    self.sprites.next_zero_in_line = true;
+                }
+                count += 1;
+            }
+        }
 
-            // Determine the visible pixel
-            let pixel = match background {
-                Some(pix) => pix,
-                None => backdrop
-            };
+        self.sprites.next_count = count;
+    }
 
-            // Put it to the screen
-            screen[x * 3] = pixel.red;
-            screen[x * 3 + 1] = pixel.green;
-            screen[x * 3 + 2] = pixel.blue;
+    fn fetch_sprite<B>(&mut self, bus: &mut B) -> Result<()> where B: Bus {
+        let slot = (self.dot - 257) / 8;
+        match (self.dot - 257) % 8 {
+            0 | 2 => {
+                // Garbage nametable fetches
+                let addr = 0x2000 | (self.scroll.v & 0x0FFF);
+                try!(self.fetch(bus, addr));
+            },
+            4 => {
+                let addr = self.sprite_pattern_addr(slot);
+                let val = try!(self.fetch(bus, addr));
+                self.load_sprite(slot, val, true);
+            },
+            6 => {
+                let addr = self.sprite_pattern_addr(slot) + 8;
+                let val = try!(self.fetch(bus, addr));
+                self.load_sprite(slot, val, false);
+
+                if slot == SPRITES_PER_SCANLINE - 1 {
+                    self.sprites.count = self.sprites.next_count;
+                    self.sprites.zero_in_line = self.sprites.next_zero_in_line;
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let y = self.secondary_oam[slot * 4] as usize;
+        let tile = self.secondary_oam[slot * 4 + 1] as u16;
+        let attr = self.secondary_oam[slot * 4 + 2];
+        let height = self.registers.ppuctrl.sprite_height();
+
+        let mut row = if slot < self.sprites.next_count {
+            self.scanline.wrapping_sub(y) % height
+        } else {
+            0
+        };
+        if attr & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            let table = (tile & 0x01) * 0x1000;
+            let tile = (tile & 0xFE) + if row >= 8 { 1 } else { 0 };
+            table | (tile << 4) | (row as u16 & 0x07)
+        } else {
+            self.registers.ppuctrl.sprite_pattern_table | (tile << 4) | row as u16
+        }
+    }
+
+    fn load_sprite(&mut self, slot: usize, val: u8, low: bool) {
+        let attr = self.secondary_oam[slot * 4 + 2];
+        let val = if slot >= self.sprites.next_count {
+            // Empty slots are still fetched, but are always transparent
+            0
+        } else if attr & 0x40 != 0 {
+            val.reverse_bits()
+        } else {
+            val
+        };
+
+        if low {
+            self.sprites.pattern_lo[slot] = val;
+            self.sprites.attribute[slot] = attr;
+            self.sprites.x[slot] = self.secondary_oam[slot * 4 + 3];
+        } else {
+            self.sprites.pattern_hi[slot] = val;
+        }
+    }
+
+    fn advance(&mut self) {
+        if self.scanline == self.timing.pre_render_scanline() && self.dot == DOTS_PER_SCANLINE - 2 &&
+            self.odd_frame && self.timing.skip_odd_dot && self.registers.ppumask.rendering() {
+            // The last dot of the pre-render line is skipped on odd frames while rendering
+            self.dot = DOTS_PER_SCANLINE;
+        } else {
+            self.dot += 1;
+        }
+
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline >= self.timing.scanlines {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
         }
     }
 
     fn start_vblank(&mut self) {
-        unimplemented!()
+        debug!(self.log, "vblank starting"; "frame" => self.frame);
+        if self.suppress_vblank {
+            self.suppress_vblank = false;
+        } else {
+            self.registers.ppustatus.vertical_blank = true;
+            if self.registers.ppuctrl.generate_nmi {
+                self.nmi = Some(self.clock.get());
+            }
+        }
+        self.frame += 1;
     }
 
     fn end_frame(&mut self) {
-        unimplemented!()
+        debug!(self.log, "frame completed"; "frame" => self.frame);
+        self.registers.ppustatus.vertical_blank = false;
+        self.registers.ppustatus.sprite_0_hit = false;
+        self.registers.ppustatus.sprite_overflow = false;
+    }
+}
+
+/// Maps an address in $3F00-$3FFF to an index in palette RAM
+///
+/// $3F10, $3F14, $3F18 and $3F1C are mirrors of $3F00, $3F04, $3F08 and $3F0C
+fn palette_index(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod scroll {
+        use hw::rp2C02::ppu::ScrollRegisters;
+
+        #[test]
+        pub fn scroll_and_addr_writes_follow_the_loopy_example() {
+            // Example sequence from http://wiki.nesdev.com/w/index.php/PPU_scrolling
+            let mut s = ScrollRegisters::new();
+            s.write_ctrl(0x00);
+            assert_eq!(0x0000, s.t);
+
+            s.write_scroll(0x7D);
+            assert_eq!(0x000F, s.t);
+            assert_eq!(0x05, s.x);
+            assert!(s.w);
+
+            s.write_scroll(0x5E);
+            assert_eq!(0x616F, s.t);
+            assert!(!s.w);
+
+            s.write_addr(0x3D);
+            assert_eq!(0x3D6F, s.t);
+
+            s.write_addr(0xF0);
+            assert_eq!(0x3DF0, s.t);
+            assert_eq!(0x3DF0, s.v);
+        }
+
+        #[test]
+        pub fn increment_x_wraps_into_next_horizontal_nametable() {
+            let mut s = ScrollRegisters::new();
+            s.v = 0x001F;
+            s.increment_x();
+            assert_eq!(0x0400, s.v);
+        }
+
+        #[test]
+        pub fn increment_y_wraps_at_row_29_into_next_vertical_nametable() {
+            let mut s = ScrollRegisters::new();
+            s.v = 0x7000 | (29 << 5);
+            s.increment_y();
+            assert_eq!(0x0800, s.v);
+        }
+
+        #[test]
+        pub fn increment_y_wraps_at_row_31_without_switching_nametable() {
+            let mut s = ScrollRegisters::new();
+            s.v = 0x7000 | (31 << 5);
+            s.increment_y();
+            assert_eq!(0x0000, s.v);
+        }
+
+        #[test]
+        pub fn copy_x_and_copy_y_transfer_their_bits_only() {
+            let mut s = ScrollRegisters::new();
+            s.t = 0x7FFF;
+            s.copy_x();
+            assert_eq!(0x041F, s.v);
+            s.copy_y();
+            assert_eq!(0x7FFF, s.v);
+        }
+    }
+
+    mod ppu {
+        use mem;
+        use mem::Memory;
+        use hw::rp2C02::ppu::{self,Rp2C02,Bus};
+
+        struct TestBus(mem::Fixed, usize, Vec<u16>);
+
+        impl mem::Memory for TestBus {
+            fn len(&self) -> u64 { self.0.len() }
+            fn get_u8(&self, addr: u64) -> mem::Result<u8> { self.0.get_u8(addr) }
+            fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> { self.0.set_u8(addr, val) }
+        }
+
+        impl Bus for TestBus {
+            fn a12_rising(&mut self, low_cycles: u64) {
+                // Ignore the short pulses between sprite fetches, as an MMC3 would
+                if low_cycles >= 10 {
+                    self.1 += 1;
+                }
+            }
+
+            fn rendering_fetch(&mut self, addr: u16, _cycle: u64) {
+                self.2.push(addr);
+            }
+        }
+
+        fn new_bus() -> TestBus {
+            TestBus(mem::Fixed::new(0x3000), 0, Vec::new())
+        }
+
+        #[test]
+        pub fn vblank_flag_is_set_at_scanline_241_dot_1() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let vblank_dot = (ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 1) as u64;
+
+            ppu.step(vblank_dot, &mut bus).unwrap();
+            assert!(!ppu.registers().ppustatus.vertical_blank);
+            ppu.step(vblank_dot + 1, &mut bus).unwrap();
+            assert!(ppu.registers().ppustatus.vertical_blank);
+        }
+
+        #[test]
+        pub fn reading_status_clears_vblank_and_write_toggle() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            ppu.step((ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 2) as u64, &mut bus).unwrap();
+            ppu.write_register(5, 0x10, &mut bus).unwrap();
+
+            assert_eq!(0x80, ppu.read_register(2, &mut bus).unwrap() & 0xE0);
+            assert_eq!(0x00, ppu.read_register(2, &mut bus).unwrap() & 0xE0);
+            assert!(!ppu.scroll().w);
+        }
+
+        #[test]
+        pub fn nmi_is_asserted_at_vblank_when_enabled() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let vblank_dot = (ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 1) as u64;
+            ppu.write_register(0, 0x80, &mut bus).unwrap();
+
+            ppu.step(vblank_dot + 1, &mut bus).unwrap();
+            assert!(ppu.nmi_output());
+            assert_eq!(Some(vblank_dot), ppu.take_nmi());
+            assert_eq!(None, ppu.take_nmi());
+        }
+
+        #[test]
+        pub fn reading_status_just_before_vblank_suppresses_flag_and_nmi() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let vblank_dot = (ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 1) as u64;
+            ppu.write_register(0, 0x80, &mut bus).unwrap();
+
+            ppu.step(vblank_dot, &mut bus).unwrap();
+            assert_eq!(0x00, ppu.read_register(2, &mut bus).unwrap() & 0x80);
+            ppu.step(vblank_dot + 10, &mut bus).unwrap();
+            assert!(!ppu.registers().ppustatus.vertical_blank);
+            assert_eq!(None, ppu.take_nmi());
+        }
+
+        #[test]
+        pub fn reading_status_as_vblank_starts_suppresses_nmi() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let vblank_dot = (ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 1) as u64;
+            ppu.write_register(0, 0x80, &mut bus).unwrap();
+
+            ppu.step(vblank_dot + 1, &mut bus).unwrap();
+            assert_eq!(0x80, ppu.read_register(2, &mut bus).unwrap() & 0x80);
+            assert_eq!(None, ppu.take_nmi());
+        }
+
+        #[test]
+        pub fn enabling_nmi_during_vblank_asserts_nmi() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let vblank_dot = (ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 1) as u64;
+
+            ppu.step(vblank_dot + 100, &mut bus).unwrap();
+            assert_eq!(None, ppu.take_nmi());
+            ppu.write_register(0, 0x80, &mut bus).unwrap();
+            assert_eq!(Some(vblank_dot + 100), ppu.take_nmi());
+        }
+
+        #[test]
+        pub fn ppudata_reads_are_buffered_except_for_palette() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            bus.set_u8(0x2000, 0x42).unwrap();
+
+            ppu.write_register(6, 0x20, &mut bus).unwrap();
+            ppu.write_register(6, 0x00, &mut bus).unwrap();
+            ppu.read_register(7, &mut bus).unwrap();
+            assert_eq!(0x42, ppu.read_register(7, &mut bus).unwrap());
+
+            ppu.write_register(6, 0x3F, &mut bus).unwrap();
+            ppu.write_register(6, 0x10, &mut bus).unwrap();
+            ppu.write_register(7, 0x2A, &mut bus).unwrap();
+            ppu.write_register(6, 0x3F, &mut bus).unwrap();
+            ppu.write_register(6, 0x00, &mut bus).unwrap();
+            assert_eq!(0x2A, ppu.read_register(7, &mut bus).unwrap() & 0x3F);
+        }
+
+        #[test]
+        pub fn odd_frames_skip_a_dot_while_rendering() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let frame = (ppu::TOTAL_SCANLINES * ppu::DOTS_PER_SCANLINE) as u64;
+            ppu.write_register(1, 0x08, &mut bus).unwrap();
+
+            ppu.step(frame, &mut bus).unwrap();
+            assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+            ppu.step(frame * 2 - 1, &mut bus).unwrap();
+            assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+        }
+
+        #[test]
+        pub fn pal_frames_have_312_scanlines_without_a_skipped_dot() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            let frame = (312 * ppu::DOTS_PER_SCANLINE) as u64;
+            ppu.set_timing(ppu::Timing::pal());
+            ppu.write_register(1, 0x08, &mut bus).unwrap();
+
+            ppu.step(frame * 2, &mut bus).unwrap();
+            assert_eq!((0, 0), (ppu.scanline(), ppu.dot()));
+            assert_eq!(2, ppu.frame());
+        }
+
+        #[test]
+        pub fn dendy_vblank_starts_at_scanline_291() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            ppu.set_timing(ppu::Timing::dendy());
+
+            ppu.step((ppu::VBLANK_SCANLINE * ppu::DOTS_PER_SCANLINE + 2) as u64, &mut bus).unwrap();
+            assert!(!ppu.registers().ppustatus.vertical_blank);
+            ppu.step((291 * ppu::DOTS_PER_SCANLINE + 2) as u64, &mut bus).unwrap();
+            assert!(ppu.registers().ppustatus.vertical_blank);
+        }
+
+        #[test]
+        pub fn greyscale_and_emphasis_are_applied_to_output() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            ppu.write_register(6, 0x3F, &mut bus).unwrap();
+            ppu.write_register(6, 0x00, &mut bus).unwrap();
+            ppu.write_register(7, 0x16, &mut bus).unwrap();
+            ppu.write_register(6, 0x00, &mut bus).unwrap();
+            ppu.write_register(6, 0x00, &mut bus).unwrap();
+
+            ppu.write_register(1, 0x21, &mut bus).unwrap();
+            ppu.step(ppu::DOTS_PER_SCANLINE as u64, &mut bus).unwrap();
+            assert_eq!(0x40 | 0x10, ppu.screen()[0]);
+
+            ppu.set_timing(ppu::Timing::pal());
+            ppu.write_register(1, 0x20, &mut bus).unwrap();
+            ppu.step(ppu::DOTS_PER_SCANLINE as u64 * 2, &mut bus).unwrap();
+            assert_eq!(0x80 | 0x16, ppu.screen()[ppu::PIXELS_PER_SCANLINE]);
+        }
+
+        #[test]
+        pub fn sprite_fetches_from_right_pattern_table_raise_a12_once_per_scanline() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            ppu.write_register(0, 0x08, &mut bus).unwrap();
+            ppu.write_register(1, 0x18, &mut bus).unwrap();
+
+            ppu.step(ppu::DOTS_PER_SCANLINE as u64, &mut bus).unwrap();
+            assert_eq!(1, bus.1);
+        }
+
+        #[test]
+        pub fn nametable_is_fetched_three_times_in_a_row_at_start_of_line() {
+            let mut ppu = Rp2C02::new(None);
+            let mut bus = new_bus();
+            ppu.write_register(1, 0x18, &mut bus).unwrap();
+
+            ppu.step(ppu::DOTS_PER_SCANLINE as u64 * 4, &mut bus).unwrap();
+            let fetches = &bus.2;
+            let repeats = (2..fetches.len())
+                .filter(|&i| fetches[i] & 0xF000 == 0x2000 && fetches[i - 2] == fetches[i] && fetches[i - 1] == fetches[i])
+                .count();
+            assert!(repeats >= 3);
+        }
     }
 }
@@ -0,0 +1,65 @@
+use std::io;
+
+pub use image::png::write_png;
+pub use image::ppm::write_ppm;
+
+/// Provides a writer for the binary PPM ("P6") format
+pub mod ppm;
+
+/// Provides a writer for the PNG format
+pub mod png;
+
+/// Represents an image made up of 24-bit RGB pixels
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    data: Vec<u8>
+}
+
+impl Image {
+    /// Creates an image from `data`, which contains the red, green and blue components of each
+    /// pixel, in rows from top to bottom
+    ///
+    /// # Panics
+    /// Panics if `data` does not contain exactly `width * height` pixels
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Image {
+        assert_eq!(width * height * 3, data.len());
+        Image {
+            width: width,
+            height: height,
+            data: data
+        }
+    }
+
+    /// Gets the width of the image, in pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Gets the height of the image, in pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets the RGB data of the image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Gets the RGB data of row `y` of the image
+    pub fn row(&self, y: usize) -> &[u8] {
+        let stride = self.width * 3;
+        &self.data[y * stride..(y + 1) * stride]
+    }
+
+    /// Writes the image to `out` in the binary PPM format
+    pub fn write_ppm<W>(&self, out: &mut W) -> io::Result<()> where W: io::Write {
+        write_ppm(self, out)
+    }
+
+    /// Writes the image to `out` in the PNG format
+    pub fn write_png<W>(&self, out: &mut W) -> io::Result<()> where W: io::Write {
+        write_png(self, out)
+    }
+}
@@ -0,0 +1,249 @@
+use std::io;
+
+use byteorder::{BigEndian,WriteBytesExt};
+
+use image::Image;
+
+const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Color type 2: each pixel is an RGB triple
+const COLOR_TYPE_RGB: u8 = 2;
+
+/// The scanline filter type which applies no filtering
+const FILTER_NONE: u8 = 0;
+
+/// The size of the window within which the compressor looks for repeated data
+const WINDOW_SIZE: usize = 32768;
+
+/// The number of bits used to hash the three bytes that start a match
+const HASH_BITS: usize = 15;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Writes `image` to `out` in the PNG format
+///
+/// The image data is compressed with a simple LZ77 compressor using the fixed Huffman codes
+/// from the DEFLATE specification, which works well for the large areas of flat color found in
+/// emulator screenshots.
+pub fn write_png<W>(image: &Image, out: &mut W) -> io::Result<()> where W: io::Write {
+    try!(out.write_all(&SIGNATURE));
+
+    let mut header = Vec::with_capacity(13);
+    try!(header.write_u32::<BigEndian>(image.width() as u32));
+    try!(header.write_u32::<BigEndian>(image.height() as u32));
+    header.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]);
+    try!(write_chunk(out, b"IHDR", &header));
+
+    // Each row is prefixed by the filter used to encode it
+    let mut raw = Vec::with_capacity((image.width() * 3 + 1) * image.height());
+    for y in 0..image.height() {
+        raw.push(FILTER_NONE);
+        raw.extend_from_slice(image.row(y));
+    }
+    try!(write_chunk(out, b"IDAT", &zlib(&raw)));
+
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk<W>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> where W: io::Write {
+    try!(out.write_u32::<BigEndian>(data.len() as u32));
+    try!(out.write_all(kind));
+    try!(out.write_all(data));
+    out.write_u32::<BigEndian>(crc32(crc32(0, kind), data))
+}
+
+/// Wraps the DEFLATE-compressed `data` in a zlib stream
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window, no preset dictionary, fastest compression level
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.write_u32::<BigEndian>(adler32(data)).unwrap();
+    out
+}
+
+/// Compresses `data` into a single DEFLATE block using the fixed Huffman codes
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.write_bits(1, 1); // Final block
+    out.write_bits(1, 2); // Fixed Huffman codes
+
+    let mut head = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = if pos + MIN_MATCH <= data.len() {
+            let hash = hash(&data[pos..]);
+            let candidate = head[hash];
+            head[hash] = pos;
+            if candidate != usize::max_value() && pos - candidate <= WINDOW_SIZE {
+                let max = ::std::cmp::min(MAX_MATCH, data.len() - pos);
+                let mut length = 0;
+                while length < max && data[candidate + length] == data[pos + length] {
+                    length += 1;
+                }
+                (length, pos - candidate)
+            } else {
+                (0, 0)
+            }
+        } else {
+            (0, 0)
+        };
+
+        if length >= MIN_MATCH {
+            write_match(&mut out, length, distance);
+            // Index the positions covered by the match so that later data can refer to them
+            for p in (pos + 1)..(pos + length) {
+                if p + MIN_MATCH <= data.len() {
+                    head[hash(&data[p..])] = p;
+                }
+            }
+            pos += length;
+        } else {
+            write_symbol(&mut out, data[pos] as u16);
+            pos += 1;
+        }
+    }
+
+    write_symbol(&mut out, 256); // End of block
+    out.finish()
+}
+
+fn hash(data: &[u8]) -> usize {
+    let val = ((data[0] as usize) << 16) | ((data[1] as usize) << 8) | data[2] as usize;
+    (val.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+fn write_match(out: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_symbol(out, 257 + code as u16);
+    out.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code]);
+
+    let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+    out.write_code(code as u32, 5);
+    out.write_bits((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code]);
+}
+
+/// Writes a literal/length symbol using the fixed Huffman code
+fn write_symbol(out: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    if symbol < 144 {
+        out.write_code(0x30 + symbol, 8);
+    } else if symbol < 256 {
+        out.write_code(0x190 + symbol - 144, 9);
+    } else if symbol < 280 {
+        out.write_code(symbol - 256, 7);
+    } else {
+        out.write_code(0xC0 + symbol - 280, 8);
+    }
+}
+
+/// Writes a bit stream, filling each byte from the least significant bit
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u8
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { out: Vec::new(), acc: 0, bits: 0 }
+    }
+
+    /// Writes the low `count` bits of `val`, least significant bit first
+    fn write_bits(&mut self, val: u32, count: u8) {
+        self.acc |= val << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code of `count` bits, most significant bit first
+    fn write_code(&mut self, code: u32, count: u8) {
+        let reversed = (0..count).fold(0, |acc, i| (acc << 1) | ((code >> i) & 1));
+        self.write_bits(reversed, count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use image::{self,Image};
+    use image::png::{crc32,adler32,deflate};
+
+    #[test]
+    pub fn crc32_matches_reference() {
+        assert_eq!(0xAE426082, crc32(0, b"IEND"));
+        assert_eq!(0xCBF43926, crc32(0, b"123456789"));
+    }
+
+    #[test]
+    pub fn adler32_matches_reference() {
+        assert_eq!(0x11E60398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    pub fn deflate_encodes_literals_with_fixed_codes() {
+        // Matches the output of zlib for the same input with fixed codes
+        assert_eq!(vec![0x4B, 0x4C, 0x4A, 0x06, 0x00], deflate(b"abc"));
+    }
+
+    #[test]
+    pub fn deflate_compresses_repeated_data() {
+        let data = vec![0x42; 10000];
+        assert!(deflate(&data).len() < 100);
+    }
+
+    #[test]
+    pub fn png_has_signature_and_chunks() {
+        let img = Image::new(2, 2, vec![0xFF; 12]);
+        let mut out = Vec::new();
+        image::write_png(&img, &mut out).unwrap();
+
+        assert_eq!(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A], &out[0..8]);
+        assert_eq!(b"IHDR", &out[12..16]);
+        assert_eq!(&[0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0], &out[16..29]);
+        assert_eq!(b"IEND", &out[out.len() - 8..out.len() - 4]);
+    }
+}
@@ -0,0 +1,23 @@
+use std::io;
+
+use image::Image;
+
+/// Writes `image` to `out` in the binary PPM ("P6") format
+pub fn write_ppm<W>(image: &Image, out: &mut W) -> io::Result<()> where W: io::Write {
+    try!(write!(out, "P6\n{} {}\n255\n", image.width(), image.height()));
+    out.write_all(image.data())
+}
+
+#[cfg(test)]
+mod test {
+    use image::{self,Image};
+
+    #[test]
+    pub fn ppm_has_header_followed_by_pixels() {
+        let img = Image::new(2, 1, vec![1, 2, 3, 4, 5, 6]);
+        let mut out = Vec::new();
+        image::write_ppm(&img, &mut out).unwrap();
+
+        assert_eq!(b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec(), out);
+    }
+}
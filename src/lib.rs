@@ -40,4 +40,10 @@ pub mod pc;
 pub mod systems;
 
 /// Contains code to manage clock cycles
-pub mod clock;
\ No newline at end of file
+pub mod clock;
+
+/// Contains code to write images, such as screenshots of emulated systems
+pub mod image;
+
+/// Contains code to synthesize and filter audio, such as the output of emulated sound hardware
+pub mod audio;
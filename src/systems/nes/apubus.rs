@@ -0,0 +1,48 @@
+use mem;
+use hw::rp2A03;
+use systems::nes;
+
+/// Represents the parts of the system reachable by the APU
+///
+/// The DMC can only read samples from $8000-$FFFF, so only the cartridge PRG is visible. The
+/// cartridge also provides any expansion audio.
+pub struct ApuBus<'a> {
+    cart: Option<&'a mut nes::Cartridge>
+}
+
+impl<'a> ApuBus<'a> {
+    /// Constructs a new `ApuBus` over the provided cartridge (if any)
+    pub fn new(cart: Option<&'a mut nes::Cartridge>) -> ApuBus<'a> {
+        ApuBus {
+            cart: cart
+        }
+    }
+}
+
+impl<'a> mem::Memory for ApuBus<'a> {
+    fn len(&self) -> u64 { 0x10000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        match self.cart {
+            Some(ref cart) if addr >= 0x8000 => cart.mapper.prg().get_u8(addr),
+            _ => Err(mem::Error::new(
+                mem::ErrorKind::MemoryNotPresent,
+                "Attempted to read a DMC sample, but there is no cartridge present"))
+        }
+    }
+
+    fn set_u8(&mut self, _addr: u64, _val: u8) -> mem::Result<()> {
+        Err(mem::Error::new(
+            mem::ErrorKind::MemoryNotWritable,
+            "The APU does not write to memory"))
+    }
+}
+
+impl<'a> rp2A03::Bus for ApuBus<'a> {
+    fn expansion_audio(&mut self) -> f32 {
+        match self.cart {
+            Some(ref mut cart) => cart.mapper.expansion_audio(),
+            None => 0.0
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the AxROM boards (iNES mapper 7)
+///
+/// The latch selects the 32K PRG ROM bank in bits 0-2, and which nametable is used for the
+/// whole screen in bit 4. The 8K of CHR RAM is not banked.
+pub struct AxRom {
+    board: Board
+}
+
+impl AxRom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, bus_conflicts: bool, logger: Option<slog::Logger>) -> AxRom {
+        AxRom {
+            board: Board::new("AxROM", prg_rom, 0x8000, chr_rom, 0x2000, chr_ram_size, nes::Mirroring::SingleScreenLower, bus_conflicts, unwrap_logger!(logger))
+        }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.prg_rom.select(0, (val & 0x07) as usize);
+        self.board.mirroring = if val & 0x10 != 0 {
+            nes::Mirroring::SingleScreenUpper
+        } else {
+            nes::Mirroring::SingleScreenLower
+        };
+    }
+}
+
+discrete_mapper!(AxRom);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::axrom::AxRom;
+
+    #[test]
+    pub fn latch_selects_prg_bank() {
+        let mut axrom = AxRom::new(numbered_banks(8, 0x8000), Vec::new(), 0x2000, false, None);
+        assert_eq!(Ok(0), axrom.get_u8(0x8000));
+
+        axrom.set_u8(0x8000, 0x06).unwrap();
+        assert_eq!(Ok(6), axrom.get_u8(0x8000));
+    }
+
+    #[test]
+    pub fn latch_selects_single_screen_nametable() {
+        let mut axrom = AxRom::new(numbered_banks(8, 0x8000), Vec::new(), 0x2000, false, None);
+        assert_eq!(Mirroring::SingleScreenLower, axrom.mirroring());
+
+        axrom.set_u8(0x8000, 0x11).unwrap();
+        assert_eq!(Mirroring::SingleScreenUpper, axrom.mirroring());
+        assert_eq!(Ok(1), axrom.get_u8(0x8000));
+    }
+}
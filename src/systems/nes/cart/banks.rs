@@ -0,0 +1,209 @@
+use mem;
+
+/// Represents ROM or RAM on a cartridge which is divided into equally sized banks, a number of
+/// which are mapped into consecutive slots of the address space at a time
+///
+/// Addresses are relative to the start of the first slot. Bank numbers wrap around the number
+/// of banks present, as the unused high bank select lines on a board are not connected.
+pub struct Banks {
+    data: Vec<u8>,
+    bank_size: usize,
+    slots: Vec<usize>,
+    writable: bool
+}
+
+impl Banks {
+    /// Creates a set of `slots` slots which map banks of `bank_size` bytes from `data`,
+    /// initially mapping the first bank into every slot
+    ///
+    /// `data` is padded with zeros to a whole number of banks (and at least one bank).
+    pub fn new(mut data: Vec<u8>, bank_size: usize, slots: usize, writable: bool) -> Banks {
+        let banks = ::std::cmp::max((data.len() + bank_size - 1) / bank_size, 1);
+        data.resize(banks * bank_size, 0);
+        Banks {
+            data: data,
+            bank_size: bank_size,
+            slots: vec![0; slots],
+            writable: writable
+        }
+    }
+
+    /// Creates a set of slots which map banks of zero-filled RAM of `size` bytes
+    ///
+    /// If `size` is 0 there is no RAM, and the banks are empty and can't be written.
+    pub fn ram(size: usize, bank_size: usize, slots: usize) -> Banks {
+        if size == 0 {
+            return Banks {
+                data: Vec::new(),
+                bank_size: bank_size,
+                slots: vec![0; slots],
+                writable: false
+            };
+        }
+        Banks::new(vec![0; size], bank_size, slots, true)
+    }
+
+    /// Determines whether there is no memory in the banks, which only RAM of size 0 has
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Gets the contents of every bank, if the banks are RAM
+    pub fn ram_data(&self) -> Option<&[u8]> {
+        if self.writable { Some(&self.data) } else { None }
+    }
+
+    /// Gets the contents of every bank for modification, if the banks are RAM
+    pub fn ram_data_mut(&mut self) -> Option<&mut [u8]> {
+        if self.writable { Some(&mut self.data) } else { None }
+    }
+
+    /// Gets the number of banks available
+    pub fn bank_count(&self) -> usize {
+        self.data.len() / self.bank_size
+    }
+
+    /// Gets the total size of the banks, in bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Gets the number of slots the banks are mapped into
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Gets the bank mapped into `slot`
+    pub fn bank(&self, slot: usize) -> usize {
+        self.slots[slot]
+    }
+
+    /// Gets the byte at `offset` from the start of the first bank, ignoring the slots
+    ///
+    /// Offsets wrap around the total size, as bank numbers do. Empty banks read as 0.
+    pub fn get_absolute(&self, offset: usize) -> u8 {
+        match self.data.len() {
+            0 => 0,
+            size => self.data[offset % size]
+        }
+    }
+
+    /// Maps `bank` into `slot`
+    pub fn select(&mut self, slot: usize, bank: usize) {
+        self.slots[slot] = match self.bank_count() {
+            0 => 0,
+            count => bank % count
+        };
+    }
+
+    /// Gets the value that reaches the cartridge when the CPU writes `val` to `addr` on a
+    /// board with bus conflicts
+    ///
+    /// The ROM drives the data bus at the same time as the CPU, and since a 0 bit wins, only
+    /// the bits which are set in both values are seen.
+    pub fn bus_conflict(&self, addr: u64, val: u8) -> u8 {
+        match self.offset(addr) {
+            Some(offset) => val & self.data[offset],
+            None => val
+        }
+    }
+
+    fn offset(&self, addr: u64) -> Option<usize> {
+        let slot = addr as usize / self.bank_size;
+        if slot < self.slots.len() && !self.data.is_empty() {
+            Some(self.slots[slot] * self.bank_size + (addr as usize % self.bank_size))
+        } else {
+            None
+        }
+    }
+
+    fn out_of_bounds(&self, addr: u64) -> mem::Error {
+        mem::Error::with_detail(
+            mem::ErrorKind::OutOfBounds,
+            "memory access out of range of banked memory",
+            format!("${:04X} is beyond the {} slots of ${:04X} bytes", addr, self.slots.len(), self.bank_size))
+    }
+}
+
+impl mem::Memory for Banks {
+    fn len(&self) -> u64 {
+        (self.slots.len() * self.bank_size) as u64
+    }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        match self.offset(addr) {
+            Some(offset) => Ok(self.data[offset]),
+            None => Err(self.out_of_bounds(addr))
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if !self.writable {
+            return Err(mem::Error::with_detail(
+                mem::ErrorKind::MemoryNotWritable,
+                "cannot write to cartridge ROM",
+                format!("${:04X} is in read-only banked memory", addr)));
+        }
+        match self.offset(addr) {
+            Some(offset) => {
+                self.data[offset] = val;
+                Ok(())
+            },
+            None => Err(self.out_of_bounds(addr))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::cart::banks::Banks;
+
+    #[test]
+    pub fn slots_map_selected_banks() {
+        let mut banks = Banks::new(vec![0, 1, 2, 3], 1, 2, false);
+        banks.select(0, 3);
+        banks.select(1, 2);
+
+        assert_eq!(Ok(3), banks.get_u8(0));
+        assert_eq!(Ok(2), banks.get_u8(1));
+        assert!(banks.get_u8(2).is_err());
+    }
+
+    #[test]
+    pub fn bank_numbers_wrap_around() {
+        let mut banks = Banks::new(vec![0, 1, 2], 2, 1, false);
+        assert_eq!(2, banks.bank_count());
+        banks.select(0, 5);
+
+        assert_eq!(Ok(2), banks.get_u8(0));
+        assert_eq!(Ok(0), banks.get_u8(1));
+    }
+
+    #[test]
+    pub fn only_ram_is_writable() {
+        let mut rom = Banks::new(vec![0; 4], 4, 1, false);
+        let mut ram = Banks::ram(4, 4, 1);
+
+        assert!(rom.set_u8(0, 1).is_err());
+        ram.set_u8(0, 1).unwrap();
+        assert_eq!(Ok(1), ram.get_u8(0));
+    }
+
+    #[test]
+    pub fn ram_of_size_zero_is_empty() {
+        let mut ram = Banks::ram(0, 4, 1);
+        ram.select(0, 1);
+
+        assert!(ram.is_empty());
+        assert_eq!(None, ram.ram_data());
+        assert!(ram.get_u8(0).is_err());
+        assert!(ram.set_u8(0, 1).is_err());
+    }
+
+    #[test]
+    pub fn absolute_reads_of_empty_banks_are_zero() {
+        let ram = Banks::ram(0, 4, 1);
+        assert_eq!(0, ram.get_absolute(5));
+    }
+}
@@ -0,0 +1,42 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the BNROM board (iNES mapper 34, submapper 2)
+///
+/// The latch selects the 32K PRG ROM bank. The 8K of CHR RAM is not banked.
+pub struct BnRom {
+    board: Board
+}
+
+impl BnRom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, logger: Option<slog::Logger>) -> BnRom {
+        BnRom {
+            board: Board::new("BNROM", prg_rom, 0x8000, chr_rom, 0x2000, chr_ram_size, mirroring, bus_conflicts, unwrap_logger!(logger))
+        }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.prg_rom.select(0, val as usize);
+    }
+}
+
+discrete_mapper!(BnRom);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::Mirroring;
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::bnrom::BnRom;
+
+    #[test]
+    pub fn latch_selects_prg_bank() {
+        let mut bnrom = BnRom::new(numbered_banks(4, 0x8000), Vec::new(), 0x2000, Mirroring::Vertical, false, None);
+        bnrom.set_u8(0xFFFF, 3).unwrap();
+
+        assert_eq!(Ok(3), bnrom.get_u8(0x8000));
+    }
+}
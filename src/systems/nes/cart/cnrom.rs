@@ -0,0 +1,52 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the CNROM board (iNES mapper 3)
+///
+/// The latch selects the 8K CHR ROM bank. The 16K or 32K of PRG ROM is not banked.
+pub struct CnRom {
+    board: Board
+}
+
+impl CnRom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, logger: Option<slog::Logger>) -> CnRom {
+        CnRom {
+            board: Board::new("CNROM", prg_rom, 0x4000, chr_rom, 0x2000, chr_ram_size, mirroring, bus_conflicts, unwrap_logger!(logger))
+        }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.chr.select(0, val as usize);
+    }
+}
+
+discrete_mapper!(CnRom);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::cnrom::CnRom;
+
+    #[test]
+    pub fn latch_selects_chr_bank() {
+        let mut cnrom = CnRom::new(numbered_banks(2, 0x4000), numbered_banks(4, 0x2000), 0x2000, Mirroring::Vertical, false, None);
+        assert_eq!(Ok(0), cnrom.chr().get_u8(0x0000));
+
+        cnrom.set_u8(0x8000, 3).unwrap();
+        assert_eq!(Ok(3), cnrom.chr().get_u8(0x0000));
+        assert_eq!(Ok(0), cnrom.get_u8(0x8000));
+        assert_eq!(Ok(1), cnrom.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn small_prg_is_mirrored() {
+        let cnrom = CnRom::new(numbered_banks(1, 0x4000), numbered_banks(4, 0x2000), 0x2000, Mirroring::Vertical, false, None);
+
+        assert_eq!(Ok(0), cnrom.get_u8(0xC000));
+    }
+}
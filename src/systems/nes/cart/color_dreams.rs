@@ -0,0 +1,44 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the boards made by Color Dreams (iNES mapper 11)
+///
+/// The latch selects the 32K PRG ROM bank in bits 0-1, and the 8K CHR ROM bank in bits 4-7.
+pub struct ColorDreams {
+    board: Board
+}
+
+impl ColorDreams {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, logger: Option<slog::Logger>) -> ColorDreams {
+        ColorDreams {
+            board: Board::new("Color Dreams", prg_rom, 0x8000, chr_rom, 0x2000, chr_ram_size, mirroring, bus_conflicts, unwrap_logger!(logger))
+        }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.prg_rom.select(0, (val & 0x03) as usize);
+        self.board.chr.select(0, (val >> 4) as usize);
+    }
+}
+
+discrete_mapper!(ColorDreams);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::color_dreams::ColorDreams;
+
+    #[test]
+    pub fn latch_selects_prg_and_chr_banks() {
+        let mut board = ColorDreams::new(numbered_banks(4, 0x8000), numbered_banks(16, 0x2000), 0x2000, Mirroring::Vertical, false, None);
+        board.set_u8(0x8000, 0xA3).unwrap();
+
+        assert_eq!(Ok(3), board.get_u8(0x8000));
+        assert_eq!(Ok(10), board.chr().get_u8(0x0000));
+    }
+}
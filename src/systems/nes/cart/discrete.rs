@@ -0,0 +1,171 @@
+use slog;
+
+use mem;
+use mem::Memory;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+
+/// Represents the parts shared by boards built from discrete logic chips: PRG ROM at
+/// $8000-$FFFF, CHR ROM (or RAM), and a latch which is loaded by writing anywhere in the ROM
+///
+/// These boards have no PRG RAM, so $6000-$7FFF is open bus.
+pub struct Board {
+    pub prg_rom: Banks,
+    pub chr: Banks,
+    pub mirroring: nes::Mirroring,
+    bus_conflicts: bool,
+    name: &'static str,
+    log: slog::Logger
+}
+
+impl Board {
+    /// Creates a board whose PRG ROM is switched in banks of `prg_bank_size` bytes, and whose
+    /// CHR is switched in banks of `chr_bank_size` bytes
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead. When the
+    /// board has `bus_conflicts`, the ROM drives the data bus while the latch is written.
+    pub fn new(name: &'static str, prg_rom: Vec<u8>, prg_bank_size: usize, chr_rom: Vec<u8>, chr_bank_size: usize, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, log: slog::Logger) -> Board {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, chr_bank_size, 0x2000 / chr_bank_size)
+        } else {
+            Banks::new(chr_rom, chr_bank_size, 0x2000 / chr_bank_size, false)
+        };
+        let mut board = Board {
+            prg_rom: Banks::new(prg_rom, prg_bank_size, 0x8000 / prg_bank_size, false),
+            chr: chr,
+            mirroring: mirroring,
+            bus_conflicts: bus_conflicts,
+            name: name,
+            log: log.new(o!("mapper" => name, "cartridge" => true))
+        };
+        for slot in 0..(0x8000 / prg_bank_size) {
+            board.prg_rom.select(slot, slot);
+        }
+        for slot in 0..(0x2000 / chr_bank_size) {
+            board.chr.select(slot, slot);
+        }
+        board
+    }
+
+    /// Gets the name of the board
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Handles a read from the CPU
+    pub fn read(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            // Nothing drives the data bus, so it keeps the high byte of the address
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Open Bus",
+                "action" => "read");
+            Ok((addr >> 8) as u8)
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank(eaddr as usize * self.prg_rom.slots() / 0x8000),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    /// Handles a write from the CPU, returning the value loaded into the latch if the write
+    /// was to the ROM
+    pub fn write(&mut self, addr: u64, val: u8) -> mem::Result<Option<u8>> {
+        if addr < 0x6000 {
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Unmapped",
+                "action" => "write");
+            Ok(None)
+        } else {
+            let val = if self.bus_conflicts { self.prg_rom.bus_conflict(addr - 0x8000, val) } else { val };
+            trace!(self.log,
+                "latch write";
+                "vaddr" => format!("${:04X}", addr),
+                "val" => format!("${:02X}", val));
+            Ok(Some(val))
+        }
+    }
+}
+
+/// Implements `Mapper`, and `Memory` for the PRG, on a discrete logic board type which has a
+/// `board` field and a `latch` method to handle the values loaded into the latch
+macro_rules! discrete_mapper {
+    ($t:ident) => {
+        impl nes::Mapper for $t {
+            fn name(&self) -> &'static str { self.board.name() }
+
+            fn mirroring(&self) -> nes::Mirroring { self.board.mirroring }
+
+            fn prg(&self) -> &mem::Memory
+            {
+                return self;
+            }
+
+            fn prg_mut(&mut self) -> &mut mem::Memory
+            {
+                return self;
+            }
+
+            fn chr(&self) -> &mem::Memory
+            {
+                return &self.board.chr;
+            }
+
+            fn chr_mut(&mut self) -> &mut mem::Memory
+            {
+                return &mut self.board.chr;
+            }
+
+            fn chr_ram(&self) -> Option<&[u8]> {
+                self.board.chr.ram_data()
+            }
+
+            fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+                self.board.chr.ram_data_mut()
+            }
+        }
+
+        impl mem::Memory for $t {
+            fn len(&self) -> u64 { 0xA000 }
+
+            fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+                self.board.read(addr)
+            }
+
+            fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+                if let Some(val) = try!(self.board.write(addr, val)) {
+                    self.latch(val);
+                }
+                Ok(())
+            }
+        }
+    }
+}
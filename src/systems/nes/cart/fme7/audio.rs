@@ -0,0 +1,277 @@
+/// The number of CPU cycles per clock of the tone, noise and envelope generators, which are
+/// clocked at the CPU's clock divided by 16
+const CLOCK_DIVIDER: u32 = 16;
+
+/// The output level of a channel at full volume, relative to the APU's mixed output
+const CHANNEL_LEVEL: f32 = 0.15;
+
+/// Represents one of the three square wave tone generators
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+    volume: u8
+}
+
+impl Tone {
+    fn new() -> Tone {
+        Tone {
+            period: 0,
+            counter: 0,
+            high: false,
+            volume: 0
+        }
+    }
+
+    fn clock(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+/// Represents the sound hardware in the Sunsoft 5B (an FME-7 with audio), which is compatible
+/// with the General Instrument AY-3-8910: three square wave channels, which may each be mixed
+/// with a shared noise generator and use a shared envelope
+///
+/// The 5B's envelope has 32 steps rather than the AY-3-8910's 16, and each step of a channel's
+/// volume is 3dB.
+pub struct Audio {
+    address: u8,
+    tones: [Tone; 3],
+    mixer: u8,
+    /// The channels which use the envelope rather than their fixed volume
+    envelope_channels: u8,
+
+    noise_period: u8,
+    noise_counter: u8,
+    noise_lfsr: u32,
+    noise_half: bool,
+
+    envelope_period: u16,
+    envelope_counter: u32,
+    envelope_shape: u8,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+
+    divider: u32,
+    levels: [f32; 32]
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        // Each of the 32 levels is 1.5dB, and the lowest is silent
+        let mut levels = [0.0; 32];
+        for (level, amplitude) in levels.iter_mut().enumerate().skip(1) {
+            *amplitude = 10.0f32.powf((level as f32 - 31.0) * 1.5 / 20.0) * CHANNEL_LEVEL;
+        }
+        Audio {
+            address: 0,
+            tones: [Tone::new(), Tone::new(), Tone::new()],
+            mixer: 0,
+            envelope_channels: 0,
+            noise_period: 0,
+            noise_counter: 0,
+            noise_lfsr: 1,
+            noise_half: false,
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+            divider: CLOCK_DIVIDER,
+            levels: levels
+        }
+    }
+
+    /// Selects the register written by `write_data` ($C000-$DFFF)
+    pub fn write_address(&mut self, val: u8) {
+        self.address = val;
+    }
+
+    /// Writes to the selected register ($E000-$FFFF)
+    pub fn write_data(&mut self, val: u8) {
+        match self.address {
+            0x00 | 0x02 | 0x04 => {
+                let tone = &mut self.tones[(self.address >> 1) as usize];
+                tone.period = (tone.period & 0xF00) | val as u16;
+            },
+            0x01 | 0x03 | 0x05 => {
+                let tone = &mut self.tones[(self.address >> 1) as usize];
+                tone.period = (tone.period & 0x0FF) | (((val & 0x0F) as u16) << 8);
+            },
+            0x06 => self.noise_period = val & 0x1F,
+            0x07 => self.mixer = val,
+            0x08 | 0x09 | 0x0A => {
+                let channel = (self.address - 0x08) as usize;
+                self.tones[channel].volume = val & 0x0F;
+                if val & 0x10 != 0 {
+                    self.envelope_channels |= 1 << channel;
+                } else {
+                    self.envelope_channels &= !(1 << channel);
+                }
+            },
+            0x0B => self.envelope_period = (self.envelope_period & 0xFF00) | val as u16,
+            0x0C => self.envelope_period = (self.envelope_period & 0x00FF) | ((val as u16) << 8),
+            0x0D => {
+                self.envelope_shape = val & 0x0F;
+                self.envelope_step = 0;
+                self.envelope_attack = val & 0x04 != 0;
+                self.envelope_holding = false;
+                self.envelope_counter = 0;
+            },
+            // Registers $E and $F are the I/O ports, which are not connected
+            _ => {}
+        }
+    }
+
+    /// Clocks the generators for one CPU cycle, and gets the output level relative to the
+    /// APU's mixed output
+    pub fn clock(&mut self) -> f32 {
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = CLOCK_DIVIDER;
+            for tone in self.tones.iter_mut() {
+                tone.clock();
+            }
+            self.clock_noise();
+            self.clock_envelope();
+        }
+        self.level()
+    }
+
+    /// Clocks the noise generator, a 17-bit LFSR which is shifted at half the rate of the tone
+    /// generators
+    fn clock_noise(&mut self) {
+        self.noise_half = !self.noise_half;
+        if self.noise_half {
+            return;
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period {
+            self.noise_counter = 0;
+            let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 0x01;
+            self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 16);
+        }
+    }
+
+    /// Clocks the envelope, which steps through 32 levels and then stops, repeats or reverses
+    /// according to its shape
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        // Each of the 32 steps takes half the time of the AY-3-8910's 16
+        self.envelope_counter += 2;
+        if self.envelope_counter < ::std::cmp::max(self.envelope_period, 1) as u32 {
+            return;
+        }
+        self.envelope_counter = 0;
+
+        self.envelope_step += 1;
+        if self.envelope_step == 32 {
+            let shape = self.envelope_shape;
+            if shape & 0x08 == 0 {
+                // Without continue, the envelope falls silent
+                self.envelope_holding = true;
+                self.envelope_step = 31;
+                self.envelope_attack = false;
+            } else if shape & 0x01 != 0 {
+                self.envelope_holding = true;
+                self.envelope_step = 31;
+                if shape & 0x02 != 0 {
+                    self.envelope_attack = !self.envelope_attack;
+                }
+            } else {
+                self.envelope_step = 0;
+                if shape & 0x02 != 0 {
+                    self.envelope_attack = !self.envelope_attack;
+                }
+            }
+        }
+    }
+
+    fn level(&self) -> f32 {
+        let envelope = if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        };
+        let noise = self.noise_lfsr & 0x01 != 0;
+
+        let mut level = 0.0;
+        for (channel, tone) in self.tones.iter().enumerate() {
+            // A channel's output is high while neither its enabled tone nor enabled noise is low
+            let tone_high = tone.high || self.mixer & (0x01 << channel) != 0;
+            let noise_high = noise || self.mixer & (0x08 << channel) != 0;
+            if !(tone_high && noise_high) {
+                continue;
+            }
+            let volume = if self.envelope_channels & (1 << channel) != 0 {
+                envelope
+            } else if tone.volume == 0 {
+                0
+            } else {
+                tone.volume * 2 + 1
+            };
+            level += self.levels[volume as usize];
+        }
+        level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::fme7::audio::Audio;
+
+    fn write(audio: &mut Audio, reg: u8, val: u8) {
+        audio.write_address(reg);
+        audio.write_data(val);
+    }
+
+    fn levels(audio: &mut Audio, cycles: usize) -> Vec<f32> {
+        (0..cycles).map(|_| audio.clock()).collect()
+    }
+
+    #[test]
+    pub fn tone_period_is_in_units_of_16_cycles() {
+        let mut audio = Audio::new();
+        write(&mut audio, 0x07, 0x3E);
+        write(&mut audio, 0x00, 0x04);
+        write(&mut audio, 0x08, 0x0F);
+
+        let levels = levels(&mut audio, 256);
+        let changes = levels.windows(2).filter(|w| w[0] != w[1]).count();
+        assert_eq!(4, changes);
+        assert_eq!(0.15, levels.iter().cloned().fold(0.0, f32::max));
+    }
+
+    #[test]
+    pub fn disabled_channels_output_their_volume() {
+        let mut audio = Audio::new();
+        write(&mut audio, 0x07, 0x3F);
+        write(&mut audio, 0x08, 0x0E);
+        write(&mut audio, 0x09, 0x0F);
+
+        let level = audio.clock();
+        assert!(level > 0.15 && level < 0.3);
+    }
+
+    #[test]
+    pub fn envelope_decays_and_holds() {
+        let mut audio = Audio::new();
+        write(&mut audio, 0x07, 0x3F);
+        write(&mut audio, 0x08, 0x10);
+        write(&mut audio, 0x0B, 0x02);
+        write(&mut audio, 0x0D, 0x09);
+
+        assert_eq!(0.15, audio.clock());
+        let levels = levels(&mut audio, 16 * 32);
+        assert!(levels.windows(2).all(|w| w[1] <= w[0]));
+        assert_eq!(0.0, levels[16 * 32 - 1]);
+    }
+}
@@ -0,0 +1,347 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use self::audio::Audio;
+
+mod audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Emulates the Sunsoft FME-7 and 5B (iNES mapper 69)
+///
+/// Sixteen registers are written through a command/parameter pair at $8000/$A000: eight 1K
+/// CHR banks, four 8K PRG banks (the first at $6000, which may map PRG RAM instead of ROM), the
+/// mirroring, and a 16-bit IRQ counter which is decremented on every CPU cycle. The 5B adds
+/// three channels of audio, whose registers are written through $C000/$E000.
+pub struct Fme7 {
+    command: u8,
+    registers: [u8; 13],
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_pending: bool,
+
+    audio: Audio,
+
+    prg_rom: Banks,
+    prg_ram: Option<Banks>,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Fme7 {
+    /// Creates an FME-7 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, logger: Option<slog::Logger>) -> Fme7 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+        let prg_ram = if prg_ram_size == 0 {
+            None
+        } else {
+            Some(Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1))
+        };
+
+        let mut fme7 = Fme7 {
+            command: 0,
+            registers: [0, 1, 2, 3, 4, 5, 6, 7, 0, 0, 1, 2, 0],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_pending: false,
+            audio: Audio::new(),
+            // The first slot is $6000-$7FFF
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 5, false),
+            prg_ram: prg_ram,
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => "FME-7", "cartridge" => true))
+        };
+        fme7.update_banks();
+        fme7
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr & 0xE000),
+            "val" => format!("${:02X}", val));
+
+        match addr & 0xE000 {
+            0x8000 => self.command = val & 0x0F,
+            0xA000 => match self.command {
+                0x0D => {
+                    self.irq_enabled = val & 0x01 != 0;
+                    self.irq_counter_enabled = val & 0x80 != 0;
+                    self.irq_pending = false;
+                },
+                0x0E => self.irq_counter = (self.irq_counter & 0xFF00) | val as u16,
+                0x0F => self.irq_counter = (self.irq_counter & 0x00FF) | ((val as u16) << 8),
+                command => self.registers[command as usize] = val
+            },
+            0xC000 => self.audio.write_address(val),
+            _ => self.audio.write_data(val)
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        for slot in 0..8 {
+            self.chr.select(slot, self.registers[slot] as usize);
+        }
+
+        let last = self.prg_rom.bank_count() - 1;
+        for slot in 0..4 {
+            self.prg_rom.select(slot, (self.registers[8 + slot] & 0x3F) as usize);
+        }
+        self.prg_rom.select(4, last);
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.registers[8] & 0x40 != 0
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.registers[8] & 0x80 != 0
+    }
+}
+
+impl nes::Mapper for Fme7 {
+    fn name(&self) -> &'static str { "FME-7" }
+
+    fn mirroring(&self) -> nes::Mirroring {
+        match self.registers[12] & 0x03 {
+            0 => nes::Mirroring::Vertical,
+            1 => nes::Mirroring::Horizontal,
+            2 => nes::Mirroring::SingleScreenLower,
+            _ => nes::Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.as_ref().and_then(|ram| ram.ram_data())
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.as_mut().and_then(|ram| ram.ram_data_mut())
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn clock(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                trace!(self.log, "irq");
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+impl mem::Memory for Fme7 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on FME-7 cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 && self.ram_selected() {
+            match self.prg_ram {
+                Some(ref ram) if self.ram_enabled() => {
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "RAM",
+                        "action" => "read");
+                    ram.get_u8(addr - 0x6000)
+                },
+                _ => {
+                    // Nothing drives the data bus, so it keeps the high byte of the address
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "Open Bus",
+                        "action" => "read");
+                    Ok((addr >> 8) as u8)
+                }
+            }
+        } else {
+            let eaddr = addr - 0x6000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on FME-7 cartridge",
+                format!("${:4X} is below the addressable range on FME-7 cartridge", addr)))
+        } else if addr < 0x8000 {
+            let writable = self.ram_selected() && self.ram_enabled();
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => if writable { "RAM" } else { "Unmapped" },
+                "action" => "write");
+            match self.prg_ram {
+                Some(ref mut ram) if writable => ram.set_u8(addr - 0x6000, val),
+                _ => Ok(())
+            }
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::{cartridge,numbered_banks};
+    use systems::nes::cart::fme7::Fme7;
+    use systems::nes::memmap::MemoryMap;
+
+    fn fme7() -> Fme7 {
+        Fme7::new(numbered_banks(32, 0x2000), numbered_banks(64, 0x0400), 0x2000, 0x2000, None)
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, val: u8) {
+        fme7.set_u8(0x8000, command).unwrap();
+        fme7.set_u8(0xA000, val).unwrap();
+    }
+
+    #[test]
+    pub fn commands_select_banks() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x03, 40);
+        command(&mut fme7, 0x09, 3);
+        command(&mut fme7, 0x0B, 5);
+
+        assert_eq!(Ok(40), fme7.chr().get_u8(0x0C00));
+        assert_eq!(Ok(3), fme7.get_u8(0x8000));
+        assert_eq!(Ok(5), fme7.get_u8(0xC000));
+        assert_eq!(Ok(31), fme7.get_u8(0xE000));
+    }
+
+    #[test]
+    pub fn first_prg_bank_maps_rom_or_ram() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x08, 7);
+        assert_eq!(Ok(7), fme7.get_u8(0x6000));
+
+        command(&mut fme7, 0x08, 0x40);
+        assert_eq!(Ok(0x60), fme7.get_u8(0x6000));
+
+        command(&mut fme7, 0x08, 0xC0);
+        fme7.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x42), fme7.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn mirroring_command_selects_mirroring() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x0C, 2);
+        assert_eq!(Mirroring::SingleScreenLower, fme7.mirroring());
+    }
+
+    #[test]
+    pub fn irq_is_raised_when_counter_wraps() {
+        let mut fme7 = fme7();
+        command(&mut fme7, 0x0E, 0x01);
+        command(&mut fme7, 0x0F, 0x00);
+        command(&mut fme7, 0x0D, 0x81);
+
+        fme7.clock();
+        assert!(!fme7.irq());
+        fme7.clock();
+        assert!(fme7.irq());
+
+        command(&mut fme7, 0x0D, 0x81);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    pub fn irq_is_raised_counter_cycles_after_reload() {
+        let mut mem = MemoryMap::new(None);
+        mem.load(cartridge(69));
+        mem.set_u8(0x8000, 0x0D).unwrap();
+        mem.set_u8(0xA000, 0x01).unwrap();
+
+        // Cycles before the reload are counted before it's written
+        mem.set_cycle(1000);
+        for &(command, val) in &[(0x0E, 10), (0x0F, 0), (0x0D, 0x81)] {
+            mem.set_u8(0x8000, command).unwrap();
+            mem.set_u8(0xA000, val).unwrap();
+        }
+
+        // The counter is decremented from 10 on the write's own cycle, and wraps on the 11th
+        mem.run_cart(1010);
+        assert!(!mem.irq());
+        mem.run_cart(1011);
+        assert!(mem.irq());
+    }
+}
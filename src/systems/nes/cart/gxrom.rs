@@ -0,0 +1,44 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the GxROM and MxROM boards (iNES mapper 66)
+///
+/// The latch selects the 32K PRG ROM bank in bits 4-5, and the 8K CHR ROM bank in bits 0-1.
+pub struct GxRom {
+    board: Board
+}
+
+impl GxRom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, logger: Option<slog::Logger>) -> GxRom {
+        GxRom {
+            board: Board::new("GxROM", prg_rom, 0x8000, chr_rom, 0x2000, chr_ram_size, mirroring, bus_conflicts, unwrap_logger!(logger))
+        }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.prg_rom.select(0, ((val >> 4) & 0x03) as usize);
+        self.board.chr.select(0, (val & 0x03) as usize);
+    }
+}
+
+discrete_mapper!(GxRom);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::gxrom::GxRom;
+
+    #[test]
+    pub fn latch_selects_prg_and_chr_banks() {
+        let mut gxrom = GxRom::new(numbered_banks(4, 0x8000), numbered_banks(4, 0x2000), 0x2000, Mirroring::Vertical, false, None);
+        gxrom.set_u8(0x8000, 0x21).unwrap();
+
+        assert_eq!(Ok(2), gxrom.get_u8(0x8000));
+        assert_eq!(Ok(1), gxrom.chr().get_u8(0x0000));
+    }
+}
@@ -0,0 +1,487 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+
+/// The value of the shift register when it is empty, a marker bit which reaches bit 0 once
+/// four bits have been shifted in
+const SHIFT_RESET: u8 = 0x10;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// The size of PRG ROM above which bit 4 of the CHR bank registers selects the 256K half of
+/// PRG ROM (SUROM and SXROM)
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// Emulates the Nintendo MMC1 (iNES mapper 1) and the SxROM boards built around it
+///
+/// The registers are written one bit at a time through a serial port at $8000-$FFFF: five
+/// writes shift a value in, and the address of the final write selects the register it is
+/// loaded into. On the larger boards, the CHR bank registers also drive the upper PRG ROM
+/// and PRG RAM address lines:
+///
+/// * SNROM: bit 4 disables PRG RAM
+/// * SOROM: bit 3 selects the 8K bank of PRG RAM out of 16K
+/// * SUROM: bit 4 selects the 256K half of PRG ROM
+/// * SXROM: bits 2-3 select the 8K bank of PRG RAM out of 32K, and bit 4 selects the 256K half
+///   of PRG ROM
+///
+/// These lines are driven by the CHR bank register for $0000, which is the one in use in 8K
+/// CHR mode (the only mode these boards are used in).
+///
+/// The mapper is its own PRG memory, as the registers written there control CHR banking too.
+pub struct Mmc1 {
+    shift: u8,
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+
+    prg_rom: Banks,
+    prg_ram: Banks,
+    chr: Banks,
+
+    /// Indicates if PRG ROM is fixed to the first 32K (submapper 5, SEROM/SHROM/SH1ROM)
+    fixed_prg: bool,
+
+    cycle: u64,
+    last_write: Option<u64>,
+    log: slog::Logger
+}
+
+impl Mmc1 {
+    /// Creates an MMC1 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, submapper: u8, logger: Option<slog::Logger>) -> Mmc1 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 2)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 2, false)
+        };
+
+        let mut mmc1 = Mmc1 {
+            shift: SHIFT_RESET,
+            // Most boards power on with the last bank fixed at $C000
+            control: 0x0C,
+            chr_banks: [0, 0],
+            prg_bank: 0,
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 2, false),
+            prg_ram: Banks::ram(prg_ram_size, PRG_RAM_BANK_SIZE, 1),
+            chr: chr,
+            fixed_prg: submapper == 5,
+            cycle: 0,
+            last_write: None,
+            log: unwrap_logger!(logger).new(o!("mapper" => "MMC1", "cartridge" => true))
+        };
+        mmc1.update_banks();
+        mmc1
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        // The serial port ignores writes on consecutive cycles, so only the first write of a
+        // read-modify-write instruction is seen
+        if let Some(last) = self.last_write {
+            if self.cycle <= last + 1 {
+                trace!(self.log,
+                    "ignored write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "val" => format!("${:02X}", val),
+                    "cycle" => self.cycle);
+                return;
+            }
+        }
+        self.last_write = Some(self.cycle);
+
+        if val & 0x80 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0x0C;
+            self.update_banks();
+            return;
+        }
+
+        let full = self.shift & 0x01 != 0;
+        self.shift = (self.shift >> 1) | ((val & 0x01) << 4);
+        if !full {
+            return;
+        }
+
+        let val = self.shift;
+        self.shift = SHIFT_RESET;
+        match (addr >> 13) & 0x03 {
+            0 => self.control = val,
+            1 => self.chr_banks[0] = val,
+            2 => self.chr_banks[1] = val,
+            _ => self.prg_bank = val
+        }
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr & 0xE000),
+            "val" => format!("${:02X}", val));
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let lines = self.chr_banks[0] as usize;
+
+        if self.fixed_prg {
+            self.prg_rom.select(0, 0);
+            self.prg_rom.select(1, 1);
+        } else {
+            let outer = if self.prg_rom.size() > PRG_OUTER_BANK_SIZE { lines & 0x10 } else { 0 };
+            let bank = (self.prg_bank & 0x0F) as usize;
+            let (first, second) = match (self.control >> 2) & 0x03 {
+                0 | 1 => (bank & 0x0E, bank | 0x01),
+                2 => (0, bank),
+                _ => (bank, 0x0F)
+            };
+            self.prg_rom.select(0, outer | first);
+            self.prg_rom.select(1, outer | second);
+        }
+
+        let ram_bank = match self.prg_ram.bank_count() {
+            4 => (lines >> 2) & 0x03,
+            2 => (lines >> 3) & 0x01,
+            _ => 0
+        };
+        self.prg_ram.select(0, ram_bank);
+
+        if self.control & 0x10 != 0 {
+            self.chr.select(0, self.chr_banks[0] as usize);
+            self.chr.select(1, self.chr_banks[1] as usize);
+        } else {
+            self.chr.select(0, (self.chr_banks[0] & 0x1E) as usize);
+            self.chr.select(1, (self.chr_banks[0] | 0x01) as usize);
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        // SNROM uses bit 4 of the CHR bank register, which would otherwise be unused with only
+        // 8K of CHR, as a second enable. SOROM has the same CHR and PRG ROM but leaves bit 4
+        // unused, and is told apart by its 16K of PRG RAM.
+        let snrom_disabled = self.chr.size() == 0x2000 &&
+            self.prg_rom.size() <= PRG_OUTER_BANK_SIZE &&
+            self.prg_ram.bank_count() == 1 &&
+            self.chr_banks[0] & 0x10 != 0;
+        self.prg_bank & 0x10 == 0 && !snrom_disabled && !self.prg_ram.is_empty()
+    }
+}
+
+impl nes::Mapper for Mmc1 {
+    fn name(&self) -> &'static str { "MMC1" }
+
+    fn mirroring(&self) -> nes::Mirroring {
+        match self.control & 0x03 {
+            0 => nes::Mirroring::SingleScreenLower,
+            1 => nes::Mirroring::SingleScreenUpper,
+            2 => nes::Mirroring::Vertical,
+            _ => nes::Mirroring::Horizontal
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn cpu_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+}
+
+impl mem::Memory for Mmc1 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                    mem::ErrorKind::OutOfBounds,
+                    "memory access out of range addressable on MMC1 cartridge",
+                    format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                // Nothing drives the data bus, so it keeps the high byte of the address
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Open Bus",
+                    "action" => "read");
+                return Ok((addr >> 8) as u8);
+            }
+            let eaddr = addr - 0x6000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_ram.bank(0),
+                "target" => "RAM",
+                "action" => "read");
+            self.prg_ram.get_u8(eaddr)
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on MMC1 cartridge",
+                format!("${:4X} is below the addressable range on MMC1 cartridge", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Disabled RAM",
+                    "action" => "write");
+                return Ok(());
+            }
+            let eaddr = addr - 0x6000;
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_ram.bank(0),
+                "target" => "RAM",
+                "action" => "write");
+            self.prg_ram.set_u8(eaddr, val)
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::mmc1::Mmc1;
+
+    fn prg(banks: usize) -> Vec<u8> {
+        numbered_banks(banks, 0x4000)
+    }
+
+    fn chr(banks: usize) -> Vec<u8> {
+        numbered_banks(banks, 0x1000)
+    }
+
+    /// Writes `val` to the register at `addr` through the serial port, a bit at a time, on
+    /// well separated cycles
+    fn write(mmc1: &mut Mmc1, addr: u64, val: u8) {
+        for i in 0..5 {
+            let cycle = mmc1.cycle + 10;
+            mmc1.cpu_cycle(cycle);
+            mmc1.set_u8(addr, (val >> i) & 0x01).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn last_bank_is_fixed_at_power_on() {
+        let mmc1 = Mmc1::new(prg(8), chr(2), 0x2000, 0, 0, None);
+
+        assert_eq!(Ok(0), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(7), mmc1.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn prg_modes_select_banks() {
+        let mut mmc1 = Mmc1::new(prg(8), chr(2), 0x2000, 0, 0, None);
+        write(&mut mmc1, 0xE000, 3);
+        assert_eq!(Ok(3), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(7), mmc1.get_u8(0xC000));
+
+        // Fix the first bank
+        write(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(Ok(0), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(3), mmc1.get_u8(0xC000));
+
+        // 32K mode ignores the low bit
+        write(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(Ok(2), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(3), mmc1.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn chr_modes_select_banks() {
+        let mut mmc1 = Mmc1::new(prg(2), chr(8), 0x2000, 0, 0, None);
+        write(&mut mmc1, 0xA000, 5);
+        write(&mut mmc1, 0xC000, 2);
+        assert_eq!(Ok(4), mmc1.chr().get_u8(0x0000));
+        assert_eq!(Ok(5), mmc1.chr().get_u8(0x1000));
+
+        write(&mut mmc1, 0x8000, 0x1C);
+        assert_eq!(Ok(5), mmc1.chr().get_u8(0x0000));
+        assert_eq!(Ok(2), mmc1.chr().get_u8(0x1000));
+    }
+
+    #[test]
+    pub fn control_selects_mirroring() {
+        let mut mmc1 = Mmc1::new(prg(2), chr(2), 0x2000, 0, 0, None);
+        write(&mut mmc1, 0x8000, 0x0C);
+        assert_eq!(Mirroring::SingleScreenLower, mmc1.mirroring());
+        write(&mut mmc1, 0x8000, 0x0E);
+        assert_eq!(Mirroring::Vertical, mmc1.mirroring());
+        write(&mut mmc1, 0x8000, 0x0F);
+        assert_eq!(Mirroring::Horizontal, mmc1.mirroring());
+    }
+
+    #[test]
+    pub fn reset_bit_clears_shift_register() {
+        let mut mmc1 = Mmc1::new(prg(8), chr(2), 0x2000, 0, 0, None);
+        mmc1.cpu_cycle(10);
+        mmc1.set_u8(0xE000, 1).unwrap();
+        mmc1.cpu_cycle(20);
+        mmc1.set_u8(0xE000, 0x80).unwrap();
+        write(&mut mmc1, 0xE000, 2);
+
+        assert_eq!(Ok(2), mmc1.get_u8(0x8000));
+    }
+
+    #[test]
+    pub fn writes_on_consecutive_cycles_are_ignored() {
+        let mut mmc1 = Mmc1::new(prg(8), chr(2), 0x2000, 0, 0, None);
+        mmc1.cpu_cycle(10);
+        mmc1.set_u8(0xE000, 0x80).unwrap();
+        // The second write of a read-modify-write instruction
+        mmc1.cpu_cycle(11);
+        mmc1.set_u8(0xE000, 0x01).unwrap();
+        write(&mut mmc1, 0xE000, 2);
+
+        assert_eq!(Ok(2), mmc1.get_u8(0x8000));
+    }
+
+    #[test]
+    pub fn prg_ram_can_be_disabled() {
+        let mut mmc1 = Mmc1::new(prg(2), chr(2), 0x2000, 0, 0, None);
+        mmc1.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x42), mmc1.get_u8(0x6000));
+
+        write(&mut mmc1, 0xE000, 0x10);
+        mmc1.set_u8(0x6000, 0x24).unwrap();
+        assert_eq!(Ok(0x60), mmc1.get_u8(0x6000));
+
+        write(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(Ok(0x42), mmc1.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn missing_prg_ram_reads_open_bus() {
+        let mut mmc1 = Mmc1::new(prg(2), chr(2), 0, 0, 0, None);
+        mmc1.set_u8(0x6000, 0x42).unwrap();
+
+        assert_eq!(Ok(0x60), mmc1.get_u8(0x6000));
+        assert!(mmc1.prg_ram().is_none());
+    }
+
+    #[test]
+    pub fn snrom_disables_prg_ram_with_chr_bank_register() {
+        let mut mmc1 = Mmc1::new(prg(16), Vec::new(), 0x2000, 0x2000, 0, None);
+        mmc1.set_u8(0x6000, 0x42).unwrap();
+        write(&mut mmc1, 0xA000, 0x10);
+
+        assert_eq!(Ok(0x60), mmc1.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn sorom_selects_prg_ram_bank_with_chr_bank_register() {
+        let mut mmc1 = Mmc1::new(prg(16), Vec::new(), 0x4000, 0x2000, 0, None);
+        mmc1.set_u8(0x6000, 0x11).unwrap();
+        write(&mut mmc1, 0xA000, 0x08);
+        assert_eq!(Ok(0x00), mmc1.get_u8(0x6000));
+        mmc1.set_u8(0x6000, 0x22).unwrap();
+
+        // Bit 4 doesn't disable the RAM as it does on SNROM
+        write(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(Ok(0x11), mmc1.get_u8(0x6000));
+        write(&mut mmc1, 0xA000, 0x18);
+        assert_eq!(Ok(0x22), mmc1.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn surom_selects_prg_half_with_chr_bank_register() {
+        let mut mmc1 = Mmc1::new(prg(32), Vec::new(), 0x2000, 0x2000, 0, None);
+        write(&mut mmc1, 0xE000, 2);
+        assert_eq!(Ok(2), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(15), mmc1.get_u8(0xC000));
+
+        write(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(Ok(18), mmc1.get_u8(0x8000));
+        assert_eq!(Ok(31), mmc1.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn sxrom_selects_prg_ram_bank_with_chr_bank_register() {
+        let mut mmc1 = Mmc1::new(prg(32), Vec::new(), 0x8000, 0x2000, 0, None);
+        mmc1.set_u8(0x6000, 0x11).unwrap();
+        write(&mut mmc1, 0xA000, 0x0C);
+        assert_eq!(Ok(0x00), mmc1.get_u8(0x6000));
+        mmc1.set_u8(0x6000, 0x33).unwrap();
+
+        write(&mut mmc1, 0xA000, 0x00);
+        assert_eq!(Ok(0x11), mmc1.get_u8(0x6000));
+        write(&mut mmc1, 0xA000, 0x0C);
+        assert_eq!(Ok(0x33), mmc1.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn chr_ram_is_writable() {
+        let mut mmc1 = Mmc1::new(prg(2), Vec::new(), 0x2000, 0x2000, 0, None);
+        mmc1.chr_mut().set_u8(0x1234, 0x42).unwrap();
+
+        assert_eq!(Ok(0x42), mmc1.chr().get_u8(0x1234));
+    }
+}
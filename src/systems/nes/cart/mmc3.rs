@@ -0,0 +1,432 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// The number of PPU cycles A12 must be held low before a rising edge clocks the IRQ counter
+///
+/// The MMC3 filters A12 through M2, so the brief drops between the sprite pattern fetches from
+/// $1000 are not counted and only one edge per scanline is seen.
+const A12_LOW_CYCLES: u64 = 10;
+
+/// Emulates the Nintendo MMC3 (iNES mapper 4) and the TxROM boards built around it
+///
+/// The eight bank registers are written through a select/data pair at $8000/$8001, with the
+/// select register also choosing whether the fixed and switchable PRG banks and the 2K and 1K
+/// CHR banks are swapped. The scanline IRQ counter is clocked by rising edges on PPU A12, which
+/// occur once per scanline when the background and sprites use different pattern tables.
+///
+/// The IRQ counter behaves differently between revisions. On the Sharp MMC3 (and the later
+/// NEC MMC3B/C) an IRQ is raised every time the counter is clocked while it is zero, so a
+/// latch of 0 raises an IRQ on every scanline. The earlier NEC MMC3A (submapper 4) only raises
+/// an IRQ when the counter is decremented to zero, or when it is reloaded after a write to
+/// $C001.
+pub struct Mmc3 {
+    bank_select: u8,
+    bank_registers: [u8; 8],
+    mirroring: nes::Mirroring,
+    ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    /// Indicates if the IRQ counter only raises an IRQ when it becomes zero (the NEC MMC3A)
+    nec_irq: bool,
+
+    prg_rom: Banks,
+    prg_ram: Banks,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Mmc3 {
+    /// Creates an MMC3 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead. Boards with
+    /// four-screen VRAM keep the provided `mirroring` regardless of the mirroring register.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, mirroring: nes::Mirroring, submapper: u8, logger: Option<slog::Logger>) -> Mmc3 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+
+        let mut mmc3 = Mmc3 {
+            bank_select: 0,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: mirroring,
+            // Many games never write to the protect register, so RAM starts out usable
+            ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            nec_irq: submapper == 4,
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1),
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => "MMC3", "cartridge" => true))
+        };
+        mmc3.update_banks();
+        mmc3
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr & 0xE001),
+            "val" => format!("${:02X}", val));
+
+        match (addr & 0xE000, addr & 0x01) {
+            (0x8000, 0) => self.bank_select = val,
+            (0x8000, _) => self.bank_registers[(self.bank_select & 0x07) as usize] = val,
+            (0xA000, 0) => if self.mirroring != nes::Mirroring::FourScreen {
+                self.mirroring = if val & 0x01 == 0 {
+                    nes::Mirroring::Vertical
+                } else {
+                    nes::Mirroring::Horizontal
+                };
+            },
+            (0xA000, _) => self.ram_protect = val,
+            (0xC000, 0) => self.irq_latch = val,
+            (0xC000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            (0xE000, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            _ => self.irq_enabled = true
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let second_last = self.prg_rom.bank_count().wrapping_sub(2);
+        let last = self.prg_rom.bank_count() - 1;
+        let r6 = (self.bank_registers[6] & 0x3F) as usize;
+        let r7 = (self.bank_registers[7] & 0x3F) as usize;
+        let (first, third) = if self.bank_select & 0x40 == 0 {
+            (r6, second_last)
+        } else {
+            (second_last, r6)
+        };
+        self.prg_rom.select(0, first);
+        self.prg_rom.select(1, r7);
+        self.prg_rom.select(2, third);
+        self.prg_rom.select(3, last);
+
+        // With inversion, the 1K banks are at $0000 and the 2K banks at $1000
+        let inversion = if self.bank_select & 0x80 == 0 { 0 } else { 4 };
+        let r = &self.bank_registers;
+        let banks = [
+            (r[0] & 0xFE) as usize, (r[0] | 0x01) as usize,
+            (r[1] & 0xFE) as usize, (r[1] | 0x01) as usize,
+            r[2] as usize, r[3] as usize, r[4] as usize, r[5] as usize
+        ];
+        for (slot, bank) in banks.iter().enumerate() {
+            self.chr.select(slot ^ inversion, *bank);
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let reloaded = self.irq_reload || self.irq_counter == 0;
+        let was_zero = self.irq_counter == 0;
+        if reloaded {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        // The MMC3A does not raise an IRQ when a zero counter is reloaded with zero, unless
+        // the reload was requested through $C001
+        let fire = self.irq_counter == 0 && (!self.nec_irq || !was_zero || self.irq_reload);
+        self.irq_reload = false;
+        if fire && self.irq_enabled {
+            trace!(self.log, "irq"; "latch" => self.irq_latch);
+            self.irq_pending = true;
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_protect & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_enabled() && self.ram_protect & 0x40 == 0
+    }
+}
+
+impl nes::Mapper for Mmc3 {
+    fn name(&self) -> &'static str { "MMC3" }
+
+    fn mirroring(&self) -> nes::Mirroring { self.mirroring }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn ppu_a12_rising(&mut self, low_cycles: u64) {
+        if low_cycles >= A12_LOW_CYCLES {
+            self.clock_irq_counter();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+impl mem::Memory for Mmc3 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on MMC3 cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                // Nothing drives the data bus, so it keeps the high byte of the address
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Open Bus",
+                    "action" => "read");
+                return Ok((addr >> 8) as u8);
+            }
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "read");
+            self.prg_ram.get_u8(addr - 0x6000)
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on MMC3 cartridge",
+                format!("${:4X} is below the addressable range on MMC3 cartridge", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_writable() {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Protected RAM",
+                    "action" => "write");
+                return Ok(());
+            }
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "write");
+            self.prg_ram.set_u8(addr - 0x6000, val)
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::mmc3::Mmc3;
+
+    fn mmc3(submapper: u8) -> Mmc3 {
+        Mmc3::new(numbered_banks(16, 0x2000), numbered_banks(64, 0x0400), 0x2000, 0x2000, Mirroring::Vertical, submapper, None)
+    }
+
+    fn set_bank(mmc3: &mut Mmc3, select: u8, bank: u8) {
+        mmc3.set_u8(0x8000, select).unwrap();
+        mmc3.set_u8(0x8001, bank).unwrap();
+    }
+
+    /// Clocks the IRQ counter as the PPU would at the start of a scanline's sprite fetches
+    fn scanline(mmc3: &mut Mmc3) {
+        mmc3.ppu_a12_rising(300);
+    }
+
+    #[test]
+    pub fn prg_mode_swaps_fixed_bank() {
+        let mut mmc3 = mmc3(0);
+        set_bank(&mut mmc3, 6, 3);
+        set_bank(&mut mmc3, 7, 5);
+
+        assert_eq!(Ok(3), mmc3.get_u8(0x8000));
+        assert_eq!(Ok(5), mmc3.get_u8(0xA000));
+        assert_eq!(Ok(14), mmc3.get_u8(0xC000));
+        assert_eq!(Ok(15), mmc3.get_u8(0xE000));
+
+        mmc3.set_u8(0x8000, 0x40).unwrap();
+        assert_eq!(Ok(14), mmc3.get_u8(0x8000));
+        assert_eq!(Ok(3), mmc3.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn chr_inversion_swaps_pattern_tables() {
+        let mut mmc3 = mmc3(0);
+        set_bank(&mut mmc3, 0, 9);
+        set_bank(&mut mmc3, 5, 33);
+
+        assert_eq!(Ok(8), mmc3.chr().get_u8(0x0000));
+        assert_eq!(Ok(9), mmc3.chr().get_u8(0x0400));
+        assert_eq!(Ok(33), mmc3.chr().get_u8(0x1C00));
+
+        mmc3.set_u8(0x8000, 0x80).unwrap();
+        assert_eq!(Ok(8), mmc3.chr().get_u8(0x1000));
+        assert_eq!(Ok(9), mmc3.chr().get_u8(0x1400));
+        assert_eq!(Ok(33), mmc3.chr().get_u8(0x0C00));
+    }
+
+    #[test]
+    pub fn mirroring_register_selects_mirroring() {
+        let mut mmc3 = mmc3(0);
+        mmc3.set_u8(0xA000, 1).unwrap();
+        assert_eq!(Mirroring::Horizontal, mmc3.mirroring());
+
+        mmc3.set_u8(0xA000, 0).unwrap();
+        assert_eq!(Mirroring::Vertical, mmc3.mirroring());
+    }
+
+    #[test]
+    pub fn prg_ram_can_be_protected() {
+        let mut mmc3 = mmc3(0);
+        mmc3.set_u8(0x6000, 0x42).unwrap();
+
+        mmc3.set_u8(0xA001, 0xC0).unwrap();
+        mmc3.set_u8(0x6000, 0x24).unwrap();
+        assert_eq!(Ok(0x42), mmc3.get_u8(0x6000));
+
+        mmc3.set_u8(0xA001, 0x00).unwrap();
+        assert_eq!(Ok(0x60), mmc3.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn irq_is_raised_when_counter_reaches_zero() {
+        let mut mmc3 = mmc3(0);
+        mmc3.set_u8(0xC000, 2).unwrap();
+        mmc3.set_u8(0xC001, 0).unwrap();
+        mmc3.set_u8(0xE001, 0).unwrap();
+
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+
+        mmc3.set_u8(0xE000, 0).unwrap();
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    pub fn short_a12_pulses_are_filtered() {
+        let mut mmc3 = mmc3(0);
+        mmc3.set_u8(0xC000, 1).unwrap();
+        mmc3.set_u8(0xE001, 0).unwrap();
+
+        scanline(&mut mmc3);
+        mmc3.ppu_a12_rising(4);
+        assert!(!mmc3.irq());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    pub fn sharp_irq_fires_every_scanline_with_zero_latch() {
+        let mut mmc3 = mmc3(0);
+        mmc3.set_u8(0xC000, 0).unwrap();
+        mmc3.set_u8(0xE001, 0).unwrap();
+
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.set_u8(0xE000, 0).unwrap();
+        mmc3.set_u8(0xE001, 0).unwrap();
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    pub fn nec_irq_fires_only_after_reload_with_zero_latch() {
+        let mut mmc3 = mmc3(4);
+        mmc3.set_u8(0xC000, 0).unwrap();
+        mmc3.set_u8(0xE001, 0).unwrap();
+
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+
+        mmc3.set_u8(0xC001, 0).unwrap();
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+}
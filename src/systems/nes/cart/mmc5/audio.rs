@@ -0,0 +1,141 @@
+use hw::rp2A03::pulse::Pulse;
+
+/// The number of CPU cycles between clocks of the pulse channels' envelopes and length
+/// counters
+///
+/// The MMC5 has no frame counter, and clocks both at a fixed rate of about 240Hz instead.
+const FRAME_PERIOD: u32 = 7457;
+
+/// Represents the sound hardware in the MMC5: two pulse channels like those of the APU (but
+/// without sweep units), and an 8-bit PCM channel written to directly by the CPU
+///
+/// Reading samples for the PCM channel from PRG ROM as the CPU reads them is not supported.
+pub struct Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    pcm: u8,
+    divider: u32,
+    odd_cycle: bool
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio {
+            pulse1: Pulse::without_sweep(),
+            pulse2: Pulse::without_sweep(),
+            pcm: 0,
+            divider: FRAME_PERIOD,
+            odd_cycle: false
+        }
+    }
+
+    /// Writes `val` to one of the registers at $5000-$5015, where `reg` is relative to $5000
+    pub fn write_register(&mut self, reg: u64, val: u8) {
+        match reg {
+            0x00 | 0x01 | 0x02 | 0x03 => self.pulse1.write_register(reg, val),
+            0x04 | 0x05 | 0x06 | 0x07 => self.pulse2.write_register(reg, val),
+            // Writes of zero are ignored, as a zero sample is what raises the PCM IRQ
+            0x11 if val != 0 => self.pcm = val,
+            0x15 => {
+                self.pulse1.length.set_enabled(val & 0x01 != 0);
+                self.pulse2.length.set_enabled(val & 0x02 != 0);
+            },
+            _ => {}
+        }
+    }
+
+    /// Gets the value of the status register ($5015), which indicates which pulse channels'
+    /// length counters are active
+    pub fn status(&self) -> u8 {
+        let mut status = 0;
+        if !self.pulse1.length.silenced() {
+            status |= 0x01;
+        }
+        if !self.pulse2.length.silenced() {
+            status |= 0x02;
+        }
+        status
+    }
+
+    /// Clocks the channels for one CPU cycle, and gets the output level relative to the APU's
+    /// mixed output
+    pub fn clock(&mut self) -> f32 {
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = FRAME_PERIOD;
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+        }
+
+        // As in the APU, the pulse timers are clocked on every other CPU cycle
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.level()
+    }
+
+    /// Gets the output level, mixing the pulses as the APU mixes its own and the PCM channel
+    /// at about the level of the DMC
+    fn level(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let pcm = self.pcm as f32 / 2.0 / 22638.0;
+        let pcm_out = if pcm == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / pcm + 100.0)
+        };
+
+        pulse_out + pcm_out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::mmc5::audio::Audio;
+
+    #[test]
+    pub fn status_reports_active_length_counters() {
+        let mut audio = Audio::new();
+        audio.write_register(0x15, 0x02);
+        audio.write_register(0x03, 0x08);
+        audio.write_register(0x07, 0x08);
+
+        assert_eq!(0x02, audio.status());
+    }
+
+    #[test]
+    pub fn pulse_is_heard() {
+        let mut audio = Audio::new();
+        audio.write_register(0x15, 0x01);
+        audio.write_register(0x00, 0xBF);
+        audio.write_register(0x02, 0x04);
+        audio.write_register(0x03, 0x08);
+
+        let peak = (0..100).map(|_| audio.clock()).fold(0.0, f32::max);
+        assert!(peak > 0.1);
+    }
+
+    #[test]
+    pub fn pcm_level_is_written_directly() {
+        let mut audio = Audio::new();
+        let silent = audio.clock();
+        audio.write_register(0x11, 0xFF);
+        let loud = audio.clock();
+        audio.write_register(0x11, 0x00);
+
+        assert_eq!(0.0, silent);
+        assert!(loud > 0.3);
+        assert_eq!(loud, audio.clock());
+    }
+}
@@ -0,0 +1,936 @@
+use std::cell::Cell;
+
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use self::audio::Audio;
+
+mod audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM_SIZE: usize = 0x0400;
+
+/// The number of PPU cycles without a rendering fetch after which the PPU is taken to have
+/// stopped rendering (the MMC5 waits for about three CPU cycles)
+const IDLE_CYCLES: u64 = 9;
+
+/// The index of the first sprite fetch within a scanline, after the 32 tiles of background
+/// (of four fetches each) that are fetched during the visible part of the line
+const SPRITE_FETCHES: usize = 128;
+
+/// The index of the first fetch of the two background tiles fetched for the following line
+const NEXT_LINE_FETCHES: usize = 160;
+
+/// The index of the first of the two unused nametable fetches at the end of the line
+const UNUSED_FETCHES: usize = 168;
+
+/// Describes how the MMC5 alters the PPU read which follows a rendering fetch
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+enum Fetch {
+    /// The read is not altered
+    Normal,
+
+    /// A nametable read is made from this offset into ExRAM instead (in the vertical split)
+    ExRam(usize),
+
+    /// An attribute read returns this value instead
+    Attribute(u8),
+
+    /// A pattern read is made from this 4K bank of CHR instead, and with this fine Y scroll
+    /// if one is given
+    Pattern(usize, Option<u64>)
+}
+
+/// The CHR seen by the PPU, which is altered for pattern fetches using the extended attributes
+/// or the vertical split
+struct Chr {
+    banks: Banks,
+    fetch: Fetch
+}
+
+impl mem::Memory for Chr {
+    fn len(&self) -> u64 { 0x2000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        match self.fetch {
+            Fetch::Pattern(bank, fine_y) => {
+                let offset = match fine_y {
+                    Some(y) => (addr & 0x0FF8) | y,
+                    None => addr & 0x0FFF
+                };
+                Ok(self.banks.get_absolute(bank * 0x1000 + offset as usize))
+            },
+            _ => self.banks.get_u8(addr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        self.banks.set_u8(addr, val)
+    }
+}
+
+/// Emulates the Nintendo MMC5 (iNES mapper 5) and the ExROM boards built around it
+///
+/// PRG is switched in four modes, from a single 32K bank to four 8K banks, and any bank other
+/// than the last may map PRG RAM instead of ROM. CHR is switched in four modes too, and has two
+/// sets of bank registers: when the PPU uses 8x16 sprites, the first set is used for sprites and
+/// the second for the background, and otherwise the first set is used for both.
+///
+/// The MMC5 has no view of the PPU's registers besides the writes to them, so it follows the
+/// PPU through its rendering fetches. A scanline is detected when the same nametable address is
+/// read three times in a row, which happens only at the start of each line, and the fetches are
+/// counted from there to tell which tile (or sprite) is being fetched. The scanline counter
+/// raises an IRQ on the line selected by $5203, and restarts at the first line after the PPU
+/// stops rendering or the CPU reads the NMI vector.
+///
+/// The 1K of ExRAM can be used as a third nametable, as extended attributes (giving each
+/// background tile its own palette and 4K CHR bank), or as plain RAM. Each nametable can also
+/// be filled with a single tile and palette, and the vertical split replaces the background on
+/// one side of the screen with a separately scrolled nametable from ExRAM.
+pub struct Mmc5 {
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    /// The PRG bank registers at $5113-$5117
+    prg_banks: [u8; 5],
+
+    /// The CHR bank registers at $5120-$5127, used for sprites
+    sprite_chr_banks: [u16; 8],
+
+    /// The CHR bank registers at $5128-$512B, used for the background
+    background_chr_banks: [u16; 4],
+
+    /// The upper bits of the CHR banks, written to $5130
+    chr_upper: u8,
+
+    /// Indicates if the background CHR registers were written after the sprite registers
+    background_chr_last: bool,
+
+    /// Indicates if the background CHR set is currently selected
+    background_chr: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: Cell<bool>,
+    in_frame: Cell<bool>,
+    scanline: u8,
+
+    tall_sprites: bool,
+    last_fetch_cycle: u64,
+    last_fetch_addr: u64,
+    repeated_fetches: u8,
+    fetch_index: usize,
+    extended_attribute: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    prg_rom: Banks,
+    prg_ram: Banks,
+
+    /// Indicates which of the slots at $8000-$FFFF map PRG ROM rather than PRG RAM
+    prg_rom_slots: [bool; 4],
+
+    chr: Chr,
+    exram: [u8; EXRAM_SIZE],
+    audio: Audio,
+    log: slog::Logger
+}
+
+impl Mmc5 {
+    /// Creates an MMC5 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, logger: Option<slog::Logger>) -> Mmc5 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+
+        let mut mmc5 = Mmc5 {
+            // The last bank is mapped into the 8K slot at $E000 at power on
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0, 0],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            sprite_chr_banks: [0; 8],
+            background_chr_banks: [0; 4],
+            chr_upper: 0,
+            background_chr_last: false,
+            background_chr: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            in_frame: Cell::new(false),
+            scanline: 0,
+            tall_sprites: false,
+            last_fetch_cycle: 0,
+            last_fetch_addr: 0,
+            repeated_fetches: 0,
+            fetch_index: 0,
+            extended_attribute: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: Banks::ram(prg_ram_size, PRG_BANK_SIZE, 5),
+            prg_rom_slots: [true; 4],
+            chr: Chr {
+                banks: chr,
+                fetch: Fetch::Normal
+            },
+            exram: [0; EXRAM_SIZE],
+            audio: Audio::new(),
+            log: unwrap_logger!(logger).new(o!("mapper" => "MMC5", "cartridge" => true))
+        };
+        mmc5.update_prg_banks();
+        mmc5.update_chr_banks();
+        mmc5
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr),
+            "val" => format!("${:02X}", val));
+
+        match addr {
+            0x5000...0x5015 => self.audio.write_register(addr - 0x5000, val),
+            0x5100 => self.prg_mode = val & 0x03,
+            0x5101 => self.chr_mode = val & 0x03,
+            0x5102 => self.ram_protect[0] = val & 0x03,
+            0x5103 => self.ram_protect[1] = val & 0x03,
+            0x5104 => self.exram_mode = val & 0x03,
+            0x5105 => self.nametables = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attribute = val & 0x03,
+            0x5113...0x5117 => self.prg_banks[(addr - 0x5113) as usize] = val,
+            0x5120...0x5127 => {
+                self.sprite_chr_banks[(addr - 0x5120) as usize] = ((self.chr_upper as u16) << 8) | val as u16;
+                self.background_chr_last = false;
+            },
+            0x5128...0x512B => {
+                self.background_chr_banks[(addr - 0x5128) as usize] = ((self.chr_upper as u16) << 8) | val as u16;
+                self.background_chr_last = true;
+            },
+            0x5130 => self.chr_upper = val & 0x03,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_target = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            0x5C00...0x5FFF => {
+                let offset = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // While ExRAM is used by the PPU, it can only be written during rendering
+                    0 | 1 => self.exram[offset] = if self.in_frame.get() { val } else { 0 },
+                    2 => self.exram[offset] = val,
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+        self.update_prg_banks();
+        self.update_chr_banks();
+    }
+
+    fn read_register(&self, addr: u64) -> u8 {
+        match addr {
+            0x5015 => self.audio.status(),
+            0x5204 => {
+                let mut status = 0;
+                if self.irq_pending.get() {
+                    status |= 0x80;
+                }
+                if self.in_frame.get() {
+                    status |= 0x40;
+                }
+                self.irq_pending.set(false);
+                status
+            },
+            0x5205 => (self.product() & 0xFF) as u8,
+            0x5206 => (self.product() >> 8) as u8,
+            0x5C00...0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            // Nothing drives the data bus, so it keeps the high byte of the address
+            _ => (addr >> 8) as u8
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    fn update_prg_banks(&mut self) {
+        // Each slot is given as the register it is mapped by, the size of the bank that
+        // register selects (in 8K banks), and the part of that bank which the slot maps
+        let slots: [(usize, usize, usize); 4] = match self.prg_mode {
+            0 => [(4, 4, 0), (4, 4, 1), (4, 4, 2), (4, 4, 3)],
+            1 => [(2, 2, 0), (2, 2, 1), (4, 2, 0), (4, 2, 1)],
+            2 => [(2, 2, 0), (2, 2, 1), (3, 1, 0), (4, 1, 0)],
+            _ => [(1, 1, 0), (2, 1, 0), (3, 1, 0), (4, 1, 0)]
+        };
+
+        self.prg_ram.select(0, (self.prg_banks[0] & 0x07) as usize);
+        for (slot, &(register, size, part)) in slots.iter().enumerate() {
+            let val = self.prg_banks[register];
+            let bank = ((val & 0x7F) as usize & !(size - 1)) | part;
+            // $5117 always maps ROM, and the other registers map RAM unless bit 7 is set
+            let rom = register == 4 || val & 0x80 != 0;
+            self.prg_rom_slots[slot] = rom;
+            if rom {
+                self.prg_rom.select(slot, bank);
+            } else {
+                self.prg_ram.select(slot + 1, bank & 0x07);
+            }
+        }
+    }
+
+    fn update_chr_banks(&mut self) {
+        let background = if self.tall_sprites { self.background_chr_last } else { false };
+        self.select_chr_set(background);
+    }
+
+    /// Maps either the sprite or the background set of CHR banks
+    fn select_chr_set(&mut self, background: bool) {
+        let b = self.background_chr_banks;
+        let registers = if background {
+            [b[0], b[1], b[2], b[3], b[0], b[1], b[2], b[3]]
+        } else {
+            self.sprite_chr_banks
+        };
+
+        for slot in 0..8 {
+            let bank = match self.chr_mode {
+                0 => registers[7] as usize * 8 + slot,
+                1 => registers[slot | 3] as usize * 4 + (slot & 3),
+                2 => registers[slot | 1] as usize * 2 + (slot & 1),
+                _ => registers[slot] as usize
+            };
+            self.chr.banks.select(slot, bank);
+        }
+        self.background_chr = background;
+    }
+
+    fn detect_scanline(&mut self) {
+        if !self.in_frame.get() {
+            self.in_frame.set(true);
+            self.scanline = 0;
+            self.irq_pending.set(false);
+        } else {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_target {
+                trace!(self.log, "irq"; "scanline" => self.scanline);
+                self.irq_pending.set(true);
+            }
+        }
+    }
+
+    /// Determines how the fetch at the current index in the scanline is altered, and selects
+    /// the CHR set for it
+    fn alter_fetch(&mut self, addr: u64) -> Fetch {
+        let index = self.fetch_index;
+        let sprites = index >= SPRITE_FETCHES && index < NEXT_LINE_FETCHES;
+        let background = self.tall_sprites && !sprites;
+        if background != self.background_chr {
+            self.select_chr_set(background);
+        }
+        if sprites || index >= UNUSED_FETCHES {
+            return Fetch::Normal;
+        }
+
+        // The first two tiles are fetched at the end of the previous line
+        let (tile, line) = if index >= NEXT_LINE_FETCHES {
+            let line = if self.in_frame.get() { self.scanline as usize + 1 } else { 0 };
+            ((index - NEXT_LINE_FETCHES) / 4, line)
+        } else {
+            (index / 4 + 2, self.scanline as usize)
+        };
+
+        if self.in_split(tile) {
+            let y = (self.split_scroll as usize + line) % 240;
+            let column = tile & 0x1F;
+            return match index % 4 {
+                0 => Fetch::ExRam((y / 8) * 32 + column),
+                1 => {
+                    let attribute = self.exram[0x3C0 + (y / 32) * 8 + column / 4];
+                    let shift = ((y / 16) & 0x01) * 4 + ((column / 2) & 0x01) * 2;
+                    Fetch::Attribute(((attribute >> shift) & 0x03) * 0x55)
+                },
+                _ => Fetch::Pattern(self.split_bank as usize, Some((y & 0x07) as u64))
+            };
+        }
+
+        if self.exram_mode == 1 {
+            match index % 4 {
+                0 => {
+                    self.extended_attribute = self.exram[(addr & 0x03FF) as usize];
+                    Fetch::Normal
+                },
+                1 => Fetch::Attribute((self.extended_attribute >> 6) * 0x55),
+                _ => {
+                    let bank = ((self.chr_upper as usize) << 6) | (self.extended_attribute & 0x3F) as usize;
+                    Fetch::Pattern(bank, None)
+                }
+            }
+        } else {
+            Fetch::Normal
+        }
+    }
+
+    fn in_split(&self, tile: usize) -> bool {
+        let count = (self.split_control & 0x1F) as usize;
+        self.split_control & 0x80 != 0 && self.exram_mode <= 1 && if self.split_control & 0x40 != 0 {
+            tile >= count
+        } else {
+            tile < count
+        }
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect[0] == 0x02 && self.ram_protect[1] == 0x01 && !self.prg_ram.is_empty()
+    }
+}
+
+impl nes::Mapper for Mmc5 {
+    fn name(&self) -> &'static str { "MMC5" }
+
+    fn mirroring(&self) -> nes::Mirroring {
+        match self.nametables {
+            0x00 => nes::Mirroring::SingleScreenLower,
+            0x55 => nes::Mirroring::SingleScreenUpper,
+            0x44 => nes::Mirroring::Vertical,
+            0x50 => nes::Mirroring::Horizontal,
+            // Each nametable is chosen separately
+            _ => nes::Mirroring::FourScreen
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.banks.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.banks.ram_data_mut()
+    }
+
+    fn ppu_fetch(&mut self, addr: u64, cycle: u64) {
+        if cycle > self.last_fetch_cycle + IDLE_CYCLES {
+            self.in_frame.set(false);
+            self.repeated_fetches = 0;
+            self.fetch_index = 0;
+        }
+        self.last_fetch_cycle = cycle;
+
+        let nametable = addr >= 0x2000 && addr < 0x3000;
+        if nametable && addr == self.last_fetch_addr {
+            self.repeated_fetches += 1;
+        } else {
+            self.repeated_fetches = 0;
+        }
+        self.last_fetch_addr = addr;
+        if self.repeated_fetches == 2 {
+            self.detect_scanline();
+            self.fetch_index = 0;
+        }
+
+        self.chr.fetch = self.alter_fetch(addr);
+        self.fetch_index += 1;
+    }
+
+    fn ppu_register_write(&mut self, reg: u64, val: u8) {
+        match reg {
+            0 => {
+                self.tall_sprites = val & 0x20 != 0;
+                self.update_chr_banks();
+            },
+            1 if val & 0x18 == 0 => {
+                self.in_frame.set(false);
+                self.chr.fetch = Fetch::Normal;
+            },
+            _ => {}
+        }
+    }
+
+    fn read_nametable(&self, vram: &mem::Memory, addr: u64) -> mem::Result<u8> {
+        match self.chr.fetch {
+            Fetch::ExRam(offset) => return Ok(self.exram[offset]),
+            Fetch::Attribute(val) => return Ok(val),
+            _ => {}
+        }
+
+        let offset = addr & 0x03FF;
+        match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+            0 => vram.get_u8(offset),
+            1 => vram.get_u8(0x0400 | offset),
+            2 if self.exram_mode <= 1 => Ok(self.exram[offset as usize]),
+            2 => Ok(0),
+            _ if offset >= 0x03C0 => Ok(self.fill_attribute * 0x55),
+            _ => Ok(self.fill_tile)
+        }
+    }
+
+    fn write_nametable(&mut self, vram: &mut mem::Memory, addr: u64, val: u8) -> mem::Result<()> {
+        let offset = addr & 0x03FF;
+        match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+            0 => vram.set_u8(offset, val),
+            1 => vram.set_u8(0x0400 | offset, val),
+            2 if self.exram_mode <= 1 => {
+                self.exram[offset as usize] = val;
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending.get()
+    }
+
+    fn expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+impl mem::Memory for Mmc5 {
+    fn len(&self) -> u64 { 0xB000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x5000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on MMC5 cartridge",
+                format!("${:4X} is below the addressable range of 0x5000-0xFFFF", addr)))
+        } else if addr < 0x6000 {
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Registers",
+                "action" => "read");
+            Ok(self.read_register(addr))
+        } else {
+            // Reading the NMI vector ends the frame, as the MMC5 can't see the PPU's vblank
+            if addr == 0xFFFA || addr == 0xFFFB {
+                self.in_frame.set(false);
+            }
+
+            let slot = ((addr - 0x6000) as usize) / PRG_BANK_SIZE;
+            if slot > 0 && self.prg_rom_slots[slot - 1] {
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "bank" => self.prg_rom.bank(slot - 1),
+                    "target" => "ROM",
+                    "action" => "read");
+                self.prg_rom.get_u8(addr - 0x8000)
+            } else if self.prg_ram.is_empty() {
+                // Nothing drives the data bus, so it keeps the high byte of the address
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Open Bus",
+                    "action" => "read");
+                Ok((addr >> 8) as u8)
+            } else {
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "bank" => self.prg_ram.bank(slot),
+                    "target" => "RAM",
+                    "action" => "read");
+                self.prg_ram.get_u8(addr - 0x6000)
+            }
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x5000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on MMC5 cartridge",
+                format!("${:4X} is below the addressable range on MMC5 cartridge", addr)))
+        } else if addr < 0x6000 {
+            self.write_register(addr, val);
+            Ok(())
+        } else {
+            let slot = ((addr - 0x6000) as usize) / PRG_BANK_SIZE;
+            if slot > 0 && self.prg_rom_slots[slot - 1] {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "ROM",
+                    "action" => "write");
+                Ok(())
+            } else if !self.ram_writable() {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Protected RAM",
+                    "action" => "write");
+                Ok(())
+            } else {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "bank" => self.prg_ram.bank(slot),
+                    "target" => "RAM",
+                    "action" => "write");
+                self.prg_ram.set_u8(addr - 0x6000, val)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem;
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::mmc5::Mmc5;
+
+    fn mmc5() -> Mmc5 {
+        Mmc5::new(numbered_banks(16, 0x2000), chr_rom(), 0x10000, 0x2000, None)
+    }
+
+    /// Creates 512K of CHR ROM, with the 16-bit number of each 1K bank in its first two bytes
+    fn chr_rom() -> Vec<u8> {
+        let mut chr = numbered_banks(512, 0x0400);
+        for i in 0..512 {
+            chr[i * 0x0400 + 1] = (i >> 8) as u8;
+        }
+        chr
+    }
+
+    /// Gets the number of the 1K CHR bank which the PPU reads from at `addr`
+    fn chr_bank(mmc5: &Mmc5, addr: u64) -> u16 {
+        let lo = mmc5.chr().get_u8(addr).unwrap() as u16;
+        let hi = mmc5.chr().get_u8(addr + 1).unwrap() as u16;
+        (hi << 8) | lo
+    }
+
+    /// Makes the rendering fetches of a PPU, in the order they are made on each scanline
+    struct Ppu {
+        cycle: u64,
+        index: usize
+    }
+
+    impl Ppu {
+        /// Creates a PPU which is about to make the unused nametable fetches at the end of the
+        /// pre-render line
+        fn new() -> Ppu {
+            Ppu { cycle: 1000, index: 168 }
+        }
+
+        fn fetch_addr(&self) -> u64 {
+            match self.index {
+                128...159 if self.index % 4 >= 2 => 0x1000,
+                128...159 => 0x2000,
+                168...169 => 0x2000,
+                _ => match self.index % 4 {
+                    0 => 0x2000,
+                    1 => 0x23C0,
+                    _ => 0x0000
+                }
+            }
+        }
+
+        /// Makes the next `count` fetches
+        fn run(&mut self, mmc5: &mut Mmc5, count: usize) {
+            for _ in 0..count {
+                let addr = self.fetch_addr();
+                mmc5.ppu_fetch(addr, self.cycle);
+                self.cycle += 2;
+                self.index = (self.index + 1) % 170;
+            }
+        }
+
+        /// Makes the fetches up to and including the first fetch of the next scanline
+        fn next_line(&mut self, mmc5: &mut Mmc5) {
+            let count = 170 - self.index + 1;
+            self.run(mmc5, count);
+        }
+    }
+
+    #[test]
+    pub fn prg_modes_select_banks() {
+        let mut mmc5 = mmc5();
+        assert_eq!(Ok(15), mmc5.get_u8(0xE000));
+        mmc5.set_u8(0x5114, 0x81).unwrap();
+        assert_eq!(Ok(1), mmc5.get_u8(0x8000));
+
+        mmc5.set_u8(0x5100, 0).unwrap();
+        mmc5.set_u8(0x5117, 0x85).unwrap();
+        assert_eq!(Ok(4), mmc5.get_u8(0x8000));
+        assert_eq!(Ok(7), mmc5.get_u8(0xE000));
+
+        mmc5.set_u8(0x5100, 1).unwrap();
+        mmc5.set_u8(0x5115, 0x83).unwrap();
+        assert_eq!(Ok(2), mmc5.get_u8(0x8000));
+        assert_eq!(Ok(3), mmc5.get_u8(0xA000));
+        assert_eq!(Ok(4), mmc5.get_u8(0xC000));
+
+        mmc5.set_u8(0x5100, 2).unwrap();
+        mmc5.set_u8(0x5116, 0x89).unwrap();
+        assert_eq!(Ok(9), mmc5.get_u8(0xC000));
+        assert_eq!(Ok(5), mmc5.get_u8(0xE000));
+    }
+
+    #[test]
+    pub fn prg_ram_is_banked_and_protected() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5113, 1).unwrap();
+        mmc5.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0), mmc5.get_u8(0x6000));
+
+        mmc5.set_u8(0x5102, 0x02).unwrap();
+        mmc5.set_u8(0x5103, 0x01).unwrap();
+        mmc5.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x42), mmc5.get_u8(0x6000));
+
+        // Bank 1 of RAM mapped at $A000 as well
+        mmc5.set_u8(0x5115, 0x01).unwrap();
+        assert_eq!(Ok(0x42), mmc5.get_u8(0xA000));
+    }
+
+    #[test]
+    pub fn chr_modes_select_banks() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5101, 3).unwrap();
+        mmc5.set_u8(0x5122, 9).unwrap();
+        assert_eq!(Ok(9), mmc5.chr().get_u8(0x0800));
+
+        mmc5.set_u8(0x5101, 1).unwrap();
+        mmc5.set_u8(0x5127, 3).unwrap();
+        assert_eq!(Ok(13), mmc5.chr().get_u8(0x1400));
+
+        mmc5.set_u8(0x5101, 0).unwrap();
+        assert_eq!(Ok(24), mmc5.chr().get_u8(0x0000));
+
+        mmc5.set_u8(0x5130, 1).unwrap();
+        mmc5.set_u8(0x5101, 3).unwrap();
+        mmc5.set_u8(0x5120, 2).unwrap();
+        assert_eq!(0x102, chr_bank(&mmc5, 0x0000));
+    }
+
+    #[test]
+    pub fn tall_sprites_use_separate_background_chr() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5101, 0).unwrap();
+        mmc5.set_u8(0x5127, 1).unwrap();
+        mmc5.set_u8(0x512B, 2).unwrap();
+        mmc5.ppu_register_write(0, 0x20);
+
+        // The background registers were written last
+        assert_eq!(Ok(16), mmc5.chr().get_u8(0x0000));
+
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        ppu.run(&mut mmc5, 2);
+        assert_eq!(Ok(16), mmc5.chr().get_u8(0x0000));
+
+        // The first sprite pattern fetch
+        ppu.run(&mut mmc5, 128);
+        assert_eq!(Ok(12), mmc5.chr().get_u8(0x1000));
+
+        // The first background fetch for the next line
+        ppu.run(&mut mmc5, 30);
+        assert_eq!(Ok(16), mmc5.chr().get_u8(0x0000));
+    }
+
+    #[test]
+    pub fn scanline_irq_is_raised_on_selected_line() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5203, 2).unwrap();
+        mmc5.set_u8(0x5204, 0x80).unwrap();
+
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        assert_eq!(Ok(0x40), mmc5.get_u8(0x5204));
+        ppu.next_line(&mut mmc5);
+        assert!(!mmc5.irq());
+        ppu.next_line(&mut mmc5);
+        assert!(mmc5.irq());
+
+        assert_eq!(Ok(0xC0), mmc5.get_u8(0x5204));
+        assert!(!mmc5.irq());
+    }
+
+    #[test]
+    pub fn frame_ends_when_fetches_stop() {
+        let mut mmc5 = mmc5();
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        assert_eq!(Ok(0x40), mmc5.get_u8(0x5204));
+
+        ppu.cycle += 341 * 20;
+        ppu.run(&mut mmc5, 1);
+        assert_eq!(Ok(0x00), mmc5.get_u8(0x5204));
+    }
+
+    #[test]
+    pub fn nmi_vector_read_ends_frame() {
+        let mut mmc5 = mmc5();
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+
+        mmc5.get_u8(0xFFFA).unwrap();
+        assert_eq!(Ok(0x00), mmc5.get_u8(0x5204));
+    }
+
+    #[test]
+    pub fn nametables_are_chosen_separately() {
+        let mut mmc5 = mmc5();
+        let mut vram = mem::Fixed::new(0x800);
+        vram.set_u8(0x0400, 0x11).unwrap();
+        mmc5.set_u8(0x5104, 2).unwrap();
+        mmc5.set_u8(0x5C00, 0x22).unwrap();
+        mmc5.set_u8(0x5104, 0).unwrap();
+        mmc5.set_u8(0x5105, 0xE4).unwrap();
+        mmc5.set_u8(0x5106, 0x33).unwrap();
+        mmc5.set_u8(0x5107, 0x02).unwrap();
+
+        assert_eq!(Ok(0x00), mmc5.read_nametable(&vram, 0x2000));
+        assert_eq!(Ok(0x11), mmc5.read_nametable(&vram, 0x2400));
+        assert_eq!(Ok(0x22), mmc5.read_nametable(&vram, 0x2800));
+        assert_eq!(Ok(0x33), mmc5.read_nametable(&vram, 0x2C00));
+        assert_eq!(Ok(0xAA), mmc5.read_nametable(&vram, 0x2FC0));
+        assert_eq!(Mirroring::FourScreen, mmc5.mirroring());
+    }
+
+    #[test]
+    pub fn exram_is_only_written_during_rendering_when_used_by_ppu() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5C00, 0x42).unwrap();
+        mmc5.set_u8(0x5104, 2).unwrap();
+        assert_eq!(Ok(0x00), mmc5.get_u8(0x5C00));
+
+        mmc5.set_u8(0x5C00, 0x42).unwrap();
+        assert_eq!(Ok(0x42), mmc5.get_u8(0x5C00));
+    }
+
+    #[test]
+    pub fn extended_attributes_select_palette_and_chr_bank() {
+        let mut mmc5 = mmc5();
+        let vram = mem::Fixed::new(0x800);
+        mmc5.set_u8(0x5104, 2).unwrap();
+        mmc5.set_u8(0x5C00, 0xC5).unwrap();
+        mmc5.set_u8(0x5104, 1).unwrap();
+        mmc5.set_u8(0x5130, 1).unwrap();
+
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        ppu.run(&mut mmc5, 1);
+        assert_eq!(Ok(0xFF), mmc5.read_nametable(&vram, 0x23C0));
+        ppu.run(&mut mmc5, 1);
+        // 4K bank $45 is 1K bank $114
+        assert_eq!(0x114, chr_bank(&mmc5, 0x0000));
+    }
+
+    #[test]
+    pub fn extended_attributes_without_chr_read_zero() {
+        let mut mmc5 = Mmc5::new(numbered_banks(16, 0x2000), Vec::new(), 0x10000, 0, None);
+        mmc5.set_u8(0x5104, 2).unwrap();
+        mmc5.set_u8(0x5C00, 0xC5).unwrap();
+        mmc5.set_u8(0x5104, 1).unwrap();
+
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        ppu.run(&mut mmc5, 2);
+        assert_eq!(Ok(0), mmc5.chr().get_u8(0x0000));
+    }
+
+    #[test]
+    pub fn vertical_split_replaces_left_tiles() {
+        let mut mmc5 = mmc5();
+        let vram = mem::Fixed::new(0x800);
+        mmc5.set_u8(0x5104, 2).unwrap();
+        mmc5.set_u8(0x5C22, 0x77).unwrap();
+        mmc5.set_u8(0x5104, 0).unwrap();
+        mmc5.set_u8(0x5200, 0x83).unwrap();
+        mmc5.set_u8(0x5201, 8).unwrap();
+        mmc5.set_u8(0x5202, 3).unwrap();
+
+        // Tile 2 of the first line, which is row 1 of the split
+        let mut ppu = Ppu::new();
+        ppu.next_line(&mut mmc5);
+        assert_eq!(Ok(0x77), mmc5.read_nametable(&vram, 0x2000));
+        ppu.run(&mut mmc5, 2);
+        assert_eq!(Ok(12), mmc5.chr().get_u8(0x0007));
+
+        // Tile 3 is outside the split
+        ppu.run(&mut mmc5, 2);
+        assert_eq!(Ok(0x00), mmc5.read_nametable(&vram, 0x2000));
+    }
+
+    #[test]
+    pub fn multiplier_gives_product() {
+        let mut mmc5 = mmc5();
+        mmc5.set_u8(0x5205, 12).unwrap();
+        mmc5.set_u8(0x5206, 34).unwrap();
+
+        assert_eq!(Ok(0x98), mmc5.get_u8(0x5205));
+        assert_eq!(Ok(0x01), mmc5.get_u8(0x5206));
+    }
+}
@@ -1,11 +1,52 @@
+use std::cmp;
+use std::io;
+
 use slog;
 
 use mem;
 use systems::nes;
 
 pub use self::nrom::NRom;
+pub use self::mmc1::Mmc1;
+pub use self::mmc3::Mmc3;
+pub use self::mmc5::Mmc5;
+pub use self::uxrom::UxRom;
+pub use self::cnrom::CnRom;
+pub use self::axrom::AxRom;
+pub use self::gxrom::GxRom;
+pub use self::bnrom::BnRom;
+pub use self::nina001::Nina001;
+pub use self::color_dreams::ColorDreams;
+pub use self::vrc4::Vrc4;
+pub use self::vrc6::Vrc6;
+pub use self::vrc7::Vrc7;
+pub use self::fme7::Fme7;
+pub use self::namco163::Namco163;
+pub use self::registry::{Registry,MapperEntry,Constructor};
+pub use self::save::{SaveStorage,FileStorage,MemoryStorage};
 
+mod banks;
+mod registry;
+mod save;
+#[macro_use]
+mod discrete;
 mod nrom;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod uxrom;
+mod cnrom;
+mod axrom;
+mod gxrom;
+mod bnrom;
+mod nina001;
+mod color_dreams;
+mod vrc_irq;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod fme7;
+mod namco163;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -17,18 +58,196 @@ pub enum Error {
 /// Represents a cartridge that has been loaded into the system
 pub struct Cartridge {
     header: nes::RomHeader,
-    pub mapper: Box<Mapper>
+    pub mapper: Box<Mapper>,
+    save: Option<Box<SaveStorage>>,
+    saved: Vec<u8>,
+    log: slog::Logger
 }
 
 impl Cartridge {
     pub fn header(&self) -> &nes::RomHeader {
         &self.header
     }
+
+    /// Determines whether the cartridge has CHR RAM in place of CHR ROM
+    pub fn has_chr_ram(&self) -> bool {
+        self.header.chr_rom_size == 0
+    }
+
+    /// Determines whether the cartridge's CHR RAM is battery-backed, which only an NES 2.0
+    /// header can specify
+    pub fn chr_ram_battery_backed(&self) -> bool {
+        self.has_chr_ram() && match self.header.version {
+            nes::rom::Version::NES2 => self.header.chr_ram_size.battery_backed != 0,
+            _ => false
+        }
+    }
+
+    /// Determines whether the cartridge has any battery-backed RAM, whose contents are kept
+    /// in its save storage
+    pub fn battery_backed(&self) -> bool {
+        self.header.sram_battery_backed || self.chr_ram_battery_backed()
+    }
+
+    /// Sets the storage which the battery-backed RAM is loaded from and flushed to
+    ///
+    /// The RAM is loaded when the cartridge is inserted into an `Nes`, and flushed when it is
+    /// ejected or dropped and periodically while it runs. The save holds the battery-backed PRG
+    /// RAM followed by the battery-backed CHR RAM.
+    pub fn set_save_storage(&mut self, storage: Box<SaveStorage>) {
+        self.saved = self.save_data();
+        self.save = Some(storage);
+    }
+
+    /// Fills the battery-backed RAM from the save storage, if anything has been saved
+    pub fn load_save(&mut self) -> io::Result<()> {
+        if !self.battery_backed() {
+            return Ok(());
+        }
+        let data = match self.save {
+            Some(ref mut storage) => try!(storage.load()),
+            None => return Ok(())
+        };
+
+        if let Some(data) = data {
+            let mut rest = &data[..];
+            let (prg_len, chr_len) = (self.prg_battery_size(), self.chr_battery_size());
+            fill(self.mapper.prg_ram_mut(), prg_len, &mut rest);
+            fill(self.mapper.chr_ram_mut(), chr_len, &mut rest);
+            self.saved = self.save_data();
+        }
+        Ok(())
+    }
+
+    /// Writes the battery-backed RAM to the save storage, if it has changed since it was last
+    /// loaded or flushed
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if !self.battery_backed() || self.save.is_none() {
+            return Ok(());
+        }
+
+        let data = self.save_data();
+        if data != self.saved {
+            if let Some(ref mut storage) = self.save {
+                try!(storage.save(&data));
+            }
+            self.saved = data;
+        }
+        Ok(())
+    }
+
+    /// Gets the contents of the battery-backed RAM, as it is laid out in a save
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(ram) = self.mapper.prg_ram() {
+            data.extend(&ram[..cmp::min(ram.len(), self.prg_battery_size())]);
+        }
+        if let Some(ram) = self.mapper.chr_ram() {
+            data.extend(&ram[..cmp::min(ram.len(), self.chr_battery_size())]);
+        }
+        data
+    }
+
+    /// Gets the number of bytes at the start of the PRG RAM which are battery-backed
+    ///
+    /// An NES 2.0 header gives the size of the battery-backed part, but an iNES header can only
+    /// say that all of it is.
+    fn prg_battery_size(&self) -> usize {
+        if !self.header.sram_battery_backed {
+            return 0;
+        }
+        match self.header.version {
+            nes::rom::Version::NES2 if self.header.prg_ram_size.battery_backed != 0 => {
+                self.header.prg_ram_size.battery_backed as usize
+            },
+            _ => usize::max_value()
+        }
+    }
+
+    /// Gets the number of bytes at the start of the CHR RAM which are battery-backed
+    fn chr_battery_size(&self) -> usize {
+        if self.chr_ram_battery_backed() {
+            self.header.chr_ram_size.battery_backed as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        // The error can't be returned from here, so callers who need to handle it should eject
+        // the cartridge or flush it themselves
+        if let Err(e) = self.flush_save() {
+            error!(self.log, "error" => format!("{}", e); "failed to flush save");
+        }
+    }
+}
+
+/// Copies as much of `data` into the first `len` bytes of `ram` as fits, and advances `data`
+/// past it
+fn fill(ram: Option<&mut [u8]>, len: usize, data: &mut &[u8]) {
+    if let Some(ram) = ram {
+        let len = cmp::min(cmp::min(ram.len(), len), data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        *data = &data[len..];
+    }
+}
+
+/// Describes how the two physical nametables in the console are arranged in the PPU address
+/// space
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Mirroring {
+    /// $2000 and $2400 share the first nametable, $2800 and $2C00 share the second
+    /// (a "vertical arrangement")
+    Horizontal,
+
+    /// $2000 and $2800 share the first nametable, $2400 and $2C00 share the second
+    /// (a "horizontal arrangement")
+    Vertical,
+
+    /// All four nametables map to the first nametable
+    SingleScreenLower,
+
+    /// All four nametables map to the second nametable
+    SingleScreenUpper,
+
+    /// The cartridge provides an additional 2K of VRAM so that all four nametables are distinct
+    FourScreen
+}
+
+impl Mirroring {
+    /// Determines the mirroring described by the iNES/NES 2.0 header
+    pub fn from_header(header: &nes::RomHeader) -> Mirroring {
+        if header.four_screen_vram {
+            Mirroring::FourScreen
+        } else if header.vertical_arrangement {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    /// Translates a nametable address ($2000-$3EFF) into an offset within nametable VRAM
+    pub fn translate(self, addr: u64) -> u64 {
+        let table = (addr >> 10) & 0x03;
+        let page = match self {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 0x01,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table
+        };
+        (page * 0x0400) | (addr & 0x03FF)
+    }
 }
 
 pub trait Mapper {
     fn name(&self) -> &'static str;
 
+    /// Gets the current nametable mirroring
+    fn mirroring(&self) -> Mirroring;
+
     /// Gets a `Memory` representing the active PRG banks
     fn prg(&self) -> &mem::Memory;
 
@@ -40,24 +259,104 @@ pub trait Mapper {
 
     /// Gets a mutable `Memory` representing the active CHR banks
     fn chr_mut(&mut self) -> &mut mem::Memory;
+
+    /// Gets the contents of all of the cartridge's PRG RAM, if it has any
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Gets the contents of all of the cartridge's PRG RAM for modification, if it has any
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Gets the contents of all of the cartridge's CHR RAM, if it has CHR RAM in place of CHR
+    /// ROM
+    fn chr_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Gets the contents of all of the cartridge's CHR RAM for modification, if it has CHR RAM
+    /// in place of CHR ROM
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Notifies the mapper that PPU address line A12 went from low to high after being held
+    /// low for `low_cycles` PPU cycles
+    fn ppu_a12_rising(&mut self, _low_cycles: u64) {}
+
+    /// Notifies the mapper that the PPU is about to read `addr` while rendering, on PPU cycle
+    /// `cycle`
+    fn ppu_fetch(&mut self, _addr: u64, _cycle: u64) {}
+
+    /// Notifies the mapper of a CPU write to PPU register `reg` (0-7), which some mappers watch
+    /// to follow the PPU's configuration
+    fn ppu_register_write(&mut self, _reg: u64, _val: u8) {}
+
+    /// Reads from the nametables ($2000-$3EFF), which come from the console's VRAM arranged
+    /// according to the mirroring unless the cartridge provides its own
+    fn read_nametable(&self, vram: &mem::Memory, addr: u64) -> mem::Result<u8> {
+        vram.get_u8(self.mirroring().translate(addr))
+    }
+
+    /// Writes to the nametables ($2000-$3EFF), which come from the console's VRAM arranged
+    /// according to the mirroring unless the cartridge provides its own
+    fn write_nametable(&mut self, vram: &mut mem::Memory, addr: u64, val: u8) -> mem::Result<()> {
+        vram.set_u8(self.mirroring().translate(addr), val)
+    }
+
+    /// Notifies the mapper of the CPU cycle on which the next write to its PRG occurs
+    ///
+    /// Writes made by a single instruction (such as the two writes of a read-modify-write
+    /// instruction) are all reported on the instruction's final cycle.
+    fn cpu_cycle(&mut self, _cycle: u64) {}
+
+    /// Clocks the mapper for one CPU cycle, for mappers with counters which count CPU cycles
+    ///
+    /// Cycles are clocked as the CPU runs them, catching up before each access to the cartridge
+    /// and at the end of each instruction, so a write is seen after the cycles before it.
+    fn clock(&mut self) {}
+
+    /// Returns a value indicating if the mapper is asserting the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Clocks any sound hardware on the cartridge for one CPU cycle, and gets its output level
+    ///
+    /// The level is relative to the APU's mixed output, where 1.0 is as loud as the APU can be.
+    fn expansion_audio(&mut self) -> f32 {
+        0.0
+    }
 }
 
 impl Cartridge {
-    pub fn new(header: nes::RomHeader, mapper: Box<Mapper>) -> Cartridge {
+    pub fn new(header: nes::RomHeader, mapper: Box<Mapper>, logger: Option<slog::Logger>) -> Cartridge {
         Cartridge {
             header: header,
             mapper: mapper,
+            save: None,
+            saved: Vec::new(),
+            log: unwrap_logger!(logger).new(o!("cartridge" => true))
         }
     }
 
-    /// Consumes the provided `Rom` and uses it to build a `Cartridge` to execute
+    /// Consumes the provided `Rom` and uses it to build a `Cartridge` to execute, with one of
+    /// the built-in mappers
     pub fn load(rom: nes::Rom, logger: Option<slog::Logger>) -> Result<Cartridge> {
+        Cartridge::load_with(rom, &Registry::new(), logger)
+    }
+
+    /// Consumes the provided `Rom` and uses it to build a `Cartridge` to execute, with a mapper
+    /// from `registry`
+    pub fn load_with(rom: nes::Rom, registry: &Registry, logger: Option<slog::Logger>) -> Result<Cartridge> {
         let log = unwrap_logger!(logger);
 
         // Pull apart the rom
         let nes::Rom { header, prg, chr } = rom;
 
-        let mapper = create_mapper(&header, prg, chr, log.clone());
+        let mapper = registry.create(&header, prg, chr, log.clone());
 
         match mapper {
             Some(m) => {
@@ -67,7 +366,7 @@ impl Cartridge {
                     "mapper" => m.name();
                     "loaded mapper {}.{} {}", header.cartridge.mapper, header.cartridge.submapper, m.name());
 
-                Ok(Cartridge { header: header, mapper: m })
+                Ok(Cartridge::new(header, m, Some(log)))
             },
             None => {
                 error!(log,
@@ -81,9 +380,173 @@ impl Cartridge {
     }
 }
 
-fn create_mapper(header: &nes::RomHeader, prg: Vec<u8>, _chr: Vec<u8>, log: slog::Logger) -> Option<Box<Mapper>> {
-    match (header.cartridge.mapper, header.cartridge.submapper) {
-        (0, _) => Some(Box::new(NRom::new(0x2000, prg, Some(log)))),
-        _ => None
+/// Gets the size of the PRG RAM on the cartridge, which is assumed to be 8K unless an NES 2.0
+/// header says otherwise
+pub fn prg_ram_size(header: &nes::RomHeader) -> usize {
+    match header.version {
+        nes::rom::Version::NES2 => header.prg_ram_size.total as usize,
+        _ => 0x2000
     }
-}
\ No newline at end of file
+}
+
+/// Determines whether the PRG ROM drives the data bus while the CPU writes to the mapper's
+/// latch, which submappers 1 and 2 of the discrete logic mappers specify
+pub fn bus_conflicts(header: &nes::RomHeader) -> bool {
+    match header.cartridge.submapper {
+        1 => false,
+        2 => true,
+        _ => header.cartridge.bus_conflicts
+    }
+}
+
+/// Gets the size of the CHR RAM on the cartridge (which is only used if there is no CHR ROM),
+/// which is assumed to be 8K unless an NES 2.0 header says otherwise
+///
+/// The size includes any battery-backed CHR RAM, which is allocated along with the rest, ahead
+/// of the volatile part.
+pub fn chr_ram_size(header: &nes::RomHeader) -> usize {
+    match header.version {
+        nes::rom::Version::NES2 if header.chr_ram_size.total != 0 => header.chr_ram_size.total as usize,
+        _ => 0x2000
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::io;
+
+    use systems::nes::{Cartridge,SaveStorage,MemoryStorage,Nes,load_rom};
+
+    /// Creates ROM of `banks` banks of `size` bytes, with the number of each bank in its first
+    /// byte
+    pub fn numbered_banks(banks: usize, size: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * size];
+        for i in 0..banks {
+            rom[i * size] = i as u8;
+        }
+        rom
+    }
+
+    /// Loads a cartridge for iNES mapper `mapper`, with 32K of PRG ROM and 8K of CHR ROM
+    pub fn cartridge(mapper: u8) -> Cartridge {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, mapper << 4, mapper & 0xF0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x8000 + 0x2000]);
+        Cartridge::load(load_rom(&mut &data[..]).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    pub fn chr_rom_is_loaded_from_file() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000]);
+        data.extend(numbered_banks(2, 0x1000));
+        let cart = Cartridge::load(load_rom(&mut &data[..]).unwrap(), None).unwrap();
+
+        assert!(!cart.has_chr_ram());
+        assert_eq!(Ok(1), cart.mapper.chr().get_u8(0x1000));
+    }
+
+    #[test]
+    pub fn battery_backed_chr_ram_is_allocated() {
+        // NES 2.0, no CHR ROM, 8K of battery-backed CHR RAM
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x70, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000]);
+        let mut cart = Cartridge::load(load_rom(&mut &data[..]).unwrap(), None).unwrap();
+
+        assert!(cart.chr_ram_battery_backed());
+        cart.mapper.chr_mut().set_u8(0x1FFF, 0x42).unwrap();
+        assert_eq!(Ok(0x42), cart.mapper.chr().get_u8(0x1FFF));
+    }
+
+    /// Creates an NROM cartridge, with 8K of PRG RAM which is battery-backed if `battery` is set
+    fn nrom(battery: bool) -> Cartridge {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, if battery { 0x02 } else { 0x00 }, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000 + 0x2000]);
+        Cartridge::load(load_rom(&mut &data[..]).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    pub fn battery_ram_is_loaded_on_insert() {
+        let mut save = vec![0; 0x2000];
+        save[0x10] = 0x42;
+        let mut cart = nrom(true);
+        cart.set_save_storage(Box::new(MemoryStorage::with_contents(save)));
+
+        let mut nes = Nes::new(None);
+        nes.load(cart);
+        assert_eq!(Ok(0x42), nes.mem().get_u8(0x6010));
+    }
+
+    #[test]
+    pub fn battery_ram_is_flushed_on_eject_and_drop() {
+        let storage = MemoryStorage::new();
+        let mut cart = nrom(true);
+        cart.set_save_storage(Box::new(storage.clone()));
+
+        let mut nes = Nes::new(None);
+        nes.load(cart);
+        nes.flush_save().unwrap();
+        assert_eq!(None, storage.contents());
+
+        nes.mem_mut().set_u8(0x6010, 0x42).unwrap();
+        nes.eject();
+        assert_eq!(Some(0x42), storage.contents().map(|s| s[0x10]));
+
+        let mut cart = nrom(true);
+        cart.set_save_storage(Box::new(storage.clone()));
+        cart.load_save().unwrap();
+        cart.mapper.prg_mut().set_u8(0x6010, 0x24).unwrap();
+        drop(cart);
+        assert_eq!(Some(0x24), storage.contents().map(|s| s[0x10]));
+    }
+
+    #[test]
+    pub fn only_battery_backed_chr_ram_is_saved() {
+        // NES 2.0, no CHR ROM, 8K of battery-backed and 8K of volatile CHR RAM
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000]);
+        let storage = MemoryStorage::new();
+        let mut cart = Cartridge::load(load_rom(&mut &data[..]).unwrap(), None).unwrap();
+        cart.set_save_storage(Box::new(storage.clone()));
+
+        assert_eq!(Some(0x4000), cart.mapper.chr_ram().map(|ram| ram.len()));
+        cart.mapper.chr_mut().set_u8(0x0010, 0x42).unwrap();
+        cart.flush_save().unwrap();
+
+        let save = storage.contents().unwrap();
+        assert_eq!(0x2000, save.len());
+        assert_eq!(0x42, save[0x10]);
+    }
+
+    /// A storage which can't be written to
+    struct ReadOnlyStorage;
+
+    impl SaveStorage for ReadOnlyStorage {
+        fn load(&mut self) -> io::Result<Option<Vec<u8>>> { Ok(None) }
+        fn save(&mut self, _data: &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"))
+        }
+    }
+
+    #[test]
+    pub fn failed_flush_is_reported() {
+        let mut cart = nrom(true);
+        cart.set_save_storage(Box::new(ReadOnlyStorage));
+        cart.mapper.prg_mut().set_u8(0x6010, 0x42).unwrap();
+
+        assert_eq!(io::ErrorKind::PermissionDenied, cart.flush_save().unwrap_err().kind());
+
+        // Dropping the cartridge tries again, and can only log the error
+        drop(cart);
+    }
+
+    #[test]
+    pub fn ram_is_not_saved_without_battery() {
+        let storage = MemoryStorage::new();
+        let mut cart = nrom(false);
+        cart.set_save_storage(Box::new(storage.clone()));
+
+        cart.mapper.prg_mut().set_u8(0x6010, 0x42).unwrap();
+        cart.flush_save().unwrap();
+        assert_eq!(None, storage.contents());
+    }
+}
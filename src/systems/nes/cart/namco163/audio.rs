@@ -0,0 +1,191 @@
+use std::cell::Cell;
+
+/// The number of CPU cycles taken to update (and output) each channel
+const CHANNEL_CYCLES: u32 = 15;
+
+/// The output level of one step of a channel's output, relative to the APU's mixed output
+///
+/// The level varies a great deal between boards, so this is a compromise.
+const LEVEL_PER_STEP: f32 = 0.0025;
+
+/// The address of the first channel's registers in sound RAM (the last channel's are at $78)
+const CHANNEL_REGISTERS: usize = 0x40;
+
+/// Represents the sound hardware in the Namco 163: up to eight wavetable channels, whose
+/// registers and 4-bit samples share 128 bytes of sound RAM
+///
+/// The channels are not mixed: the chip updates and outputs one channel at a time, each for 15
+/// CPU cycles, so with many channels enabled each is heard for only part of the time, and the
+/// switching between them can be heard as a high pitched whine. The channels enabled are the
+/// last N, where N is set in the register at $7F.
+pub struct Audio {
+    ram: [u8; 128],
+    address: Cell<u8>,
+    auto_increment: bool,
+    disabled: bool,
+    channel: usize,
+    divider: u32,
+    level: f32
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio {
+            ram: [0; 128],
+            address: Cell::new(0),
+            auto_increment: false,
+            disabled: false,
+            channel: 7,
+            divider: CHANNEL_CYCLES,
+            level: 0.0
+        }
+    }
+
+    /// Sets the address in sound RAM accessed through the data port, and whether it is
+    /// incremented after each access ($F800)
+    pub fn write_address(&mut self, val: u8) {
+        self.address.set(val & 0x7F);
+        self.auto_increment = val & 0x80 != 0;
+    }
+
+    /// Reads from sound RAM through the data port ($4800)
+    pub fn read_data(&self) -> u8 {
+        let val = self.ram[self.address.get() as usize];
+        self.increment();
+        val
+    }
+
+    /// Writes to sound RAM through the data port ($4800)
+    pub fn write_data(&mut self, val: u8) {
+        self.ram[self.address.get() as usize] = val;
+        self.increment();
+    }
+
+    /// Silences the output while `disabled` is set ($E000)
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// Clocks the sound hardware for one CPU cycle, and gets the output level relative to the
+    /// APU's mixed output
+    pub fn clock(&mut self) -> f32 {
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = CHANNEL_CYCLES;
+            let enabled = ((self.ram[0x7F] >> 4) & 0x07) as usize + 1;
+            self.channel = if self.channel <= 8 - enabled { 7 } else { self.channel - 1 };
+            let output = self.update_channel(self.channel);
+            self.level = output as f32 * LEVEL_PER_STEP;
+        }
+
+        if self.disabled { 0.0 } else { self.level }
+    }
+
+    fn increment(&self) {
+        if self.auto_increment {
+            self.address.set((self.address.get() + 1) & 0x7F);
+        }
+    }
+
+    /// Advances a channel's phase, and gets its output
+    fn update_channel(&mut self, channel: usize) -> i32 {
+        let regs = CHANNEL_REGISTERS + channel * 8;
+        let frequency = self.ram[regs] as u32
+            | (self.ram[regs + 2] as u32) << 8
+            | ((self.ram[regs + 4] & 0x03) as u32) << 16;
+        let mut phase = self.ram[regs + 1] as u32
+            | (self.ram[regs + 3] as u32) << 8
+            | (self.ram[regs + 5] as u32) << 16;
+        let length = 256 - (self.ram[regs + 4] & 0xFC) as u32;
+
+        // The phase is 8.16 fixed point, in samples
+        phase = (phase + frequency) % (length << 16);
+        self.ram[regs + 1] = phase as u8;
+        self.ram[regs + 3] = (phase >> 8) as u8;
+        self.ram[regs + 5] = (phase >> 16) as u8;
+
+        // Samples are packed two to a byte, the first in the low nibble
+        let index = (((phase >> 16) + self.ram[regs + 6] as u32) & 0xFF) as usize;
+        let sample = (self.ram[index >> 1] >> ((index & 0x01) * 4)) & 0x0F;
+        let volume = (self.ram[regs + 7] & 0x0F) as i32;
+        (sample as i32 - 8) * volume
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::namco163::audio::Audio;
+
+    fn levels(audio: &mut Audio, cycles: usize) -> Vec<f32> {
+        (0..cycles).map(|_| audio.clock()).collect()
+    }
+
+    /// Sets up channel `channel` to play a wave of 16 samples stored at sample `wave`, at
+    /// `volume`, with a frequency of one sample per update
+    fn channel(audio: &mut Audio, channel: u8, wave: u8, volume: u8) {
+        let regs = 0x40 + channel * 8;
+        audio.write_address(0x80 | regs);
+        for val in [0x00, 0x00, 0x00, 0x00, 0xF1, 0x00, wave, volume].iter() {
+            audio.write_data(*val);
+        }
+    }
+
+    #[test]
+    pub fn data_port_increments_address() {
+        let mut audio = Audio::new();
+        audio.write_address(0x90);
+        audio.write_data(1);
+        audio.write_data(2);
+
+        audio.write_address(0x90);
+        assert_eq!(1, audio.read_data());
+        assert_eq!(2, audio.read_data());
+        audio.write_address(0x10);
+        assert_eq!(1, audio.read_data());
+        assert_eq!(1, audio.read_data());
+    }
+
+    #[test]
+    pub fn channel_plays_wave_from_sound_ram() {
+        let mut audio = Audio::new();
+        // A square wave: eight samples of 15 then eight of 0
+        audio.write_address(0x80);
+        for val in [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00].iter() {
+            audio.write_data(*val);
+        }
+        channel(&mut audio, 7, 0, 0x0F);
+
+        // The channel is first updated on the fifteenth cycle, starting from the second sample
+        let levels = levels(&mut audio, 15 * 16);
+        let updates: Vec<f32> = (0..16).map(|n| levels[15 * n + 14]).collect();
+        assert!(updates[0..7].iter().all(|&l| l > 0.0));
+        assert!(updates[7..15].iter().all(|&l| l < 0.0));
+        assert!(updates[15] > 0.0);
+        assert_eq!(7.0 * 15.0 * 0.0025, levels[20]);
+    }
+
+    #[test]
+    pub fn channels_are_output_in_turn() {
+        let mut audio = Audio::new();
+        audio.write_address(0x00);
+        audio.write_data(0xFF);
+        channel(&mut audio, 7, 0, 0x0F);
+        channel(&mut audio, 6, 0x40, 0x0F);
+        audio.write_address(0x7F);
+        audio.write_data(0x1F);
+
+        let levels = levels(&mut audio, 15 * 4);
+        assert!(levels[15] < 0.0);
+        assert!(levels[30] > 0.0);
+        assert!(levels[45] < 0.0);
+    }
+
+    #[test]
+    pub fn disable_silences_output() {
+        let mut audio = Audio::new();
+        channel(&mut audio, 7, 0x40, 0x0F);
+        audio.set_disabled(true);
+
+        assert!(levels(&mut audio, 100).iter().all(|&l| l == 0.0));
+    }
+}
@@ -0,0 +1,433 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use self::audio::Audio;
+
+mod audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Emulates the Namco 163 (iNES mapper 19)
+///
+/// PRG is switched in three 8K banks with the last fixed at $E000, and CHR in eight 1K banks.
+/// Each of the four nametables is also switched, either to a 1K bank of CHR ROM or to one of
+/// the pages of the console's VRAM (bank numbers $E0 and above). The IRQ counter counts CPU
+/// cycles up to $7FFF, and the chip has up to eight channels of wavetable audio.
+///
+/// CHR bank numbers of $E0 and above select the console's VRAM for the pattern tables too
+/// (unless disabled through $E800), but that isn't supported, and they select CHR ROM instead.
+pub struct Namco163 {
+    chr_registers: [u8; 8],
+    nametable_registers: [u8; 4],
+    prg_registers: [u8; 3],
+    write_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Audio,
+
+    prg_rom: Banks,
+    prg_ram: Option<Banks>,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Namco163 {
+    /// Creates a Namco 163 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, logger: Option<slog::Logger>) -> Namco163 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+        let prg_ram = if prg_ram_size == 0 {
+            None
+        } else {
+            Some(Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1))
+        };
+
+        let mut namco163 = Namco163 {
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            nametable_registers: [0xE0, 0xE1, 0xE0, 0xE1],
+            prg_registers: [0, 1, 2],
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Audio::new(),
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: prg_ram,
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => "Namco 163", "cartridge" => true))
+        };
+        namco163.update_banks();
+        namco163
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr & 0xF800),
+            "val" => format!("${:02X}", val));
+
+        match addr & 0xF800 {
+            0x8000...0xB800 => self.chr_registers[((addr - 0x8000) >> 11) as usize] = val,
+            0xC000...0xD800 => self.nametable_registers[((addr - 0xC000) >> 11) as usize] = val,
+            0xE000 => {
+                self.prg_registers[0] = val & 0x3F;
+                self.audio.set_disabled(val & 0x40 != 0);
+            },
+            0xE800 => self.prg_registers[1] = val & 0x3F,
+            0xF000 => self.prg_registers[2] = val & 0x3F,
+            _ => {
+                self.write_protect = val;
+                self.audio.write_address(val);
+            }
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let last = self.prg_rom.bank_count() - 1;
+        for slot in 0..3 {
+            self.prg_rom.select(slot, self.prg_registers[slot] as usize);
+        }
+        self.prg_rom.select(3, last);
+
+        for slot in 0..8 {
+            self.chr.select(slot, self.chr_registers[slot] as usize);
+        }
+    }
+
+    /// Determines whether a write to PRG RAM at `addr` is allowed, which requires the upper
+    /// bits of $F800 to be $4, and the bit for the 2K section containing `addr` to be clear
+    fn ram_writable(&self, addr: u64) -> bool {
+        let section = (addr - 0x6000) >> 11;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << section) == 0
+    }
+
+    /// Gets the page of the console's VRAM selected for nametable `table`, if any
+    fn vram_page(&self, table: usize) -> Option<u64> {
+        let bank = self.nametable_registers[table];
+        if bank >= 0xE0 {
+            Some((bank & 0x01) as u64)
+        } else {
+            None
+        }
+    }
+}
+
+impl nes::Mapper for Namco163 {
+    fn name(&self) -> &'static str { "Namco 163" }
+
+    /// Gets the mirroring which best describes the nametable registers
+    ///
+    /// Nametables are read and written through `read_nametable` and `write_nametable`, so this
+    /// is only descriptive.
+    fn mirroring(&self) -> nes::Mirroring {
+        let pages = (self.vram_page(0), self.vram_page(1), self.vram_page(2), self.vram_page(3));
+        match pages {
+            (Some(0), Some(0), Some(1), Some(1)) => nes::Mirroring::Horizontal,
+            (Some(0), Some(0), Some(0), Some(0)) => nes::Mirroring::SingleScreenLower,
+            (Some(1), Some(1), Some(1), Some(1)) => nes::Mirroring::SingleScreenUpper,
+            _ => nes::Mirroring::Vertical
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.as_ref().and_then(|ram| ram.ram_data())
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.as_mut().and_then(|ram| ram.ram_data_mut())
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn read_nametable(&self, vram: &mem::Memory, addr: u64) -> mem::Result<u8> {
+        let table = ((addr >> 10) & 0x03) as usize;
+        match self.vram_page(table) {
+            Some(page) => vram.get_u8((page * 0x0400) | (addr & 0x03FF)),
+            None => {
+                let bank = self.nametable_registers[table] as usize;
+                Ok(self.chr.get_absolute(bank * CHR_BANK_SIZE + (addr & 0x03FF) as usize))
+            }
+        }
+    }
+
+    fn write_nametable(&mut self, vram: &mut mem::Memory, addr: u64, val: u8) -> mem::Result<()> {
+        match self.vram_page(((addr >> 10) & 0x03) as usize) {
+            Some(page) => vram.set_u8((page * 0x0400) | (addr & 0x03FF), val),
+            // Nametables in CHR ROM can't be written
+            None => Ok(())
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                trace!(self.log, "irq");
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+impl mem::Memory for Namco163 {
+    fn len(&self) -> u64 { 0xB800 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x4800 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on Namco 163 cartridge",
+                format!("${:4X} is below the addressable range of 0x4800-0xFFFF", addr)))
+        } else if addr < 0x6000 {
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Registers",
+                "action" => "read");
+            Ok(match addr & 0xF800 {
+                0x4800 => self.audio.read_data(),
+                0x5000 => self.irq_counter as u8,
+                _ => ((self.irq_counter >> 8) as u8) | if self.irq_enabled { 0x80 } else { 0 }
+            })
+        } else if addr < 0x8000 {
+            match self.prg_ram {
+                Some(ref ram) => {
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "RAM",
+                        "action" => "read");
+                    ram.get_u8(addr - 0x6000)
+                },
+                None => {
+                    // Nothing drives the data bus, so it keeps the high byte of the address
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "Open Bus",
+                        "action" => "read");
+                    Ok((addr >> 8) as u8)
+                }
+            }
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x4800 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on Namco 163 cartridge",
+                format!("${:4X} is below the addressable range on Namco 163 cartridge", addr)))
+        } else if addr < 0x6000 {
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Registers",
+                "action" => "write");
+            match addr & 0xF800 {
+                0x4800 => self.audio.write_data(val),
+                // Writing either half of the IRQ counter acknowledges the IRQ
+                0x5000 => {
+                    self.irq_counter = (self.irq_counter & 0x7F00) | val as u16;
+                    self.irq_pending = false;
+                },
+                _ => {
+                    self.irq_counter = (self.irq_counter & 0x00FF) | (((val & 0x7F) as u16) << 8);
+                    self.irq_enabled = val & 0x80 != 0;
+                    self.irq_pending = false;
+                }
+            }
+            Ok(())
+        } else if addr < 0x8000 {
+            let writable = self.ram_writable(addr);
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => if writable { "RAM" } else { "Protected RAM" },
+                "action" => "write");
+            match self.prg_ram {
+                Some(ref mut ram) if writable => ram.set_u8(addr - 0x6000, val),
+                _ => Ok(())
+            }
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem;
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::{cartridge,numbered_banks};
+    use systems::nes::cart::namco163::Namco163;
+    use systems::nes::memmap::MemoryMap;
+
+    fn namco163() -> Namco163 {
+        Namco163::new(numbered_banks(32, 0x2000), numbered_banks(256, 0x0400), 0x2000, 0x2000, None)
+    }
+
+    #[test]
+    pub fn registers_select_banks() {
+        let mut namco163 = namco163();
+        namco163.set_u8(0x8800, 40).unwrap();
+        namco163.set_u8(0xE000, 3).unwrap();
+        namco163.set_u8(0xE800, 5).unwrap();
+        namco163.set_u8(0xF000, 7).unwrap();
+
+        assert_eq!(Ok(40), namco163.chr().get_u8(0x0400));
+        assert_eq!(Ok(3), namco163.get_u8(0x8000));
+        assert_eq!(Ok(5), namco163.get_u8(0xA000));
+        assert_eq!(Ok(7), namco163.get_u8(0xC000));
+        assert_eq!(Ok(31), namco163.get_u8(0xE000));
+    }
+
+    #[test]
+    pub fn nametables_map_vram_or_chr_rom() {
+        let mut namco163 = namco163();
+        let mut vram = mem::Fixed::new(0x800);
+        namco163.set_u8(0xC000, 0xE0).unwrap();
+        namco163.set_u8(0xC800, 0xE0).unwrap();
+        namco163.set_u8(0xD000, 0xE1).unwrap();
+        namco163.set_u8(0xD800, 0x21).unwrap();
+        assert_eq!(Mirroring::Vertical, namco163.mirroring());
+
+        namco163.write_nametable(&mut vram, 0x2401, 0x42).unwrap();
+        namco163.write_nametable(&mut vram, 0x2801, 0x24).unwrap();
+        namco163.write_nametable(&mut vram, 0x2C00, 0x99).unwrap();
+        assert_eq!(Ok(0x42), vram.get_u8(0x0001));
+        assert_eq!(Ok(0x24), vram.get_u8(0x0401));
+
+        assert_eq!(Ok(0x42), namco163.read_nametable(&vram, 0x2001));
+        assert_eq!(Ok(0x21), namco163.read_nametable(&vram, 0x2C00));
+    }
+
+    #[test]
+    pub fn nametables_in_missing_chr_read_zero() {
+        let mut namco163 = Namco163::new(numbered_banks(32, 0x2000), Vec::new(), 0x2000, 0, None);
+        let vram = mem::Fixed::new(0x800);
+        namco163.set_u8(0xC000, 0x05).unwrap();
+        assert_eq!(Ok(0), namco163.read_nametable(&vram, 0x2010));
+    }
+
+    #[test]
+    pub fn prg_ram_is_write_protected() {
+        let mut namco163 = namco163();
+        namco163.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x00), namco163.get_u8(0x6000));
+
+        namco163.set_u8(0xF800, 0x41).unwrap();
+        namco163.set_u8(0x6000, 0x42).unwrap();
+        namco163.set_u8(0x6800, 0x24).unwrap();
+        assert_eq!(Ok(0x00), namco163.get_u8(0x6000));
+        assert_eq!(Ok(0x24), namco163.get_u8(0x6800));
+    }
+
+    #[test]
+    pub fn irq_is_raised_when_counter_reaches_7fff() {
+        let mut namco163 = namco163();
+        namco163.set_u8(0x5000, 0xFD).unwrap();
+        namco163.set_u8(0x5800, 0xFF).unwrap();
+
+        namco163.clock();
+        assert!(!namco163.irq());
+        namco163.clock();
+        assert!(namco163.irq());
+        namco163.clock();
+        assert_eq!(Ok(0xFF), namco163.get_u8(0x5800));
+        assert_eq!(Ok(0xFF), namco163.get_u8(0x5000));
+
+        namco163.set_u8(0x5000, 0).unwrap();
+        assert!(!namco163.irq());
+    }
+
+    #[test]
+    pub fn irq_is_raised_counter_cycles_after_reload() {
+        let mut mem = MemoryMap::new(None);
+        mem.load(cartridge(19));
+
+        mem.set_cycle(1000);
+        mem.set_u8(0x5000, 0xF5).unwrap();
+        mem.set_u8(0x5800, 0xFF).unwrap();
+
+        // Reads see the counter as of their own cycle
+        mem.set_cycle(1004);
+        assert_eq!(Ok(0xF9), mem.get_u8(0x5000));
+
+        // $7FF5 reaches $7FFF after 10 cycles
+        mem.run_cart(1009);
+        assert!(!mem.irq());
+        mem.run_cart(1010);
+        assert!(mem.irq());
+    }
+}
@@ -0,0 +1,187 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+
+/// Emulates the AVE NINA-001 board (iNES mapper 34, submapper 1)
+///
+/// The bank registers sit at the top of the 8K of PRG RAM, so writes to $7FFD-$7FFF are stored
+/// in RAM as well as selecting the 32K PRG ROM bank and the two 4K CHR ROM banks.
+pub struct Nina001 {
+    prg_rom: Banks,
+    prg_ram: Banks,
+    chr: Banks,
+    mirroring: nes::Mirroring,
+    log: slog::Logger
+}
+
+impl Nina001 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, logger: Option<slog::Logger>) -> Nina001 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, 0x1000, 2)
+        } else {
+            Banks::new(chr_rom, 0x1000, 2, false)
+        };
+        let mut nina = Nina001 {
+            prg_rom: Banks::new(prg_rom, 0x8000, 1, false),
+            prg_ram: Banks::ram(0x2000, 0x2000, 1),
+            chr: chr,
+            mirroring: mirroring,
+            log: unwrap_logger!(logger).new(o!("mapper" => "NINA-001", "cartridge" => true))
+        };
+        nina.chr.select(1, 1);
+        nina
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        trace!(self.log,
+            "register write";
+            "vaddr" => format!("${:04X}", addr),
+            "val" => format!("${:02X}", val));
+        match addr {
+            0x7FFD => self.prg_rom.select(0, (val & 0x01) as usize),
+            0x7FFE => self.chr.select(0, (val & 0x0F) as usize),
+            0x7FFF => self.chr.select(1, (val & 0x0F) as usize),
+            _ => {}
+        }
+    }
+}
+
+impl nes::Mapper for Nina001 {
+    fn name(&self) -> &'static str { "NINA-001" }
+
+    fn mirroring(&self) -> nes::Mirroring { self.mirroring }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+}
+
+impl mem::Memory for Nina001 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on NINA-001 cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "read");
+            self.prg_ram.get_u8(addr - 0x6000)
+        } else {
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank(0),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(addr - 0x8000)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on NINA-001 cartridge",
+                format!("${:4X} is below the addressable range on NINA-001 cartridge", addr)))
+        } else if addr < 0x8000 {
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "write");
+            self.write_register(addr, val);
+            self.prg_ram.set_u8(addr - 0x6000, val)
+        } else {
+            // ROM! Can't write to that!
+            error!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::MemoryNotWritable),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::MemoryNotWritable,
+                "cannot write to cartridge PRG ROM",
+                format!("${:4X} is in the read-only memory on NINA-001 cartridge", addr)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::nina001::Nina001;
+
+    #[test]
+    pub fn registers_select_prg_and_chr_banks() {
+        let mut nina = Nina001::new(numbered_banks(2, 0x8000), numbered_banks(16, 0x1000), 0x2000, Mirroring::Vertical, None);
+        nina.set_u8(0x7FFD, 1).unwrap();
+        nina.set_u8(0x7FFE, 7).unwrap();
+        nina.set_u8(0x7FFF, 12).unwrap();
+
+        assert_eq!(Ok(1), nina.get_u8(0x8000));
+        assert_eq!(Ok(7), nina.chr().get_u8(0x0000));
+        assert_eq!(Ok(12), nina.chr().get_u8(0x1000));
+    }
+
+    #[test]
+    pub fn register_writes_are_stored_in_ram() {
+        let mut nina = Nina001::new(numbered_banks(2, 0x8000), numbered_banks(16, 0x1000), 0x2000, Mirroring::Vertical, None);
+        nina.set_u8(0x7FFE, 3).unwrap();
+
+        assert_eq!(Ok(3), nina.get_u8(0x7FFE));
+    }
+}
@@ -2,27 +2,38 @@ use slog;
 
 use mem;
 use systems::nes;
+use systems::nes::cart::banks::Banks;
 
 struct Prg {
-    ram: mem::Fixed,
+    ram: Vec<u8>,
     rom: mem::Fixed,
     log: slog::Logger,
 }
 
 pub struct NRom {
     prg: Prg,
-    chr: mem::Empty
+    chr: Banks,
+    mirroring: nes::Mirroring
 }
 
 impl NRom {
-    pub fn new(ram_size: usize, rom: Vec<u8>, logger: Option<slog::Logger>) -> NRom {
+    /// Creates an NROM board with `ram_size` bytes of PRG RAM, and the provided PRG ROM and CHR
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(ram_size: usize, rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, logger: Option<slog::Logger>) -> NRom {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, 0x2000, 1)
+        } else {
+            Banks::new(chr_rom, 0x2000, 1, false)
+        };
         NRom {
             prg: Prg {
-                ram: mem::Fixed::new(ram_size),
+                ram: vec![0; ram_size],
                 rom: mem::Fixed::from_contents(rom),
                 log: unwrap_logger!(logger).new(o!("mapper" => "NRom", "cartridge" => true))
             },
-            chr: mem::Empty
+            chr: chr,
+            mirroring: mirroring
         }
     }
 }
@@ -30,6 +41,8 @@ impl NRom {
 impl nes::Mapper for NRom {
     fn name(&self) -> &'static str { "NRom" }
 
+    fn mirroring(&self) -> nes::Mirroring { self.mirroring }
+
     fn prg(&self) -> &mem::Memory
     {
         return &self.prg;
@@ -49,6 +62,22 @@ impl nes::Mapper for NRom {
     {
         return &mut self.chr;
     }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        if self.prg.ram.is_empty() { None } else { Some(&self.prg.ram) }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if self.prg.ram.is_empty() { None } else { Some(&mut self.prg.ram) }
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
 }
 
 impl mem::Memory for Prg {
@@ -66,16 +95,24 @@ impl mem::Memory for Prg {
                     mem::ErrorKind::OutOfBounds,
                     "memory access out of range addressable on NROM cartridge",
                     format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 && self.ram.is_empty() {
+            // Nothing drives the data bus, so it keeps the high byte of the address
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Open Bus",
+                "action" => "read");
+            Ok((addr >> 8) as u8)
         } else if addr < 0x8000 {
             // RAM! Mirrored as needed
-            let eaddr = (addr - 0x6000) % self.ram.len();
+            let eaddr = (addr - 0x6000) % self.ram.len() as u64;
             trace!(self.log,
                 "read";
                 "vaddr" => format!("${:04X}", addr),
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "RAM",
                 "action" => "read");
-            self.ram.get_u8(eaddr)
+            Ok(self.ram[eaddr as usize])
         } else {
             // ROM! Mirrored again as needed
             let eaddr = (addr - 0x8000) % self.rom.len();
@@ -101,16 +138,24 @@ impl mem::Memory for Prg {
                 mem::ErrorKind::OutOfBounds,
                 "memory access out of range addressable on NROM cartridge",
                 format!("${:4X} is below the addressable range on NROM cartridge", addr)))
+        } else if addr < 0x8000 && self.ram.is_empty() {
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Unmapped",
+                "action" => "write");
+            Ok(())
         } else if addr < 0x8000 {
             // RAM! Mirrored as needed
-            let eaddr = (addr - 0x6000) % self.ram.len();
+            let eaddr = (addr - 0x6000) % self.ram.len() as u64;
             trace!(self.log,
                 "write";
                 "vaddr" => format!("${:04X}", addr),
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "RAM",
                 "action" => "write");
-            self.ram.set_u8(eaddr, val)
+            self.ram[eaddr as usize] = val;
+            Ok(())
         } else {
             // ROM! Can't write to that!
             error!(self.log,
@@ -125,3 +170,36 @@ impl mem::Memory for Prg {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::nrom::NRom;
+
+    #[test]
+    pub fn chr_rom_is_read_only() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x1FFF] = 0x42;
+        let mut nrom = NRom::new(0x2000, vec![0; 0x4000], chr, 0x2000, Mirroring::Vertical, None);
+
+        assert_eq!(Ok(0x42), nrom.chr().get_u8(0x1FFF));
+        assert!(nrom.chr_mut().set_u8(0x1FFF, 0x24).is_err());
+    }
+
+    #[test]
+    pub fn missing_prg_ram_reads_open_bus() {
+        let mut nrom = NRom::new(0, vec![0; 0x4000], vec![], 0x2000, Mirroring::Vertical, None);
+
+        nrom.prg_mut().set_u8(0x6010, 0x42).unwrap();
+        assert_eq!(Ok(0x60), nrom.prg().get_u8(0x6010));
+        assert!(nrom.prg_ram().is_none());
+    }
+
+    #[test]
+    pub fn chr_ram_is_provided_without_chr_rom() {
+        let mut nrom = NRom::new(0x2000, vec![0; 0x4000], vec![], 0x2000, Mirroring::Vertical, None);
+
+        nrom.chr_mut().set_u8(0x1FFF, 0x24).unwrap();
+        assert_eq!(Ok(0x24), nrom.chr().get_u8(0x1FFF));
+    }
+}
@@ -0,0 +1,252 @@
+use std::ops::Range;
+
+use slog;
+
+use systems::nes;
+use systems::nes::cart::{Mapper,Mirroring};
+use systems::nes::cart::{NRom,Mmc1,Mmc3,Mmc5,UxRom,CnRom,AxRom,GxRom,BnRom,Nina001,ColorDreams,Vrc4,Vrc6,Vrc7,Fme7,Namco163};
+use super::{prg_ram_size,chr_ram_size,bus_conflicts};
+
+/// Builds a mapper for a ROM from its header, PRG ROM and CHR ROM
+///
+/// `cart::prg_ram_size`, `cart::chr_ram_size` and `cart::bus_conflicts` work out the board
+/// details that most mappers need from the header.
+pub type Constructor = fn(&nes::RomHeader, Vec<u8>, Vec<u8>, slog::Logger) -> Box<Mapper>;
+
+/// Describes a mapper implementation, and the iNES mapper and submappers it is used for
+#[derive(Clone)]
+pub struct MapperEntry {
+    /// The iNES mapper number
+    pub mapper: u16,
+
+    /// The submappers handled by this entry (submapper 0 is used when the header doesn't
+    /// specify one)
+    pub submappers: Range<u8>,
+
+    /// A short description of the mapper, naming the chips or boards emulated
+    pub description: &'static str,
+
+    /// Indicates if boards using the mapper can have battery-backed save RAM
+    ///
+    /// This is for information only, such as listing the mappers. Whether a cartridge's RAM is
+    /// saved is decided by its ROM header.
+    pub battery: bool,
+
+    /// Builds the mapper
+    pub constructor: Constructor
+}
+
+impl MapperEntry {
+    /// Creates an entry for every submapper of `mapper`
+    pub fn new(mapper: u16, description: &'static str, battery: bool, constructor: Constructor) -> MapperEntry {
+        MapperEntry::with_submappers(mapper, 0..16, description, battery, constructor)
+    }
+
+    /// Creates an entry for the submappers in `submappers` of `mapper`
+    pub fn with_submappers(mapper: u16, submappers: Range<u8>, description: &'static str, battery: bool, constructor: Constructor) -> MapperEntry {
+        MapperEntry {
+            mapper: mapper,
+            submappers: submappers,
+            description: description,
+            battery: battery,
+            constructor: constructor
+        }
+    }
+
+    /// Determines whether this entry handles `submapper` of `mapper`
+    pub fn handles(&self, mapper: u16, submapper: u8) -> bool {
+        self.mapper == mapper && submapper >= self.submappers.start && submapper < self.submappers.end
+    }
+}
+
+/// Represents the set of mappers which cartridges can be built with
+///
+/// A registry starts out with the mappers built into this crate, and others (or replacements
+/// for the built-in ones) can be registered. When more than one entry handles a mapper and
+/// submapper, the one registered last is used.
+pub struct Registry {
+    entries: Vec<MapperEntry>
+}
+
+impl Registry {
+    /// Creates a registry containing the built-in mappers
+    pub fn new() -> Registry {
+        Registry {
+            entries: builtin_entries()
+        }
+    }
+
+    /// Creates a registry containing no mappers
+    pub fn empty() -> Registry {
+        Registry {
+            entries: Vec::new()
+        }
+    }
+
+    /// Registers a mapper, taking precedence over any registered before for the same mapper
+    /// and submappers
+    pub fn register(&mut self, entry: MapperEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Gets the registered mappers, in the order they were registered
+    pub fn entries(&self) -> &[MapperEntry] {
+        &self.entries
+    }
+
+    /// Finds the entry used to build `submapper` of `mapper`
+    pub fn find(&self, mapper: u16, submapper: u8) -> Option<&MapperEntry> {
+        self.entries.iter().rev().find(|e| e.handles(mapper, submapper))
+    }
+
+    /// Builds the mapper for the ROM with `header`, if one is registered
+    pub fn create(&self, header: &nes::RomHeader, prg: Vec<u8>, chr: Vec<u8>, log: slog::Logger) -> Option<Box<Mapper>> {
+        self.find(header.cartridge.mapper, header.cartridge.submapper)
+            .map(|entry| (entry.constructor)(header, prg, chr, log))
+    }
+}
+
+fn builtin_entries() -> Vec<MapperEntry> {
+    vec![
+        MapperEntry::new(0, "NROM", true, |header, prg, chr, log| {
+            Box::new(NRom::new(prg_ram_size(header), prg, chr, chr_ram_size(header), Mirroring::from_header(header), Some(log)))
+        }),
+        MapperEntry::new(1, "MMC1 (SxROM)", true, |header, prg, chr, log| {
+            Box::new(Mmc1::new(prg, chr, prg_ram_size(header), chr_ram_size(header), header.cartridge.submapper, Some(log)))
+        }),
+        MapperEntry::new(2, "UxROM", false, |header, prg, chr, log| {
+            Box::new(UxRom::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), bus_conflicts(header), Some(log)))
+        }),
+        MapperEntry::new(3, "CNROM", false, |header, prg, chr, log| {
+            Box::new(CnRom::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), bus_conflicts(header), Some(log)))
+        }),
+        MapperEntry::new(4, "MMC3 (TxROM)", true, |header, prg, chr, log| {
+            Box::new(Mmc3::new(prg, chr, prg_ram_size(header), chr_ram_size(header), Mirroring::from_header(header), header.cartridge.submapper, Some(log)))
+        }),
+        MapperEntry::new(5, "MMC5 (ExROM)", true, |header, prg, chr, log| {
+            // Most MMC5 games have 8K of PRG RAM or none, but a few have up to 64K, and iNES
+            // headers can't say which
+            let prg_ram_size = match header.version {
+                nes::rom::Version::NES2 => prg_ram_size(header),
+                _ => 0x10000
+            };
+            Box::new(Mmc5::new(prg, chr, prg_ram_size, chr_ram_size(header), Some(log)))
+        }),
+        MapperEntry::new(7, "AxROM", false, |header, prg, chr, log| {
+            Box::new(AxRom::new(prg, chr, chr_ram_size(header), bus_conflicts(header), Some(log)))
+        }),
+        MapperEntry::new(11, "Color Dreams", false, |header, prg, chr, log| {
+            Box::new(ColorDreams::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), header.cartridge.bus_conflicts, Some(log)))
+        }),
+        MapperEntry::new(19, "Namco 163", true, |header, prg, chr, log| {
+            Box::new(Namco163::new(prg, chr, prg_ram_size(header), chr_ram_size(header), Some(log)))
+        }),
+        MapperEntry::new(21, "VRC4a, VRC4c", true, vrc4),
+        MapperEntry::new(22, "VRC2a", false, vrc4),
+        MapperEntry::new(23, "VRC4e, VRC4f, VRC2b", true, vrc4),
+        MapperEntry::new(24, "VRC6a", true, vrc6),
+        MapperEntry::new(25, "VRC4b, VRC4d, VRC2c", true, vrc4),
+        MapperEntry::new(26, "VRC6b", true, vrc6),
+        // Submapper 0 leaves the board unspecified, but only NINA-001 has banked CHR ROM
+        MapperEntry::with_submappers(34, 0..1, "BNROM or NINA-001, by CHR size", false, |header, prg, chr, log| {
+            if chr.len() > 0x2000 {
+                Box::new(Nina001::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), Some(log)))
+            } else {
+                Box::new(BnRom::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), header.cartridge.bus_conflicts, Some(log)))
+            }
+        }),
+        MapperEntry::with_submappers(34, 1..2, "NINA-001", false, |header, prg, chr, log| {
+            Box::new(Nina001::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), Some(log)))
+        }),
+        MapperEntry::with_submappers(34, 2..16, "BNROM", false, |header, prg, chr, log| {
+            Box::new(BnRom::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), header.cartridge.bus_conflicts, Some(log)))
+        }),
+        MapperEntry::new(66, "GxROM", false, |header, prg, chr, log| {
+            Box::new(GxRom::new(prg, chr, chr_ram_size(header), Mirroring::from_header(header), header.cartridge.bus_conflicts, Some(log)))
+        }),
+        MapperEntry::new(69, "Sunsoft FME-7, 5B", true, |header, prg, chr, log| {
+            Box::new(Fme7::new(prg, chr, prg_ram_size(header), chr_ram_size(header), Some(log)))
+        }),
+        MapperEntry::new(85, "VRC7", true, |header, prg, chr, log| {
+            Box::new(Vrc7::new(prg, chr, prg_ram_size(header), chr_ram_size(header), header.cartridge.submapper, Some(log)))
+        })
+    ]
+}
+
+/// Builds a VRC2 or VRC4, whose wiring depends on the mapper number as well as the submapper
+fn vrc4(header: &nes::RomHeader, prg: Vec<u8>, chr: Vec<u8>, log: slog::Logger) -> Box<Mapper> {
+    Box::new(Vrc4::new(prg, chr, prg_ram_size(header), chr_ram_size(header), header.cartridge.mapper as u8, header.cartridge.submapper, Some(log)))
+}
+
+/// Builds a VRC6, whose wiring depends on the mapper number
+fn vrc6(header: &nes::RomHeader, prg: Vec<u8>, chr: Vec<u8>, log: slog::Logger) -> Box<Mapper> {
+    Box::new(Vrc6::new(prg, chr, prg_ram_size(header), chr_ram_size(header), header.cartridge.mapper as u8, Some(log)))
+}
+
+#[cfg(test)]
+mod test {
+    use slog;
+
+    use mem;
+    use systems::nes;
+    use systems::nes::{Cartridge,Mapper,Mirroring,load_rom};
+    use systems::nes::cart::{Registry,MapperEntry};
+
+    /// A mapper with unbanked PRG and no CHR, for registering
+    struct Custom {
+        prg: mem::Fixed,
+        chr: mem::Empty
+    }
+
+    impl Mapper for Custom {
+        fn name(&self) -> &'static str { "Custom" }
+        fn mirroring(&self) -> Mirroring { Mirroring::Horizontal }
+        fn prg(&self) -> &mem::Memory { &self.prg }
+        fn prg_mut(&mut self) -> &mut mem::Memory { &mut self.prg }
+        fn chr(&self) -> &mem::Memory { &self.chr }
+        fn chr_mut(&mut self) -> &mut mem::Memory { &mut self.chr }
+    }
+
+    fn custom(_header: &nes::RomHeader, prg: Vec<u8>, _chr: Vec<u8>, _log: slog::Logger) -> Box<Mapper> {
+        Box::new(Custom {
+            prg: mem::Fixed::from_contents(prg),
+            chr: mem::Empty
+        })
+    }
+
+    /// Creates an NES 2.0 ROM for `submapper` of `mapper` (up to $FF)
+    fn rom(mapper: u8, submapper: u8) -> nes::Rom {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, mapper << 4, (mapper & 0xF0) | 0x08, submapper << 4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x8000 + 0x2000]);
+        load_rom(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    pub fn builtin_mappers_are_found_by_submapper() {
+        let registry = Registry::new();
+        assert_eq!("MMC3 (TxROM)", registry.find(4, 4).unwrap().description);
+        assert_eq!("NINA-001", registry.find(34, 1).unwrap().description);
+        assert_eq!("BNROM", registry.find(34, 2).unwrap().description);
+        assert!(registry.find(0x142, 0).is_none());
+        assert!(Registry::empty().find(0, 0).is_none());
+    }
+
+    #[test]
+    pub fn registered_mappers_take_precedence() {
+        let mut registry = Registry::new();
+        registry.register(MapperEntry::with_submappers(4, 3..4, "Custom MMC3", false, custom));
+
+        assert_eq!("Custom MMC3", registry.find(4, 3).unwrap().description);
+        assert_eq!("MMC3 (TxROM)", registry.find(4, 4).unwrap().description);
+    }
+
+    #[test]
+    pub fn cartridge_is_built_with_registered_mapper() {
+        let mut registry = Registry::new();
+        assert!(Cartridge::load_with(rom(0xF0, 0), &registry, None).is_err());
+
+        registry.register(MapperEntry::new(0xF0, "Custom", false, custom));
+        let cart = Cartridge::load_with(rom(0xF0, 0), &registry, None).unwrap();
+        assert_eq!("Custom", cart.mapper.name());
+    }
+}
@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self,Read,Write};
+use std::path::{Path,PathBuf};
+use std::rc::Rc;
+
+/// Keeps the contents of a cartridge's battery-backed RAM between sessions
+pub trait SaveStorage {
+    /// Reads the saved contents, or `None` if nothing has been saved yet
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replaces the saved contents with `data`
+    fn save(&mut self, data: &[u8]) -> io::Result<()>;
+}
+
+/// Keeps battery-backed RAM in a file, conventionally a `.sav` file alongside the ROM
+pub struct FileStorage {
+    path: PathBuf
+}
+
+impl FileStorage {
+    /// Creates a storage which keeps the save in the file at `path`
+    pub fn new<P>(path: P) -> FileStorage where P: AsRef<Path> {
+        FileStorage {
+            path: path.as_ref().to_path_buf()
+        }
+    }
+
+    /// Creates a storage which keeps the save for the ROM at `rom_path` in a `.sav` file
+    /// with the same name
+    pub fn for_rom<P>(rom_path: P) -> FileStorage where P: AsRef<Path> {
+        FileStorage::new(rom_path.as_ref().with_extension("sav"))
+    }
+
+    /// Gets the path of the save file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl SaveStorage for FileStorage {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::File::open(&self.path) {
+            Ok(mut file) => {
+                let mut data = Vec::new();
+                try!(file.read_to_end(&mut data));
+                Ok(Some(data))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn save(&mut self, data: &[u8]) -> io::Result<()> {
+        // Write the new save alongside the old one and then swap it in, so the old save
+        // survives if writing fails part of the way through
+        let mut temp = OsString::from(self.path.as_os_str());
+        temp.push(".tmp");
+        {
+            let mut file = try!(fs::File::create(&temp));
+            try!(file.write_all(data));
+            try!(file.sync_all());
+        }
+        fs::rename(&temp, &self.path)
+    }
+}
+
+/// Keeps battery-backed RAM in memory
+///
+/// Clones share the same contents, so a clone kept aside can be used to examine the save after
+/// the cartridge has flushed it.
+#[derive(Clone)]
+pub struct MemoryStorage {
+    data: Rc<RefCell<Option<Vec<u8>>>>
+}
+
+impl MemoryStorage {
+    /// Creates a storage with nothing saved in it
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            data: Rc::new(RefCell::new(None))
+        }
+    }
+
+    /// Creates a storage holding a save of `data`
+    pub fn with_contents(data: Vec<u8>) -> MemoryStorage {
+        MemoryStorage {
+            data: Rc::new(RefCell::new(Some(data)))
+        }
+    }
+
+    /// Gets the saved contents, if anything has been saved
+    pub fn contents(&self) -> Option<Vec<u8>> {
+        self.data.borrow().clone()
+    }
+}
+
+impl SaveStorage for MemoryStorage {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.contents())
+    }
+
+    fn save(&mut self, data: &[u8]) -> io::Result<()> {
+        *self.data.borrow_mut() = Some(data.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use systems::nes::cart::{SaveStorage,FileStorage};
+
+    #[test]
+    pub fn save_path_is_named_after_rom() {
+        assert_eq!(Path::new("roms/game.sav"), FileStorage::for_rom("roms/game.nes").path());
+    }
+
+    #[test]
+    pub fn file_storage_round_trips_save() {
+        let path = env::temp_dir().join(format!("remy-save-test-{}.sav", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut storage = FileStorage::new(&path);
+        assert_eq!(None, storage.load().unwrap());
+
+        storage.save(&[1, 2, 3]).unwrap();
+        storage.save(&[4, 5]).unwrap();
+        assert_eq!(Some(vec![4, 5]), storage.load().unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
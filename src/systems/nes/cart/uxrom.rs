@@ -0,0 +1,61 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::discrete::Board;
+
+/// Emulates the UxROM boards (iNES mapper 2)
+///
+/// The latch selects the 16K PRG ROM bank at $8000, and the last bank is fixed at $C000. The
+/// 8K of CHR is not banked.
+pub struct UxRom {
+    board: Board
+}
+
+impl UxRom {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: nes::Mirroring, bus_conflicts: bool, logger: Option<slog::Logger>) -> UxRom {
+        let mut board = Board::new("UxROM", prg_rom, 0x4000, chr_rom, 0x2000, chr_ram_size, mirroring, bus_conflicts, unwrap_logger!(logger));
+        let last = board.prg_rom.bank_count() - 1;
+        board.prg_rom.select(0, 0);
+        board.prg_rom.select(1, last);
+        UxRom { board: board }
+    }
+
+    fn latch(&mut self, val: u8) {
+        self.board.prg_rom.select(0, val as usize);
+    }
+}
+
+discrete_mapper!(UxRom);
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::Mirroring;
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::uxrom::UxRom;
+
+    #[test]
+    pub fn latch_selects_first_prg_bank() {
+        let mut uxrom = UxRom::new(numbered_banks(8, 0x4000), Vec::new(), 0x2000, Mirroring::Vertical, false, None);
+        assert_eq!(Ok(0), uxrom.get_u8(0x8000));
+        assert_eq!(Ok(7), uxrom.get_u8(0xC000));
+
+        uxrom.set_u8(0x8000, 5).unwrap();
+        assert_eq!(Ok(5), uxrom.get_u8(0x8000));
+        assert_eq!(Ok(7), uxrom.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn bus_conflicts_mask_latched_value() {
+        let mut prg = numbered_banks(8, 0x4000);
+        prg[0x0010] = 0x06;
+        let mut uxrom = UxRom::new(prg.clone(), Vec::new(), 0x2000, Mirroring::Vertical, true, None);
+        uxrom.set_u8(0x8010, 0x03).unwrap();
+        assert_eq!(Ok(2), uxrom.get_u8(0x8000));
+
+        let mut uxrom = UxRom::new(prg, Vec::new(), 0x2000, Mirroring::Vertical, false, None);
+        uxrom.set_u8(0x8010, 0x03).unwrap();
+        assert_eq!(Ok(3), uxrom.get_u8(0x8000));
+    }
+}
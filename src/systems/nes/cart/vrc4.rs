@@ -0,0 +1,435 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use systems::nes::cart::vrc_irq::VrcIrq;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Emulates the Konami VRC2 and VRC4 (iNES mappers 21, 22, 23 and 25)
+///
+/// Each page of registers ($8000, $9000, ... $F000) has four registers, selected by two address
+/// lines. Which lines those are depends on the board, and the mapper number and submapper
+/// identify the wiring:
+///
+/// | Mapper | Submapper | Chip  | Lines  |
+/// |--------|-----------|-------|--------|
+/// | 21     | 1         | VRC4a | A1, A2 |
+/// | 21     | 2         | VRC4c | A6, A7 |
+/// | 22     | any       | VRC2a | A1, A0 |
+/// | 23     | 1         | VRC4f | A0, A1 |
+/// | 23     | 2         | VRC4e | A2, A3 |
+/// | 23     | 3         | VRC2b | A0, A1 |
+/// | 25     | 1         | VRC4b | A1, A0 |
+/// | 25     | 2         | VRC4d | A3, A2 |
+/// | 25     | 3         | VRC2c | A1, A0 |
+///
+/// Without a submapper, the board isn't known, so a VRC4 with both of the mapper's wirings
+/// combined is emulated, which works since games only write to one set of addresses.
+///
+/// The VRC2 is a subset of the VRC4, lacking the PRG swap mode, single screen mirroring and the
+/// IRQ counter. Boards without PRG RAM have a 1-bit latch at $6000-$6FFF instead, which some
+/// games use for copy protection. The VRC2a ignores the low bit of the CHR bank numbers.
+pub struct Vrc4 {
+    /// The address lines connected to the register select inputs, as masks
+    select_lines: (u64, u64),
+    vrc2: bool,
+    chr_shift: u8,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u16; 8],
+    prg_swap: bool,
+    mirroring: nes::Mirroring,
+    irq: VrcIrq,
+    latch: u8,
+
+    prg_rom: Banks,
+    prg_ram: Option<Banks>,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Vrc4 {
+    /// Creates a VRC2 or VRC4 board for iNES mapper `mapper` and `submapper`, with the provided
+    /// PRG ROM and CHR, and `prg_ram_size` bytes of PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, mapper: u8, submapper: u8, logger: Option<slog::Logger>) -> Vrc4 {
+        const A0: u64 = 0x01;
+        const A1: u64 = 0x02;
+        const A2: u64 = 0x04;
+        const A3: u64 = 0x08;
+        const A6: u64 = 0x40;
+        const A7: u64 = 0x80;
+        let (select_lines, vrc2) = match (mapper, submapper) {
+            (21, 1) => ((A1, A2), false),
+            (21, 2) => ((A6, A7), false),
+            (21, _) => ((A1 | A6, A2 | A7), false),
+            (22, _) => ((A1, A0), true),
+            (23, 1) => ((A0, A1), false),
+            (23, 2) => ((A2, A3), false),
+            (23, 3) => ((A0, A1), true),
+            (23, _) => ((A0 | A2, A1 | A3), false),
+            (25, 1) => ((A1, A0), false),
+            (25, 2) => ((A3, A2), false),
+            (25, 3) => ((A1, A0), true),
+            (_, _) => ((A1 | A3, A0 | A2), false)
+        };
+        let name = if vrc2 { "VRC2" } else { "VRC4" };
+
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+        let prg_ram = if prg_ram_size == 0 {
+            None
+        } else {
+            Some(Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1))
+        };
+
+        let mut vrc4 = Vrc4 {
+            select_lines: select_lines,
+            vrc2: vrc2,
+            chr_shift: if mapper == 22 { 1 } else { 0 },
+            prg_banks: [0, 1],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            prg_swap: false,
+            mirroring: nes::Mirroring::Vertical,
+            irq: VrcIrq::new(),
+            latch: 0,
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: prg_ram,
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => name, "cartridge" => true))
+        };
+        vrc4.update_banks();
+        vrc4
+    }
+
+    /// Gets the register (0-3) within a page selected by `addr`
+    fn register(&self, addr: u64) -> u64 {
+        let (a0, a1) = self.select_lines;
+        let low = if addr & a0 != 0 { 1 } else { 0 };
+        let high = if addr & a1 != 0 { 2 } else { 0 };
+        low | high
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        let reg = self.register(addr);
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", (addr & 0xF000) | reg),
+            "val" => format!("${:02X}", val));
+
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_banks[0] = val & 0x1F,
+            (0x9000, _) if self.vrc2 => self.mirroring = if val & 0x01 == 0 {
+                nes::Mirroring::Vertical
+            } else {
+                nes::Mirroring::Horizontal
+            },
+            (0x9000, 0) | (0x9000, 1) => self.mirroring = match val & 0x03 {
+                0 => nes::Mirroring::Vertical,
+                1 => nes::Mirroring::Horizontal,
+                2 => nes::Mirroring::SingleScreenLower,
+                _ => nes::Mirroring::SingleScreenUpper
+            },
+            (0x9000, 2) => self.prg_swap = val & 0x02 != 0,
+            (0xA000, _) => self.prg_banks[1] = val & 0x1F,
+            (0xB000, _) | (0xC000, _) | (0xD000, _) | (0xE000, _) => {
+                let bank = ((((addr & 0xF000) - 0xB000) >> 11) | (reg >> 1)) as usize;
+                let old = self.chr_banks[bank];
+                self.chr_banks[bank] = if reg & 0x01 == 0 {
+                    (old & 0x1F0) | (val & 0x0F) as u16
+                } else {
+                    let high_mask = if self.vrc2 { 0x0F } else { 0x1F };
+                    (old & 0x00F) | (((val & high_mask) as u16) << 4)
+                };
+            },
+            (0xF000, _) if self.vrc2 => {},
+            (0xF000, 0) => {
+                let latch = self.irq.latch();
+                self.irq.set_latch((latch & 0xF0) | (val & 0x0F));
+            },
+            (0xF000, 1) => {
+                let latch = self.irq.latch();
+                self.irq.set_latch((latch & 0x0F) | (val << 4));
+            },
+            (0xF000, 2) => self.irq.write_control(val),
+            (0xF000, _) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let second_last = self.prg_rom.bank_count().wrapping_sub(2);
+        let last = self.prg_rom.bank_count() - 1;
+        let (first, third) = if self.prg_swap {
+            (second_last, self.prg_banks[0] as usize)
+        } else {
+            (self.prg_banks[0] as usize, second_last)
+        };
+        self.prg_rom.select(0, first);
+        self.prg_rom.select(1, self.prg_banks[1] as usize);
+        self.prg_rom.select(2, third);
+        self.prg_rom.select(3, last);
+
+        for slot in 0..8 {
+            self.chr.select(slot, (self.chr_banks[slot] >> self.chr_shift) as usize);
+        }
+    }
+}
+
+impl nes::Mapper for Vrc4 {
+    fn name(&self) -> &'static str {
+        if self.vrc2 { "VRC2" } else { "VRC4" }
+    }
+
+    fn mirroring(&self) -> nes::Mirroring { self.mirroring }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.as_ref().and_then(|ram| ram.ram_data())
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.as_mut().and_then(|ram| ram.ram_data_mut())
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+}
+
+impl mem::Memory for Vrc4 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            match self.prg_ram {
+                Some(ref ram) => {
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "RAM",
+                        "action" => "read");
+                    ram.get_u8(addr - 0x6000)
+                },
+                None if self.vrc2 && addr < 0x7000 => {
+                    // Only the lowest bit is driven by the latch
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "Latch",
+                        "action" => "read");
+                    Ok(((addr >> 8) as u8 & 0xFE) | self.latch)
+                },
+                None => {
+                    // Nothing drives the data bus, so it keeps the high byte of the address
+                    trace!(self.log,
+                        "read";
+                        "vaddr" => format!("${:04X}", addr),
+                        "target" => "Open Bus",
+                        "action" => "read");
+                    Ok((addr >> 8) as u8)
+                }
+            }
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC cartridge",
+                format!("${:4X} is below the addressable range on VRC cartridge", addr)))
+        } else if addr < 0x8000 {
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => if self.prg_ram.is_some() { "RAM" } else { "Latch" },
+                "action" => "write");
+            match self.prg_ram {
+                Some(ref mut ram) => ram.set_u8(addr - 0x6000, val),
+                None => {
+                    if self.vrc2 && addr < 0x7000 {
+                        self.latch = val & 0x01;
+                    }
+                    Ok(())
+                }
+            }
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::vrc4::Vrc4;
+
+    fn vrc4(mapper: u8, submapper: u8) -> Vrc4 {
+        Vrc4::new(numbered_banks(16, 0x2000), numbered_banks(256, 0x0400), 0x2000, 0x2000, mapper, submapper, None)
+    }
+
+    #[test]
+    pub fn prg_swap_mode_swaps_fixed_bank() {
+        let mut vrc4 = vrc4(23, 1);
+        vrc4.set_u8(0x8000, 3).unwrap();
+        vrc4.set_u8(0xA000, 5).unwrap();
+
+        assert_eq!(Ok(3), vrc4.get_u8(0x8000));
+        assert_eq!(Ok(5), vrc4.get_u8(0xA000));
+        assert_eq!(Ok(14), vrc4.get_u8(0xC000));
+        assert_eq!(Ok(15), vrc4.get_u8(0xE000));
+
+        vrc4.set_u8(0x9002, 0x02).unwrap();
+        assert_eq!(Ok(14), vrc4.get_u8(0x8000));
+        assert_eq!(Ok(3), vrc4.get_u8(0xC000));
+    }
+
+    #[test]
+    pub fn chr_banks_are_written_in_nibbles() {
+        let mut vrc4 = vrc4(23, 1);
+        vrc4.set_u8(0xB000, 0x05).unwrap();
+        vrc4.set_u8(0xB001, 0x02).unwrap();
+        vrc4.set_u8(0xE003, 0x01).unwrap();
+
+        assert_eq!(Ok(0x25), vrc4.chr().get_u8(0x0000));
+        assert_eq!(Ok(0x17), vrc4.chr().get_u8(0x1C00));
+    }
+
+    #[test]
+    pub fn submappers_select_address_lines() {
+        let mut vrc4c = vrc4(21, 2);
+        vrc4c.set_u8(0xB040, 0x03).unwrap();
+        vrc4c.set_u8(0xB080, 0x09).unwrap();
+        assert_eq!(Ok(0x30), vrc4c.chr().get_u8(0x0000));
+        assert_eq!(Ok(0x09), vrc4c.chr().get_u8(0x0400));
+
+        let mut vrc4d = vrc4(25, 2);
+        vrc4d.set_u8(0xB008, 0x03).unwrap();
+        vrc4d.set_u8(0xB004, 0x09).unwrap();
+        assert_eq!(Ok(0x30), vrc4d.chr().get_u8(0x0000));
+        assert_eq!(Ok(0x09), vrc4d.chr().get_u8(0x0400));
+    }
+
+    #[test]
+    pub fn unknown_board_combines_wirings() {
+        let mut vrc4 = vrc4(25, 0);
+        vrc4.set_u8(0xB002, 0x03).unwrap();
+        vrc4.set_u8(0xB004, 0x09).unwrap();
+        assert_eq!(Ok(0x30), vrc4.chr().get_u8(0x0000));
+        assert_eq!(Ok(0x09), vrc4.chr().get_u8(0x0400));
+    }
+
+    #[test]
+    pub fn vrc2a_ignores_low_chr_bank_bit() {
+        let mut vrc2 = vrc4(22, 0);
+        vrc2.set_u8(0xB000, 0x07).unwrap();
+        vrc2.set_u8(0xB002, 0x01).unwrap();
+        assert_eq!(Ok(0x0B), vrc2.chr().get_u8(0x0000));
+    }
+
+    #[test]
+    pub fn mirroring_register_selects_mirroring() {
+        let mut vrc4a = vrc4(21, 1);
+        vrc4a.set_u8(0x9000, 1).unwrap();
+        assert_eq!(Mirroring::Horizontal, vrc4a.mirroring());
+        vrc4a.set_u8(0x9000, 3).unwrap();
+        assert_eq!(Mirroring::SingleScreenUpper, vrc4a.mirroring());
+
+        let mut vrc2 = vrc4(23, 3);
+        vrc2.set_u8(0x9000, 3).unwrap();
+        assert_eq!(Mirroring::Horizontal, vrc2.mirroring());
+    }
+
+    #[test]
+    pub fn vrc2_without_ram_has_latch() {
+        let mut vrc2 = Vrc4::new(numbered_banks(16, 0x2000), vec![], 0, 0x2000, 23, 3, None);
+        vrc2.set_u8(0x6000, 0xFF).unwrap();
+        assert_eq!(Ok(0x61), vrc2.get_u8(0x6000));
+        assert_eq!(Ok(0x70), vrc2.get_u8(0x7000));
+    }
+
+    #[test]
+    pub fn irq_counts_cpu_cycles() {
+        let mut vrc4 = vrc4(21, 1);
+        vrc4.set_u8(0xF000, 0x0E).unwrap();
+        vrc4.set_u8(0xF002, 0x0F).unwrap();
+        vrc4.set_u8(0xF004, 0x06).unwrap();
+
+        vrc4.clock();
+        assert!(!vrc4.irq());
+        vrc4.clock();
+        assert!(vrc4.irq());
+
+        vrc4.set_u8(0xF006, 0).unwrap();
+        assert!(!vrc4.irq());
+    }
+}
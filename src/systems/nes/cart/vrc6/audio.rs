@@ -0,0 +1,229 @@
+/// The output level of one step of the channels' outputs, relative to the APU's mixed output
+///
+/// This makes a VRC6 pulse channel at full volume about as loud as one of the APU's.
+const LEVEL_PER_STEP: f32 = 0.0099;
+
+/// Represents one of the VRC6's pulse channels, which have 16 step duty cycles and no envelope,
+/// sweep or length counter
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    constant: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            volume: 0,
+            duty: 0,
+            constant: false,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0
+        }
+    }
+
+    fn write_register(&mut self, reg: u64, val: u8) {
+        match reg {
+            0 => {
+                self.constant = val & 0x80 != 0;
+                self.duty = (val >> 4) & 0x07;
+                self.volume = val & 0x0F;
+            },
+            1 => self.period = (self.period & 0xF00) | val as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | (((val & 0x0F) as u16) << 8);
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// Represents the VRC6's sawtooth channel, which adds its rate to an accumulator on every
+/// other clock of its timer, and resets the accumulator on the fourteenth
+struct Sawtooth {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0
+        }
+    }
+
+    fn write_register(&mut self, reg: u64, val: u8) {
+        match reg {
+            0 => self.rate = val & 0x3F,
+            1 => self.period = (self.period & 0xF00) | val as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | (((val & 0x0F) as u16) << 8);
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 0x01 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// Represents the sound hardware in the VRC6: two pulse channels and a sawtooth channel, all
+/// clocked on every CPU cycle
+pub struct Audio {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    sawtooth: Sawtooth,
+    halt: bool,
+    shift: u8
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio {
+            pulse1: Pulse::new(),
+            pulse2: Pulse::new(),
+            sawtooth: Sawtooth::new(),
+            halt: false,
+            shift: 0
+        }
+    }
+
+    /// Writes `val` to one of the registers at $9000-$9003, $A000-$A002 or $B000-$B002, where
+    /// `addr` has already had the board's address lines untangled
+    pub fn write_register(&mut self, addr: u64, val: u8) {
+        match (addr & 0xF000, addr & 0x03) {
+            (0x9000, 3) => {
+                // Each timer's period can be shortened by 4 or 8 bits for testing
+                self.halt = val & 0x01 != 0;
+                self.shift = if val & 0x04 != 0 {
+                    8
+                } else if val & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            },
+            (0x9000, reg) => self.pulse1.write_register(reg, val),
+            (0xA000, reg) => self.pulse2.write_register(reg, val),
+            (_, reg) => self.sawtooth.write_register(reg, val)
+        }
+    }
+
+    /// Clocks the channels for one CPU cycle, and gets the output level relative to the APU's
+    /// mixed output
+    pub fn clock(&mut self) -> f32 {
+        if !self.halt {
+            self.pulse1.clock(self.shift);
+            self.pulse2.clock(self.shift);
+            self.sawtooth.clock(self.shift);
+        }
+
+        let sum = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        sum as f32 * LEVEL_PER_STEP
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::vrc6::audio::Audio;
+
+    fn levels(audio: &mut Audio, cycles: usize) -> Vec<f32> {
+        (0..cycles).map(|_| audio.clock()).collect()
+    }
+
+    #[test]
+    pub fn pulse_duty_cycle_has_sixteen_steps() {
+        let mut audio = Audio::new();
+        audio.write_register(0x9000, 0x3F);
+        audio.write_register(0x9001, 0x00);
+        audio.write_register(0x9002, 0x80);
+
+        let high = levels(&mut audio, 16).iter().filter(|&&l| l > 0.0).count();
+        assert_eq!(4, high);
+    }
+
+    #[test]
+    pub fn sawtooth_accumulates_rate() {
+        let mut audio = Audio::new();
+        audio.write_register(0xB000, 0x2A);
+        audio.write_register(0xB001, 0x00);
+        audio.write_register(0xB002, 0x80);
+
+        let levels = levels(&mut audio, 14);
+        let peak = levels.iter().cloned().fold(0.0, f32::max);
+        assert_eq!((0x2A * 6 >> 3) as f32 * 0.0099, peak);
+        assert_eq!(0.0, levels[13]);
+    }
+
+    #[test]
+    pub fn halt_stops_channels() {
+        let mut audio = Audio::new();
+        audio.write_register(0x9000, 0x0F);
+        audio.write_register(0x9002, 0x80);
+        audio.write_register(0x9003, 0x01);
+
+        let levels = levels(&mut audio, 32);
+        assert!(levels.iter().all(|&l| l == levels[0]));
+    }
+}
@@ -0,0 +1,349 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use systems::nes::cart::vrc_irq::VrcIrq;
+use self::audio::Audio;
+
+mod audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Emulates the Konami VRC6 (iNES mappers 24 and 26)
+///
+/// PRG is switched as a 16K bank at $8000 and an 8K bank at $C000, with the last 8K bank fixed
+/// at $E000, and CHR is switched in 1K or 2K banks depending on the banking mode. Each page of
+/// registers has four registers, selected by A0 and A1 on the VRC6a (mapper 24), and by A1 and
+/// A0 on the VRC6b (mapper 26). The VRC6 also has the VRC IRQ counter, and two pulse channels
+/// and a sawtooth channel of expansion audio.
+///
+/// Nametables always come from the console's VRAM, with the mirroring selected in the banking
+/// mode register: the modes which map nametables from CHR ROM were not used by any game.
+pub struct Vrc6 {
+    swap_lines: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    banking_mode: u8,
+    irq: VrcIrq,
+    audio: Audio,
+
+    prg_rom: Banks,
+    prg_ram: Banks,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Vrc6 {
+    /// Creates a VRC6 board for iNES mapper `mapper`, with the provided PRG ROM and CHR, and
+    /// `prg_ram_size` bytes of PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, mapper: u8, logger: Option<slog::Logger>) -> Vrc6 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+
+        let mut vrc6 = Vrc6 {
+            swap_lines: mapper == 26,
+            prg_banks: [0, 2],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            banking_mode: 0,
+            irq: VrcIrq::new(),
+            audio: Audio::new(),
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1),
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => "VRC6", "cartridge" => true))
+        };
+        vrc6.update_banks();
+        vrc6
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        let reg = if self.swap_lines {
+            ((addr & 0x01) << 1) | ((addr & 0x02) >> 1)
+        } else {
+            addr & 0x03
+        };
+        let addr = (addr & 0xF000) | reg;
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", addr),
+            "val" => format!("${:02X}", val));
+
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_banks[0] = (val & 0x0F) << 1,
+            (0xB000, 3) => self.banking_mode = val,
+            (0x9000, _) | (0xA000, _) | (0xB000, _) => self.audio.write_register(addr, val),
+            (0xC000, _) => self.prg_banks[1] = val & 0x1F,
+            (0xD000, _) => self.chr_banks[reg as usize] = val,
+            (0xE000, _) => self.chr_banks[4 + reg as usize] = val,
+            (0xF000, 0) => self.irq.set_latch(val),
+            (0xF000, 1) => self.irq.write_control(val),
+            (0xF000, 2) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let last = self.prg_rom.bank_count() - 1;
+        self.prg_rom.select(0, self.prg_banks[0] as usize);
+        self.prg_rom.select(1, self.prg_banks[0] as usize + 1);
+        self.prg_rom.select(2, self.prg_banks[1] as usize);
+        self.prg_rom.select(3, last);
+
+        let r = self.chr_banks;
+        let banks = match self.banking_mode & 0x03 {
+            // Eight 1K banks
+            0 => r,
+            // Four 2K banks
+            1 => [
+                r[0] & 0xFE, r[0] | 0x01, r[1] & 0xFE, r[1] | 0x01,
+                r[2] & 0xFE, r[2] | 0x01, r[3] & 0xFE, r[3] | 0x01
+            ],
+            // Four 1K banks, then two 2K banks
+            _ => [
+                r[0], r[1], r[2], r[3],
+                r[4] & 0xFE, r[4] | 0x01, r[5] & 0xFE, r[5] | 0x01
+            ]
+        };
+        for (slot, bank) in banks.iter().enumerate() {
+            self.chr.select(slot, *bank as usize);
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.banking_mode & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+}
+
+impl nes::Mapper for Vrc6 {
+    fn name(&self) -> &'static str { "VRC6" }
+
+    fn mirroring(&self) -> nes::Mirroring {
+        match (self.banking_mode >> 2) & 0x03 {
+            0 => nes::Mirroring::Vertical,
+            1 => nes::Mirroring::Horizontal,
+            2 => nes::Mirroring::SingleScreenLower,
+            _ => nes::Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+
+    fn expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+impl mem::Memory for Vrc6 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC6 cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                // Nothing drives the data bus, so it keeps the high byte of the address
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Open Bus",
+                    "action" => "read");
+                return Ok((addr >> 8) as u8);
+            }
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "read");
+            self.prg_ram.get_u8(addr - 0x6000)
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC6 cartridge",
+                format!("${:4X} is below the addressable range on VRC6 cartridge", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Disabled RAM",
+                    "action" => "write");
+                return Ok(());
+            }
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "write");
+            self.prg_ram.set_u8(addr - 0x6000, val)
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::vrc6::Vrc6;
+
+    fn vrc6(mapper: u8) -> Vrc6 {
+        Vrc6::new(numbered_banks(16, 0x2000), numbered_banks(64, 0x0400), 0x2000, 0x2000, mapper, None)
+    }
+
+    #[test]
+    pub fn prg_banks_are_16k_and_8k() {
+        let mut vrc6 = vrc6(24);
+        vrc6.set_u8(0x8000, 3).unwrap();
+        vrc6.set_u8(0xC000, 9).unwrap();
+
+        assert_eq!(Ok(6), vrc6.get_u8(0x8000));
+        assert_eq!(Ok(7), vrc6.get_u8(0xA000));
+        assert_eq!(Ok(9), vrc6.get_u8(0xC000));
+        assert_eq!(Ok(15), vrc6.get_u8(0xE000));
+    }
+
+    #[test]
+    pub fn chr_banking_modes() {
+        let mut vrc6 = vrc6(24);
+        for reg in 0..4 {
+            vrc6.set_u8(0xD000 + reg, 10 + 2 * reg as u8).unwrap();
+            vrc6.set_u8(0xE000 + reg, 20 + 2 * reg as u8).unwrap();
+        }
+        vrc6.set_u8(0xB003, 0x20).unwrap();
+        assert_eq!(Ok(12), vrc6.chr().get_u8(0x0400));
+        assert_eq!(Ok(26), vrc6.chr().get_u8(0x1C00));
+
+        vrc6.set_u8(0xB003, 0x21).unwrap();
+        assert_eq!(Ok(11), vrc6.chr().get_u8(0x0400));
+        assert_eq!(Ok(12), vrc6.chr().get_u8(0x0800));
+        assert_eq!(Ok(17), vrc6.chr().get_u8(0x1C00));
+
+        vrc6.set_u8(0xB003, 0x22).unwrap();
+        assert_eq!(Ok(16), vrc6.chr().get_u8(0x0C00));
+        assert_eq!(Ok(20), vrc6.chr().get_u8(0x1000));
+        assert_eq!(Ok(23), vrc6.chr().get_u8(0x1C00));
+    }
+
+    #[test]
+    pub fn vrc6b_swaps_address_lines() {
+        let mut vrc6 = vrc6(26);
+        vrc6.set_u8(0xD001, 30).unwrap();
+        vrc6.set_u8(0xD002, 31).unwrap();
+
+        assert_eq!(Ok(31), vrc6.chr().get_u8(0x0400));
+        assert_eq!(Ok(30), vrc6.chr().get_u8(0x0800));
+
+        vrc6.set_u8(0xB003, 0x84).unwrap();
+        assert_eq!(Mirroring::Horizontal, vrc6.mirroring());
+    }
+
+    #[test]
+    pub fn prg_ram_is_enabled_by_banking_mode() {
+        let mut vrc6 = vrc6(24);
+        vrc6.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x60), vrc6.get_u8(0x6000));
+
+        vrc6.set_u8(0xB003, 0x80).unwrap();
+        vrc6.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x42), vrc6.get_u8(0x6000));
+    }
+
+    #[test]
+    pub fn irq_counts_scanlines() {
+        let mut vrc6 = vrc6(24);
+        vrc6.set_u8(0xF000, 0xFF).unwrap();
+        vrc6.set_u8(0xF001, 0x02).unwrap();
+
+        for _ in 0..113 {
+            vrc6.clock();
+        }
+        assert!(!vrc6.irq());
+        vrc6.clock();
+        assert!(vrc6.irq());
+
+        vrc6.set_u8(0xF002, 0).unwrap();
+        assert!(!vrc6.irq());
+    }
+}
@@ -0,0 +1,450 @@
+use std::f32::consts::PI;
+
+/// The number of CPU cycles per sample of the FM synthesizer, which runs at the 3.58MHz
+/// crystal frequency divided by 72 (about 49.7kHz)
+const SAMPLE_CYCLES: u32 = 36;
+
+/// The FM synthesizer's sample rate, in Hz
+const SAMPLE_RATE: f32 = 49716.0;
+
+/// The output level of a channel at full amplitude, relative to the APU's mixed output
+const CHANNEL_LEVEL: f32 = 0.1;
+
+/// The largest attenuation of the envelope generator, in steps of 0.375dB, at which an operator
+/// is silent
+const MAX_ATTENUATION: f32 = 128.0;
+
+/// The frequencies of the tremolo (AM) and vibrato LFOs, in Hz
+const AM_RATE: f32 = 3.7;
+const VIBRATO_RATE: f32 = 6.4;
+
+/// The depth of the tremolo, in dB, and of the vibrato, as a fraction of the frequency
+const AM_DEPTH: f32 = 4.8;
+const VIBRATO_DEPTH: f32 = 0.008;
+
+/// The number of sine cycles by which a modulator at full amplitude shifts the carrier's phase
+const MODULATION: f32 = 4.0;
+
+/// Frequency multipliers, doubled so that the first (a half) is a whole number
+const MULTIPLIERS: [f32; 16] = [1.0, 2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0, 16.0, 18.0, 20.0, 20.0, 24.0, 24.0, 30.0, 30.0];
+
+/// Modulator feedback levels, as the number of sine cycles its own output shifts its phase by
+const FEEDBACK: [f32; 8] = [0.0, 1.0 / 32.0, 1.0 / 16.0, 1.0 / 8.0, 1.0 / 4.0, 1.0 / 2.0, 1.0, 2.0];
+
+/// The key scale levels in dB for block 7, indexed by the top 4 bits of the frequency number,
+/// at 6dB per octave
+const KEY_SCALE_LEVELS: [f32; 16] = [0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0];
+
+/// The fraction of the key scale level applied by each key scale setting (0, 1.5, 3 and 6dB per
+/// octave)
+const KEY_SCALES: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+
+/// The VRC7's built-in instruments 1-15, which differ from those of the YM2413
+///
+/// Each has a modulator and carrier byte for AM, vibrato, sustained envelope, key scale rate
+/// and multiplier, then the modulator's key scale level and total level, the carrier's key
+/// scale level with the waveforms and feedback, and then the attack and decay rates and the
+/// sustain level and release rate of each.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06]
+];
+
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release
+}
+
+/// Represents one of the two operators of a channel: a sine wave oscillator with an envelope
+#[derive(Copy,Clone)]
+struct Operator {
+    /// The phase, in sine cycles
+    phase: f32,
+
+    /// The envelope's attenuation, in steps of 0.375dB
+    attenuation: f32,
+    state: EnvelopeState,
+
+    /// The last two outputs, used for feedback
+    outputs: [f32; 2]
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0.0,
+            attenuation: MAX_ATTENUATION,
+            state: EnvelopeState::Release,
+            outputs: [0.0; 2]
+        }
+    }
+}
+
+/// The parameters of one operator, decoded from an instrument
+struct OperatorPatch {
+    am: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale: f32,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: f32,
+    release: u8
+}
+
+impl OperatorPatch {
+    /// Decodes the parameters of the modulator (`op` 0) or carrier (`op` 1) from `patch`
+    fn new(patch: &[u8; 8], op: usize) -> OperatorPatch {
+        let flags = patch[op];
+        let rates = patch[4 + op];
+        let levels = patch[6 + op];
+        OperatorPatch {
+            am: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale: KEY_SCALES[(patch[2 + op] >> 6) as usize],
+            rectified: patch[3] & (0x08 << op) != 0,
+            attack: rates >> 4,
+            decay: rates & 0x0F,
+            sustain_level: (levels >> 4) as f32 * 8.0,
+            release: levels & 0x0F
+        }
+    }
+}
+
+/// Represents one of the six FM channels, each a modulator operator modulating the phase of a
+/// carrier operator
+#[derive(Copy,Clone)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            fnum: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new()
+        }
+    }
+
+    fn set_key_on(&mut self, key_on: bool) {
+        if key_on && !self.key_on {
+            for op in [&mut self.modulator, &mut self.carrier].iter_mut() {
+                op.phase = 0.0;
+                op.state = EnvelopeState::Attack;
+            }
+        } else if !key_on && self.key_on {
+            self.modulator.state = EnvelopeState::Release;
+            self.carrier.state = EnvelopeState::Release;
+        }
+        self.key_on = key_on;
+    }
+
+    /// Gets the key code used by the key scale rate, from the block and frequency number
+    fn key_code(&self) -> u8 {
+        (self.block << 1) | (self.fnum >> 8) as u8
+    }
+
+    /// Gets the attenuation in dB applied by the key scale level, at 6dB per octave
+    fn key_scale_level(&self) -> f32 {
+        let level = KEY_SCALE_LEVELS[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        level.max(0.0)
+    }
+}
+
+/// Represents the sound hardware in the VRC7: a Yamaha YM2413 (OPLL) derivative with six FM
+/// channels and a different set of built-in instruments
+///
+/// The rhythm mode and the test register of the YM2413 are absent. The synthesis is a
+/// floating point approximation of the chip's, rather than a reproduction of its log-sine and
+/// exponent tables and envelope counters.
+pub struct Audio {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    reset: bool,
+    divider: u32,
+    am_phase: f32,
+    vibrato_phase: f32,
+    level: f32
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio {
+            address: 0,
+            custom: [0; 8],
+            channels: [Channel::new(); 6],
+            reset: false,
+            divider: SAMPLE_CYCLES,
+            am_phase: 0.0,
+            vibrato_phase: 0.0,
+            level: 0.0
+        }
+    }
+
+    /// Selects the register written by `write_data` ($9010)
+    pub fn write_address(&mut self, val: u8) {
+        self.address = val;
+    }
+
+    /// Writes to the selected register ($9030)
+    pub fn write_data(&mut self, val: u8) {
+        if self.reset {
+            return;
+        }
+        match self.address {
+            0x00...0x07 => self.custom[self.address as usize] = val,
+            0x10...0x15 => {
+                let channel = &mut self.channels[(self.address & 0x0F) as usize];
+                channel.fnum = (channel.fnum & 0x100) | val as u16;
+            },
+            0x20...0x25 => {
+                let channel = &mut self.channels[(self.address & 0x0F) as usize];
+                channel.fnum = (channel.fnum & 0x0FF) | (((val & 0x01) as u16) << 8);
+                channel.block = (val >> 1) & 0x07;
+                channel.sustain = val & 0x20 != 0;
+                channel.set_key_on(val & 0x10 != 0);
+            },
+            0x30...0x35 => {
+                let channel = &mut self.channels[(self.address & 0x0F) as usize];
+                channel.instrument = val >> 4;
+                channel.volume = val & 0x0F;
+            },
+            _ => {}
+        }
+    }
+
+    /// Holds the synthesizer in reset, silencing it and clearing its registers, while `reset`
+    /// is set
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset {
+            *self = Audio::new();
+        }
+        self.reset = reset;
+    }
+
+    /// Clocks the synthesizer for one CPU cycle, and gets the output level relative to the
+    /// APU's mixed output
+    pub fn clock(&mut self) -> f32 {
+        if self.reset {
+            return 0.0;
+        }
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = SAMPLE_CYCLES;
+            self.level = self.sample();
+        }
+        self.level
+    }
+
+    /// Produces one sample from all six channels
+    fn sample(&mut self) -> f32 {
+        self.am_phase = (self.am_phase + AM_RATE / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / SAMPLE_RATE).fract();
+        let am = AM_DEPTH * (1.0 + (2.0 * PI * self.am_phase).sin()) / 2.0;
+        let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin();
+
+        let mut level = 0.0;
+        for ch in 0..6 {
+            let patch = match self.channels[ch].instrument {
+                0 => self.custom,
+                instrument => PATCHES[instrument as usize - 1]
+            };
+            let channel = &mut self.channels[ch];
+            let modulator = OperatorPatch::new(&patch, 0);
+            let carrier = OperatorPatch::new(&patch, 1);
+
+            // The modulator's phase is shifted by its own output, and its output shifts the
+            // carrier's phase
+            let feedback = FEEDBACK[(patch[3] & 0x07) as usize] * (channel.modulator.outputs[0] + channel.modulator.outputs[1]) / 2.0;
+            let total_level = (patch[2] & 0x3F) as f32 * 0.75;
+            let modulation = Audio::operator(channel, false, &modulator, total_level, feedback, am, vibrato);
+            let volume = channel.volume as f32 * 3.0;
+            level += Audio::operator(channel, true, &carrier, volume, modulation * MODULATION, am, vibrato);
+        }
+        level * CHANNEL_LEVEL
+    }
+
+    /// Advances the phase and envelope of one of `channel`'s operators for a sample, and gets
+    /// its output, with its phase shifted by `modulation` cycles
+    fn operator(channel: &mut Channel, carrier: bool, patch: &OperatorPatch, total_level: f32, modulation: f32, am: f32, vibrato: f32) -> f32 {
+        let key_code = channel.key_code();
+        let key_scale_level = channel.key_scale_level() * patch.key_scale;
+        let sustain = channel.sustain;
+        let mut frequency = (channel.fnum as f32) * (1u32 << channel.block) as f32 * patch.multiplier / (1 << 20) as f32;
+        if patch.vibrato {
+            frequency *= vibrato;
+        }
+        let op = if carrier { &mut channel.carrier } else { &mut channel.modulator };
+
+        op.phase = (op.phase + frequency).fract();
+        Audio::envelope(op, patch, key_code, sustain);
+
+        let mut attenuation = op.attenuation * 0.375 + total_level + key_scale_level;
+        if patch.am {
+            attenuation += am;
+        }
+        let mut output = (2.0 * PI * (op.phase + modulation)).sin();
+        if patch.rectified && output < 0.0 {
+            output = 0.0;
+        }
+        output *= 10.0f32.powf(-attenuation / 20.0);
+        if op.attenuation >= MAX_ATTENUATION {
+            output = 0.0;
+        }
+
+        op.outputs = [op.outputs[1], output];
+        output
+    }
+
+    /// Advances an operator's envelope by one sample
+    fn envelope(op: &mut Operator, patch: &OperatorPatch, key_code: u8, sustain: bool) {
+        let key_scale = if patch.key_scale_rate { key_code } else { key_code >> 2 };
+        let step = |rate: u8| -> f32 {
+            if rate == 0 {
+                return 0.0;
+            }
+            let rate = ::std::cmp::min(rate * 4 + key_scale, 63);
+            ((4 + (rate & 0x03) as u32) << (rate >> 2)) as f32 / 65536.0
+        };
+
+        match op.state {
+            EnvelopeState::Attack => {
+                if patch.attack == 15 {
+                    op.attenuation = 0.0;
+                } else {
+                    op.attenuation -= (op.attenuation + 1.0) * step(patch.attack) / 4.0;
+                }
+                if op.attenuation <= 0.0 {
+                    op.attenuation = 0.0;
+                    op.state = EnvelopeState::Decay;
+                }
+            },
+            EnvelopeState::Decay => {
+                op.attenuation += step(patch.decay);
+                if op.attenuation >= patch.sustain_level {
+                    op.attenuation = patch.sustain_level;
+                    op.state = EnvelopeState::Sustain;
+                }
+            },
+            // A percussive instrument keeps decaying at the release rate while the key is held
+            EnvelopeState::Sustain => if !patch.sustained {
+                op.attenuation += step(patch.release);
+            },
+            EnvelopeState::Release => {
+                let rate = if sustain {
+                    5
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    7
+                };
+                op.attenuation += step(rate);
+            }
+        }
+        op.attenuation = op.attenuation.min(MAX_ATTENUATION);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::vrc7::audio::Audio;
+
+    fn write(audio: &mut Audio, reg: u8, val: u8) {
+        audio.write_address(reg);
+        audio.write_data(val);
+    }
+
+    /// Gets the peak level over `cycles` CPU cycles
+    fn peak(audio: &mut Audio, cycles: usize) -> f32 {
+        (0..cycles).map(|_| audio.clock().abs()).fold(0.0, f32::max)
+    }
+
+    /// Plays an A440 with the flute instrument at full volume on channel 0
+    fn play(audio: &mut Audio) {
+        write(audio, 0x30, 0x40);
+        write(audio, 0x10, 0x22);
+        write(audio, 0x20, 0x19);
+    }
+
+    #[test]
+    pub fn key_on_plays_note() {
+        let mut audio = Audio::new();
+        assert_eq!(0.0, peak(&mut audio, 1000));
+
+        play(&mut audio);
+        assert!(peak(&mut audio, 200000) > 0.05);
+    }
+
+    #[test]
+    pub fn key_off_releases_note() {
+        let mut audio = Audio::new();
+        play(&mut audio);
+        peak(&mut audio, 200000);
+
+        write(&mut audio, 0x20, 0x09);
+        peak(&mut audio, 500000);
+        assert!(peak(&mut audio, 1000) < 0.001);
+    }
+
+    #[test]
+    pub fn volume_attenuates_carrier() {
+        let mut loud = Audio::new();
+        let mut quiet = Audio::new();
+        play(&mut loud);
+        play(&mut quiet);
+        write(&mut quiet, 0x30, 0x48);
+
+        let loud_peak = peak(&mut loud, 200000);
+        let quiet_peak = peak(&mut quiet, 200000);
+        assert!(quiet_peak < loud_peak / 8.0);
+    }
+
+    #[test]
+    pub fn reset_silences_output() {
+        let mut audio = Audio::new();
+        play(&mut audio);
+        peak(&mut audio, 200000);
+
+        audio.set_reset(true);
+        assert_eq!(0.0, peak(&mut audio, 1000));
+        play(&mut audio);
+        audio.set_reset(false);
+        assert_eq!(0.0, peak(&mut audio, 1000));
+    }
+}
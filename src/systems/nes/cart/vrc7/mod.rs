@@ -0,0 +1,317 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::cart::banks::Banks;
+use systems::nes::cart::vrc_irq::VrcIrq;
+use self::audio::Audio;
+
+mod audio;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Emulates the Konami VRC7 (iNES mapper 85)
+///
+/// PRG is switched in three 8K banks with the last fixed at $E000, and CHR in eight 1K banks.
+/// Each page of registers has two registers, selected by A4 on the VRC7a (submapper 2) and by
+/// A3 on the VRC7b (submapper 1); without a submapper either line selects the second register.
+/// The VRC7 also has the VRC IRQ counter, and an FM synthesizer whose registers are written
+/// through $9010 and $9030 on both boards.
+pub struct Vrc7 {
+    /// The address line which selects the second register of each page, as a mask
+    select_line: u64,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: VrcIrq,
+    audio: Audio,
+
+    prg_rom: Banks,
+    prg_ram: Banks,
+    chr: Banks,
+    log: slog::Logger
+}
+
+impl Vrc7 {
+    /// Creates a VRC7 board with the provided PRG ROM and CHR, and `prg_ram_size` bytes of
+    /// PRG RAM
+    ///
+    /// If `chr_rom` is empty, the board has `chr_ram_size` bytes of CHR RAM instead.
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, prg_ram_size: usize, chr_ram_size: usize, submapper: u8, logger: Option<slog::Logger>) -> Vrc7 {
+        let chr = if chr_rom.is_empty() {
+            Banks::ram(chr_ram_size, CHR_BANK_SIZE, 8)
+        } else {
+            Banks::new(chr_rom, CHR_BANK_SIZE, 8, false)
+        };
+
+        let mut vrc7 = Vrc7 {
+            select_line: match submapper {
+                1 => 0x08,
+                2 => 0x10,
+                _ => 0x18
+            },
+            prg_banks: [0, 1, 2],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            control: 0,
+            irq: VrcIrq::new(),
+            audio: Audio::new(),
+            prg_rom: Banks::new(prg_rom, PRG_BANK_SIZE, 4, false),
+            prg_ram: Banks::ram(prg_ram_size, PRG_BANK_SIZE, 1),
+            chr: chr,
+            log: unwrap_logger!(logger).new(o!("mapper" => "VRC7", "cartridge" => true))
+        };
+        vrc7.update_banks();
+        vrc7
+    }
+
+    fn write_register(&mut self, addr: u64, val: u8) {
+        // The synthesizer decodes its own addresses, using A4 and A5
+        match addr & 0xF030 {
+            0x9010 => return self.audio.write_address(val),
+            0x9030 => return self.audio.write_data(val),
+            _ => {}
+        }
+
+        let reg = if addr & self.select_line != 0 { 1 } else { 0 };
+        trace!(self.log,
+            "register write";
+            "register" => format!("${:04X}", (addr & 0xF000) | (reg << 4)),
+            "val" => format!("${:02X}", val));
+
+        match (addr & 0xF000, reg) {
+            (0x8000, _) => self.prg_banks[reg as usize] = val & 0x3F,
+            (0x9000, 0) => self.prg_banks[2] = val & 0x3F,
+            (0xA000, _) | (0xB000, _) | (0xC000, _) | (0xD000, _) => {
+                let bank = ((((addr & 0xF000) - 0xA000) >> 11) | reg) as usize;
+                self.chr_banks[bank] = val;
+            },
+            (0xE000, 0) => {
+                self.control = val;
+                self.audio.set_reset(val & 0x40 != 0);
+            },
+            (0xE000, _) => self.irq.set_latch(val),
+            (0xF000, 0) => self.irq.write_control(val),
+            (0xF000, _) => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_banks();
+    }
+
+    fn update_banks(&mut self) {
+        let last = self.prg_rom.bank_count() - 1;
+        for slot in 0..3 {
+            self.prg_rom.select(slot, self.prg_banks[slot] as usize);
+        }
+        self.prg_rom.select(3, last);
+
+        for slot in 0..8 {
+            self.chr.select(slot, self.chr_banks[slot] as usize);
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+}
+
+impl nes::Mapper for Vrc7 {
+    fn name(&self) -> &'static str { "VRC7" }
+
+    fn mirroring(&self) -> nes::Mirroring {
+        match self.control & 0x03 {
+            0 => nes::Mirroring::Vertical,
+            1 => nes::Mirroring::Horizontal,
+            2 => nes::Mirroring::SingleScreenLower,
+            _ => nes::Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return self;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return self;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.ram_data()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.ram_data_mut()
+    }
+
+    fn chr_ram(&self) -> Option<&[u8]> {
+        self.chr.ram_data()
+    }
+
+    fn chr_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.chr.ram_data_mut()
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+
+    fn expansion_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+}
+
+impl mem::Memory for Vrc7 {
+    fn len(&self) -> u64 { 0xA000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "read");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC7 cartridge",
+                format!("${:4X} is below the addressable range of 0x6000-0xFFFF", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                // Nothing drives the data bus, so it keeps the high byte of the address
+                trace!(self.log,
+                    "read";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Open Bus",
+                    "action" => "read");
+                return Ok((addr >> 8) as u8);
+            }
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "read");
+            self.prg_ram.get_u8(addr - 0x6000)
+        } else {
+            let eaddr = addr - 0x8000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "bank" => self.prg_rom.bank((eaddr as usize) / PRG_BANK_SIZE),
+                "target" => "ROM",
+                "action" => "read");
+            self.prg_rom.get_u8(eaddr)
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr < 0x6000 {
+            // Out of range!
+            error!(self.log,
+                "error";
+                "vaddr" => format!("${:04X}", addr),
+                "error" => stringify!(mem::ErrorKind::OutOfBounds),
+                "action" => "write");
+            Err(mem::Error::with_detail(
+                mem::ErrorKind::OutOfBounds,
+                "memory access out of range addressable on VRC7 cartridge",
+                format!("${:4X} is below the addressable range on VRC7 cartridge", addr)))
+        } else if addr < 0x8000 {
+            if !self.ram_enabled() {
+                trace!(self.log,
+                    "write";
+                    "vaddr" => format!("${:04X}", addr),
+                    "target" => "Disabled RAM",
+                    "action" => "write");
+                return Ok(());
+            }
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "RAM",
+                "action" => "write");
+            self.prg_ram.set_u8(addr - 0x6000, val)
+        } else {
+            self.write_register(addr, val);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mem::Memory;
+    use systems::nes::{Mapper,Mirroring};
+    use systems::nes::cart::test::numbered_banks;
+    use systems::nes::cart::vrc7::Vrc7;
+
+    fn vrc7(submapper: u8) -> Vrc7 {
+        Vrc7::new(numbered_banks(32, 0x2000), numbered_banks(64, 0x0400), 0x2000, 0x2000, submapper, None)
+    }
+
+    #[test]
+    pub fn prg_banks_are_8k() {
+        let mut vrc7 = vrc7(2);
+        vrc7.set_u8(0x8000, 3).unwrap();
+        vrc7.set_u8(0x8010, 5).unwrap();
+        vrc7.set_u8(0x9000, 7).unwrap();
+
+        assert_eq!(Ok(3), vrc7.get_u8(0x8000));
+        assert_eq!(Ok(5), vrc7.get_u8(0xA000));
+        assert_eq!(Ok(7), vrc7.get_u8(0xC000));
+        assert_eq!(Ok(31), vrc7.get_u8(0xE000));
+    }
+
+    #[test]
+    pub fn submappers_select_address_line() {
+        let mut vrc7a = vrc7(2);
+        vrc7a.set_u8(0xD008, 40).unwrap();
+        vrc7a.set_u8(0xD010, 41).unwrap();
+        assert_eq!(Ok(40), vrc7a.chr().get_u8(0x1800));
+        assert_eq!(Ok(41), vrc7a.chr().get_u8(0x1C00));
+
+        let mut vrc7b = vrc7(1);
+        vrc7b.set_u8(0xD008, 42).unwrap();
+        assert_eq!(Ok(42), vrc7b.chr().get_u8(0x1C00));
+    }
+
+    #[test]
+    pub fn control_selects_mirroring_and_enables_ram() {
+        let mut vrc7 = vrc7(0);
+        vrc7.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x60), vrc7.get_u8(0x6000));
+
+        vrc7.set_u8(0xE000, 0x81).unwrap();
+        vrc7.set_u8(0x6000, 0x42).unwrap();
+        assert_eq!(Ok(0x42), vrc7.get_u8(0x6000));
+        assert_eq!(Mirroring::Horizontal, vrc7.mirroring());
+    }
+
+    #[test]
+    pub fn irq_counts_cpu_cycles() {
+        let mut vrc7 = vrc7(0);
+        vrc7.set_u8(0xE010, 0xFF).unwrap();
+        vrc7.set_u8(0xF000, 0x06).unwrap();
+
+        vrc7.clock();
+        assert!(vrc7.irq());
+        vrc7.set_u8(0xF010, 0).unwrap();
+        assert!(!vrc7.irq());
+    }
+}
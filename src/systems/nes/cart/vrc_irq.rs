@@ -0,0 +1,144 @@
+/// The number of CPU cycles in a scanline, multiplied by 3 so that it is a whole number
+const SCANLINE_CYCLES_X3: i16 = 341;
+
+/// Represents the IRQ counter shared by the Konami VRC4, VRC6 and VRC7
+///
+/// The 8-bit counter counts up from the latched value, and raises an IRQ (reloading itself)
+/// when it overflows. It is clocked either on every CPU cycle, or once per scanline using a
+/// prescaler which approximates a scanline as 113 2/3 CPU cycles, since the VRC chips can't see
+/// the PPU.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool
+}
+
+impl VrcIrq {
+    pub fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: SCANLINE_CYCLES_X3,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false
+        }
+    }
+
+    /// Sets the value loaded into the counter when it is enabled or overflows
+    pub fn set_latch(&mut self, val: u8) {
+        self.latch = val;
+    }
+
+    /// Gets the value loaded into the counter when it is enabled or overflows
+    pub fn latch(&self) -> u8 {
+        self.latch
+    }
+
+    /// Writes to the control register, which acknowledges any pending IRQ
+    pub fn write_control(&mut self, val: u8) {
+        self.enable_after_ack = val & 0x01 != 0;
+        self.enabled = val & 0x02 != 0;
+        self.cycle_mode = val & 0x04 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = SCANLINE_CYCLES_X3;
+        }
+    }
+
+    /// Acknowledges a pending IRQ, enabling or disabling the counter as requested by the last
+    /// write to the control register
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Clocks the counter for one CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += SCANLINE_CYCLES_X3;
+                self.clock_counter();
+            }
+        }
+    }
+
+    /// Returns a value indicating if an IRQ is pending
+    pub fn irq(&self) -> bool {
+        self.pending
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::cart::vrc_irq::VrcIrq;
+
+    #[test]
+    pub fn cycle_mode_counts_cpu_cycles() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xFD);
+        irq.write_control(0x06);
+
+        irq.clock();
+        irq.clock();
+        assert!(!irq.irq());
+        irq.clock();
+        assert!(irq.irq());
+    }
+
+    #[test]
+    pub fn scanline_mode_counts_scanlines() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xFE);
+        irq.write_control(0x02);
+
+        for _ in 0..227 {
+            irq.clock();
+        }
+        assert!(!irq.irq());
+        irq.clock();
+        assert!(irq.irq());
+    }
+
+    #[test]
+    pub fn acknowledge_restores_enable_from_control() {
+        let mut irq = VrcIrq::new();
+        irq.set_latch(0xFF);
+        irq.write_control(0x07);
+        irq.clock();
+        assert!(irq.irq());
+
+        irq.acknowledge();
+        assert!(!irq.irq());
+        irq.clock();
+        assert!(irq.irq());
+
+        irq.write_control(0x06);
+        irq.clock();
+        irq.acknowledge();
+        irq.clock();
+        assert!(!irq.irq());
+    }
+}
@@ -0,0 +1,146 @@
+use hw::rp2C02;
+use systems::nes::input::{Buttons,Device,Input,Joypad};
+
+/// The number of bits shifted out by each half of the Four Score: two controllers, then the
+/// signature
+const FOUR_SCORE_BITS: u32 = 24;
+
+/// Emulates one half of the NES Four Score, which plugs into both controller ports
+///
+/// The half in each port reads out the buttons of two controllers (those for the port, and the
+/// port plus two) followed by a signature identifying the port, so a `FourScore` should be
+/// plugged into each port.
+pub struct FourScore {
+    first: usize,
+    second: usize,
+    buttons: [Buttons; 2],
+    signature: u8,
+    shift: u32,
+    strobe: bool
+}
+
+impl FourScore {
+    /// Creates the half of a Four Score that plugs into `port` (0 or 1)
+    pub fn new(port: usize) -> FourScore {
+        FourScore {
+            first: port,
+            second: port + 2,
+            buttons: [Buttons::NONE(), Buttons::NONE()],
+            signature: if port == 0 { 0x10 } else { 0x20 },
+            shift: 0,
+            strobe: false
+        }
+    }
+
+    fn latch(&mut self) {
+        self.shift = (self.buttons[0].bits as u32) |
+            ((self.buttons[1].bits as u32) << 8) |
+            ((self.signature as u32) << 16);
+    }
+}
+
+impl Device for FourScore {
+    fn name(&self) -> &'static str { "Four Score" }
+
+    fn set_input(&mut self, input: Input) {
+        if let Input::Joypad(controller, buttons) = input {
+            if controller == self.first {
+                self.buttons[0] = buttons;
+            } else if controller == self.second {
+                self.buttons[1] = buttons;
+            }
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val & 0x01 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, _port: usize, _ppu: &rp2C02::Rp2C02) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+
+        let bit = (self.shift & 0x01) as u8;
+        self.shift = (self.shift >> 1) | (1 << (FOUR_SCORE_BITS - 1));
+        bit
+    }
+}
+
+/// Emulates the Famicom four player adapters which use the simple protocol
+///
+/// The adapter plugs into the expansion port, and reports the third and fourth controllers on
+/// bit 1 of $4016 and $4017, alongside the controllers built into the console on bit 0.
+pub struct FourPlayerAdapter {
+    joypads: [Joypad; 2]
+}
+
+impl FourPlayerAdapter {
+    pub fn new() -> FourPlayerAdapter {
+        FourPlayerAdapter {
+            joypads: [Joypad::new(2), Joypad::new(3)]
+        }
+    }
+}
+
+impl Device for FourPlayerAdapter {
+    fn name(&self) -> &'static str { "Four Player Adapter" }
+
+    fn set_input(&mut self, input: Input) {
+        for joypad in self.joypads.iter_mut() {
+            joypad.set_input(input);
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        for joypad in self.joypads.iter_mut() {
+            joypad.set_strobe(val & 0x01 != 0);
+        }
+    }
+
+    fn read(&mut self, port: usize, _ppu: &rp2C02::Rp2C02) -> u8 {
+        self.joypads[port].read_bit() << 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::Rp2C02;
+    use systems::nes::input::{Buttons,Device,Input,FourScore,FourPlayerAdapter};
+
+    fn read_bits<D>(device: &mut D, port: usize, count: usize) -> Vec<u8> where D: Device {
+        let ppu = Rp2C02::new(None);
+        (0..count).map(|_| device.read(port, &ppu)).collect()
+    }
+
+    #[test]
+    pub fn four_score_reads_two_controllers_then_signature() {
+        let mut four_score = FourScore::new(1);
+        four_score.set_input(Input::Joypad(1, Buttons::A()));
+        four_score.set_input(Input::Joypad(3, Buttons::RIGHT()));
+        four_score.set_input(Input::Joypad(0, Buttons::B()));
+        four_score.write(1);
+        four_score.write(0);
+
+        let bits = read_bits(&mut four_score, 1, 26);
+        assert_eq!(&[1, 0, 0, 0, 0, 0, 0, 0], &bits[0..8]);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 1], &bits[8..16]);
+        assert_eq!(&[0, 0, 0, 0, 0, 1, 0, 0], &bits[16..24]);
+        assert_eq!(&[1, 1], &bits[24..26]);
+    }
+
+    #[test]
+    pub fn four_player_adapter_reports_on_bit_one() {
+        let mut adapter = FourPlayerAdapter::new();
+        adapter.set_input(Input::Joypad(2, Buttons::A()));
+        adapter.set_input(Input::Joypad(3, Buttons::B()));
+        adapter.write(1);
+        adapter.write(0);
+
+        assert_eq!(vec![0x02, 0x00], read_bits(&mut adapter, 0, 2));
+        assert_eq!(vec![0x00, 0x02], read_bits(&mut adapter, 1, 2));
+    }
+}
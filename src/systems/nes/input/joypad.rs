@@ -0,0 +1,185 @@
+use std::ops;
+
+use hw::rp2C02;
+use systems::nes::input::{Device,Input};
+
+/// Represents the set of buttons held on a standard controller
+///
+/// The bits are in the order the controller reports them, starting with A in bit 0.
+#[derive(Copy,Clone,Debug,Default,Eq,PartialEq)]
+pub struct Buttons {
+    pub bits: u8
+}
+
+impl Buttons {
+    #[inline] #[allow(non_snake_case)] pub fn A() -> Buttons        { Buttons::new(0b00000001) }
+    #[inline] #[allow(non_snake_case)] pub fn B() -> Buttons        { Buttons::new(0b00000010) }
+    #[inline] #[allow(non_snake_case)] pub fn SELECT() -> Buttons   { Buttons::new(0b00000100) }
+    #[inline] #[allow(non_snake_case)] pub fn START() -> Buttons    { Buttons::new(0b00001000) }
+    #[inline] #[allow(non_snake_case)] pub fn UP() -> Buttons       { Buttons::new(0b00010000) }
+    #[inline] #[allow(non_snake_case)] pub fn DOWN() -> Buttons     { Buttons::new(0b00100000) }
+    #[inline] #[allow(non_snake_case)] pub fn LEFT() -> Buttons     { Buttons::new(0b01000000) }
+    #[inline] #[allow(non_snake_case)] pub fn RIGHT() -> Buttons    { Buttons::new(0b10000000) }
+    #[inline] #[allow(non_snake_case)] pub fn NONE() -> Buttons     { Buttons::new(0b00000000) }
+
+    /// Creates a new `Buttons` structure from the provided 8-bit value
+    pub fn new(bits: u8) -> Buttons {
+        Buttons { bits: bits }
+    }
+
+    /// Returns a value indicating if all of the specified buttons are held
+    pub fn intersects(&self, other: Buttons) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    /// Presses or releases the specified buttons
+    pub fn set_if(&mut self, buttons: Buttons, held: bool) {
+        if held {
+            self.bits |= buttons.bits;
+        } else {
+            self.bits &= !buttons.bits;
+        }
+    }
+}
+
+impl ops::BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, rhs: Buttons) -> Buttons {
+        Buttons::new(self.bits | rhs.bits)
+    }
+}
+
+/// Emulates a standard controller
+///
+/// While the strobe signal is high, the controller continuously latches the buttons into its
+/// shift register. Each read then shifts out one button, and once all eight have been read the
+/// official controllers report 1.
+pub struct Joypad {
+    controller: usize,
+    buttons: Buttons,
+    shift: u8,
+    strobe: bool
+}
+
+impl Joypad {
+    /// Creates a controller with no buttons held, which responds to the input for the
+    /// `controller`th controller in the system
+    pub fn new(controller: usize) -> Joypad {
+        Joypad {
+            controller: controller,
+            buttons: Buttons::NONE(),
+            shift: 0,
+            strobe: false
+        }
+    }
+
+    /// Gets the buttons held on the controller
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Sets the buttons held on the controller
+    ///
+    /// The buttons are only seen by the program the next time it strobes the controller.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.bits;
+        }
+    }
+
+    /// Sets the level of the strobe signal
+    pub fn set_strobe(&mut self, strobe: bool) {
+        self.strobe = strobe;
+        if strobe {
+            self.shift = self.buttons.bits;
+        }
+    }
+
+    /// Reads the next button from the shift register
+    pub fn read_bit(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons.bits & 0x01;
+        }
+
+        let bit = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+}
+
+impl Device for Joypad {
+    fn name(&self) -> &'static str { "Joypad" }
+
+    fn set_input(&mut self, input: Input) {
+        if let Input::Joypad(controller, buttons) = input {
+            if controller == self.controller {
+                self.set_buttons(buttons);
+            }
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.set_strobe(val & 0x01 != 0);
+    }
+
+    fn read(&mut self, _port: usize, _ppu: &rp2C02::Rp2C02) -> u8 {
+        self.read_bit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::input::{Buttons,Joypad};
+
+    fn read_all(joypad: &mut Joypad) -> Vec<u8> {
+        (0..10).map(|_| joypad.read_bit()).collect()
+    }
+
+    #[test]
+    pub fn buttons_are_read_in_order_after_strobe() {
+        let mut joypad = Joypad::new(0);
+        joypad.set_buttons(Buttons::A() | Buttons::START() | Buttons::RIGHT());
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+
+        assert_eq!(vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1], read_all(&mut joypad));
+    }
+
+    #[test]
+    pub fn reads_return_a_while_strobe_is_high() {
+        let mut joypad = Joypad::new(0);
+        joypad.set_buttons(Buttons::A());
+        joypad.set_strobe(true);
+
+        assert_eq!(vec![1; 10], read_all(&mut joypad));
+    }
+
+    #[test]
+    pub fn buttons_changed_after_strobe_are_not_seen_until_next_strobe() {
+        let mut joypad = Joypad::new(0);
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+        joypad.set_buttons(Buttons::B());
+
+        assert_eq!(0, joypad.read_bit());
+        assert_eq!(0, joypad.read_bit());
+
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+        assert_eq!(0, joypad.read_bit());
+        assert_eq!(1, joypad.read_bit());
+    }
+
+    #[test]
+    pub fn set_if_presses_and_releases_buttons() {
+        let mut buttons = Buttons::UP() | Buttons::LEFT();
+        buttons.set_if(Buttons::UP(), false);
+        buttons.set_if(Buttons::SELECT(), true);
+
+        assert_eq!(Buttons::LEFT() | Buttons::SELECT(), buttons);
+        assert!(buttons.intersects(Buttons::LEFT()));
+        assert!(!buttons.intersects(Buttons::UP()));
+    }
+}
@@ -0,0 +1,208 @@
+pub use self::joypad::{Buttons,Joypad};
+pub use self::four_score::{FourScore,FourPlayerAdapter};
+pub use self::zapper::Zapper;
+pub use self::vaus::Vaus;
+pub use self::power_pad::PowerPad;
+
+use hw::rp2C02;
+
+/// Contains the standard controller
+pub mod joypad;
+
+/// Contains the adapters which connect four standard controllers
+pub mod four_score;
+
+/// Contains the Zapper light gun
+pub mod zapper;
+
+/// Contains the Arkanoid Vaus paddle controller
+pub mod vaus;
+
+/// Contains the Power Pad floor mat
+pub mod power_pad;
+
+/// The value the upper bits of the controller ports read as
+///
+/// Only the low bits are driven by the controllers, so the rest hold the last value on the data
+/// bus, which for the usual absolute reads of $4016/$4017 is the high byte of the address.
+pub const OPEN_BUS: u8 = 0x40;
+
+/// Describes the state of a control on an input device, as set by the user each frame
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Input {
+    /// The buttons held on a standard controller, numbered from 0 across the whole system (so
+    /// controllers 2 and 3 are those attached through a Four Score or four player adapter)
+    Joypad(usize, Buttons),
+
+    /// The position on the screen a Zapper is aimed at, if it is aimed at the screen at all,
+    /// and whether its trigger is pulled
+    Zapper(Option<(u8, u8)>, bool),
+
+    /// The position of the knob on an Arkanoid Vaus controller, and whether its button is held
+    Paddle(u8, bool),
+
+    /// The buttons held on a Power Pad, with button 1 in bit 0 through to button 12 in bit 11
+    PowerPad(u16)
+}
+
+/// Represents a device that can be plugged into a controller port or the expansion port
+pub trait Device {
+    fn name(&self) -> &'static str;
+
+    /// Updates the state of the device's controls
+    ///
+    /// Every device receives every input, and ignores those for controls it doesn't have.
+    fn set_input(&mut self, input: Input);
+
+    /// Receives a write to $4016, whose low three bits are sent to every device (bit 0 is the
+    /// strobe signal used by most of them)
+    fn write(&mut self, val: u8);
+
+    /// Reads the data lines the device drives (bits 0-4) when $4016 (`port` 0) or $4017
+    /// (`port` 1) is read
+    ///
+    /// A device in a controller port is only read through that port, while a device in the
+    /// expansion port is read through both. `ppu` allows light guns to see the screen.
+    fn read(&mut self, port: usize, ppu: &rp2C02::Rp2C02) -> u8;
+}
+
+/// Identifies a port that a device can be plugged into
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Port {
+    /// The first controller port, read through $4016
+    One,
+
+    /// The second controller port, read through $4017
+    Two,
+
+    /// The Famicom's expansion port, read through both $4016 and $4017
+    Expansion
+}
+
+impl Port {
+    fn index(self) -> usize {
+        match self {
+            Port::One => 0,
+            Port::Two => 1,
+            Port::Expansion => 2
+        }
+    }
+}
+
+/// Represents the controller ports on the front of the console, and the expansion port
+pub struct Ports {
+    devices: [Option<Box<Device>>; 3]
+}
+
+impl Ports {
+    /// Creates a set of ports with a standard controller plugged into each controller port
+    pub fn new() -> Ports {
+        let mut ports = Ports { devices: [None, None, None] };
+        ports.configure(0);
+        ports
+    }
+
+    /// Plugs the devices described by the default expansion device field of an NES 2.0
+    /// header into the ports
+    ///
+    /// Standard controllers are used for devices which aren't emulated.
+    pub fn configure(&mut self, expansion_device: u8) {
+        let (one, two, expansion): (Box<Device>, Box<Device>, Option<Box<Device>>) = match expansion_device {
+            0x02 => (Box::new(FourScore::new(0)), Box::new(FourScore::new(1)), None),
+            0x03 => (Box::new(Joypad::new(0)), Box::new(Joypad::new(1)), Some(Box::new(FourPlayerAdapter::new()))),
+            0x08 => (Box::new(Joypad::new(0)), Box::new(Zapper::new()), None),
+            0x09 => (Box::new(Zapper::new()), Box::new(Zapper::new()), None),
+            0x0B | 0x0C => (Box::new(Joypad::new(0)), Box::new(PowerPad::new()), None),
+            0x0F => (Box::new(Joypad::new(0)), Box::new(Vaus::nes()), None),
+            0x10 => (Box::new(Joypad::new(0)), Box::new(Joypad::new(1)), Some(Box::new(Vaus::famicom()))),
+            _ => (Box::new(Joypad::new(0)), Box::new(Joypad::new(1)), None)
+        };
+        self.devices = [Some(one), Some(two), expansion];
+    }
+
+    /// Plugs `device` into `port`, or unplugs the device in `port` if `None` is provided
+    pub fn plug(&mut self, port: Port, device: Option<Box<Device>>) {
+        self.devices[port.index()] = device;
+    }
+
+    /// Gets the name of the device plugged into `port`, if any
+    pub fn device_name(&self, port: Port) -> Option<&'static str> {
+        self.devices[port.index()].as_ref().map(|d| d.name())
+    }
+
+    /// Updates the state of the controls on every device
+    pub fn set_input(&mut self, input: Input) {
+        for device in self.devices.iter_mut() {
+            if let Some(ref mut d) = *device {
+                d.set_input(input);
+            }
+        }
+    }
+
+    /// Handles a write to $4016
+    pub fn write(&mut self, val: u8) {
+        for device in self.devices.iter_mut() {
+            if let Some(ref mut d) = *device {
+                d.write(val);
+            }
+        }
+    }
+
+    /// Handles a read from $4016 (`port` 0) or $4017 (`port` 1), which clocks the devices read
+    /// through that port
+    pub fn read(&mut self, port: usize, ppu: &rp2C02::Rp2C02) -> u8 {
+        let mut val = OPEN_BUS;
+        if let Some(ref mut d) = self.devices[port] {
+            val |= d.read(port, ppu) & 0x1F;
+        }
+        if let Some(ref mut d) = self.devices[Port::Expansion.index()] {
+            val |= d.read(port, ppu) & 0x1F;
+        }
+        val
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::Rp2C02;
+    use systems::nes::input::{Buttons,Input,Port,Ports};
+
+    fn read_bits(ports: &mut Ports, port: usize, count: usize) -> Vec<u8> {
+        let ppu = Rp2C02::new(None);
+        (0..count).map(|_| ports.read(port, &ppu)).collect()
+    }
+
+    #[test]
+    pub fn standard_controllers_are_plugged_in_by_default() {
+        let mut ports = Ports::new();
+        ports.set_input(Input::Joypad(1, Buttons::B()));
+        ports.write(1);
+        ports.write(0);
+
+        assert_eq!(vec![0x40, 0x41, 0x40], read_bits(&mut ports, 1, 3));
+        assert_eq!(vec![0x40, 0x40, 0x40], read_bits(&mut ports, 0, 3));
+    }
+
+    #[test]
+    pub fn header_selects_devices() {
+        let mut ports = Ports::new();
+        ports.configure(0x08);
+        assert_eq!(Some("Joypad"), ports.device_name(Port::One));
+        assert_eq!(Some("Zapper"), ports.device_name(Port::Two));
+        assert_eq!(None, ports.device_name(Port::Expansion));
+
+        ports.configure(0x10);
+        assert_eq!(Some("Vaus"), ports.device_name(Port::Expansion));
+    }
+
+    #[test]
+    pub fn unplugged_port_reads_open_bus() {
+        let mut ports = Ports::new();
+        ports.plug(Port::One, None);
+        ports.set_input(Input::Joypad(0, Buttons::A()));
+        ports.write(1);
+        ports.write(0);
+
+        assert_eq!(vec![0x40], read_bits(&mut ports, 0, 1));
+    }
+}
@@ -0,0 +1,85 @@
+use hw::rp2C02;
+use systems::nes::input::{Device,Input};
+
+/// The buttons (numbered from 1) shifted out through bit 3, in order
+const FIRST_SEQUENCE: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+
+/// The buttons (numbered from 1) shifted out through bit 4, in order, after which it reads 1
+const SECOND_SEQUENCE: [u8; 4] = [4, 3, 12, 8];
+
+/// Emulates the Power Pad (also sold as the Family Trainer), a floor mat with 12 buttons
+///
+/// The buttons are latched while the strobe signal is high, then shifted out through two
+/// serial lines at once. Side B of the mat uses the same buttons as side A, only with different
+/// numbers printed on them, so the input always uses the numbers of side A.
+pub struct PowerPad {
+    buttons: u16,
+    first: u8,
+    second: u8,
+    strobe: bool
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad {
+            buttons: 0,
+            first: 0,
+            second: 0,
+            strobe: false
+        }
+    }
+
+    fn latch(&mut self) {
+        let buttons = self.buttons;
+        let held = |button: &u8| (buttons >> (button - 1)) & 0x01 != 0;
+        self.first = FIRST_SEQUENCE.iter().rev().fold(0, |acc, b| (acc << 1) | if held(b) { 1 } else { 0 });
+        self.second = SECOND_SEQUENCE.iter().rev().fold(0xF, |acc, b| (acc << 1) | if held(b) { 1 } else { 0 });
+    }
+}
+
+impl Device for PowerPad {
+    fn name(&self) -> &'static str { "Power Pad" }
+
+    fn set_input(&mut self, input: Input) {
+        if let Input::PowerPad(buttons) = input {
+            self.buttons = buttons;
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val & 0x01 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn read(&mut self, _port: usize, _ppu: &rp2C02::Rp2C02) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+
+        let val = ((self.first & 0x01) << 3) | ((self.second & 0x01) << 4);
+        self.first = (self.first >> 1) | 0x80;
+        self.second = (self.second >> 1) | 0x80;
+        val
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::Rp2C02;
+    use systems::nes::input::{Device,Input,PowerPad};
+
+    #[test]
+    pub fn buttons_are_shifted_out_in_two_sequences() {
+        let ppu = Rp2C02::new(None);
+        let mut pad = PowerPad::new();
+        // Buttons 1, 3 and 7
+        pad.set_input(Input::PowerPad(0b000001000101));
+        pad.write(1);
+        pad.write(0);
+
+        let bits: Vec<u8> = (0..9).map(|_| pad.read(1, &ppu)).collect();
+        assert_eq!(vec![0x00, 0x18, 0x00, 0x00, 0x10, 0x10, 0x10, 0x18, 0x18], bits);
+    }
+}
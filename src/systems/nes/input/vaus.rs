@@ -0,0 +1,109 @@
+use hw::rp2C02;
+use systems::nes::input::{Device,Input};
+
+/// Emulates the Arkanoid Vaus controller, a paddle with a single button
+///
+/// The position of the knob is latched while the strobe signal is high, then shifted out most
+/// significant bit first, inverted. The NES version plugs into a controller port and reports
+/// the position in bit 3 and the button in bit 4. The Famicom version plugs into the expansion
+/// port and reports the button in bit 1 of $4016 and the position in bit 1 of $4017.
+pub struct Vaus {
+    famicom: bool,
+    position: u8,
+    button: bool,
+    shift: u8,
+    strobe: bool
+}
+
+impl Vaus {
+    /// Creates the NES version of the controller
+    pub fn nes() -> Vaus {
+        Vaus::new(false)
+    }
+
+    /// Creates the Famicom version of the controller
+    pub fn famicom() -> Vaus {
+        Vaus::new(true)
+    }
+
+    fn new(famicom: bool) -> Vaus {
+        Vaus {
+            famicom: famicom,
+            // The knob's usable range is roughly $62-$F2, start it in the middle
+            position: 0xAA,
+            button: false,
+            shift: 0,
+            strobe: false
+        }
+    }
+
+    fn shift_out(&mut self) -> u8 {
+        if self.strobe {
+            self.shift = self.position;
+        }
+
+        let bit = (!self.shift >> 7) & 0x01;
+        self.shift <<= 1;
+        bit
+    }
+}
+
+impl Device for Vaus {
+    fn name(&self) -> &'static str { "Vaus" }
+
+    fn set_input(&mut self, input: Input) {
+        if let Input::Paddle(position, button) = input {
+            self.position = position;
+            self.button = button;
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    fn read(&mut self, port: usize, _ppu: &rp2C02::Rp2C02) -> u8 {
+        let button = if self.button { 1 } else { 0 };
+        if !self.famicom {
+            (self.shift_out() << 3) | (button << 4)
+        } else if port == 0 {
+            button << 1
+        } else {
+            self.shift_out() << 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::Rp2C02;
+    use systems::nes::input::{Device,Input,Vaus};
+
+    #[test]
+    pub fn nes_vaus_shifts_out_inverted_position_with_button() {
+        let ppu = Rp2C02::new(None);
+        let mut vaus = Vaus::nes();
+        vaus.set_input(Input::Paddle(0b10100000, true));
+        vaus.write(1);
+        vaus.write(0);
+
+        let bits: Vec<u8> = (0..4).map(|_| vaus.read(1, &ppu)).collect();
+        assert_eq!(vec![0x10, 0x18, 0x10, 0x18], bits);
+    }
+
+    #[test]
+    pub fn famicom_vaus_reports_button_and_position_on_separate_ports() {
+        let ppu = Rp2C02::new(None);
+        let mut vaus = Vaus::famicom();
+        vaus.set_input(Input::Paddle(0b01000000, true));
+        vaus.write(1);
+        vaus.write(0);
+
+        assert_eq!(0x02, vaus.read(0, &ppu));
+        assert_eq!(0x02, vaus.read(1, &ppu));
+        assert_eq!(0x00, vaus.read(1, &ppu));
+    }
+}
@@ -0,0 +1,96 @@
+use hw::rp2C02;
+use hw::rp2C02::ppu::{DOTS_PER_SCANLINE,PIXELS_PER_SCANLINE,SCANLINES_PER_FRAME};
+use systems::nes::input::{Device,Input};
+
+/// The number of scanlines after the beam passes the aimed-at spot that the light sensor keeps
+/// sensing it, as the phosphor and the sensor's circuit take time to decay
+const LIGHT_SCANLINES: usize = 20;
+
+/// The brightness (0-255) above which the light sensor senses a pixel
+const LIGHT_THRESHOLD: u32 = 0x80;
+
+/// Emulates the Zapper light gun
+///
+/// The Zapper reports whether its light sensor sees light in bit 3 (0 when light is sensed),
+/// and whether its trigger is pulled in bit 4. The sensor only sees light shortly after the
+/// beam has drawn a bright pixel where the Zapper is aimed.
+pub struct Zapper {
+    aim: Option<(u8, u8)>,
+    trigger: bool
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper {
+            aim: None,
+            trigger: false
+        }
+    }
+
+    /// Returns a value indicating if the light sensor sees light, given the current position
+    /// of the beam
+    pub fn senses_light(&self, ppu: &rp2C02::Rp2C02) -> bool {
+        let (x, y) = match self.aim {
+            Some((x, y)) if (y as usize) < SCANLINES_PER_FRAME => (x as usize, y as usize),
+            _ => return false
+        };
+
+        // The pixel at x is drawn on dot x + 1
+        let aimed = y * DOTS_PER_SCANLINE + x + 1;
+        let beam = ppu.scanline() * DOTS_PER_SCANLINE + ppu.dot();
+        if beam <= aimed || beam > aimed + LIGHT_SCANLINES * DOTS_PER_SCANLINE {
+            return false;
+        }
+
+        let value = ppu.screen()[y * PIXELS_PER_SCANLINE + x];
+        let pixel = ppu.output_palette().pixel(value);
+        let brightness = (pixel.red as u32 * 299 + pixel.green as u32 * 587 + pixel.blue as u32 * 114) / 1000;
+        brightness >= LIGHT_THRESHOLD
+    }
+}
+
+impl Device for Zapper {
+    fn name(&self) -> &'static str { "Zapper" }
+
+    fn set_input(&mut self, input: Input) {
+        if let Input::Zapper(aim, trigger) = input {
+            self.aim = aim;
+            self.trigger = trigger;
+        }
+    }
+
+    fn write(&mut self, _val: u8) {}
+
+    fn read(&mut self, _port: usize, ppu: &rp2C02::Rp2C02) -> u8 {
+        let light = if self.senses_light(ppu) { 0x00 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0x00 };
+        light | trigger
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hw::rp2C02::Rp2C02;
+    use systems::nes::input::{Device,Input,Zapper};
+
+    #[test]
+    pub fn trigger_is_reported_in_bit_4() {
+        let ppu = Rp2C02::new(None);
+        let mut zapper = Zapper::new();
+
+        assert_eq!(0x08, zapper.read(1, &ppu));
+        zapper.set_input(Input::Zapper(None, true));
+        assert_eq!(0x18, zapper.read(1, &ppu));
+    }
+
+    #[test]
+    pub fn light_is_not_sensed_off_screen_or_before_the_beam_arrives() {
+        let ppu = Rp2C02::new(None);
+        let mut zapper = Zapper::new();
+
+        zapper.set_input(Input::Zapper(Some((10, 250)), false));
+        assert!(!zapper.senses_light(&ppu));
+        zapper.set_input(Input::Zapper(Some((10, 10)), false));
+        assert!(!zapper.senses_light(&ppu));
+    }
+}
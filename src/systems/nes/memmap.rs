@@ -1,12 +1,37 @@
+use std::io;
+use std::cell::{Cell,Ref,RefMut,RefCell};
+
 use slog;
 
 use mem;
+use audio::VgmLog;
+use hw::{rp2A03,rp2C02};
 use systems::nes;
+use systems::nes::input;
+use systems::nes::ppubus::PpuBus;
+use systems::nes::apubus::ApuBus;
+
+/// The number of CPU cycles the CPU is suspended for while OAM DMA copies a page to the PPU
+const OAM_DMA_CYCLES: u64 = 513;
+
+/// The number of CPU cycles the CPU is halted for while the DMC reads a sample byte
+const DMC_DMA_CYCLES: u64 = 4;
 
 /// Represents the memory map for a Nintendo Entertainment System
 pub struct MemoryMap {
     ram: mem::Fixed,
-    cart: Option<nes::Cartridge>,
+    vram: RefCell<mem::Fixed>,
+    ppu: RefCell<rp2C02::Rp2C02>,
+    apu: RefCell<rp2A03::Apu>,
+    ports: RefCell<input::Ports>,
+    cart: RefCell<Option<nes::Cartridge>>,
+    cycle: Cell<u64>,
+    cart_cycle: Cell<u64>,
+    dma_cycles: Cell<u64>,
+    oam_dma: Cell<Option<(u64, u64)>>,
+    last_dmc_read: Cell<Option<u64>>,
+    region: nes::Region,
+    vgm: Option<VgmLog>,
     log: slog::Logger,
     memlog: slog::Logger
 }
@@ -18,7 +43,18 @@ impl MemoryMap {
         let memlog = log.new(o!("cartridge" => false));
         MemoryMap {
             ram: mem::Fixed::new(0x0800),
-            cart: None,
+            vram: RefCell::new(mem::Fixed::new(0x1000)),
+            ppu: RefCell::new(rp2C02::Rp2C02::new(Some(log.clone()))),
+            apu: RefCell::new(rp2A03::Apu::new(Some(log.clone()))),
+            ports: RefCell::new(input::Ports::new()),
+            cart: RefCell::new(None),
+            cycle: Cell::new(0),
+            cart_cycle: Cell::new(0),
+            dma_cycles: Cell::new(0),
+            oam_dma: Cell::new(None),
+            last_dmc_read: Cell::new(None),
+            region: nes::Region::Ntsc,
+            vgm: None,
             log: log,
             memlog: memlog
         }
@@ -27,24 +63,297 @@ impl MemoryMap {
 
     /// Loads the provided cartridge into the `MemoryMap`, releasing the cartridge previously
     /// loaded, if any
-    pub fn load(&mut self, cart: nes::Cartridge) {
+    ///
+    /// The cartridge's battery-backed RAM is loaded from its save storage.
+    pub fn load(&mut self, mut cart: nes::Cartridge) {
         info!(self.log,
             "mapper" => cart.mapper.name();
             "Loaded {} cartridge", cart.mapper.name());
-        self.cart = Some(cart);
+        if let Err(e) = cart.load_save() {
+            error!(self.log, "error" => format!("{}", e); "failed to load save, starting with blank RAM");
+        }
+        *self.cart.borrow_mut() = Some(cart);
     }
 
     /// Releases the cartridge currently loaded, if any
     pub fn eject(&mut self) {
-        let old_cart = self.cart.take();
+        let old_cart = self.cart.borrow_mut().take();
         if old_cart.is_none() {
             panic!("Can't eject cartridge, there is no cartridge loaded!");
         }
-        let old_cart = old_cart.unwrap();
+        let mut old_cart = old_cart.unwrap();
 
         info!(self.log,
             "mapper" => old_cart.mapper.name();
             "Ejecting {} cartridge", old_cart.mapper.name());
+        if let Err(e) = old_cart.flush_save() {
+            error!(self.log, "error" => format!("{}", e); "failed to flush save");
+        }
+    }
+
+    /// Writes the battery-backed RAM of the cartridge currently loaded (if any) to its save
+    /// storage, if it has changed
+    pub fn flush_save(&self) -> io::Result<()> {
+        match *self.cart.borrow_mut() {
+            Some(ref mut cart) => cart.flush_save(),
+            None => Ok(())
+        }
+    }
+
+    /// Clears the internal RAM, as when the console is switched on
+    pub fn clear_ram(&mut self) {
+        self.ram = mem::Fixed::new(0x0800);
+    }
+
+    /// Gets the region whose timing the attached hardware follows
+    pub fn region(&self) -> nes::Region {
+        self.region
+    }
+
+    /// Switches the attached hardware to the timing of `region`
+    pub fn set_region(&mut self, region: nes::Region) {
+        info!(self.log, "region" => region; "Switching to {:?} timing", region);
+        self.region = region;
+        self.ppu.borrow_mut().set_timing(region.ppu_timing());
+
+        let mut apu = self.apu.borrow_mut();
+        let sample_rate = apu.sample_rate();
+        apu.set_timing(region.apu_timing());
+        apu.set_sample_rate(sample_rate, region.cpu_clock_rate());
+    }
+
+    /// Gets the PPU attached to the memory map
+    pub fn ppu(&self) -> Ref<rp2C02::Rp2C02> {
+        self.ppu.borrow()
+    }
+
+    /// Gets a mutable reference to the PPU attached to the memory map
+    pub fn ppu_mut(&self) -> RefMut<rp2C02::Rp2C02> {
+        self.ppu.borrow_mut()
+    }
+
+    /// Gets the APU attached to the memory map
+    pub fn apu(&self) -> Ref<rp2A03::Apu> {
+        self.apu.borrow()
+    }
+
+    /// Gets a mutable reference to the APU attached to the memory map
+    pub fn apu_mut(&self) -> RefMut<rp2A03::Apu> {
+        self.apu.borrow_mut()
+    }
+
+    /// Gets the controller ports attached to the memory map
+    pub fn ports(&self) -> Ref<input::Ports> {
+        self.ports.borrow()
+    }
+
+    /// Gets a mutable reference to the controller ports attached to the memory map
+    pub fn ports_mut(&self) -> RefMut<input::Ports> {
+        self.ports.borrow_mut()
+    }
+
+    /// Starts logging writes to the APU registers in the VGM format
+    ///
+    /// The log only contains the writes made after it is started, so it should be started
+    /// before the program initializes the APU. Any log already in progress is discarded.
+    pub fn start_vgm_log(&mut self) {
+        let clock_rate = self.region.cpu_clock_rate();
+        info!(self.log, "cycle" => self.cycle.get(); "Starting VGM log");
+        self.vgm = Some(VgmLog::new(clock_rate, clock_rate as u32, self.cycle.get()));
+    }
+
+    /// Stops logging writes to the APU registers, and writes the VGM file to `out`
+    ///
+    /// Returns `Ok(false)` if no log was in progress.
+    pub fn finish_vgm_log<W>(&mut self, cycle: u64, out: &mut W) -> io::Result<bool> where W: io::Write {
+        match self.vgm.take() {
+            Some(vgm) => {
+                info!(self.log, "cycle" => cycle; "Finishing VGM log");
+                try!(vgm.finish(cycle, out));
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    /// Sets the CPU cycle at which subsequent memory accesses take place
+    ///
+    /// Accesses to the PPU registers first run the PPU up to this cycle so that the CPU
+    /// observes (and affects) the PPU at the correct dot, and accesses to the cartridge first
+    /// clock the cartridge up to this cycle.
+    pub fn set_cycle(&self, cycle: u64) {
+        self.cycle.set(cycle);
+    }
+
+    /// Runs the PPU until it has caught up with `cycle` CPU cycles
+    pub fn run_ppu(&self, cycle: u64) -> rp2C02::Result<()> {
+        self.set_cycle(cycle);
+        let target = self.region.cpu_to_ppu_cycles(cycle);
+        self.with_ppu(|ppu, bus| ppu.step(target, bus))
+    }
+
+    /// Runs the APU until it has caught up with `cycle` CPU cycles
+    ///
+    /// The cycles for which the CPU is halted while the DMC reads samples are added to the DMA
+    /// cycles.
+    pub fn run_apu(&self, cycle: u64) -> rp2A03::Result<()> {
+        let reads = {
+            let mut apu = self.apu.borrow_mut();
+            let mut cart = self.cart.borrow_mut();
+            try!(apu.step(cycle, &mut ApuBus::new(cart.as_mut())));
+            apu.take_dma_reads()
+        };
+        for read in reads {
+            self.dma_cycles.set(self.dma_cycles.get() + self.dmc_stall(read));
+            self.last_dmc_read.set(Some(read));
+        }
+        Ok(())
+    }
+
+    /// Clocks the cartridge for each CPU cycle until it has caught up with `cycle` CPU cycles
+    pub fn run_cart(&self, cycle: u64) {
+        let start = self.cart_cycle.get();
+        if cycle <= start {
+            return;
+        }
+        if let Some(ref mut cart) = *self.cart.borrow_mut() {
+            for _ in start..cycle {
+                cart.mapper.clock();
+            }
+        }
+        self.cart_cycle.set(cycle);
+    }
+
+    /// Returns a value indicating if the APU or the cartridge is asserting the CPU's IRQ line
+    pub fn irq(&self) -> bool {
+        let cart_irq = match *self.cart.borrow() {
+            Some(ref cart) => cart.mapper.irq(),
+            None => false
+        };
+        self.apu.borrow().irq() || cart_irq
+    }
+
+    /// Gets the CPU cycle during which the PPU last asserted its NMI output, if it has been
+    /// asserted since the last call
+    pub fn take_nmi(&self) -> Option<u64> {
+        let region = self.region;
+        self.ppu.borrow_mut().take_nmi().map(|dot| region.ppu_to_cpu_cycles(dot))
+    }
+
+    /// Gets the number of CPU cycles stolen by DMA since the last call, and resets the count
+    pub fn take_dma_cycles(&mut self) -> u64 {
+        let cycles = self.dma_cycles.get();
+        self.dma_cycles.set(0);
+        cycles
+    }
+
+    fn with_ppu<F, T>(&self, f: F) -> rp2C02::Result<T> where F: FnOnce(&mut rp2C02::Rp2C02, &mut PpuBus) -> rp2C02::Result<T> {
+        let mut ppu = self.ppu.borrow_mut();
+        let mut vram = self.vram.borrow_mut();
+        let mut cart = self.cart.borrow_mut();
+        let mut bus = PpuBus::new(&mut *vram, cart.as_mut());
+        f(&mut *ppu, &mut bus)
+    }
+
+    fn sync_ppu(&self) -> mem::Result<()> {
+        let cycle = self.cycle.get();
+        self.run_ppu(cycle).map_err(ppu_error)
+    }
+
+    fn sync_apu(&self) -> mem::Result<()> {
+        let cycle = self.cycle.get();
+        self.run_apu(cycle).map_err(apu_error)
+    }
+
+    fn sync_cart(&self) {
+        let cycle = self.cycle.get();
+        self.run_cart(cycle);
+    }
+
+    /// Returns a value indicating if the DMC reads a sample byte on `cycle`, which halts the
+    /// CPU during the access it is making on that cycle
+    fn dmc_read_on(&self, cycle: u64) -> mem::Result<bool> {
+        try!(self.run_apu(cycle + 1).map_err(apu_error));
+        Ok(self.last_dmc_read.get() == Some(cycle))
+    }
+
+    /// Gets the number of cycles the CPU is halted for by a DMC read on `cycle`
+    ///
+    /// When the read occurs during OAM DMA the CPU is already halted, so the read steals fewer
+    /// cycles, except at the very end of the OAM DMA where it has to realign.
+    fn dmc_stall(&self, cycle: u64) -> u64 {
+        match self.oam_dma.get() {
+            Some((start, end)) if cycle >= start && cycle < end => {
+                if cycle + 2 == end {
+                    1
+                } else if cycle + 1 == end {
+                    3
+                } else {
+                    2
+                }
+            },
+            _ => DMC_DMA_CYCLES
+        }
+    }
+
+    /// Records a write to an APU register in the VGM log, along with the DMC sample that will
+    /// be played if the write may start one
+    fn log_apu_write(&mut self, reg: u64, val: u8) {
+        let cycle = self.cycle.get();
+        if self.vgm.is_none() {
+            return;
+        }
+
+        if reg == 0x12 || reg == 0x13 || (reg == 0x15 && val & 0x10 != 0) {
+            let (addr, len) = {
+                let apu = self.apu.borrow();
+                let dmc = apu.dmc();
+                (dmc.sample_address(), dmc.sample_length())
+            };
+            // Samples which run past $FFFF wrap around to $8000, but VGM players only need the
+            // part of the sample in the DMC's usual range
+            let end = ::std::cmp::min(addr as u64 + len as u64, 0x10000);
+            let sample: Vec<u8> = match *self.cart.borrow() {
+                Some(ref cart) => (addr as u64..end).map(|a| cart.mapper.prg().get_u8(a).unwrap_or(0)).collect(),
+                None => Vec::new()
+            };
+            if let Some(ref mut vgm) = self.vgm {
+                vgm.write_dpcm(addr, &sample);
+            }
+        }
+
+        if let Some(ref mut vgm) = self.vgm {
+            vgm.write_apu(cycle, reg as u8, val);
+        }
+    }
+
+    fn oam_dma(&mut self, page: u8) -> mem::Result<()> {
+        use mem::Memory;
+
+        let base = (page as u64) << 8;
+        for i in 0..0x100 {
+            let val = try!(self.get_u8(base + i));
+            try!(self.with_ppu(|ppu, bus| ppu.write_register(0x04, val, bus)).map_err(ppu_error));
+        }
+
+        // An extra alignment cycle is needed if the DMA starts on an odd CPU cycle
+        let cycles = OAM_DMA_CYCLES + (self.cycle.get() & 1);
+        let start = self.cycle.get() + 1 + self.dma_cycles.get();
+        self.oam_dma.set(Some((start, start + cycles)));
+        self.dma_cycles.set(self.dma_cycles.get() + cycles);
+        Ok(())
+    }
+}
+
+fn ppu_error(err: rp2C02::Error) -> mem::Error {
+    match err {
+        rp2C02::Error::ErrorAccessingMemory(e) => e
+    }
+}
+
+fn apu_error(err: rp2A03::Error) -> mem::Error {
+    match err {
+        rp2A03::Error::ErrorAccessingMemory(e) => e
     }
 }
 
@@ -70,8 +379,12 @@ impl mem::Memory for MemoryMap {
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "PPU",
                 "action" => "read");
-            // Todo: Do something!
-            Ok(0)
+            try!(self.sync_ppu());
+            if eaddr == 0x07 && try!(self.dmc_read_on(self.cycle.get())) {
+                // The CPU repeats the read while it is halted, so the PPU sees an extra read
+                try!(self.with_ppu(|ppu, bus| ppu.read_register(eaddr, bus)).map_err(ppu_error));
+            }
+            self.with_ppu(|ppu, bus| ppu.read_register(eaddr, bus)).map_err(ppu_error)
         }
         else if addr < 0x4200 {
             let eaddr = addr - 0x4000;
@@ -81,10 +394,26 @@ impl mem::Memory for MemoryMap {
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "APU/IO",
                 "action" => "read");
-            // Todo: Do something!
-            Ok(0)
+            if eaddr == 0x15 {
+                try!(self.sync_apu());
+                Ok(self.apu.borrow_mut().read_register(eaddr).unwrap_or(0))
+            } else if eaddr == 0x16 || eaddr == 0x17 {
+                let port = (eaddr - 0x16) as usize;
+                // Light guns need to see the screen as it is at the time of the read
+                try!(self.sync_ppu());
+                let ppu = self.ppu.borrow();
+                if try!(self.dmc_read_on(self.cycle.get())) {
+                    // The CPU repeats the read while it is halted, so the controller is clocked
+                    // an extra time and the program misses a bit
+                    self.ports.borrow_mut().read(port, &*ppu);
+                }
+                Ok(self.ports.borrow_mut().read(port, &*ppu))
+            } else {
+                Ok(0)
+            }
         } else {
-            match self.cart {
+            self.sync_cart();
+            match *self.cart.borrow() {
                 None => {
                     error!(self.memlog,
                         "error";
@@ -123,8 +452,21 @@ impl mem::Memory for MemoryMap {
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "PPU",
                 "action" => "write");
-            // Todo: Do something!
-            Ok(())
+            try!(self.sync_ppu());
+            if let Some(ref mut cart) = *self.cart.borrow_mut() {
+                cart.mapper.ppu_register_write(eaddr, val);
+            }
+            self.with_ppu(|ppu, bus| ppu.write_register(eaddr, val, bus)).map_err(ppu_error)
+        }
+        else if addr == 0x4014 {
+            trace!(self.memlog,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "page" => format!("${:02X}", val),
+                "target" => "OAM DMA",
+                "action" => "write");
+            try!(self.sync_ppu());
+            self.oam_dma(val)
         }
         else if addr < 0x4200 {
             let eaddr = addr - 0x4000;
@@ -134,10 +476,17 @@ impl mem::Memory for MemoryMap {
                 "paddr" => format!("${:04X}", eaddr),
                 "target" => "APU/IO",
                 "action" => "write");
-            // Todo: Do something!
+            if eaddr == 0x16 {
+                self.ports.borrow_mut().write(val);
+            } else if eaddr < 0x18 {
+                try!(self.sync_apu());
+                self.apu.borrow_mut().write_register(eaddr, val);
+                self.log_apu_write(eaddr, val);
+            }
             Ok(())
         } else {
-            match self.cart {
+            self.sync_cart();
+            match *self.cart.borrow_mut() {
                 None => {
                     error!(self.memlog,
                         "error";
@@ -151,6 +500,7 @@ impl mem::Memory for MemoryMap {
                 },
                 Some(ref mut cart) => {
                     // Cartridge has it's own logging
+                    cart.mapper.cpu_cycle(self.cycle.get());
                     cart.mapper.prg_mut().set_u8(addr, val)
                 }
             }
@@ -1,13 +1,17 @@
-pub use self::cart::{Mapper,Cartridge};
+pub use self::cart::{Mapper,Cartridge,Mirroring,SaveStorage,FileStorage,MemoryStorage};
 pub use self::rom::{Rom,RomHeader,load_rom};
+pub use self::region::Region;
+
+use std::io;
+use std::cell::{Ref,RefMut};
 
 use slog;
 
 use mem;
+use image;
 use hw::mos6502::{self,exec};
 use hw::mos6502::instr::decoder;
-
-//use hw::rp2C02;
+use hw::{rp2A03,rp2C02};
 
 /// Contains code to load and manipulate ROMs in the iNES and NES 2.0 formats
 pub mod rom;
@@ -15,10 +19,28 @@ pub mod rom;
 /// Contains code to emulate cartridge hardware (Mappers, etc.)
 pub mod cart;
 
+/// Contains the timing differences between regional variants of the console
+pub mod region;
+
+/// Contains code to emulate the controllers plugged into the console
+pub mod input;
+
+/// Contains code to record and play back input movies in FCEUX's FM2 format
+pub mod movie;
+
+/// Contains code to load music rips in the NSF and NSFe formats, and play them
+pub mod nsf;
+
 mod memmap;
+mod ppubus;
+mod apubus;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// The number of frames between flushes of the cartridge's battery-backed RAM to its save
+/// storage (about five seconds)
+const SAVE_INTERVAL_FRAMES: u64 = 300;
+
 pub struct Error {
     kind: ErrorKind,
     address: u64,
@@ -48,14 +70,19 @@ impl ::std::fmt::Debug for Error {
 #[derive(Debug)]
 pub enum ErrorKind {
     InstructionDecodeError(decoder::Error),
-    ExecutionError(exec::Error)
+    ExecutionError(exec::Error),
+    PpuError(rp2C02::Error),
+    ApuError(rp2A03::Error)
 }
 
 /// Represents a complete NES system, including all necessary hardware and memory
 pub struct Nes {
     pub cpu: mos6502::Mos6502,
     pub mem: memmap::MemoryMap,
-    
+
+    interrupt: Option<mos6502::Interrupt>,
+    forced_region: Option<Region>,
+    save_frame: u64,
     log: slog::Logger
 }
 
@@ -71,6 +98,9 @@ impl Nes {
         Nes {
             cpu: cpu,
             mem: memmap::MemoryMap::new(Some(log.clone())),
+            interrupt: None,
+            forced_region: None,
+            save_frame: 0,
             log: log
         }
     }
@@ -86,6 +116,44 @@ impl Nes {
         Ok(())
     }
 
+    /// Presses the reset button
+    ///
+    /// The CPU behaves as if it were interrupted without writing to the stack, so the stack
+    /// pointer is decremented by 3 and interrupts are disabled. The PPU and APU are reset, then
+    /// execution continues from the reset vector. RAM and the cartridge are untouched.
+    pub fn soft_reset(&mut self) -> mem::Result<()> {
+        info!(self.log, "cycle" => self.cpu.clock.get(); "reset button pressed");
+        self.cpu.registers.sp = self.cpu.registers.sp.wrapping_sub(3);
+        self.cpu.flags.set(mos6502::Flags::INTERRUPT());
+        self.cancel_interrupts();
+        self.mem.ppu_mut().reset();
+        self.mem.apu_mut().reset();
+        self.reset()
+    }
+
+    /// Switches the console off and on again
+    ///
+    /// The CPU registers, RAM and APU registers return to their power-on state, the PPU is
+    /// reset, then execution starts from the reset vector. The cartridge stays loaded, and
+    /// the cycle counters keep counting from where they were.
+    pub fn power_cycle(&mut self) -> mem::Result<()> {
+        info!(self.log, "cycle" => self.cpu.clock.get(); "power cycled");
+        self.cpu.registers = mos6502::cpu::Registers::new();
+        self.cpu.flags.replace(mos6502::Flags::new(0x24));
+        self.cancel_interrupts();
+        self.mem.clear_ram();
+        self.mem.ppu_mut().reset();
+        {
+            let mut apu = self.mem.apu_mut();
+            for reg in 0x00..0x14 {
+                apu.write_register(reg, 0x00);
+            }
+            apu.write_register(0x17, 0x00);
+            apu.reset();
+        }
+        self.reset()
+    }
+
     /// Gets a mutable reference to the current memory
     pub fn mem_mut(&mut self) -> &mut mem::Memory {
         &mut self.mem
@@ -96,18 +164,147 @@ impl Nes {
         &self.mem
     }
 
+    /// Gets the PPU
+    pub fn ppu(&self) -> Ref<rp2C02::Rp2C02> {
+        self.mem.ppu()
+    }
+
+    /// Gets the APU
+    pub fn apu(&self) -> Ref<rp2A03::Apu> {
+        self.mem.apu()
+    }
+
+    /// Sets the rate, in Hz, of the audio samples produced by the APU
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let clock_rate = self.region().cpu_clock_rate();
+        self.mem.apu_mut().set_sample_rate(sample_rate, clock_rate);
+    }
+
+    /// Takes the audio samples produced by the APU since the last call
+    ///
+    /// Calling this after each call to `run_frame` produces the samples for each frame, the
+    /// number of which only varies by one sample from frame to frame.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.mem.apu_mut().take_samples()
+    }
+
+    /// Gets a mutable reference to the APU, to control its mixer
+    pub fn apu_mut(&mut self) -> RefMut<rp2A03::Apu> {
+        self.mem.apu_mut()
+    }
+
+    /// Enables or disables producing samples for `channel` on its own, in addition to the
+    /// mixed output
+    pub fn set_channel_output(&mut self, channel: rp2A03::Channel, enabled: bool) {
+        self.mem.apu_mut().set_channel_output(channel, enabled);
+    }
+
+    /// Takes the audio samples produced for `channel` since the last call
+    pub fn take_channel_samples(&mut self, channel: rp2A03::Channel) -> Vec<i16> {
+        self.mem.apu_mut().take_channel_samples(channel)
+    }
+
+    /// Sets the buttons held on the `controller`th standard controller (0 and 1 are plugged
+    /// into the controller ports, 2 and 3 are attached through a Four Score or adapter)
+    ///
+    /// This is typically called once per frame, before `run_frame`. The program only sees the
+    /// new buttons the next time it strobes the controller.
+    pub fn set_buttons(&mut self, controller: usize, buttons: input::Buttons) {
+        self.set_input(input::Input::Joypad(controller, buttons));
+    }
+
+    /// Updates the state of the controls on the input devices that have them
+    pub fn set_input(&mut self, input: input::Input) {
+        self.mem.ports_mut().set_input(input);
+    }
+
+    /// Plugs `device` into `port`, or unplugs the device in `port` if `None` is provided
+    ///
+    /// Loading a cartridge plugs in the devices it expects, so this should be called after
+    /// `load`.
+    pub fn plug(&mut self, port: input::Port, device: Option<Box<input::Device>>) {
+        self.mem.ports_mut().plug(port, device);
+    }
+
+    /// Starts logging writes to the APU registers in the VGM format
+    ///
+    /// The log only contains the writes made after it is started, so it should be started
+    /// before the cartridge is reset.
+    pub fn start_vgm_log(&mut self) {
+        let cycle = self.cpu.clock.get();
+        self.mem.set_cycle(cycle);
+        self.mem.start_vgm_log();
+    }
+
+    /// Stops logging writes to the APU registers, and writes the VGM file to `out`
+    ///
+    /// Returns `Ok(false)` if no log was in progress.
+    pub fn finish_vgm_log<W>(&mut self, out: &mut W) -> io::Result<bool> where W: io::Write {
+        let cycle = self.cpu.clock.get();
+        self.mem.finish_vgm_log(cycle, out)
+    }
+
+    /// Gets the region whose timing is being emulated
+    pub fn region(&self) -> Region {
+        self.mem.region()
+    }
+
+    /// Forces the system to emulate the timing of `region`, or if `None` is provided, to use
+    /// the region specified by the header of each cartridge as it is loaded
+    pub fn force_region(&mut self, region: Option<Region>) {
+        self.forced_region = region;
+        if let Some(r) = region {
+            self.mem.set_region(r);
+        }
+    }
+
+    /// Gets the most recently rendered frame as an RGB image
+    ///
+    /// The PPU draws directly into the frame, so this should be called when the PPU is in
+    /// vertical blank to avoid capturing parts of two frames.
+    pub fn framebuffer(&self) -> image::Image {
+        let mut data = vec![0; rp2C02::ppu::BYTES_PER_SCREEN];
+        self.mem.ppu().render(&mut data);
+        image::Image::new(rp2C02::ppu::PIXELS_PER_SCANLINE, rp2C02::ppu::SCANLINES_PER_FRAME, data)
+    }
+
+    /// Sets the palette used to convert the PPU's output to RGB
+    pub fn set_palette(&mut self, palette: rp2C02::Palette) {
+        self.mem.ppu_mut().set_output_palette(palette);
+    }
+
     /// Loads a cartridge into the NES
+    ///
+    /// Unless a region has been forced, the system switches to the region the cartridge was
+    /// designed for. The input devices the cartridge expects (or standard controllers, if it
+    /// doesn't specify any) are plugged in. The cartridge's battery-backed RAM is loaded from
+    /// its save storage, and flushed back to it every few seconds while the system runs.
     pub fn load(&mut self, cart: Cartridge) {
+        let region = self.forced_region.unwrap_or_else(|| Region::from_header(cart.header()));
+        self.mem.set_region(region);
+        self.mem.ports_mut().configure(cart.header().expansion_device);
         self.mem.load(cart);
+        self.save_frame = self.mem.ppu().frame();
     }
 
-    /// Ejects the cartridge from the NES
+    /// Ejects the cartridge from the NES, flushing its battery-backed RAM to its save storage
     pub fn eject(&mut self) {
         self.mem.eject();
     }
 
-    /// Runs a single frame of the system
+    /// Writes the cartridge's battery-backed RAM to its save storage, if it has changed
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        self.save_frame = self.mem.ppu().frame();
+        self.mem.flush_save()
+    }
+
+    /// Runs a single instruction (or interrupt sequence) on the system
     pub fn step(&mut self) -> Result<()> {
+        // Service any interrupt that was detected while the previous instruction executed
+        if let Some(interrupt) = self.interrupt.take() {
+            return self.service(interrupt);
+        }
+
         // Fetch next instruction
         let addr = self.cpu.pc.get();
         let instr: mos6502::Instruction = match self.cpu.pc.decode(&self.mem) {
@@ -119,6 +316,10 @@ impl Nes {
             ))
         };
 
+        // Memory accesses are assumed to happen on the final cycle of the instruction, which is
+        // where loads and stores perform them
+        self.mem.set_cycle(self.cpu.clock.get() + instr.base_cycles() - 1);
+
         // Dispatch the instruction
         trace!(self.log,
             "instr" => instr,
@@ -137,10 +338,98 @@ impl Nes {
             "cycle" => self.cpu.clock.get();
             "dispatched");
 
-        // Run the PPU as necessary
-        //let cycles = self.cpu.clock.get();
-        //self.ppu.step(cycles);
+        // Suspend the CPU for any DMA triggered by the instruction (or by the DMC while it
+        // executed)
+        let last_cycle = self.cpu.clock.get() - 1;
+        let dma_cycles = self.mem.take_dma_cycles();
+        self.cpu.clock.tick(dma_cycles);
+
+        // Run the PPU, APU and cartridge until they have caught up with the CPU
+        self.mem.run_cart(self.cpu.clock.get());
+        if let Err(e) = self.mem.run_apu(self.cpu.clock.get()) {
+            return Err(Error::new(
+                ErrorKind::ApuError(e),
+                addr,
+                Some(instr)
+            ));
+        }
+        if let Err(e) = self.mem.run_ppu(self.cpu.clock.get()) {
+            return Err(Error::new(
+                ErrorKind::PpuError(e),
+                addr,
+                Some(instr)
+            ));
+        }
+
+        // Interrupts are polled before the final cycle of the instruction, so one detected
+        // during that cycle is serviced after the next instruction instead
+        self.update_interrupts();
+        self.interrupt = self.cpu.poll_interrupt(last_cycle);
+
+        if self.mem.ppu().frame() >= self.save_frame + SAVE_INTERVAL_FRAMES {
+            if let Err(e) = self.flush_save() {
+                error!(self.log, "error" => format!("{}", e); "failed to flush save");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the system until the PPU has finished rendering the current frame
+    pub fn run_frame(&mut self) -> Result<()> {
+        let frame = self.mem.ppu().frame();
+        while self.mem.ppu().frame() == frame {
+            try!(self.step());
+        }
+        Ok(())
+    }
+
+    fn service(&mut self, interrupt: mos6502::Interrupt) -> Result<()> {
+        let addr = self.cpu.pc.get();
+        self.mem.set_cycle(self.cpu.clock.get());
+
+        debug!(self.log,
+            "interrupt" => interrupt,
+            "cycle" => self.cpu.clock.get();
+            "servicing interrupt");
+        if let Err(e) = mos6502::interrupt(interrupt, &mut self.cpu, &mut self.mem, Some(self.log.clone())) {
+            return Err(Error::new(
+                ErrorKind::ExecutionError(e),
+                addr,
+                None
+            ));
+        }
+
+        if let Err(e) = self.mem.run_apu(self.cpu.clock.get()) {
+            return Err(Error::new(
+                ErrorKind::ApuError(e),
+                addr,
+                None
+            ));
+        }
+        if let Err(e) = self.mem.run_ppu(self.cpu.clock.get()) {
+            return Err(Error::new(
+                ErrorKind::PpuError(e),
+                addr,
+                None
+            ));
+        }
 
+        // The first instruction of the handler always executes before another interrupt
+        self.update_interrupts();
         Ok(())
     }
+
+    fn cancel_interrupts(&mut self) {
+        self.interrupt = None;
+        self.mem.take_nmi();
+        self.cpu.acknowledge(mos6502::Interrupt::Nmi);
+    }
+
+    fn update_interrupts(&mut self) {
+        if let Some(cycle) = self.mem.take_nmi() {
+            self.cpu.nmi(cycle);
+        }
+        self.cpu.set_irq(self.mem.irq());
+    }
 }
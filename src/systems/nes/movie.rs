@@ -0,0 +1,775 @@
+use std::{error,io,fmt};
+use std::io::BufRead;
+
+use slog;
+
+use mem;
+use hw::mos6502::exec;
+use systems::nes::{ErrorKind,Nes,Region};
+use systems::nes::input::{self,Buttons,Input,Port};
+
+/// The version of the FM2 format that is read and written
+pub const VERSION: u32 = 3;
+
+/// The command that presses the reset button at the start of a frame
+pub const SOFT_RESET: u8 = 0x01;
+
+/// The command that switches the console off and on again at the start of a frame
+pub const POWER: u8 = 0x02;
+
+/// The order of the buttons in the input log, starting with the button in bit 7
+const BUTTON_NAMES: &'static [u8; 8] = b"RLDUTSBA";
+
+/// Represents the result of an operation performed on a movie
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Represents an error that occurs while loading, recording or playing a movie
+#[derive(Debug)]
+pub enum Error {
+    /// Indicates that the movie was written in a version of the format other than version 3
+    UnsupportedVersion(u32),
+
+    /// Indicates that the movie's input log is in the binary format, which is not supported
+    BinaryNotSupported,
+
+    /// Indicates that the movie starts from a save state, which can't be loaded
+    SaveStateNotSupported,
+
+    /// Indicates that the movie uses an input device which is not supported, or that a
+    /// device which can't be recorded is plugged into the system
+    UnsupportedDevice(String),
+
+    /// Indicates that the value of a header field is invalid
+    InvalidHeader(String),
+
+    /// Indicates that the line of the input log with the given (one-based) line number is
+    /// invalid
+    InvalidFrame(usize),
+
+    /// Indicates that the system failed while playing or recording the movie
+    SystemError(::systems::nes::Error),
+
+    /// Indicates that an I/O error occurred while reading or writing the movie
+    IoError(io::Error)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::UnsupportedVersion(_)   => "unsupported FM2 version",
+            &Error::BinaryNotSupported      => "binary FM2 input logs are not supported",
+            &Error::SaveStateNotSupported   => "movies starting from a save state are not supported",
+            &Error::UnsupportedDevice(_)    => "unsupported input device",
+            &Error::InvalidHeader(_)        => "FM2 header is invalid",
+            &Error::InvalidFrame(_)         => "FM2 input log is invalid",
+            &Error::SystemError(_)          => "error running system",
+            &Error::IoError(_)              => "i/o error"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::IoError(ref err) => Some(err),
+            _                        => None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::UnsupportedVersion(v)       => write!(fmt, "unsupported FM2 version: {}", v),
+            &Error::UnsupportedDevice(ref d)    => write!(fmt, "unsupported input device: {}", d),
+            &Error::InvalidHeader(ref key)      => write!(fmt, "FM2 header field '{}' is invalid", key),
+            &Error::InvalidFrame(line)          => write!(fmt, "FM2 input log is invalid at line {}", line),
+            &Error::SystemError(ref err)        => write!(fmt, "error running system: {:?}", err),
+            &Error::IoError(ref err)            => write!(fmt, "i/o error: {}", err),
+            _                                   => error::Error::description(self).fmt(fmt)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<::systems::nes::Error> for Error {
+    fn from(err: ::systems::nes::Error) -> Error {
+        Error::SystemError(err)
+    }
+}
+
+/// Describes the device plugged into a controller port during a movie
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum PortDevice {
+    None,
+    Gamepad,
+    Zapper
+}
+
+impl PortDevice {
+    /// Gets the device identified by `val` in the `port0` and `port1` header fields
+    pub fn from_number(val: u32) -> Option<PortDevice> {
+        match val {
+            0 => Some(PortDevice::None),
+            1 => Some(PortDevice::Gamepad),
+            2 => Some(PortDevice::Zapper),
+            _ => None
+        }
+    }
+
+    /// Gets the value identifying the device in the `port0` and `port1` header fields
+    pub fn number(self) -> u32 {
+        match self {
+            PortDevice::None => 0,
+            PortDevice::Gamepad => 1,
+            PortDevice::Zapper => 2
+        }
+    }
+}
+
+/// Describes the input given to the system during a single frame of a movie
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct Frame {
+    /// The commands (`SOFT_RESET` or `POWER`) issued before the frame runs
+    pub commands: u8,
+
+    /// The buttons held on each standard controller (controllers 2 and 3 are only used with
+    /// a Four Score)
+    pub joypads: [Buttons; 4],
+
+    /// The position on the screen the Zapper is aimed at, if any
+    pub zapper: Option<(u8, u8)>,
+
+    /// Indicates if the Zapper's trigger is pulled
+    pub trigger: bool
+}
+
+impl Frame {
+    /// Creates a frame with no commands and no input
+    pub fn new() -> Frame {
+        Frame {
+            commands: 0,
+            joypads: [Buttons::NONE(); 4],
+            zapper: None,
+            trigger: false
+        }
+    }
+
+    /// Creates a frame in which `buttons` are held on the first controller
+    pub fn with_buttons(buttons: Buttons) -> Frame {
+        let mut frame = Frame::new();
+        frame.joypads[0] = buttons;
+        frame
+    }
+}
+
+/// Represents a recording of the input given to the system during each frame, in the format
+/// used by FCEUX (FM2)
+///
+/// See the [FCEUX documentation](http://www.fceux.com/web/help/fceux.html?FM2.html) for
+/// details of the format.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Movie {
+    pub version: u32,
+    pub emu_version: u32,
+    pub rerecord_count: u32,
+
+    /// Indicates if the movie was recorded on a PAL system
+    pub pal: bool,
+
+    pub rom_filename: String,
+
+    /// The checksum of the ROM, which is not verified during playback
+    pub rom_checksum: String,
+
+    pub guid: String,
+
+    /// Indicates if a Four Score is plugged in, in which case four standard controllers are
+    /// recorded instead of the devices given by `ports`
+    pub fourscore: bool,
+
+    pub ports: [PortDevice; 2],
+    pub comments: Vec<String>,
+
+    /// The subtitles shown during playback, along with the frame each is shown from
+    pub subtitles: Vec<(usize, String)>,
+
+    /// The save state the movie starts from (as base64 text), if it doesn't start from
+    /// power-on
+    pub savestate: Option<String>,
+
+    pub frames: Vec<Frame>
+}
+
+impl Movie {
+    /// Creates an empty movie which starts from power-on, with standard controllers in both
+    /// ports
+    pub fn new() -> Movie {
+        Movie {
+            version: VERSION,
+            emu_version: 0,
+            rerecord_count: 0,
+            pal: false,
+            rom_filename: String::new(),
+            rom_checksum: String::new(),
+            guid: new_guid(),
+            fourscore: false,
+            ports: [PortDevice::Gamepad, PortDevice::Gamepad],
+            comments: Vec::new(),
+            subtitles: Vec::new(),
+            savestate: None,
+            frames: Vec::new()
+        }
+    }
+
+    /// Writes the movie to `out` in the FM2 format
+    pub fn write<W>(&self, out: &mut W) -> io::Result<()> where W: io::Write {
+        try!(writeln!(out, "version {}", self.version));
+        try!(writeln!(out, "emuVersion {}", self.emu_version));
+        try!(writeln!(out, "rerecordCount {}", self.rerecord_count));
+        try!(writeln!(out, "palFlag {}", if self.pal { 1 } else { 0 }));
+        try!(writeln!(out, "romFilename {}", self.rom_filename));
+        try!(writeln!(out, "romChecksum {}", self.rom_checksum));
+        try!(writeln!(out, "guid {}", self.guid));
+        try!(writeln!(out, "fourscore {}", if self.fourscore { 1 } else { 0 }));
+        try!(writeln!(out, "port0 {}", self.ports[0].number()));
+        try!(writeln!(out, "port1 {}", self.ports[1].number()));
+        try!(writeln!(out, "port2 0"));
+        for comment in self.comments.iter() {
+            try!(writeln!(out, "comment {}", comment));
+        }
+        for &(frame, ref text) in self.subtitles.iter() {
+            try!(writeln!(out, "subtitle {} {}", frame, text));
+        }
+        if let Some(ref savestate) = self.savestate {
+            try!(writeln!(out, "savestate {}", savestate));
+        }
+
+        for frame in self.frames.iter() {
+            try!(writeln!(out, "{}", self.format_frame(frame)));
+        }
+        Ok(())
+    }
+
+    /// Configures `nes` to play the movie from the beginning
+    ///
+    /// The devices the movie was recorded with are plugged in, the system is switched to the
+    /// region the movie was recorded in, and then it is power cycled.
+    pub fn start(&self, nes: &mut Nes) -> Result<()> {
+        if self.savestate.is_some() {
+            return Err(Error::SaveStateNotSupported);
+        }
+
+        nes.force_region(Some(if self.pal { Region::Pal } else { Region::Ntsc }));
+        if self.fourscore {
+            nes.plug(Port::One, Some(Box::new(input::FourScore::new(0))));
+            nes.plug(Port::Two, Some(Box::new(input::FourScore::new(1))));
+        } else {
+            for (i, &port) in [Port::One, Port::Two].iter().enumerate() {
+                let device: Option<Box<input::Device>> = match self.ports[i] {
+                    PortDevice::None => None,
+                    PortDevice::Gamepad => Some(Box::new(input::Joypad::new(i))),
+                    PortDevice::Zapper => Some(Box::new(input::Zapper::new()))
+                };
+                nes.plug(port, device);
+            }
+        }
+        nes.plug(Port::Expansion, None);
+
+        nes.power_cycle().map_err(|e| memory_error(nes, e))
+    }
+
+    fn format_frame(&self, frame: &Frame) -> String {
+        let mut line = format!("|{}|", frame.commands);
+        if self.fourscore {
+            for buttons in frame.joypads.iter() {
+                line.push_str(&format_buttons(*buttons));
+                line.push('|');
+            }
+        } else {
+            for (i, device) in self.ports.iter().enumerate() {
+                match *device {
+                    PortDevice::None => {},
+                    PortDevice::Gamepad => line.push_str(&format_buttons(frame.joypads[i])),
+                    PortDevice::Zapper => {
+                        let (x, y) = frame.zapper.unwrap_or((0, 255));
+                        line.push_str(&format!("{} {} {} 0 0", x, y, if frame.trigger { 1 } else { 0 }));
+                    }
+                }
+                line.push('|');
+            }
+        }
+        // The expansion port is always empty
+        line.push('|');
+        line
+    }
+
+    fn parse_frame(&self, line: &str, line_number: usize) -> Result<Frame> {
+        let invalid = || Error::InvalidFrame(line_number);
+
+        // The line starts and ends with a separator, leaving empty fields on either side
+        let fields: Vec<&str> = line.split('|').collect();
+        let columns = if self.fourscore { 4 } else { 2 };
+        if fields.len() != columns + 4 {
+            return Err(invalid());
+        }
+
+        let mut frame = Frame::new();
+        frame.commands = try!(fields[1].trim().parse().map_err(|_| invalid()));
+        for i in 0..columns {
+            let field = fields[2 + i];
+            let device = if self.fourscore { PortDevice::Gamepad } else { self.ports[i] };
+            match device {
+                PortDevice::None => {},
+                PortDevice::Gamepad => frame.joypads[i] = try!(parse_buttons(field).ok_or_else(&invalid)),
+                PortDevice::Zapper => {
+                    let (position, trigger) = try!(parse_zapper(field).ok_or_else(&invalid));
+                    frame.zapper = position;
+                    frame.trigger = trigger;
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
+
+/// Loads a movie in the text-based FM2 format
+pub fn load_movie<R>(input: &mut R) -> Result<Movie> where R: io::Read {
+    let mut movie = Movie::new();
+    movie.guid = String::new();
+
+    let reader = io::BufReader::new(input);
+    for (i, line) in reader.lines().enumerate() {
+        let line = try!(line);
+        let line = line.trim_right_matches('\r');
+        if line.starts_with('|') {
+            let frame = try!(movie.parse_frame(line, i + 1));
+            movie.frames.push(frame);
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = match line.find(' ') {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => (line, "")
+        };
+        match key {
+            "version" => movie.version = try!(parse_number(key, value)),
+            "emuVersion" => movie.emu_version = try!(parse_number(key, value)),
+            "rerecordCount" => movie.rerecord_count = try!(parse_number(key, value)),
+            "palFlag" => movie.pal = try!(parse_number(key, value)) != 0,
+            "romFilename" => movie.rom_filename = value.to_string(),
+            "romChecksum" => movie.rom_checksum = value.to_string(),
+            "guid" => movie.guid = value.to_string(),
+            "fourscore" => movie.fourscore = try!(parse_number(key, value)) != 0,
+            "port0" | "port1" => {
+                let port = if key == "port0" { 0 } else { 1 };
+                let val = try!(parse_number(key, value));
+                movie.ports[port] = try!(PortDevice::from_number(val).ok_or_else(|| {
+                    Error::UnsupportedDevice(format!("{} {}", key, value))
+                }));
+            },
+            "port2" => if try!(parse_number(key, value)) != 0 {
+                return Err(Error::UnsupportedDevice(format!("{} {}", key, value)));
+            },
+            "binary" => if try!(parse_number(key, value)) != 0 {
+                return Err(Error::BinaryNotSupported);
+            },
+            "comment" => movie.comments.push(value.to_string()),
+            "subtitle" => {
+                let (frame, text) = match value.find(' ') {
+                    Some(pos) => (&value[..pos], &value[pos + 1..]),
+                    None => (value, "")
+                };
+                let frame = try!(parse_number(key, frame));
+                movie.subtitles.push((frame as usize, text.to_string()));
+            },
+            "savestate" => movie.savestate = Some(value.to_string()),
+            // The remaining fields (such as the microphone and FDS flags) don't affect playback
+            _ => {}
+        }
+    }
+
+    if movie.version != VERSION {
+        return Err(Error::UnsupportedVersion(movie.version));
+    }
+    Ok(movie)
+}
+
+/// Plays a movie back on a system, one frame at a time
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    log: slog::Logger
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie, logger: Option<slog::Logger>) -> MoviePlayer {
+        MoviePlayer {
+            movie: movie,
+            frame: 0,
+            log: unwrap_logger!(logger)
+        }
+    }
+
+    /// Gets the movie being played
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Gets the number of frames played so far
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Returns a value indicating if every frame of the movie has been played
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Prepares `nes` (which should have the movie's ROM loaded) to play the movie from the
+    /// beginning
+    pub fn start(&mut self, nes: &mut Nes) -> Result<()> {
+        info!(self.log,
+            "frames" => self.movie.frames.len();
+            "starting movie of {}", self.movie.rom_filename);
+        self.frame = 0;
+        self.movie.start(nes)
+    }
+
+    /// Runs the next frame of the movie on `nes`
+    ///
+    /// Returns `Ok(false)` without running the system once the movie has finished.
+    pub fn run_frame(&mut self, nes: &mut Nes) -> Result<bool> {
+        if self.finished() {
+            return Ok(false);
+        }
+
+        let frame = self.movie.frames[self.frame];
+        try!(run_frame(nes, &frame, &self.log));
+        self.frame += 1;
+        Ok(true)
+    }
+}
+
+/// Records the input given to a system into a movie, one frame at a time
+pub struct MovieRecorder {
+    movie: Movie,
+    log: slog::Logger
+}
+
+impl MovieRecorder {
+    /// Starts recording a movie of `nes`, which should have the ROM named `rom_filename`
+    /// loaded, from power-on
+    ///
+    /// The movie records the devices plugged into the system's controller ports, which must
+    /// be standard controllers, Zappers or a Four Score. The system is power cycled, so the
+    /// recording starts from the same state it will be played back from.
+    pub fn new(nes: &mut Nes, rom_filename: &str, logger: Option<slog::Logger>) -> Result<MovieRecorder> {
+        let mut movie = Movie::new();
+        movie.rom_filename = rom_filename.to_string();
+        movie.pal = nes.region() == Region::Pal;
+
+        {
+            let ports = nes.mem.ports();
+            if let Some(name) = ports.device_name(Port::Expansion) {
+                return Err(Error::UnsupportedDevice(name.to_string()));
+            }
+            movie.fourscore = ports.device_name(Port::One) == Some("Four Score");
+            if !movie.fourscore {
+                for (i, &port) in [Port::One, Port::Two].iter().enumerate() {
+                    movie.ports[i] = match ports.device_name(port) {
+                        None => PortDevice::None,
+                        Some("Joypad") => PortDevice::Gamepad,
+                        Some("Zapper") => PortDevice::Zapper,
+                        Some(name) => return Err(Error::UnsupportedDevice(name.to_string()))
+                    };
+                }
+            }
+        }
+
+        let log = unwrap_logger!(logger);
+        info!(log, "fourscore" => movie.fourscore; "recording movie of {}", rom_filename);
+        try!(nes.power_cycle().map_err(|e| memory_error(nes, e)));
+        Ok(MovieRecorder {
+            movie: movie,
+            log: log
+        })
+    }
+
+    /// Gets the movie recorded so far
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Gets a mutable reference to the movie recorded so far, to fill in its other fields
+    pub fn movie_mut(&mut self) -> &mut Movie {
+        &mut self.movie
+    }
+
+    /// Runs a single frame on `nes` with the input given by `frame`, and records it
+    pub fn run_frame(&mut self, nes: &mut Nes, frame: Frame) -> Result<()> {
+        try!(run_frame(nes, &frame, &self.log));
+        self.movie.frames.push(frame);
+        Ok(())
+    }
+
+    /// Stops recording, and gets the movie that was recorded
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Issues the commands in `frame`, updates the input devices, then runs a frame
+fn run_frame(nes: &mut Nes, frame: &Frame, log: &slog::Logger) -> Result<()> {
+    if frame.commands & POWER != 0 {
+        try!(nes.power_cycle().map_err(|e| memory_error(nes, e)));
+    } else if frame.commands & SOFT_RESET != 0 {
+        try!(nes.soft_reset().map_err(|e| memory_error(nes, e)));
+    }
+    if frame.commands & !(POWER | SOFT_RESET) != 0 {
+        warn!(log,
+            "commands" => frame.commands;
+            "ignoring unsupported movie commands (FDS and VS System commands)");
+    }
+
+    for (i, buttons) in frame.joypads.iter().enumerate() {
+        nes.set_buttons(i, *buttons);
+    }
+    nes.set_input(Input::Zapper(frame.zapper, frame.trigger));
+    try!(nes.run_frame());
+    Ok(())
+}
+
+fn memory_error(nes: &Nes, err: mem::Error) -> Error {
+    Error::SystemError(::systems::nes::Error::new(
+        ErrorKind::ExecutionError(exec::Error::ErrorReadingMemory(err)),
+        nes.cpu.pc.get(),
+        None))
+}
+
+fn parse_number(key: &str, value: &str) -> Result<u32> {
+    value.trim().parse().map_err(|_| Error::InvalidHeader(key.to_string()))
+}
+
+/// Parses the buttons of a standard controller, where any character other than `.` or a space
+/// indicates that the button in that position is held
+fn parse_buttons(field: &str) -> Option<Buttons> {
+    if field.len() != BUTTON_NAMES.len() {
+        return None;
+    }
+    let bits = field.bytes().enumerate()
+        .filter(|&(_, c)| c != b'.' && c != b' ')
+        .fold(0, |bits, (i, _)| bits | (0x80 >> i));
+    Some(Buttons::new(bits))
+}
+
+fn format_buttons(buttons: Buttons) -> String {
+    BUTTON_NAMES.iter().enumerate()
+        .map(|(i, &name)| if buttons.bits & (0x80 >> i) != 0 { name as char } else { '.' })
+        .collect()
+}
+
+/// Parses the state of a Zapper, given as its X and Y coordinates, the mouse buttons held (the
+/// trigger is the first) and two unused values
+fn parse_zapper(field: &str) -> Option<(Option<(u8, u8)>, bool)> {
+    let values: Vec<u32> = match field.split_whitespace().map(|v| v.parse()).collect() {
+        Ok(v) => v,
+        Err(_) => return None
+    };
+    if values.len() < 3 {
+        return None;
+    }
+
+    let (x, y) = (values[0], values[1]);
+    let position = if x < 256 && y < 240 { Some((x as u8, y as u8)) } else { None };
+    Some((position, values[2] & 0x01 != 0))
+}
+
+/// Creates a GUID to identify a new movie, derived from the current time
+fn new_guid() -> String {
+    use std::time::{SystemTime,UNIX_EPOCH};
+
+    let mut state = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() ^ ((d.subsec_nanos() as u64) << 32),
+        Err(_) => 0
+    } | 1;
+    let mut bytes = [0u8; 16];
+    for b in bytes.iter_mut() {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        *b = (state >> 24) as u8;
+    }
+
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{}-{}-{}-{}-{}",
+        hex[0..4].concat(), hex[4..6].concat(), hex[6..8].concat(), hex[8..10].concat(), hex[10..16].concat())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use systems::nes::{Cartridge,Nes,load_rom};
+    use systems::nes::input::Buttons;
+    use systems::nes::movie::{self,Error,Frame,Movie,MoviePlayer,MovieRecorder,PortDevice,load_movie};
+
+    // Counts the number of times A is seen held on the first controller in $00:
+    //   LDA #1; STA $4016; LDA #0; STA $4016
+    //   LDA $4016; AND #1; CLC; ADC $00; STA $00
+    //   JMP $8000
+    const PROGRAM: [u8; 23] = [
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40,
+        0xAD, 0x16, 0x40, 0x29, 0x01, 0x18, 0x65, 0x00, 0x85, 0x00,
+        0x4C, 0x00, 0x80
+    ];
+
+    fn system() -> Nes {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0; 0x8000];
+        prg[0..PROGRAM.len()].copy_from_slice(&PROGRAM);
+        prg[0x7FFD] = 0x80;
+        data.extend_from_slice(&prg);
+
+        let rom = load_rom(&mut Cursor::new(data)).unwrap();
+        let mut nes = Nes::new(None);
+        nes.load(Cartridge::load(rom, None).unwrap());
+        nes
+    }
+
+    const FM2: &'static str = "version 3\n\
+        emuVersion 20600\n\
+        rerecordCount 4\n\
+        palFlag 0\n\
+        romFilename smb\n\
+        romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+        guid 52DD4B3B-0F26-4B68-B1F5-5A8D3D4B0B0B\n\
+        fourscore 0\n\
+        port0 1\n\
+        port1 2\n\
+        port2 0\n\
+        comment author somebody\n\
+        subtitle 10 hello there\n\
+        |0|........|0 255 0 0 0||\n\
+        |1|R..U...A|128 120 1 0 0||\n\
+        |0|.L.....A|10 300 0 0 0||\n";
+
+    #[test]
+    pub fn header_is_parsed() {
+        let movie = load_movie(&mut Cursor::new(FM2)).unwrap();
+
+        assert_eq!(20600, movie.emu_version);
+        assert_eq!(4, movie.rerecord_count);
+        assert_eq!("smb", movie.rom_filename);
+        assert_eq!("52DD4B3B-0F26-4B68-B1F5-5A8D3D4B0B0B", movie.guid);
+        assert_eq!([PortDevice::Gamepad, PortDevice::Zapper], movie.ports);
+        assert_eq!(vec!["author somebody".to_string()], movie.comments);
+        assert_eq!(vec![(10, "hello there".to_string())], movie.subtitles);
+    }
+
+    #[test]
+    pub fn frames_are_parsed() {
+        let movie = load_movie(&mut Cursor::new(FM2)).unwrap();
+
+        assert_eq!(3, movie.frames.len());
+        assert_eq!(Frame::new(), movie.frames[0]);
+
+        let frame = movie.frames[1];
+        assert_eq!(movie::SOFT_RESET, frame.commands);
+        assert_eq!(Buttons::RIGHT() | Buttons::UP() | Buttons::A(), frame.joypads[0]);
+        assert_eq!(Some((128, 120)), frame.zapper);
+        assert!(frame.trigger);
+
+        // Coordinates outside the screen mean the Zapper isn't aimed at it
+        assert_eq!(None, movie.frames[2].zapper);
+    }
+
+    #[test]
+    pub fn written_movie_can_be_loaded() {
+        let mut movie = load_movie(&mut Cursor::new(FM2)).unwrap();
+        movie.frames[2].zapper = None;
+        let mut out = Vec::new();
+        movie.write(&mut out).unwrap();
+
+        assert_eq!(movie, load_movie(&mut Cursor::new(out)).unwrap());
+    }
+
+    #[test]
+    pub fn four_score_movies_have_four_controllers() {
+        let fm2 = "version 3\nfourscore 1\n|0|.......A|......B.|.....S..|....T...||\n";
+        let movie = load_movie(&mut Cursor::new(fm2)).unwrap();
+
+        assert_eq!([Buttons::A(), Buttons::B(), Buttons::SELECT(), Buttons::START()], movie.frames[0].joypads);
+    }
+
+    #[test]
+    pub fn malformed_frame_is_rejected() {
+        match load_movie(&mut Cursor::new("version 3\nport0 1\nport1 1\n|0|......||\n")) {
+            Err(Error::InvalidFrame(4)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    pub fn unsupported_movies_are_rejected() {
+        match load_movie(&mut Cursor::new("version 2\n")) {
+            Err(Error::UnsupportedVersion(2)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        match load_movie(&mut Cursor::new("version 3\nport0 5\n")) {
+            Err(Error::UnsupportedDevice(_)) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    pub fn new_movies_have_guid() {
+        assert_eq!(36, Movie::new().guid.len());
+    }
+
+    #[test]
+    pub fn recorded_movie_plays_back_identically() {
+        let mut nes = system();
+        let mut recorder = MovieRecorder::new(&mut nes, "count.nes", None).unwrap();
+        for i in 0..10 {
+            let mut frame = Frame::with_buttons(if i >= 3 && i < 6 { Buttons::A() } else { Buttons::NONE() });
+            if i == 7 {
+                frame.commands = movie::SOFT_RESET;
+            }
+            recorder.run_frame(&mut nes, frame).unwrap();
+        }
+        let count = nes.mem().get_u8(0x0000).unwrap();
+        let cycles = nes.cpu.clock.get();
+        assert!(count != 0);
+
+        let mut out = Vec::new();
+        recorder.finish().write(&mut out).unwrap();
+        let mut nes = system();
+        let mut player = MoviePlayer::new(load_movie(&mut Cursor::new(out)).unwrap(), None);
+        player.start(&mut nes).unwrap();
+        while player.run_frame(&mut nes).unwrap() {}
+
+        assert!(player.finished());
+        assert_eq!(10, player.frame());
+        assert_eq!(Ok(count), nes.mem().get_u8(0x0000));
+        assert_eq!(cycles, nes.cpu.clock.get());
+    }
+
+    #[test]
+    pub fn movies_from_save_states_are_rejected() {
+        let mut movie = Movie::new();
+        movie.savestate = Some("base64:AAAA".to_string());
+
+        match movie.start(&mut system()) {
+            Err(Error::SaveStateNotSupported) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+}
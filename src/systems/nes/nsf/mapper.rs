@@ -0,0 +1,264 @@
+use slog;
+
+use mem;
+use systems::nes;
+use systems::nes::nsf::{self,Nsf};
+
+/// The address of the player's idle loop, which routines return to once they have finished
+///
+/// The loop sits in the otherwise unused space between the APU registers and the bank
+/// switching registers, where no expansion chip has registers either.
+pub const DRIVER_ADDRESS: u16 = 0x4200;
+
+/// The idle loop: `JMP DRIVER_ADDRESS`
+const DRIVER: [u8; 3] = [0x4C, DRIVER_ADDRESS as u8, (DRIVER_ADDRESS >> 8) as u8];
+
+const BANK_REGISTERS: u64 = 0x5FF8;
+
+struct Prg {
+    ram: mem::Fixed,
+    rom: Vec<u8>,
+    banks: [usize; 8],
+    bank_switched: bool,
+    log: slog::Logger
+}
+
+/// Emulates the hardware of an NSF player: 4K bank switching of the music data at $8000-$FFFF
+/// through the registers at $5FF8-$5FFF, 8K of RAM at $6000-$7FFF, and the player's idle loop
+pub struct NsfMapper {
+    prg: Prg,
+    chr: mem::Fixed
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf, logger: Option<slog::Logger>) -> NsfMapper {
+        let bank_switched = nsf.is_bank_switched();
+        let rom = if bank_switched {
+            // The load address gives the offset of the data within the first bank
+            let padding = (nsf.load_address as usize) & (nsf::BANK_SIZE - 1);
+            let mut rom = vec![0; padding];
+            rom.extend_from_slice(&nsf.data);
+            let banks = (rom.len() + nsf::BANK_SIZE - 1) / nsf::BANK_SIZE;
+            rom.resize(::std::cmp::max(banks, 1) * nsf::BANK_SIZE, 0);
+            rom
+        } else {
+            // The data is loaded at its address, and anything beyond $FFFF is dropped
+            let offset = (nsf.load_address as usize).saturating_sub(0x8000);
+            let mut rom = vec![0; 0x8000];
+            let len = ::std::cmp::min(nsf.data.len(), rom.len() - offset);
+            rom[offset..offset + len].copy_from_slice(&nsf.data[0..len]);
+            rom
+        };
+
+        let mut prg = Prg {
+            ram: mem::Fixed::new(0x2000),
+            rom: rom,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            bank_switched: bank_switched,
+            log: unwrap_logger!(logger).new(o!("mapper" => "NSF", "cartridge" => true))
+        };
+        if bank_switched {
+            for (i, bank) in nsf.banks.iter().enumerate() {
+                prg.select_bank(i, *bank);
+            }
+        }
+
+        NsfMapper {
+            prg: prg,
+            chr: mem::Fixed::new(0x2000)
+        }
+    }
+}
+
+impl Prg {
+    fn select_bank(&mut self, slot: usize, bank: u8) {
+        let banks = self.rom.len() / nsf::BANK_SIZE;
+        self.banks[slot] = bank as usize % banks;
+        trace!(self.log,
+            "bank switch";
+            "slot" => slot,
+            "bank" => bank);
+    }
+
+    fn rom_offset(&self, addr: u64) -> usize {
+        let slot = ((addr - 0x8000) as usize) / nsf::BANK_SIZE;
+        self.banks[slot] * nsf::BANK_SIZE + (addr as usize & (nsf::BANK_SIZE - 1))
+    }
+}
+
+impl nes::Mapper for NsfMapper {
+    fn name(&self) -> &'static str { "NSF" }
+
+    fn mirroring(&self) -> nes::Mirroring { nes::Mirroring::Vertical }
+
+    fn prg(&self) -> &mem::Memory
+    {
+        return &self.prg;
+    }
+
+    fn prg_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.prg;
+    }
+
+    fn chr(&self) -> &mem::Memory
+    {
+        return &self.chr;
+    }
+
+    fn chr_mut(&mut self) -> &mut mem::Memory
+    {
+        return &mut self.chr;
+    }
+}
+
+impl mem::Memory for Prg {
+    fn len(&self) -> u64 { 0x10000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        let driver = DRIVER_ADDRESS as u64;
+        if addr >= driver && addr < driver + DRIVER.len() as u64 {
+            Ok(DRIVER[(addr - driver) as usize])
+        } else if addr < 0x6000 {
+            // Nothing else is mapped here, so the data bus keeps the last value placed on it
+            // (typically the high byte of the address)
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Open Bus",
+                "action" => "read");
+            Ok((addr >> 8) as u8)
+        } else if addr < 0x8000 {
+            let eaddr = addr - 0x6000;
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "paddr" => format!("${:04X}", eaddr),
+                "target" => "RAM",
+                "action" => "read");
+            self.ram.get_u8(eaddr)
+        } else {
+            let eaddr = self.rom_offset(addr);
+            trace!(self.log,
+                "read";
+                "vaddr" => format!("${:04X}", addr),
+                "paddr" => format!("${:05X}", eaddr),
+                "target" => "ROM",
+                "action" => "read");
+            Ok(self.rom[eaddr])
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        if addr >= BANK_REGISTERS && addr < 0x6000 {
+            if self.bank_switched {
+                self.select_bank((addr - BANK_REGISTERS) as usize, val);
+            }
+            Ok(())
+        } else if addr >= 0x6000 && addr < 0x8000 {
+            let eaddr = addr - 0x6000;
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "paddr" => format!("${:04X}", eaddr),
+                "target" => "RAM",
+                "action" => "write");
+            self.ram.set_u8(eaddr, val)
+        } else {
+            // The registers of expansion sound chips are spread across this space (and overlap
+            // the ROM), so writes to them are ignored rather than treated as errors
+            trace!(self.log,
+                "write";
+                "vaddr" => format!("${:04X}", addr),
+                "target" => "Unmapped",
+                "action" => "write");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::Mapper;
+    use systems::nes::nsf::{Nsf,Format,ExpansionChips};
+    use systems::nes::nsf::mapper::{NsfMapper,DRIVER_ADDRESS};
+    use systems::nes::rom::TvSystem;
+
+    fn nsf(load_address: u16, banks: [u8; 8], data: Vec<u8>) -> Nsf {
+        Nsf {
+            format: Format::Nsf,
+            version: 1,
+            songs: 1,
+            starting_song: 0,
+            load_address: load_address,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: 16639,
+            pal_speed: 19997,
+            banks: banks,
+            tv_system: TvSystem::NTSC,
+            chips: ExpansionChips::from_header_byte(0),
+            tracks: Vec::new(),
+            playlist: Vec::new(),
+            data: data
+        }
+    }
+
+    #[test]
+    pub fn data_is_loaded_at_load_address() {
+        let mapper = NsfMapper::new(&nsf(0x8100, [0; 8], vec![0x12, 0x34]), None);
+
+        assert_eq!(Ok(0x00), mapper.prg().get_u8(0x80FF));
+        assert_eq!(Ok(0x12), mapper.prg().get_u8(0x8100));
+        assert_eq!(Ok(0x34), mapper.prg().get_u8(0x8101));
+    }
+
+    #[test]
+    pub fn bank_switched_data_is_offset_within_first_bank() {
+        let mut data = vec![0xAA; 0x1000];
+        data.extend_from_slice(&[0xBB; 0x1000]);
+        let mapper = NsfMapper::new(&nsf(0x8010, [1, 0, 0, 0, 0, 0, 0, 2], data), None);
+
+        // Bank 0 holds the padding and the start of the data
+        assert_eq!(Ok(0x00), mapper.prg().get_u8(0x900F));
+        assert_eq!(Ok(0xAA), mapper.prg().get_u8(0x9010));
+        // Bank 1 is selected at $8000
+        assert_eq!(Ok(0xAA), mapper.prg().get_u8(0x800F));
+        assert_eq!(Ok(0xBB), mapper.prg().get_u8(0x8010));
+        // Bank 2 is selected at $F000
+        assert_eq!(Ok(0xBB), mapper.prg().get_u8(0xF00F));
+        assert_eq!(Ok(0x00), mapper.prg().get_u8(0xF010));
+    }
+
+    #[test]
+    pub fn bank_registers_switch_banks() {
+        let mut data = vec![0xAA; 0x1000];
+        data.extend_from_slice(&[0xBB; 0x1000]);
+        let mut mapper = NsfMapper::new(&nsf(0x8000, [0, 1, 0, 0, 0, 0, 0, 0], data), None);
+
+        assert_eq!(Ok(0xAA), mapper.prg().get_u8(0x8000));
+        mapper.prg_mut().set_u8(0x5FF8, 1).unwrap();
+        assert_eq!(Ok(0xBB), mapper.prg().get_u8(0x8000));
+    }
+
+    #[test]
+    pub fn ram_is_writable() {
+        let mut mapper = NsfMapper::new(&nsf(0x8000, [0; 8], vec![0]), None);
+
+        mapper.prg_mut().set_u8(0x6123, 0x42).unwrap();
+        assert_eq!(Ok(0x42), mapper.prg().get_u8(0x6123));
+    }
+
+    #[test]
+    pub fn driver_loops_forever() {
+        let mapper = NsfMapper::new(&nsf(0x8000, [0; 8], vec![0]), None);
+
+        assert_eq!(Ok(0x4C), mapper.prg().get_u8(DRIVER_ADDRESS as u64));
+        assert_eq!(Ok(DRIVER_ADDRESS as u8), mapper.prg().get_u8(DRIVER_ADDRESS as u64 + 1));
+        assert_eq!(Ok((DRIVER_ADDRESS >> 8) as u8), mapper.prg().get_u8(DRIVER_ADDRESS as u64 + 2));
+    }
+}
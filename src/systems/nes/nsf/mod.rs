@@ -0,0 +1,630 @@
+pub use self::mapper::NsfMapper;
+pub use self::player::NsfPlayer;
+
+use std::{error,io,fmt};
+
+use byteorder::{LittleEndian,ReadBytesExt};
+
+use systems::nes::rom::{CartridgeInfo,RamSize,RomHeader,TvSystem,Version};
+
+mod mapper;
+mod player;
+
+const HEADER_SIZE: usize = 0x80;
+
+/// The size of the banks selected by the bank switching registers
+pub const BANK_SIZE: usize = 0x1000;
+
+/// The default time between calls to PLAY on NTSC consoles, in microseconds (about 60.1Hz)
+pub const DEFAULT_NTSC_SPEED: u16 = 16639;
+
+/// The default time between calls to PLAY on PAL consoles, in microseconds (about 50.0Hz)
+pub const DEFAULT_PAL_SPEED: u16 = 19997;
+
+/// The iNES mapper number of the board with the same bank switching registers as an NSF player
+const NSF_MAPPER: u16 = 31;
+
+/// Represents the result of an operation performed on an NSF file
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Represents an error that occurs while operating on an NSF or NSFe file
+#[derive(Debug)]
+pub enum Error {
+    /// Indicates that the file is neither an NSF nor an NSFe file
+    InvalidSignature,
+
+    /// Indicates that the header (or NSFe `INFO` chunk) is invalid
+    InvalidHeader,
+
+    /// Indicates that a chunk required by the NSFe format is missing
+    MissingChunk(&'static str),
+
+    /// Indicates that the NSFe file contains a chunk which must be understood to play it, but
+    /// which is not supported
+    UnsupportedChunk(String),
+
+    /// Indicates that an unexpected end-of-file was reached while reading an NSFe chunk
+    EndOfFileDuringChunk,
+
+    /// Indicates that an I/O error occurred while reading the file
+    IoError(io::Error)
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::InvalidSignature      => "NSF file signature is invalid",
+            &Error::InvalidHeader         => "NSF file header is invalid",
+            &Error::MissingChunk(_)       => "NSFe file is missing a required chunk",
+            &Error::UnsupportedChunk(_)   => "NSFe file contains an unsupported chunk",
+            &Error::EndOfFileDuringChunk  => "unexpected end of file while reading NSFe chunk",
+            &Error::IoError(_)            => "i/o error"
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::IoError(ref err) => Some(err),
+            _                        => None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::MissingChunk(id)         => write!(fmt, "NSFe file is missing the '{}' chunk", id),
+            &Error::UnsupportedChunk(ref id) => write!(fmt, "NSFe file contains the unsupported '{}' chunk", id),
+            &Error::IoError(ref err)         => write!(fmt, "i/o error: {}", err),
+            _                                => error::Error::description(self).fmt(fmt)
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+/// Describes the format a `Nsf` was loaded from
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub enum Format {
+    /// The original NSF format, with a fixed-size header
+    Nsf,
+
+    /// The NSFe format, made up of chunks containing additional metadata
+    Nsfe
+}
+
+/// Describes the sound chips, in addition to the APU, used by an NSF
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+pub struct ExpansionChips {
+    pub vrc6: bool,
+    pub vrc7: bool,
+    pub fds: bool,
+    pub mmc5: bool,
+    pub namco163: bool,
+    pub sunsoft5b: bool
+}
+
+impl ExpansionChips {
+    /// Creates an `ExpansionChips` based on the expansion chip byte of the NSF header
+    pub fn from_header_byte(val: u8) -> ExpansionChips {
+        ExpansionChips {
+            vrc6: val & 0x01 != 0,
+            vrc7: val & 0x02 != 0,
+            fds: val & 0x04 != 0,
+            mmc5: val & 0x08 != 0,
+            namco163: val & 0x10 != 0,
+            sunsoft5b: val & 0x20 != 0
+        }
+    }
+
+    /// Returns a value indicating if any expansion chip is used
+    pub fn any(&self) -> bool {
+        self.vrc6 || self.vrc7 || self.fds || self.mmc5 || self.namco163 || self.sunsoft5b
+    }
+
+    /// Gets the names of the expansion chips which are used
+    pub fn names(&self) -> Vec<&'static str> {
+        let chips = [
+            (self.vrc6, "VRC6"),
+            (self.vrc7, "VRC7"),
+            (self.fds, "FDS"),
+            (self.mmc5, "MMC5"),
+            (self.namco163, "Namco 163"),
+            (self.sunsoft5b, "Sunsoft 5B")
+        ];
+        chips.iter().filter(|c| c.0).map(|c| c.1).collect()
+    }
+}
+
+/// Contains the metadata an NSFe file provides for a single track
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct Track {
+    /// The name of the track
+    pub title: Option<String>,
+
+    /// The author of the track, if it differs from the artist of the file
+    pub author: Option<String>,
+
+    /// The length of the track, in milliseconds, before it starts fading out
+    pub length: Option<u32>,
+
+    /// The length of the fade out at the end of the track, in milliseconds
+    pub fade: Option<u32>
+}
+
+/// Represents a music rip, loaded from the NSF or NSFe format
+pub struct Nsf {
+    /// The format the file was loaded from
+    pub format: Format,
+
+    /// The version of the NSF format (always 1 for NSFe files)
+    pub version: u8,
+
+    /// The number of songs (tracks) in the file
+    pub songs: u8,
+
+    /// The zero-based index of the song to play first
+    pub starting_song: u8,
+
+    /// The address the data is loaded at (or, when bank switching is used, the offset of the
+    /// data within the first bank)
+    pub load_address: u16,
+
+    /// The address of the routine which initializes a song
+    pub init_address: u16,
+
+    /// The address of the routine which is called to play each tick of a song
+    pub play_address: u16,
+
+    /// The name of the game the music is from
+    pub title: String,
+
+    /// The composer of the music
+    pub artist: String,
+
+    /// The copyright holder of the music
+    pub copyright: String,
+
+    /// The person who ripped the music (NSFe only)
+    pub ripper: String,
+
+    /// The time between calls to PLAY on NTSC consoles, in microseconds
+    pub ntsc_speed: u16,
+
+    /// The time between calls to PLAY on PAL consoles, in microseconds
+    pub pal_speed: u16,
+
+    /// The initial values of the bank switching registers at $5FF8-$5FFF, which are all 0 if
+    /// the file does not use bank switching
+    pub banks: [u8; 8],
+
+    /// The TV system the music was written for
+    pub tv_system: TvSystem,
+
+    /// The expansion sound chips used by the music
+    pub chips: ExpansionChips,
+
+    /// The metadata provided for each track (NSFe only)
+    pub tracks: Vec<Track>,
+
+    /// The order in which the tracks should be played (NSFe only)
+    pub playlist: Vec<u8>,
+
+    /// The program and music data
+    pub data: Vec<u8>
+}
+
+impl ::std::fmt::Debug for Nsf {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        fmt.debug_struct("Nsf")
+            .field("format", &self.format)
+            .field("title", &self.title)
+            .field("songs", &self.songs)
+            .field("load_address", &format!("${:04X}", self.load_address))
+            .field("init_address", &format!("${:04X}", self.init_address))
+            .field("play_address", &format!("${:04X}", self.play_address))
+            .field("banks", &self.banks)
+            .field("tv_system", &self.tv_system)
+            .field("chips", &self.chips)
+            .field("data", &self.data.len())
+            .finish()
+    }
+}
+
+impl Nsf {
+    /// Returns a value indicating if the file uses the bank switching registers
+    pub fn is_bank_switched(&self) -> bool {
+        self.banks.iter().any(|b| *b != 0)
+    }
+
+    /// Gets the metadata for the zero-based `song`, if the file provides any
+    pub fn track(&self, song: u8) -> Option<&Track> {
+        self.tracks.get(song as usize)
+    }
+
+    /// Builds an iNES header describing a cartridge which behaves like an NSF player
+    pub fn header(&self) -> RomHeader {
+        RomHeader {
+            prg_rom_size: ((self.data.len() + 0x3FFF) / 0x4000) as u16,
+            chr_rom_size: 0,
+            prg_ram_size: RamSize { battery_backed: 0, total: 0x2000 },
+            chr_ram_size: RamSize { battery_backed: 0, total: 0x2000 },
+            cartridge: CartridgeInfo::new(NSF_MAPPER, 0, false),
+            version: Version::NES2,
+            vertical_arrangement: false,
+            four_screen_vram: false,
+            sram_battery_backed: false,
+            sram_present: true,
+            trainer_present: false,
+            vs_unisystem: false,
+            playchoice_10: false,
+            tv_system: self.tv_system,
+            expansion_device: 0x01
+        }
+    }
+}
+
+/// Reads an NSF or NSFe file from the provided reader
+///
+/// # Arguments
+/// * `input` - The `std::io::Read` instance to read the file from
+pub fn load_nsf<R>(input: &mut R) -> Result<Nsf> where R: io::Read {
+    let mut signature = [0u8; 4];
+    try!(read_exact(input, &mut signature).map_err(|_| Error::InvalidSignature));
+
+    match &signature {
+        b"NESM" => read_nsf(input),
+        b"NSFE" => read_nsfe(input),
+        _ => Err(Error::InvalidSignature)
+    }
+}
+
+fn read_nsf<R>(input: &mut R) -> Result<Nsf> where R: io::Read {
+    // Read the rest of the header, the signature has already been read
+    let mut header = [0u8; HEADER_SIZE];
+    try!(read_exact(input, &mut header[4..]).map_err(|_| Error::InvalidHeader));
+    if header[4] != 0x1A {
+        return Err(Error::InvalidSignature);
+    }
+
+    let songs = header[0x06];
+    if songs == 0 {
+        return Err(Error::InvalidHeader);
+    }
+
+    let mut banks = [0u8; 8];
+    banks.copy_from_slice(&header[0x70..0x78]);
+
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+
+    Ok(Nsf {
+        format: Format::Nsf,
+        version: header[0x05],
+        songs: songs,
+        starting_song: header[0x07].saturating_sub(1),
+        load_address: word_at(&header, 0x08),
+        init_address: word_at(&header, 0x0A),
+        play_address: word_at(&header, 0x0C),
+        title: read_string(&header[0x0E..0x2E]),
+        artist: read_string(&header[0x2E..0x4E]),
+        copyright: read_string(&header[0x4E..0x6E]),
+        ripper: String::new(),
+        ntsc_speed: default_speed(word_at(&header, 0x6E), DEFAULT_NTSC_SPEED),
+        pal_speed: default_speed(word_at(&header, 0x78), DEFAULT_PAL_SPEED),
+        banks: banks,
+        tv_system: tv_system(header[0x7A]),
+        chips: ExpansionChips::from_header_byte(header[0x7B]),
+        tracks: Vec::new(),
+        playlist: Vec::new(),
+        data: data
+    })
+}
+
+fn read_nsfe<R>(input: &mut R) -> Result<Nsf> where R: io::Read {
+    let mut nsf = Nsf {
+        format: Format::Nsfe,
+        version: 1,
+        songs: 1,
+        starting_song: 0,
+        load_address: 0,
+        init_address: 0,
+        play_address: 0,
+        title: String::new(),
+        artist: String::new(),
+        copyright: String::new(),
+        ripper: String::new(),
+        ntsc_speed: DEFAULT_NTSC_SPEED,
+        pal_speed: DEFAULT_PAL_SPEED,
+        banks: [0; 8],
+        tv_system: TvSystem::NTSC,
+        chips: ExpansionChips::from_header_byte(0),
+        tracks: Vec::new(),
+        playlist: Vec::new(),
+        data: Vec::new()
+    };
+
+    let mut info = false;
+    let mut data = false;
+    loop {
+        // Each chunk is a 32-bit length and a four character ID, followed by its contents
+        let len = match input.read_u32::<LittleEndian>() {
+            Ok(len) => len as usize,
+            Err(_) => return Err(Error::MissingChunk("NEND"))
+        };
+        let mut id = [0u8; 4];
+        try!(read_exact(input, &mut id).map_err(|_| Error::EndOfFileDuringChunk));
+        let mut chunk = vec![0u8; len];
+        try!(read_exact(input, &mut chunk).map_err(|_| Error::EndOfFileDuringChunk));
+
+        match &id {
+            b"INFO" => {
+                if chunk.len() < 9 {
+                    return Err(Error::InvalidHeader);
+                }
+                nsf.load_address = word_at(&chunk, 0);
+                nsf.init_address = word_at(&chunk, 2);
+                nsf.play_address = word_at(&chunk, 4);
+                nsf.tv_system = tv_system(chunk[6]);
+                nsf.chips = ExpansionChips::from_header_byte(chunk[7]);
+                nsf.songs = chunk.get(8).cloned().unwrap_or(1);
+                nsf.starting_song = chunk.get(9).cloned().unwrap_or(0);
+                info = true;
+            },
+            b"DATA" => {
+                if !info {
+                    return Err(Error::MissingChunk("INFO"));
+                }
+                nsf.data = chunk;
+                data = true;
+            },
+            b"NEND" => break,
+            b"BANK" => {
+                // Missing bytes are treated as zero
+                for (bank, val) in nsf.banks.iter_mut().zip(chunk.iter()) {
+                    *bank = *val;
+                }
+            },
+            b"RATE" => {
+                if chunk.len() >= 2 {
+                    nsf.ntsc_speed = default_speed(word_at(&chunk, 0), DEFAULT_NTSC_SPEED);
+                }
+                if chunk.len() >= 4 {
+                    nsf.pal_speed = default_speed(word_at(&chunk, 2), DEFAULT_PAL_SPEED);
+                }
+            },
+            b"auth" => {
+                let mut strings = read_strings(&chunk).into_iter();
+                nsf.title = strings.next().unwrap_or_else(String::new);
+                nsf.artist = strings.next().unwrap_or_else(String::new);
+                nsf.copyright = strings.next().unwrap_or_else(String::new);
+                nsf.ripper = strings.next().unwrap_or_else(String::new);
+            },
+            b"plst" => nsf.playlist = chunk,
+            b"tlbl" => {
+                for (i, title) in read_strings(&chunk).into_iter().enumerate() {
+                    track_mut(&mut nsf.tracks, i).title = Some(title);
+                }
+            },
+            b"taut" => {
+                for (i, author) in read_strings(&chunk).into_iter().enumerate() {
+                    track_mut(&mut nsf.tracks, i).author = Some(author);
+                }
+            },
+            b"time" => {
+                for (i, time) in read_times(&chunk).into_iter().enumerate() {
+                    track_mut(&mut nsf.tracks, i).length = time;
+                }
+            },
+            b"fade" => {
+                for (i, time) in read_times(&chunk).into_iter().enumerate() {
+                    track_mut(&mut nsf.tracks, i).fade = time;
+                }
+            },
+            _ => {
+                // Chunks with an upper case ID must be understood to play the file, the rest
+                // are optional metadata
+                if id[0] >= b'A' && id[0] <= b'Z' {
+                    return Err(Error::UnsupportedChunk(String::from_utf8_lossy(&id).into_owned()));
+                }
+            }
+        }
+    }
+
+    if !info {
+        Err(Error::MissingChunk("INFO"))
+    } else if !data {
+        Err(Error::MissingChunk("DATA"))
+    } else {
+        Ok(nsf)
+    }
+}
+
+fn read_exact<R>(input: &mut R, buf: &mut [u8]) -> io::Result<()> where R: io::Read {
+    let mut read = 0;
+    while read < buf.len() {
+        match try!(input.read(&mut buf[read..])) {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of file")),
+            n => read += n
+        }
+    }
+    Ok(())
+}
+
+fn word_at(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) | ((data[offset + 1] as u16) << 8)
+}
+
+fn default_speed(speed: u16, default: u16) -> u16 {
+    if speed == 0 { default } else { speed }
+}
+
+fn tv_system(val: u8) -> TvSystem {
+    if val & 0x02 != 0 {
+        TvSystem::Dual
+    } else if val & 0x01 != 0 {
+        TvSystem::PAL
+    } else {
+        TvSystem::NTSC
+    }
+}
+
+/// Reads a string which is terminated (or padded) by NUL characters
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[0..end]).into_owned()
+}
+
+/// Reads a sequence of NUL-terminated strings
+fn read_strings(data: &[u8]) -> Vec<String> {
+    let data = if data.last() == Some(&0) { &data[0..data.len() - 1] } else { data };
+    if data.is_empty() {
+        return Vec::new();
+    }
+    data.split(|c| *c == 0).map(read_string).collect()
+}
+
+/// Reads a sequence of times in milliseconds, where negative times are unspecified
+fn read_times(data: &[u8]) -> Vec<Option<u32>> {
+    data.chunks(4).filter(|c| c.len() == 4).map(|c| {
+        let time = (c[0] as u32) | ((c[1] as u32) << 8) | ((c[2] as u32) << 16) | ((c[3] as u32) << 24);
+        if (time as i32) < 0 { None } else { Some(time) }
+    }).collect()
+}
+
+fn track_mut(tracks: &mut Vec<Track>, index: usize) -> &mut Track {
+    while tracks.len() <= index {
+        tracks.push(Track::default());
+    }
+    &mut tracks[index]
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::nsf::{self,Format,Error};
+    use systems::nes::rom::TvSystem;
+
+    fn nsf_file() -> Vec<u8> {
+        let mut file = vec![0u8; 0x80];
+        file[0..5].copy_from_slice(b"NESM\x1A");
+        file[0x05] = 1;
+        file[0x06] = 3;
+        file[0x07] = 2;
+        file[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        file[0x0E..0x13].copy_from_slice(b"Title");
+        file[0x2E..0x34].copy_from_slice(b"Artist");
+        file[0x6E..0x70].copy_from_slice(&[0x1A, 0x41]);
+        file[0x7A] = 0x02;
+        file[0x7B] = 0x21;
+        file.extend_from_slice(&[0xEA; 16]);
+        file
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u32;
+        let mut chunk = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    pub fn nsf_header_is_read() {
+        let nsf = nsf::load_nsf(&mut &nsf_file()[..]).unwrap();
+
+        assert_eq!(Format::Nsf, nsf.format);
+        assert_eq!(3, nsf.songs);
+        assert_eq!(1, nsf.starting_song);
+        assert_eq!(0x8000, nsf.load_address);
+        assert_eq!(0x8003, nsf.init_address);
+        assert_eq!(0x8006, nsf.play_address);
+        assert_eq!("Title", nsf.title);
+        assert_eq!("Artist", nsf.artist);
+        assert_eq!("", nsf.copyright);
+        assert_eq!(0x411A, nsf.ntsc_speed);
+        assert_eq!(nsf::DEFAULT_PAL_SPEED, nsf.pal_speed);
+        assert_eq!(TvSystem::Dual, nsf.tv_system);
+        assert!(nsf.chips.vrc6 && nsf.chips.sunsoft5b && !nsf.chips.fds);
+        assert!(!nsf.is_bank_switched());
+        assert_eq!(vec![0xEA; 16], nsf.data);
+    }
+
+    #[test]
+    pub fn nsf_bank_switching_values_are_read() {
+        let mut file = nsf_file();
+        file[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        let nsf = nsf::load_nsf(&mut &file[..]).unwrap();
+
+        assert!(nsf.is_bank_switched());
+        assert_eq!([0, 1, 2, 3, 4, 5, 6, 7], nsf.banks);
+    }
+
+    #[test]
+    pub fn invalid_signature_is_rejected() {
+        match nsf::load_nsf(&mut &b"NES\x1A0000"[..]) {
+            Err(Error::InvalidSignature) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    pub fn nsfe_chunks_are_read() {
+        let mut file = b"NSFE".to_vec();
+        file.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x01, 0x10, 0x02, 0x01]));
+        file.extend(chunk(b"BANK", &[0, 1]));
+        file.extend(chunk(b"RATE", &[0x1A, 0x41]));
+        file.extend(chunk(b"DATA", &[0xEA; 8]));
+        file.extend(chunk(b"auth", b"Game\0Artist\0Copyright\0Ripper\0"));
+        file.extend(chunk(b"tlbl", b"First\0Second\0"));
+        file.extend(chunk(b"time", &[0x10, 0x27, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]));
+        file.extend(chunk(b"plst", &[1, 0]));
+        file.extend(chunk(b"text", b"ignored"));
+        file.extend(chunk(b"NEND", &[]));
+        let nsf = nsf::load_nsf(&mut &file[..]).unwrap();
+
+        assert_eq!(Format::Nsfe, nsf.format);
+        assert_eq!(2, nsf.songs);
+        assert_eq!(1, nsf.starting_song);
+        assert_eq!(0x8006, nsf.play_address);
+        assert_eq!(TvSystem::PAL, nsf.tv_system);
+        assert!(nsf.chips.namco163);
+        assert_eq!([0, 1, 0, 0, 0, 0, 0, 0], nsf.banks);
+        assert_eq!(0x411A, nsf.ntsc_speed);
+        assert_eq!(nsf::DEFAULT_PAL_SPEED, nsf.pal_speed);
+        assert_eq!("Game", nsf.title);
+        assert_eq!("Ripper", nsf.ripper);
+        assert_eq!(Some("Second".to_string()), nsf.track(1).unwrap().title);
+        assert_eq!(Some(10000), nsf.track(0).unwrap().length);
+        assert_eq!(None, nsf.track(1).unwrap().length);
+        assert_eq!(vec![1, 0], nsf.playlist);
+        assert_eq!(vec![0xEA; 8], nsf.data);
+    }
+
+    #[test]
+    pub fn nsfe_without_data_is_rejected() {
+        let mut file = b"NSFE".to_vec();
+        file.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x00, 0x01]));
+        file.extend(chunk(b"NEND", &[]));
+
+        match nsf::load_nsf(&mut &file[..]) {
+            Err(Error::MissingChunk("DATA")) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    pub fn nsfe_with_unknown_required_chunk_is_rejected() {
+        let mut file = b"NSFE".to_vec();
+        file.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x00, 0x01]));
+        file.extend(chunk(b"ABCD", &[]));
+
+        match nsf::load_nsf(&mut &file[..]) {
+            Err(Error::UnsupportedChunk(ref id)) if id == "ABCD" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use slog;
+
+use mem;
+use hw::mos6502::{self,exec};
+use systems::nes::{self,Cartridge,ErrorKind,Nes,Region};
+use systems::nes::nsf::{Nsf,NsfMapper};
+use systems::nes::nsf::mapper::DRIVER_ADDRESS;
+
+/// The longest INIT may run before playback starts regardless, in seconds
+///
+/// Some rips never return from INIT, and play their music from it instead.
+const INIT_TIMEOUT: f64 = 1.0;
+
+/// Plays the songs in an NSF file on the CPU and APU of an emulated NES
+///
+/// The player acts like the minimal driver of a hardware NSF player: it calls INIT to start a
+/// song, then calls PLAY at the rate given by the file. Routines are called by pushing the
+/// address of the player's idle loop as their return address, so the CPU spins in the loop
+/// between calls.
+pub struct NsfPlayer {
+    nes: Nes,
+    nsf: Nsf,
+    song: u8,
+    play_period: f64,
+    next_play: f64,
+    log: slog::Logger
+}
+
+impl NsfPlayer {
+    /// Creates a player for `nsf`, which runs with the timing of the region the music was
+    /// written for
+    pub fn new(nsf: Nsf, logger: Option<slog::Logger>) -> NsfPlayer {
+        let log = unwrap_logger!(logger);
+
+        let mut nes = Nes::new(Some(log.clone()));
+        let mapper = NsfMapper::new(&nsf, Some(log.clone()));
+        nes.load(Cartridge::new(nsf.header(), Box::new(mapper), Some(log.clone())));
+
+        if nsf.chips.any() {
+            warn!(log,
+                "chips" => nsf.chips.names().join(",");
+                "expansion sound chips are not emulated, only the APU will be heard");
+        }
+
+        NsfPlayer {
+            nes: nes,
+            song: nsf.starting_song,
+            nsf: nsf,
+            play_period: 0.0,
+            next_play: 0.0,
+            log: log
+        }
+    }
+
+    /// Gets the file being played
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    /// Gets the system the file is being played on
+    pub fn nes(&self) -> &Nes {
+        &self.nes
+    }
+
+    /// Gets a mutable reference to the system the file is being played on, to collect the
+    /// audio it produces
+    pub fn nes_mut(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+
+    /// Gets the zero-based index of the song being played
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Gets the number of times PLAY is called per second
+    pub fn play_rate(&self) -> f64 {
+        1000000.0 / self.speed() as f64
+    }
+
+    /// Starts playing the zero-based `song`
+    ///
+    /// The RAM, APU and bank switching registers are reset to the state the music expects,
+    /// then INIT is called and run to completion.
+    pub fn start_song(&mut self, song: u8) -> nes::Result<()> {
+        info!(self.log,
+            "song" => song;
+            "starting song {} of {}", song + 1, self.nsf.songs);
+        self.song = song;
+
+        let cycle = self.nes.cpu.clock.get();
+        self.nes.mem.set_cycle(cycle);
+        if let Err(e) = self.reset_hardware() {
+            return Err(self.memory_error(e));
+        }
+
+        let pal = self.nes.region() != Region::Ntsc;
+        self.nes.cpu.registers.a = song;
+        self.nes.cpu.registers.x = if pal { 1 } else { 0 };
+        self.nes.cpu.registers.y = 0;
+        self.nes.cpu.registers.sp = 0xFD;
+        // The music is driven by the player, so interrupts are left disabled
+        self.nes.cpu.flags.replace(mos6502::Flags::new(0x24));
+
+        let init = self.nsf.init_address;
+        try!(self.call(init));
+        let timeout = cycle + (INIT_TIMEOUT * self.nes.region().cpu_clock_rate()) as u64;
+        while !self.idle() && self.nes.cpu.clock.get() < timeout {
+            try!(self.nes.step());
+        }
+        if !self.idle() {
+            warn!(self.log, "song" => song; "INIT did not return, starting playback anyway");
+        }
+
+        self.play_period = self.speed() as f64 * self.nes.region().cpu_clock_rate() / 1000000.0;
+        self.next_play = self.nes.cpu.clock.get() as f64;
+        Ok(())
+    }
+
+    /// Calls PLAY, then runs the system until PLAY is next due
+    ///
+    /// If the previous call to PLAY has not returned yet, it is left to run instead.
+    pub fn run_tick(&mut self) -> nes::Result<()> {
+        if self.idle() {
+            let play = self.nsf.play_address;
+            try!(self.call(play));
+        } else {
+            debug!(self.log,
+                "cycle" => self.nes.cpu.clock.get();
+                "PLAY overran its period");
+        }
+
+        self.next_play += self.play_period;
+        while (self.nes.cpu.clock.get() as f64) < self.next_play {
+            try!(self.nes.step());
+        }
+        Ok(())
+    }
+
+    fn speed(&self) -> u16 {
+        match self.nes.region() {
+            Region::Ntsc => self.nsf.ntsc_speed,
+            Region::Pal | Region::Dendy => self.nsf.pal_speed
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.nes.cpu.pc.get() == DRIVER_ADDRESS as u64
+    }
+
+    /// Starts executing the routine at `addr`, which returns to the idle loop
+    fn call(&mut self, addr: u16) -> nes::Result<()> {
+        let ret = DRIVER_ADDRESS - 1;
+        let result = self.nes.cpu.push(&mut self.nes.mem, (ret >> 8) as u8)
+            .and_then(|_| self.nes.cpu.push(&mut self.nes.mem, ret as u8));
+        if let Err(e) = result {
+            return Err(self.memory_error(e));
+        }
+
+        trace!(self.log, "addr" => format!("${:04X}", addr); "calling routine");
+        self.nes.cpu.pc.set(addr as u64);
+        Ok(())
+    }
+
+    fn reset_hardware(&mut self) -> mem::Result<()> {
+        use mem::Memory;
+
+        let mem = &mut self.nes.mem;
+        for addr in 0x0000..0x0800 {
+            try!(mem.set_u8(addr, 0));
+        }
+        for addr in 0x6000..0x8000 {
+            try!(mem.set_u8(addr, 0));
+        }
+
+        for reg in 0x4000..0x4014 {
+            try!(mem.set_u8(reg, 0));
+        }
+        try!(mem.set_u8(0x4015, 0x00));
+        try!(mem.set_u8(0x4015, 0x0F));
+        try!(mem.set_u8(0x4017, 0x40));
+
+        for (i, bank) in self.nsf.banks.iter().enumerate() {
+            try!(mem.set_u8(0x5FF8 + i as u64, *bank));
+        }
+        Ok(())
+    }
+
+    fn memory_error(&self, err: mem::Error) -> nes::Error {
+        nes::Error::new(
+            ErrorKind::ExecutionError(exec::Error::ErrorReadingMemory(err)),
+            self.nes.cpu.pc.get(),
+            None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::nsf::{Nsf,NsfPlayer,Format,ExpansionChips};
+    use systems::nes::rom::TvSystem;
+
+    // INIT ($8000): STA $00; RTS
+    // PLAY ($8003): INC $01; RTS
+    fn nsf() -> Nsf {
+        Nsf {
+            format: Format::Nsf,
+            version: 1,
+            songs: 2,
+            starting_song: 0,
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8003,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: 16639,
+            pal_speed: 19997,
+            banks: [0; 8],
+            tv_system: TvSystem::NTSC,
+            chips: ExpansionChips::from_header_byte(0),
+            tracks: Vec::new(),
+            playlist: Vec::new(),
+            data: vec![0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]
+        }
+    }
+
+    #[test]
+    pub fn init_is_called_with_song() {
+        let mut player = NsfPlayer::new(nsf(), None);
+        player.start_song(1).unwrap();
+
+        assert_eq!(Ok(1), player.nes().mem().get_u8(0x0000));
+        assert_eq!(1, player.song());
+    }
+
+    #[test]
+    pub fn play_is_called_once_per_tick() {
+        let mut player = NsfPlayer::new(nsf(), None);
+        player.start_song(0).unwrap();
+        for _ in 0..10 {
+            player.run_tick().unwrap();
+        }
+
+        assert_eq!(Ok(10), player.nes().mem().get_u8(0x0001));
+    }
+
+    #[test]
+    pub fn ticks_follow_play_rate() {
+        let mut player = NsfPlayer::new(nsf(), None);
+        player.start_song(0).unwrap();
+        let start = player.nes().cpu.clock.get();
+        for _ in 0..60 {
+            player.run_tick().unwrap();
+        }
+
+        // 60 ticks at 16639us is just under one second of 1.79MHz CPU cycles
+        let cycles = player.nes().cpu.clock.get() - start;
+        assert!(cycles > 1786790 && cycles < 1786820, "ran for {} cycles", cycles);
+        assert!((player.play_rate() - 60.1) < 0.01);
+    }
+}
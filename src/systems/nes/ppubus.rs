@@ -0,0 +1,74 @@
+use mem;
+use hw::rp2C02;
+use systems::nes;
+
+/// Represents the address space seen by the PPU
+///
+/// Pattern tables ($0000-$1FFF) come from the cartridge CHR, nametables ($2000-$3EFF) come from
+/// the console's VRAM arranged according to the cartridge's mirroring.
+pub struct PpuBus<'a> {
+    vram: &'a mut mem::Fixed,
+    cart: Option<&'a mut nes::Cartridge>
+}
+
+impl<'a> PpuBus<'a> {
+    /// Constructs a new `PpuBus` over the provided VRAM and cartridge (if any)
+    pub fn new(vram: &'a mut mem::Fixed, cart: Option<&'a mut nes::Cartridge>) -> PpuBus<'a> {
+        PpuBus {
+            vram: vram,
+            cart: cart
+        }
+    }
+}
+
+impl<'a> mem::Memory for PpuBus<'a> {
+    fn len(&self) -> u64 { 0x4000 }
+
+    fn get_u8(&self, addr: u64) -> mem::Result<u8> {
+        let addr = addr & 0x3FFF;
+        if addr < 0x2000 {
+            match self.cart {
+                Some(ref cart) => cart.mapper.chr().get_u8(addr),
+                None => Err(mem::Error::new(
+                    mem::ErrorKind::MemoryNotPresent,
+                    "Attempted to read from cartridge CHR, but there is no cartridge present"))
+            }
+        } else {
+            match self.cart {
+                Some(ref cart) => cart.mapper.read_nametable(&*self.vram, addr),
+                None => self.vram.get_u8(nes::Mirroring::Horizontal.translate(addr))
+            }
+        }
+    }
+
+    fn set_u8(&mut self, addr: u64, val: u8) -> mem::Result<()> {
+        let addr = addr & 0x3FFF;
+        if addr < 0x2000 {
+            match self.cart {
+                Some(ref mut cart) => cart.mapper.chr_mut().set_u8(addr, val),
+                None => Err(mem::Error::new(
+                    mem::ErrorKind::MemoryNotPresent,
+                    "Attempted to write to cartridge CHR, but there is no cartridge present"))
+            }
+        } else {
+            match self.cart {
+                Some(ref mut cart) => cart.mapper.write_nametable(&mut *self.vram, addr, val),
+                None => self.vram.set_u8(nes::Mirroring::Horizontal.translate(addr), val)
+            }
+        }
+    }
+}
+
+impl<'a> rp2C02::Bus for PpuBus<'a> {
+    fn a12_rising(&mut self, low_cycles: u64) {
+        if let Some(ref mut cart) = self.cart {
+            cart.mapper.ppu_a12_rising(low_cycles);
+        }
+    }
+
+    fn rendering_fetch(&mut self, addr: u16, cycle: u64) {
+        if let Some(ref mut cart) = self.cart {
+            cart.mapper.ppu_fetch(addr as u64, cycle);
+        }
+    }
+}
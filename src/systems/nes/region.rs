@@ -0,0 +1,136 @@
+use hw::{rp2A03,rp2C02};
+use systems::nes::rom::{RomHeader,TvSystem};
+
+/// The master clock rate of an NTSC console, in Hz
+const NTSC_MASTER_CLOCK: u64 = 21477272;
+
+/// The master clock rate of PAL and Dendy consoles, in Hz
+const PAL_MASTER_CLOCK: u64 = 26601712;
+
+/// Describes the regional variant of the console being emulated
+///
+/// The variants differ in their master clock rate, how that clock is divided between the CPU
+/// and PPU, the length of each frame, and some of the APU timing tables.
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Region {
+    /// The NTSC console, with an RP2A03 CPU and RP2C02 PPU
+    Ntsc,
+
+    /// The PAL console, with an RP2A07 CPU and RP2C07 PPU
+    Pal,
+
+    /// The Dendy famiclone, which runs PAL clocks with NTSC-like CPU and vblank timing
+    Dendy
+}
+
+serialize_via_debug!(Region);
+
+impl Region {
+    /// Determines the region a ROM was designed for from its iNES/NES 2.0 header
+    ///
+    /// ROMs which don't specify a region, or support multiple regions, run as NTSC.
+    pub fn from_header(header: &RomHeader) -> Region {
+        match header.tv_system {
+            TvSystem::PAL => Region::Pal,
+            TvSystem::Dendy => Region::Dendy,
+            TvSystem::NTSC | TvSystem::Dual | TvSystem::Unknown => Region::Ntsc
+        }
+    }
+
+    /// Gets the master clock rate, in Hz
+    pub fn master_clock(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_MASTER_CLOCK,
+            Region::Pal | Region::Dendy => PAL_MASTER_CLOCK
+        }
+    }
+
+    /// Gets the number of master clock cycles in a CPU cycle
+    pub fn cpu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15
+        }
+    }
+
+    /// Gets the number of master clock cycles in a PPU cycle
+    pub fn ppu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5
+        }
+    }
+
+    /// Gets the CPU clock rate, in Hz
+    pub fn cpu_clock_rate(self) -> f64 {
+        self.master_clock() as f64 / self.cpu_divider() as f64
+    }
+
+    /// Gets the number of frames displayed per second
+    pub fn frame_rate(self) -> f64 {
+        let timing = self.ppu_timing();
+        let dots = (timing.scanlines * rp2C02::ppu::DOTS_PER_SCANLINE) as f64;
+        let ppu_clock_rate = self.master_clock() as f64 / self.ppu_divider() as f64;
+        if timing.skip_odd_dot {
+            // Every other frame is one dot shorter while rendering
+            ppu_clock_rate / (dots - 0.5)
+        } else {
+            ppu_clock_rate / dots
+        }
+    }
+
+    /// Converts a CPU cycle count to the number of PPU cycles that elapse in the same time
+    pub fn cpu_to_ppu_cycles(self, cycles: u64) -> u64 {
+        cycles * self.cpu_divider() / self.ppu_divider()
+    }
+
+    /// Converts a PPU cycle count to the CPU cycle during which it occurs
+    pub fn ppu_to_cpu_cycles(self, cycles: u64) -> u64 {
+        cycles * self.ppu_divider() / self.cpu_divider()
+    }
+
+    /// Gets the frame timing of the region's PPU
+    pub fn ppu_timing(self) -> rp2C02::Timing {
+        match self {
+            Region::Ntsc => rp2C02::Timing::ntsc(),
+            Region::Pal => rp2C02::Timing::pal(),
+            Region::Dendy => rp2C02::Timing::dendy()
+        }
+    }
+
+    /// Gets the timing of the region's APU
+    ///
+    /// The Dendy's CPU is a clone of the NTSC RP2A03, so it uses the NTSC tables.
+    pub fn apu_timing(self) -> rp2A03::Timing {
+        match self {
+            Region::Ntsc | Region::Dendy => rp2A03::Timing::ntsc(),
+            Region::Pal => rp2A03::Timing::pal()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use systems::nes::Region;
+
+    #[test]
+    pub fn ntsc_runs_three_ppu_cycles_per_cpu_cycle() {
+        assert_eq!(3, Region::Ntsc.cpu_to_ppu_cycles(1));
+        assert_eq!(1, Region::Ntsc.ppu_to_cpu_cycles(5));
+    }
+
+    #[test]
+    pub fn pal_runs_three_point_two_ppu_cycles_per_cpu_cycle() {
+        assert_eq!(16, Region::Pal.cpu_to_ppu_cycles(5));
+        assert_eq!(5, Region::Pal.ppu_to_cpu_cycles(16));
+        assert_eq!(4, Region::Pal.ppu_to_cpu_cycles(15));
+    }
+
+    #[test]
+    pub fn frame_rates_match_hardware() {
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Dendy.frame_rate() - 50.0070).abs() < 0.001);
+    }
+}
@@ -71,7 +71,10 @@ pub enum TvSystem {
     PAL,
 
     /// Indicates that the ROM is compatible with either the NTSC or the PAL television system
-    Dual
+    Dual,
+
+    /// Indicates that the ROM requires a Dendy (a PAL famiclone with NTSC-like timing)
+    Dendy
 }
 
 /// Describes the version of a ROM
@@ -91,10 +94,10 @@ pub enum Version {
 #[derive(Debug)]
 pub struct RamSize {
     /// Indicates the amount of RAM that is battery-backed
-    pub battery_backed: u16,
+    pub battery_backed: u32,
 
     /// Indicates the total amount of RAM (sum of battery-backed and non-battery-backed RAM)
-    pub total: u16
+    pub total: u32
 }
 
 impl RamSize {
@@ -110,8 +113,8 @@ impl RamSize {
             Version::INES => RamSize::empty(),
 
             Version::NES2 => {
-                let bat = get_full_size(((val & 0xF0) >> 4) as u16);
-                let non_bat = get_full_size((val & 0x0F) as u16);
+                let bat = get_full_size(((val & 0xF0) >> 4) as u32);
+                let non_bat = get_full_size((val & 0x0F) as u32);
 
                 RamSize {
                     battery_backed: bat,
@@ -122,10 +125,10 @@ impl RamSize {
     }
 }
 
-fn get_full_size(inp: u16) -> u16 {
+fn get_full_size(inp: u32) -> u32 {
     match inp {
         0 => 0,
-        x => (2 as u16).pow(6 + x as u32)
+        x => 64 << x
     }
 }
 
@@ -197,6 +200,10 @@ pub struct RomHeader {
 
     /// Indicates the TV system that this ROM was designed for
     pub tv_system: TvSystem,
+
+    /// Indicates the input device this ROM expects to be plugged in, as the NES 2.0 default
+    /// expansion device number (0 if unspecified)
+    pub expansion_device: u8,
 }
 
 /// Represents an NES ROM, loaded from the iNES/NES2.0 format
@@ -263,10 +270,10 @@ fn read_header<R>(input: &mut R) -> Result<RomHeader> where R: io::Read {
     // Based on algorithm in http://wiki.nesdev.com/w/index.php/INES#Variant_comparison
     let version = if header[7] & 0x0C == 0x08 {
         Version::NES2
-    } else if header[12..15].iter().all(|i| { *i == 0 }) {
-        Version::ArchaicINES
-    } else {
+    } else if header[12..16].iter().all(|i| { *i == 0 }) {
         Version::INES
+    } else {
+        Version::ArchaicINES
     };
 
     // Read ROM sizes 
@@ -288,30 +295,36 @@ fn read_header<R>(input: &mut R) -> Result<RomHeader> where R: io::Read {
     let mut mapper = ((header[6] & 0xF0) >> 4) as u16;
     let mut submapper : u8 = 0;
 
-    // If this is iNES, read the second nybble
-    if version == Version::INES {
+    // If this is iNES or NES 2.0, read the second nybble
+    if version != Version::ArchaicINES {
         mapper = (mapper | ((header[7] as u16 & 0xF0))) as u16;
     }
 
     // If this is NES 2.0, read the third nybble and submapper
     if version == Version::NES2 {
         mapper = (mapper | ((header[8] as u16 & 0x0F) << 8)) as u16;
-        submapper = (header[8] & 0xF0) << 4;
+        submapper = (header[8] & 0xF0) >> 4;
     }
 
     // Read TV System
     let tv_system = match version {
         Version::ArchaicINES => TvSystem::Unknown,
         Version::INES => if header[9] & 0x01 == 0 { TvSystem::NTSC } else { TvSystem::PAL },
-        Version::NES2 => if header[12] & 0x02 != 0 { 
-            TvSystem::Dual
-        } else if header[12] & 0x01 != 0 {
-            TvSystem::PAL
-        } else {
-            TvSystem::NTSC
+        Version::NES2 => match header[12] & 0x03 {
+            0 => TvSystem::NTSC,
+            1 => TvSystem::PAL,
+            2 => TvSystem::Dual,
+            _ => TvSystem::Dendy
         }
     };
 
+    // Read the default expansion device
+    let expansion_device = match version {
+        Version::ArchaicINES |
+        Version::INES => 0,
+        Version::NES2 => header[15] & 0x3F
+    };
+
     // Read Ram Sizes
     let prg_ram = RamSize::from_header_byte(header[10], version);
     let chr_ram = RamSize::from_header_byte(header[11], version);
@@ -330,7 +343,8 @@ fn read_header<R>(input: &mut R) -> Result<RomHeader> where R: io::Read {
         trainer_present: (header[6] & 0x04) != 0,
         vs_unisystem: (header[7] & 0x01) != 0,
         playchoice_10: (header[7] & 0x02) != 0,
-        tv_system: tv_system
+        tv_system: tv_system,
+        expansion_device: expansion_device
     })
 }
 
@@ -341,3 +355,52 @@ fn verify_signature(sig: &[u8]) -> bool {
         sig[2] == 0x53 && // 'S'
         sig[3] == 0x1A    // EOF
 }
+
+#[cfg(test)]
+mod test {
+    use systems::nes::rom::{load_rom,RamSize,Version};
+
+    #[test]
+    pub fn reads_nes2_mapper_and_submapper() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x20, 0x48, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000]);
+        let rom = load_rom(&mut &data[..]).unwrap();
+
+        assert_eq!(Version::NES2, rom.header.version);
+        assert_eq!(0x142, rom.header.cartridge.mapper);
+        assert_eq!(2, rom.header.cartridge.submapper);
+    }
+
+    #[test]
+    pub fn ignores_mapper_high_nibble_with_archaic_header() {
+        // Old tools wrote text such as "DiskDude!" into the end of the header
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x40, 0x44, 0x69, 0x73, 0x6B, 0x44, 0x75, 0x64, 0x65, 0x21];
+        data.extend(vec![0; 0x4000]);
+        let rom = load_rom(&mut &data[..]).unwrap();
+
+        assert_eq!(Version::ArchaicINES, rom.header.version);
+        assert_eq!(0x04, rom.header.cartridge.mapper);
+    }
+
+    #[test]
+    pub fn reads_ines_mapper_without_submapper() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x40, 0x10, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend(vec![0; 0x4000]);
+        let rom = load_rom(&mut &data[..]).unwrap();
+
+        assert_eq!(Version::INES, rom.header.version);
+        assert_eq!(0x14, rom.header.cartridge.mapper);
+        assert_eq!(0, rom.header.cartridge.submapper);
+    }
+
+    #[test]
+    pub fn reads_nes2_ram_sizes_of_64k_and_more() {
+        let ram = RamSize::from_header_byte(0x0A, Version::NES2);
+        assert_eq!(0, ram.battery_backed);
+        assert_eq!(0x10000, ram.total);
+
+        let ram = RamSize::from_header_byte(0x99, Version::NES2);
+        assert_eq!(0x8000, ram.battery_backed);
+        assert_eq!(0x10000, ram.total);
+    }
+}
@@ -0,0 +1,48 @@
+//! Tests the NES APU using blargg's apu_test roms
+extern crate remy;
+
+mod common;
+
+#[test]
+pub fn apu_can_run_1_len_ctr_rom() {
+    run_test("1-len_ctr.nes");
+}
+
+#[test]
+pub fn apu_can_run_2_len_table_rom() {
+    run_test("2-len_table.nes");
+}
+
+#[test]
+pub fn apu_can_run_3_irq_flag_rom() {
+    run_test("3-irq_flag.nes");
+}
+
+#[test]
+pub fn apu_can_run_4_jitter_rom() {
+    run_test("4-jitter.nes");
+}
+
+#[test]
+pub fn apu_can_run_5_len_timing_rom() {
+    run_test("5-len_timing.nes");
+}
+
+#[test]
+pub fn apu_can_run_6_irq_flag_timing_rom() {
+    run_test("6-irq_flag_timing.nes");
+}
+
+#[test]
+pub fn apu_can_run_7_dmc_basics_rom() {
+    run_test("7-dmc_basics.nes");
+}
+
+#[test]
+pub fn apu_can_run_8_dmc_rates_rom() {
+    run_test("8-dmc_rates.nes");
+}
+
+fn run_test(rom_name: &str) {
+    common::run_rom(&["apu_test", "rom_singles", rom_name]);
+}
@@ -0,0 +1,92 @@
+//! Helpers shared by the tests which run programs on the NES
+#![allow(dead_code)]
+
+use std::{env,fs};
+use std::path::PathBuf;
+
+use remy::systems::nes;
+
+/// The longest a test rom is allowed to run for, in CPU cycles (roughly 20 seconds)
+pub const MAX_CYCLES: u64 = 20 * 1789773;
+
+/// Gets the location of the file at `path`, relative to tests/roms
+pub fn rom_path(path: &[&str]) -> PathBuf {
+    let mut romfile = env::current_dir().unwrap();
+    romfile.push("tests");
+    romfile.push("roms");
+    for part in path {
+        romfile.push(part);
+    }
+    romfile
+}
+
+/// Creates a NES with the rom at `path` (relative to tests/roms) inserted, and resets it
+pub fn load(path: &[&str]) -> nes::Nes {
+    let rom = nes::load_rom(&mut fs::File::open(rom_path(path)).expect("failed to open ROM file")).expect("failed to load ROM");
+    insert(rom)
+}
+
+/// Runs the blargg test rom at `path` (relative to tests/roms) through `run_test`
+///
+/// Not every test suite can be redistributed with the source, so the test is skipped if the rom
+/// isn't there.
+pub fn run_rom(path: &[&str]) {
+    let romfile = rom_path(path);
+    if !romfile.exists() {
+        println!("skipping test: {} not found", romfile.display());
+        return;
+    }
+
+    let mut nes = load(path);
+    run_test(&mut nes, path[path.len() - 1]);
+}
+
+/// Creates a NES with `rom` inserted, and resets it
+pub fn insert(rom: nes::Rom) -> nes::Nes {
+    // Create a NES
+    let mut nes = nes::Nes::new(None);
+
+    // Load the cartridge into the nes
+    let cart = nes::Cartridge::load(rom, None).expect("failed to load ROM into cartridge");
+    nes.load(cart);
+    nes.reset().expect("failed to reset NES");
+    nes
+}
+
+/// Runs a test rom which reports its result at $6000 the way blargg's test roms do, and
+/// checks that it passed
+pub fn run_test(nes: &mut nes::Nes, rom_name: &str) {
+    // blargg's test roms report their status at $6000, once $6001-$6003 contain the signature
+    // $DE $B0 $61. $80 means the test is still running, anything else is the result code.
+    while nes.cpu.clock.get() < MAX_CYCLES {
+        nes.step().expect("error stepping NES");
+
+        let mut signature = [0u8; 3];
+        nes.mem().get(0x6001, &mut signature).expect("failed to read test signature");
+        if signature != [0xDE, 0xB0, 0x61] {
+            continue;
+        }
+
+        let status = nes.mem().get_u8(0x6000).expect("failed to read test status");
+        if status < 0x80 {
+            assert!(status == 0, "{} failed with status {}: {}", rom_name, status, read_text(nes));
+            return;
+        }
+    }
+
+    panic!("{} did not complete: {}", rom_name, read_text(nes));
+}
+
+/// Reads the text a test rom has written from $6004
+pub fn read_text(nes: &nes::Nes) -> String {
+    let mut text = Vec::new();
+    let mut addr = 0x6004;
+    loop {
+        match nes.mem().get_u8(addr) {
+            Ok(0) | Err(_) => break,
+            Ok(c) => text.push(c)
+        }
+        addr += 1;
+    }
+    String::from_utf8_lossy(&text).into_owned()
+}
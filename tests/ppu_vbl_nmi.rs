@@ -0,0 +1,84 @@
+//! Tests the NES PPU using blargg's ppu_vbl_nmi and scanline roms
+extern crate remy;
+
+mod common;
+
+use std::fs;
+use std::io::Read;
+
+#[test]
+pub fn ppu_can_run_01_vbl_basics_rom() {
+    run_test("01-vbl_basics.nes");
+}
+
+#[test]
+pub fn ppu_can_run_02_vbl_set_time_rom() {
+    run_test("02-vbl_set_time.nes");
+}
+
+#[test]
+pub fn ppu_can_run_03_vbl_clear_time_rom() {
+    run_test("03-vbl_clear_time.nes");
+}
+
+#[test]
+pub fn ppu_can_run_04_nmi_control_rom() {
+    run_test("04-nmi_control.nes");
+}
+
+#[test]
+pub fn ppu_can_run_05_nmi_timing_rom() {
+    run_test("05-nmi_timing.nes");
+}
+
+#[test]
+pub fn ppu_can_run_06_suppression_rom() {
+    run_test("06-suppression.nes");
+}
+
+#[test]
+pub fn ppu_can_run_07_nmi_on_timing_rom() {
+    run_test("07-nmi_on_timing.nes");
+}
+
+#[test]
+pub fn ppu_can_run_08_nmi_off_timing_rom() {
+    run_test("08-nmi_off_timing.nes");
+}
+
+#[test]
+pub fn ppu_can_run_09_even_odd_frames_rom() {
+    run_test("09-even_odd_frames.nes");
+}
+
+#[test]
+pub fn ppu_can_run_10_even_odd_timing_rom() {
+    run_test("10-even_odd_timing.nes");
+}
+
+#[test]
+pub fn ppu_can_run_scanline_rom() {
+    // The scanline rom is a visual test and reports no status, so compare the frame it draws
+    // against a screenshot taken on hardware, kept as scanline.ppm next to the rom
+    let romfile = common::rom_path(&["scanline", "scanline.nes"]);
+    let reference = common::rom_path(&["scanline", "scanline.ppm"]);
+    if !romfile.exists() || !reference.exists() {
+        println!("skipping test: {} or {} not found", romfile.display(), reference.display());
+        return;
+    }
+
+    let mut nes = common::load(&["scanline", "scanline.nes"]);
+    while nes.ppu().frame() < 60 {
+        nes.run_frame().expect("error running frame");
+    }
+
+    let mut frame = Vec::new();
+    nes.framebuffer().write_ppm(&mut frame).expect("failed to encode frame");
+    let mut expected = Vec::new();
+    fs::File::open(reference).and_then(|mut f| f.read_to_end(&mut expected)).expect("failed to read reference frame");
+    assert!(frame == expected, "scanline.nes frame 60 differs from scanline.ppm");
+}
+
+fn run_test(rom_name: &str) {
+    common::run_rom(&["ppu_vbl_nmi", "rom_singles", rom_name]);
+}
@@ -1,4 +1,6 @@
-//! Tests the NES using the inst_test rom
+//! Runs a ROM on the NES, either until a blargg-style test completes, or until a given frame
+//! is reached and captured to an image, optionally recording the audio to WAV or VGM files or
+//! playing back an input movie first
 extern crate remy;
 
 #[macro_use]
@@ -7,9 +9,13 @@ extern crate slog_term;
 
 use slog::DrainExt;
 
-use std::{env,fs};
+use std::{env,fs,io};
+use std::path::Path;
 
+use remy::audio::WavWriter;
+use remy::hw::rp2A03::Channel;
 use remy::systems::nes;
+use remy::systems::nes::movie;
 
 fn read_test_status(nes: &nes::Nes) -> String {
     let mut s = String::new();
@@ -33,10 +39,22 @@ pub fn main() {
         "location" => move |info: &slog::Record| format!("{}:{}", info.module(), info.line())
     ));
 
-    let rom_path = match env::args().nth(1) {
-        Some(r) => r,
+    let options = match Options::parse(env::args().skip(1)) {
+        Some(o) => o,
         None => {
-            println!("usage: nesrun [path to ROM file]");
+            println!("usage: nesrun [--frame N] [--output FILE] [--audio FILE [--channels]] [--vgm FILE] [--movie FILE] [--save] [path to ROM file]");
+            println!("");
+            println!("  --frame N      Runs until frame N has been rendered, then captures it");
+            println!("  --output FILE  The file to write the captured frame to (.png or .ppm),");
+            println!("                 defaults to frame<N>.png");
+            println!("  --audio FILE   Records the mixed audio output to a WAV file");
+            println!("  --channels     Also records each APU channel to its own WAV file, named");
+            println!("                 after the audio file (e.g. FILE-pulse1.wav)");
+            println!("  --vgm FILE     Logs the writes to the APU to a VGM file");
+            println!("  --movie FILE   Plays back an FM2 input movie from power-on before running");
+            println!("                 to the frame or the end of the test");
+            println!("  --save         Loads battery-backed RAM from a .sav file next to the ROM,");
+            println!("                 and saves it there when the run finishes");
             return;
         }
     };
@@ -45,8 +63,11 @@ pub fn main() {
     let mut nes = nes::Nes::new(Some(log.clone()));
 
     // Load the test rom
-    let rom = nes::load_rom(&mut fs::File::open(rom_path).expect("failed to open ROM file")).expect("failed to load ROM");
-    let cart = nes::Cartridge::load(rom, Some(log.clone())).expect("failed to load ROM into cartridge");
+    let rom = nes::load_rom(&mut fs::File::open(&options.rom_path).expect("failed to open ROM file")).expect("failed to load ROM");
+    let mut cart = nes::Cartridge::load(rom, Some(log.clone())).expect("failed to load ROM into cartridge");
+    if options.save {
+        cart.set_save_storage(Box::new(nes::FileStorage::for_rom(&options.rom_path)));
+    }
 
     // Load the cartridge into the nes
     nes.load(cart);
@@ -54,11 +75,33 @@ pub fn main() {
     // Reset the system
     nes.reset().expect("error resetting NES");
 
+    let mut recorder = options.audio.as_ref().map(|path| Recorder::new(&mut nes, path, options.channels));
+    if options.vgm.is_some() {
+        nes.start_vgm_log();
+    }
+
+    if let Some(ref path) = options.movie {
+        play_movie(&mut nes, path, &mut recorder, &log);
+    }
+
+    if let Some(frame) = options.frame {
+        capture_frame(&mut nes, frame, &options.output_path(frame), &mut recorder);
+        if let Some(r) = recorder {
+            r.finish(&mut nes);
+        }
+        finish_vgm(&mut nes, &options.vgm);
+        nes.eject();
+        return;
+    }
+
     let mut status = 0;
     let mut started = false;
     loop {
         // Step one cycle forward
         nes.step().expect("error stepping NES");
+        if let Some(ref mut r) = recorder {
+            r.update(&mut nes);
+        }
 
         // Read the test status
         let new_status = nes.mem().get_u8(0x6000).expect("failed to read test status");
@@ -79,8 +122,187 @@ pub fn main() {
         }
     }
 
+    if let Some(r) = recorder {
+        r.finish(&mut nes);
+    }
+    finish_vgm(&mut nes, &options.vgm);
+
     let result = read_test_status(&nes);
 
     println!("Result:{}", result);
+    nes.eject();
+}
+
+
+struct Options {
+    rom_path: String,
+    frame: Option<u64>,
+    output: Option<String>,
+    audio: Option<String>,
+    channels: bool,
+    vgm: Option<String>,
+    movie: Option<String>,
+    save: bool
+}
+
+impl Options {
+    fn parse<I>(mut args: I) -> Option<Options> where I: Iterator<Item=String> {
+        let mut rom_path = None;
+        let mut frame = None;
+        let mut output = None;
+        let mut audio = None;
+        let mut channels = false;
+        let mut vgm = None;
+        let mut movie = None;
+        let mut save = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--frame" => frame = Some(match args.next().and_then(|f| f.parse().ok()) {
+                    Some(f) => f,
+                    None => return None
+                }),
+                "--output" => output = Some(match args.next() {
+                    Some(o) => o,
+                    None => return None
+                }),
+                "--audio" => audio = Some(match args.next() {
+                    Some(a) => a,
+                    None => return None
+                }),
+                "--channels" => channels = true,
+                "--vgm" => vgm = Some(match args.next() {
+                    Some(v) => v,
+                    None => return None
+                }),
+                "--movie" => movie = Some(match args.next() {
+                    Some(m) => m,
+                    None => return None
+                }),
+                "--save" => save = true,
+                _ if rom_path.is_none() => rom_path = Some(arg),
+                _ => return None
+            }
+        }
+
+        if channels && audio.is_none() {
+            return None;
+        }
+
+        rom_path.map(|r| Options {
+            rom_path: r,
+            frame: frame,
+            output: output,
+            audio: audio,
+            channels: channels,
+            vgm: vgm,
+            movie: movie,
+            save: save
+        })
+    }
+
+    fn output_path(&self, frame: u64) -> String {
+        self.output.clone().unwrap_or_else(|| format!("frame{}.png", frame))
+    }
 }
 
+fn capture_frame(nes: &mut nes::Nes, frame: u64, output_path: &str, recorder: &mut Option<Recorder>) {
+    // The frame counter advances as vertical blank starts, once the frame has been drawn
+    while nes.ppu().frame() < frame {
+        nes.step().expect("error stepping NES");
+        if let Some(ref mut r) = *recorder {
+            r.update(nes);
+        }
+    }
+
+    let image = nes.framebuffer();
+    let mut file = fs::File::create(output_path).expect("failed to create output file");
+    let result = match Path::new(output_path).extension().and_then(|e| e.to_str()) {
+        Some("ppm") => image.write_ppm(&mut file),
+        _ => image.write_png(&mut file)
+    };
+    result.expect("failed to write frame");
+
+    println!("Captured frame {} to {}", frame, output_path);
+}
+
+fn play_movie(nes: &mut nes::Nes, path: &str, recorder: &mut Option<Recorder>, log: &slog::Logger) {
+    let movie = movie::load_movie(&mut fs::File::open(path).expect("failed to open movie file")).expect("failed to load movie");
+    let mut player = movie::MoviePlayer::new(movie, Some(log.clone()));
+    player.start(nes).expect("error starting movie");
+    while player.run_frame(nes).expect("error playing movie") {
+        if let Some(ref mut r) = *recorder {
+            r.update(nes);
+        }
+    }
+
+    println!("Played {} frames of {}", player.frame(), path);
+}
+
+fn finish_vgm(nes: &mut nes::Nes, path: &Option<String>) {
+    if let Some(ref path) = *path {
+        let mut file = io::BufWriter::new(fs::File::create(path).expect("failed to create VGM file"));
+        nes.finish_vgm_log(&mut file).expect("failed to write VGM file");
+        println!("Logged APU writes to {}", path);
+    }
+}
+
+type WavFile = WavWriter<io::BufWriter<fs::File>>;
+
+/// Records the audio produced by the NES to WAV files, once per frame
+struct Recorder {
+    frame: u64,
+    mixed: WavFile,
+    channels: Vec<(Channel, WavFile)>
+}
+
+impl Recorder {
+    fn new(nes: &mut nes::Nes, path: &str, channels: bool) -> Recorder {
+        let channels = if channels {
+            let stem = Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or("audio").to_string();
+            Channel::all().iter().map(|&channel| {
+                nes.set_channel_output(channel, true);
+                let channel_path = Path::new(path).with_file_name(format!("{}-{}.wav", stem, channel.name()));
+                (channel, create_wav(&channel_path, nes.apu().sample_rate()))
+            }).collect()
+        } else {
+            Vec::new()
+        };
+
+        Recorder {
+            frame: nes.ppu().frame(),
+            mixed: create_wav(Path::new(path), nes.apu().sample_rate()),
+            channels: channels
+        }
+    }
+
+    /// Records the samples produced by the NES if a new frame has started
+    fn update(&mut self, nes: &mut nes::Nes) {
+        if nes.ppu().frame() != self.frame {
+            self.frame = nes.ppu().frame();
+            self.record(nes);
+        }
+    }
+
+    fn record(&mut self, nes: &mut nes::Nes) {
+        self.mixed.write_samples(&nes.take_samples()).expect("failed to write audio");
+        for &mut (channel, ref mut wav) in self.channels.iter_mut() {
+            wav.write_samples(&nes.take_channel_samples(channel)).expect("failed to write audio");
+        }
+    }
+
+    fn finish(mut self, nes: &mut nes::Nes) {
+        self.record(nes);
+        let samples = self.mixed.samples();
+        self.mixed.finish().expect("failed to write audio");
+        for (_, wav) in self.channels {
+            wav.finish().expect("failed to write audio");
+        }
+
+        println!("Recorded {} audio samples", samples);
+    }
+}
+
+fn create_wav(path: &Path, sample_rate: u32) -> WavFile {
+    let file = fs::File::create(path).expect("failed to create audio file");
+    WavWriter::new(io::BufWriter::new(file), sample_rate).expect("failed to write audio")
+}
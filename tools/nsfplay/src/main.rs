@@ -0,0 +1,151 @@
+//! Plays a track from an NSF or NSFe file on the NES, and records it to a WAV file
+extern crate remy;
+
+#[macro_use]
+extern crate slog;
+extern crate slog_term;
+
+use slog::DrainExt;
+
+use std::{env,fs,io};
+use std::path::Path;
+
+use remy::audio::WavWriter;
+use remy::systems::nes::nsf;
+
+/// The length a track is played for when neither the command line nor the file gives one
+const DEFAULT_SECONDS: f64 = 120.0;
+
+pub fn main() {
+    // Set up console logging
+    let drain = slog_term::streamer().build().fuse();
+    let log = slog::Logger::root(slog::level_filter(slog::Level::Info, drain), o!(
+        "location" => move |info: &slog::Record| format!("{}:{}", info.module(), info.line())
+    ));
+
+    let options = match Options::parse(env::args().skip(1)) {
+        Some(o) => o,
+        None => {
+            println!("usage: nsfplay [--track N] [--seconds S] [--rate HZ] [--output FILE] [path to NSF file]");
+            println!("");
+            println!("  --track N      The track to play, starting from 1, defaults to the file's");
+            println!("                 starting track");
+            println!("  --seconds S    The number of seconds to play the track for, defaults to the");
+            println!("                 length given by an NSFe file, or {} seconds", DEFAULT_SECONDS);
+            println!("  --rate HZ      The sample rate of the recording, defaults to 44100");
+            println!("  --output FILE  The WAV file to record to, defaults to <NSF name>-<track>.wav");
+            return;
+        }
+    };
+
+    let nsf = nsf::load_nsf(&mut fs::File::open(&options.nsf_path).expect("failed to open NSF file")).expect("failed to load NSF file");
+    println!("{} - {} ({})", nsf.title, nsf.artist, nsf.copyright);
+
+    let song = match options.track {
+        Some(t) if t >= 1 && t <= nsf.songs => t - 1,
+        Some(t) => {
+            println!("Track {} does not exist, the file has {} tracks", t, nsf.songs);
+            return;
+        },
+        None => nsf.starting_song
+    };
+
+    // NSFe files can give the length of each track, along with how long to fade it out for
+    let (length, fade) = match nsf.track(song) {
+        Some(track) => (track.length.map(|l| l as f64 / 1000.0), track.fade.map(|f| f as f64 / 1000.0)),
+        None => (None, None)
+    };
+    let seconds = options.seconds.or(length.map(|l| l + fade.unwrap_or(0.0))).unwrap_or(DEFAULT_SECONDS);
+    let fade = if options.seconds.is_none() { fade.unwrap_or(0.0) } else { 0.0 };
+    if let Some(title) = nsf.track(song).and_then(|t| t.title.clone()) {
+        println!("Track {}: {}", song + 1, title);
+    }
+
+    let output = options.output.clone().unwrap_or_else(|| {
+        let stem = Path::new(&options.nsf_path).file_stem().and_then(|s| s.to_str()).unwrap_or("nsf").to_string();
+        format!("{}-{}.wav", stem, song + 1)
+    });
+
+    let mut player = nsf::NsfPlayer::new(nsf, Some(log.clone()));
+    player.nes_mut().set_sample_rate(options.rate);
+    player.start_song(song).expect("error starting track");
+    // Discard anything produced while the track was initialized
+    player.nes_mut().take_samples();
+
+    let file = fs::File::create(&output).expect("failed to create output file");
+    let mut wav = WavWriter::new(io::BufWriter::new(file), options.rate).expect("failed to write audio");
+    let total = (seconds * options.rate as f64) as u64;
+    let fade_start = total.saturating_sub((fade * options.rate as f64) as u64);
+    while (wav.samples() as u64) < total {
+        player.run_tick().expect("error playing track");
+
+        let position = wav.samples() as u64;
+        let samples: Vec<i16> = player.nes_mut().take_samples().into_iter()
+            .take((total - position) as usize)
+            .enumerate()
+            .map(|(i, s)| fade_out(s, position + i as u64, fade_start, total))
+            .collect();
+        wav.write_samples(&samples).expect("failed to write audio");
+    }
+    wav.finish().expect("failed to write audio");
+
+    println!("Recorded {} seconds of track {} to {}", seconds, song + 1, output);
+}
+
+/// Scales `sample` down linearly from `fade_start` until it is silent at `end`
+fn fade_out(sample: i16, position: u64, fade_start: u64, end: u64) -> i16 {
+    if position < fade_start {
+        sample
+    } else {
+        let volume = (end - position) as f64 / (end - fade_start) as f64;
+        (sample as f64 * volume) as i16
+    }
+}
+
+struct Options {
+    nsf_path: String,
+    track: Option<u8>,
+    seconds: Option<f64>,
+    rate: u32,
+    output: Option<String>
+}
+
+impl Options {
+    fn parse<I>(mut args: I) -> Option<Options> where I: Iterator<Item=String> {
+        let mut nsf_path = None;
+        let mut track = None;
+        let mut seconds = None;
+        let mut rate = 44100;
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--track" => track = Some(match args.next().and_then(|t| t.parse().ok()) {
+                    Some(t) => t,
+                    None => return None
+                }),
+                "--seconds" => seconds = Some(match args.next().and_then(|s| s.parse().ok()) {
+                    Some(s) => s,
+                    None => return None
+                }),
+                "--rate" => rate = match args.next().and_then(|r| r.parse().ok()) {
+                    Some(r) => r,
+                    None => return None
+                },
+                "--output" => output = Some(match args.next() {
+                    Some(o) => o,
+                    None => return None
+                }),
+                _ if nsf_path.is_none() => nsf_path = Some(arg),
+                _ => return None
+            }
+        }
+
+        nsf_path.map(|n| Options {
+            nsf_path: n,
+            track: track,
+            seconds: seconds,
+            rate: rate,
+            output: output
+        })
+    }
+}
@@ -9,10 +9,16 @@ fn main() {
         Some(r) => r,
         None => {
             println!("usage: romdump [path to ROM file]");
+            println!("       romdump --mappers");
             return;
         }
     };
 
+    if rom_path == "--mappers" {
+        list_mappers();
+        return;
+    }
+
     println!("Loading ROM: {}", rom_path);
 
     let rom = nes::load_rom(&mut fs::File::open(rom_path).expect("failed to open ROM file")).expect("failed to load ROM file");
@@ -25,7 +31,12 @@ fn main() {
     println!("    PRG RAM Size: {} bytes ({} bytes of which battery backed)", rom.header.prg_ram_size.total, rom.header.prg_ram_size.battery_backed);
     println!("    CHR RAM Size: {} bytes ({} bytes of which battery backed)", rom.header.chr_ram_size.total, rom.header.chr_ram_size.battery_backed);
     println!("    Cartridge Info:");
-    println!("      Mapper: {}", rom.header.cartridge.mapper);
+    let registry = nes::cart::Registry::new();
+    let description = match registry.find(rom.header.cartridge.mapper, rom.header.cartridge.submapper) {
+        Some(entry) => entry.description,
+        None => "Unsupported"
+    };
+    println!("      Mapper: {} ({})", rom.header.cartridge.mapper, description);
     println!("      Submapper: {}", rom.header.cartridge.submapper);
     println!("      Bus Conflicts?: {}", rom.header.cartridge.bus_conflicts);
     println!("    Use Vertical Arrangement?: {}", rom.header.vertical_arrangement);
@@ -34,12 +45,32 @@ fn main() {
     println!("    Trainer Present?: {}", rom.header.trainer_present);
     println!("    Designed for Vs. Unisystem?: {}", rom.header.vs_unisystem);
     println!("    Designed for PlayChoice-10?: {}", rom.header.playchoice_10);
+    println!("    Default Expansion Device: {}", rom.header.expansion_device);
     println!("");
     println!("  Memory:");
     println!("    Total PRG ROM: {}", format_size(rom.prg.len()));
     println!("    Total CHR ROM: {}", format_size(rom.chr.len()));
 }
 
+fn list_mappers() {
+    let registry = nes::cart::Registry::new();
+    let mut entries = registry.entries().to_vec();
+    entries.sort_by_key(|e| (e.mapper, e.submappers.start));
+
+    println!("Supported Mappers:");
+    for entry in entries {
+        let submappers = if entry.submappers.start == 0 && entry.submappers.end >= 16 {
+            String::new()
+        } else if entry.submappers.end - entry.submappers.start == 1 {
+            format!(".{}", entry.submappers.start)
+        } else {
+            format!(".{}-{}", entry.submappers.start, entry.submappers.end - 1)
+        };
+        let mapper = format!("{}{}", entry.mapper, submappers);
+        println!("  {:<8} {}{}", mapper, entry.description, if entry.battery { " [battery]" } else { "" });
+    }
+}
+
 fn format_size(size: usize) -> String {
     if size < 1024 {
         format!("{} bytes", size)